@@ -3,6 +3,7 @@ set -eu
 
 if which systemctl &> /dev/null; then
     systemctl enable "/opt/Mullvad VPN/resources/mullvad-daemon.service"
+    systemctl enable "/opt/Mullvad VPN/resources/mullvad-early-boot-blocking.service"
     systemctl start mullvad-daemon.service
 elif /sbin/init --version | grep upstart &> /dev/null; then
     ln -s "/opt/Mullvad VPN/resources/mullvad-daemon.conf" /etc/init/
@@ -6,4 +6,5 @@ if which systemctl &> /dev/null; then
         systemctl stop mullvad-daemon.service
         systemctl disable mullvad-daemon.service
     fi
+    systemctl disable mullvad-early-boot-blocking.service &> /dev/null || true
 fi
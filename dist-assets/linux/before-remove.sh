@@ -13,6 +13,7 @@ if which systemctl &> /dev/null; then
     # the user might've disabled or stopped the service themselves already
     systemctl stop mullvad-daemon.service || true
     systemctl disable mullvad-daemon.service || true
+    systemctl disable mullvad-early-boot-blocking.service || true
 elif /sbin/init --version | grep upstart &> /dev/null; then
     stop mullvad-daemon
     rm -f /etc/init/mullvad-daemon.conf
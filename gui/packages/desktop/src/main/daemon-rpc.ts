@@ -50,6 +50,13 @@ const constraint = <T>(constraintValue: SchemaNode<T>) => {
   );
 };
 
+const wireguardPeerSchema = object({
+  public_key: string,
+  allowed_ips: arrayOf(string),
+  endpoint: string,
+  preshared_key: maybe(string),
+});
+
 const customTunnelEndpointSchema = oneOf(
   object({
     openvpn: object({
@@ -67,12 +74,10 @@ const customTunnelEndpointSchema = oneOf(
         private_key: string,
         addresses: arrayOf(string),
       }),
-      peer: object({
-        public_key: string,
-        allowed_ips: arrayOf(string),
-        endpoint: string,
-      }),
+      peer: wireguardPeerSchema,
       gateway: string,
+      ipv6_gateway: maybe(string),
+      additional_peers: maybe(arrayOf(wireguardPeerSchema)),
     }),
   }),
 );
@@ -180,7 +185,12 @@ const accountDataSchema = partialObject({
 const tunnelStateTransitionSchema = oneOf(
   object({
     state: enumeration('disconnecting'),
-    details: enumeration('nothing', 'block', 'reconnect'),
+    details: oneOf(
+      enumeration('nothing', 'block'),
+      object({
+        reconnect: enumeration('requested', 'tunnel_down', 'traffic_stalled', 'back_online'),
+      }),
+    ),
   }),
   object({
     state: enumeration('connecting', 'connected'),
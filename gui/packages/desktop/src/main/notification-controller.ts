@@ -1,7 +1,7 @@
 import { Notification, shell } from 'electron';
 import config from '../config.json';
 
-import { TunnelStateTransition } from '../shared/daemon-rpc-types';
+import { afterDisconnectAction, TunnelStateTransition } from '../shared/daemon-rpc-types';
 
 export default class NotificationController {
   private lastTunnelStateAnnouncement?: { body: string; notification: Notification };
@@ -33,7 +33,7 @@ export default class NotificationController {
         }
         break;
       case 'disconnecting':
-        switch (tunnelState.details) {
+        switch (afterDisconnectAction(tunnelState.details)) {
           case 'nothing':
           case 'block':
             // no-op
@@ -3,7 +3,11 @@ import * as React from 'react';
 import { Component, View } from 'reactxp';
 import { links } from '../../config.json';
 import { NoCreditError, NoInternetError } from '../../main/errors';
-import { ITunnelEndpoint, parseSocketAddress } from '../../shared/daemon-rpc-types';
+import {
+  afterDisconnectAction,
+  ITunnelEndpoint,
+  parseSocketAddress,
+} from '../../shared/daemon-rpc-types';
 import * as AppButton from './AppButton';
 import styles from './ConnectStyles';
 import { Container, Header, Layout } from './Layout';
@@ -155,7 +159,7 @@ export default class Connect extends Component<IProps> {
             return HeaderBarStyle.success;
         }
       case 'disconnecting':
-        switch (status.details) {
+        switch (afterDisconnectAction(status.details)) {
           case 'block':
           case 'reconnect':
             return HeaderBarStyle.success;
@@ -230,7 +234,7 @@ export default class Connect extends Component<IProps> {
       case 'disconnected':
         return MarkerStyle.unsecure;
       case 'disconnecting':
-        switch (status.details) {
+        switch (afterDisconnectAction(status.details)) {
           case 'block':
           case 'reconnect':
             return MarkerStyle.secure;
@@ -246,7 +250,7 @@ export default class Connect extends Component<IProps> {
     const status = this.props.connection.status;
 
     return status.state === 'connecting' ||
-      (status.state === 'disconnecting' && status.details === 'reconnect')
+      (status.state === 'disconnecting' && afterDisconnectAction(status.details) === 'reconnect')
       ? 'spinner'
       : 'marker';
   }
@@ -12,7 +12,11 @@ import {
   NotificationTitle,
 } from './NotificationBanner';
 
-import { BlockReason, TunnelStateTransition } from '../../shared/daemon-rpc-types';
+import {
+  afterDisconnectAction,
+  BlockReason,
+  TunnelStateTransition,
+} from '../../shared/daemon-rpc-types';
 import AccountExpiry from '../lib/account-expiry';
 import { AuthFailure } from '../lib/auth-failure';
 import { IVersionReduxState } from '../redux/version/reducers';
@@ -93,7 +97,7 @@ export default class NotificationArea extends Component<IProps, State> {
         }
 
       case 'disconnecting':
-        if (tunnelState.details === 'reconnect') {
+        if (afterDisconnectAction(tunnelState.details) === 'reconnect') {
           return {
             visible: true,
             type: 'blocking',
@@ -4,7 +4,11 @@ import { Component, Styles, Text, Types, View } from 'reactxp';
 import { colors } from '../../config.json';
 import * as AppButton from './AppButton';
 
-import { RelayProtocol, TunnelStateTransition } from '../../shared/daemon-rpc-types';
+import {
+  afterDisconnectAction,
+  RelayProtocol,
+  TunnelStateTransition,
+} from '../../shared/daemon-rpc-types';
 
 export interface IRelayInAddress {
   ip: string;
@@ -152,7 +156,7 @@ export default class TunnelControl extends Component<ITunnelControlProps> {
         break;
 
       case 'disconnecting':
-        switch (this.props.tunnelState.details) {
+        switch (afterDisconnectAction(this.props.tunnelState.details)) {
           case 'block':
             state = 'blocked';
             break;
@@ -26,7 +26,9 @@ export type BlockReason =
     }
   | { reason: 'auth_failed'; details?: string };
 
-export type AfterDisconnect = 'nothing' | 'block' | 'reconnect';
+export type ReconnectReason = 'requested' | 'tunnel_down' | 'traffic_stalled' | 'back_online';
+
+export type AfterDisconnect = 'nothing' | 'block' | { reconnect: ReconnectReason };
 
 export type TunnelState = 'connecting' | 'connected' | 'disconnecting' | 'disconnected' | 'blocked';
 
@@ -204,6 +206,12 @@ export interface ISocketAddress {
   port: number;
 }
 
+export function afterDisconnectAction(
+  afterDisconnect: AfterDisconnect,
+): 'nothing' | 'block' | 'reconnect' {
+  return typeof afterDisconnect === 'object' ? 'reconnect' : afterDisconnect;
+}
+
 export function parseSocketAddress(socketAddrStr: string): ISocketAddress {
   const re = new RegExp(/(.+):(\d+)$/);
   const matches = socketAddrStr.match(re);
@@ -22,12 +22,12 @@ describe('components/NotificationArea', () => {
   );
 
   it('handles disconnecting state', () => {
-    for (const reason of ['nothing', 'block', 'reconnect']) {
+    for (const details of ['nothing', 'block']) {
       const component = shallow(
         <NotificationArea
           tunnelState={{
             state: 'disconnecting',
-            details: { reason },
+            details,
           }}
           version={defaultVersion}
           accountExpiry={defaultExpiry}
@@ -37,6 +37,22 @@ describe('components/NotificationArea', () => {
     }
   });
 
+  it('handles reconnecting state', () => {
+    const component = shallow(
+      <NotificationArea
+        tunnelState={{
+          state: 'disconnecting',
+          details: { reconnect: 'traffic_stalled' },
+        }}
+        version={defaultVersion}
+        accountExpiry={defaultExpiry}
+      />,
+    );
+
+    expect(component.state('type')).to.be.equal('blocking');
+    expect(component.state('visible')).to.be.true;
+  });
+
   it('handles connected or disconnected states', () => {
     for (const state of ['connected', 'disconnected']) {
       const component = shallow(
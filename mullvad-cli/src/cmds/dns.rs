@@ -0,0 +1,261 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::{value_t_or_exit, values_t};
+use std::net::IpAddr;
+use talpid_types::net::dns::EncryptedDnsUpstream;
+
+pub struct Dns;
+
+impl Command for Dns {
+    fn name(&self) -> &'static str {
+        "dns"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Control which DNS servers are used inside the tunnel")
+            .setting(clap::AppSettings::SubcommandRequired)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Change the DNS servers used inside the tunnel")
+                    .setting(clap::AppSettings::SubcommandRequired)
+                    .subcommand(
+                        clap::SubCommand::with_name("default")
+                            .about("Use the default DNS server of the relay"),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("custom")
+                            .about(
+                                "Use the given DNS servers. Servers on the local network require \
+                                 local network sharing to be allowed",
+                            )
+                            .arg(
+                                clap::Arg::with_name("servers")
+                                    .help("The IP addresses of the DNS servers to use")
+                                    .required(true)
+                                    .multiple(true),
+                            ),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("block")
+                    .about(
+                        "Block content by using a filtering DNS server on the relay. Has no \
+                         effect while custom DNS servers are used",
+                    )
+                    .arg(
+                        clap::Arg::with_name("content")
+                            .required(true)
+                            .possible_values(&["ads", "trackers", "malware"]),
+                    )
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("encrypted")
+                    .about(
+                        "Forward all DNS queries to an encrypted DNS server through a local stub \
+                         resolver. Only supported on Linux",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequired)
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .about("Set the encrypted DNS server to use")
+                            .setting(clap::AppSettings::SubcommandRequired)
+                            .subcommand(
+                                clap::SubCommand::with_name("https")
+                                    .about("Use a DNS-over-HTTPS server")
+                                    .arg(create_address_arg())
+                                    .arg(create_hostname_arg())
+                                    .arg(
+                                        clap::Arg::with_name("path")
+                                            .help("The path that queries are posted to")
+                                            .long("path")
+                                            .default_value("/dns-query"),
+                                    ),
+                            )
+                            .subcommand(
+                                clap::SubCommand::with_name("tls")
+                                    .about("Use a DNS-over-TLS server")
+                                    .arg(create_address_arg())
+                                    .arg(create_hostname_arg()),
+                            ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("unset").about("Stop using encrypted DNS"),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("split")
+                    .about(
+                        "Resolve names within a domain with other DNS servers than the ones used \
+                         inside the tunnel, e.g. an internal DNS server on the local network. Only \
+                         supported with systemd-resolved",
+                    )
+                    .setting(clap::AppSettings::SubcommandRequired)
+                    .subcommand(
+                        clap::SubCommand::with_name("set")
+                            .about("Set the DNS servers to use for a domain")
+                            .arg(
+                                clap::Arg::with_name("domain")
+                                    .help("The domain, e.g. corp.example")
+                                    .required(true),
+                            )
+                            .arg(
+                                clap::Arg::with_name("servers")
+                                    .help("The IP addresses of the DNS servers to use")
+                                    .required(true)
+                                    .multiple(true),
+                            ),
+                    )
+                    .subcommand(
+                        clap::SubCommand::with_name("unset")
+                            .about("Resolve a domain like all other domains again")
+                            .arg(clap::Arg::with_name("domain").required(true)),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("get")
+                    .about("Display the DNS servers used inside the tunnel"),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            if let Some(_matches) = set_matches.subcommand_matches("default") {
+                self.set(Vec::new())
+            } else if let Some(custom_matches) = set_matches.subcommand_matches("custom") {
+                let servers = values_t!(custom_matches.values_of("servers"), IpAddr)
+                    .unwrap_or_else(|e| e.exit());
+                self.set(servers)
+            } else {
+                unreachable!("No dns set command given");
+            }
+        } else if let Some(block_matches) = matches.subcommand_matches("block") {
+            let content = value_t_or_exit!(block_matches.value_of("content"), String);
+            let policy = value_t_or_exit!(block_matches.value_of("policy"), String);
+            self.block(&content, policy == "on")
+        } else if let Some(encrypted_matches) = matches.subcommand_matches("encrypted") {
+            if let Some(set_matches) = encrypted_matches.subcommand_matches("set") {
+                let upstream = if let Some(https_matches) = set_matches.subcommand_matches("https")
+                {
+                    EncryptedDnsUpstream::Https {
+                        address: value_t_or_exit!(https_matches.value_of("address"), IpAddr),
+                        hostname: value_t_or_exit!(https_matches.value_of("hostname"), String),
+                        path: value_t_or_exit!(https_matches.value_of("path"), String),
+                    }
+                } else if let Some(tls_matches) = set_matches.subcommand_matches("tls") {
+                    EncryptedDnsUpstream::Tls {
+                        address: value_t_or_exit!(tls_matches.value_of("address"), IpAddr),
+                        hostname: value_t_or_exit!(tls_matches.value_of("hostname"), String),
+                    }
+                } else {
+                    unreachable!("No encrypted DNS protocol given");
+                };
+                self.set_encrypted(Some(upstream))
+            } else if let Some(_matches) = encrypted_matches.subcommand_matches("unset") {
+                self.set_encrypted(None)
+            } else {
+                unreachable!("No dns encrypted command given");
+            }
+        } else if let Some(split_matches) = matches.subcommand_matches("split") {
+            if let Some(set_matches) = split_matches.subcommand_matches("set") {
+                let domain = value_t_or_exit!(set_matches.value_of("domain"), String);
+                let servers = values_t!(set_matches.values_of("servers"), IpAddr)
+                    .unwrap_or_else(|e| e.exit());
+                self.set_split(domain, servers)
+            } else if let Some(unset_matches) = split_matches.subcommand_matches("unset") {
+                let domain = value_t_or_exit!(unset_matches.value_of("domain"), String);
+                self.set_split(domain, Vec::new())
+            } else {
+                unreachable!("No dns split command given");
+            }
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            self.get()
+        } else {
+            unreachable!("No dns command given");
+        }
+    }
+}
+
+impl Dns {
+    fn set(&self, servers: Vec<IpAddr>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_custom_dns(servers)?;
+        println!("Changed DNS setting");
+        Ok(())
+    }
+
+    fn block(&self, content: &str, block: bool) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let mut content_blocking = rpc.get_settings()?.get_dns_content_blocking();
+        match content {
+            "ads" => content_blocking.block_ads = block,
+            "trackers" => content_blocking.block_trackers = block,
+            "malware" => content_blocking.block_malware = block,
+            _ => unreachable!("Invalid content to block"),
+        }
+        rpc.set_dns_content_blocking(content_blocking)?;
+        println!("Changed DNS content blocking setting");
+        Ok(())
+    }
+
+    fn set_encrypted(&self, upstream: Option<EncryptedDnsUpstream>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_encrypted_dns(upstream)?;
+        println!("Changed encrypted DNS setting");
+        Ok(())
+    }
+
+    fn set_split(&self, domain: String, servers: Vec<IpAddr>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_split_dns(domain, servers)?;
+        println!("Changed split DNS setting");
+        Ok(())
+    }
+
+    fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let settings = rpc.get_settings()?;
+        let custom_dns = settings.get_custom_dns();
+        if custom_dns.is_empty() {
+            println!("DNS: default");
+        } else {
+            println!("DNS: custom ({})", format_servers(&custom_dns));
+        }
+        for (domain, servers) in settings.get_split_dns() {
+            println!("DNS for {}: {}", domain, format_servers(&servers));
+        }
+        println!(
+            "Blocked through DNS: {}",
+            settings.get_dns_content_blocking()
+        );
+        match settings.get_encrypted_dns() {
+            Some(upstream) => println!("Encrypted DNS: {}", upstream),
+            None => println!("Encrypted DNS: off"),
+        }
+        Ok(())
+    }
+}
+
+fn format_servers(servers: &[IpAddr]) -> String {
+    servers
+        .iter()
+        .map(|server| server.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn create_address_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("address")
+        .help("The IP address of the DNS server")
+        .required(true)
+}
+
+fn create_hostname_arg() -> clap::Arg<'static, 'static> {
+    clap::Arg::with_name("hostname")
+        .help("The name used to verify the certificate of the DNS server")
+        .required(true)
+}
@@ -16,15 +16,29 @@ pub use self::connect::Connect;
 mod disconnect;
 pub use self::disconnect::Disconnect;
 
+mod dns;
+pub use self::dns::Dns;
+
 mod block_when_disconnected;
 pub use self::block_when_disconnected::BlockWhenDisconnected;
 
+mod persistent_block;
+pub use self::persistent_block::PersistentBlock;
+
+mod port_forward;
+pub use self::port_forward::PortForward;
+
 mod relay;
 pub use self::relay::Relay;
 
 mod lan;
 pub use self::lan::Lan;
 
+#[cfg(target_os = "linux")]
+mod namespace;
+#[cfg(target_os = "linux")]
+pub use self::namespace::Namespace;
+
 mod tunnel;
 pub use self::tunnel::Tunnel;
 
@@ -33,18 +47,24 @@ pub use self::version::Version;
 
 /// Returns a map of all available subcommands with their name as key.
 pub fn get_commands() -> HashMap<&'static str, Box<dyn Command>> {
-    let commands: Vec<Box<dyn Command>> = vec![
+    #[cfg_attr(not(target_os = "linux"), allow(unused_mut))]
+    let mut commands: Vec<Box<dyn Command>> = vec![
         Box::new(Account),
         Box::new(AutoConnect),
         Box::new(BlockWhenDisconnected),
         Box::new(Connect),
         Box::new(Disconnect),
+        Box::new(Dns),
         Box::new(Lan),
+        Box::new(PersistentBlock),
+        Box::new(PortForward),
         Box::new(Relay),
         Box::new(Status),
         Box::new(Tunnel),
         Box::new(Version),
     ];
+    #[cfg(target_os = "linux")]
+    commands.push(Box::new(Namespace));
     let mut map = HashMap::new();
     for cmd in commands {
         if map.insert(cmd.name(), cmd).is_some() {
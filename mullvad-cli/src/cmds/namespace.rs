@@ -0,0 +1,130 @@
+use crate::{new_rpc_client, Command, ErrorKind, Result};
+
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::Read,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// How often to check whether the shell has exited.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+pub struct Namespace;
+
+impl Command for Namespace {
+    fn name(&self) -> &'static str {
+        "namespace"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about(
+                "Run programs in the network namespace of the tunnel. Requires a namespace to be \
+                 set with `mullvad tunnel wireguard namespace set`",
+            )
+            .setting(clap::AppSettings::SubcommandRequired)
+            .subcommand(
+                clap::SubCommand::with_name("exec")
+                    .about("Start a program in the background in the tunnel namespace")
+                    .setting(clap::AppSettings::TrailingVarArg)
+                    .arg(
+                        clap::Arg::with_name("command")
+                            .help("The program to run, followed by its arguments")
+                            .required(true)
+                            .multiple(true),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("shell")
+                    .about("Start your shell in the tunnel namespace on this terminal"),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        match matches.subcommand() {
+            ("exec", Some(exec_matches)) => {
+                let command = exec_matches
+                    .values_of("command")
+                    .unwrap()
+                    .map(String::from)
+                    .collect();
+                self.exec(command)
+            }
+            ("shell", _) => self.shell(),
+            _ => unreachable!("No namespace command given"),
+        }
+    }
+}
+
+impl Namespace {
+    fn exec(&self, command: Vec<String>) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let request = SpawnRequest::new()?;
+        let pid = request.send(|id| rpc.spawn_in_tunnel_namespace(id, command))?;
+        println!("Started process {}", pid);
+        Ok(())
+    }
+
+    fn shell(&self) -> Result<()> {
+        if unsafe { libc::isatty(0) } != 1 {
+            bail!(ErrorKind::NotATerminal);
+        }
+        let terminal = fs::read_link("/proc/self/fd/0")?
+            .to_string_lossy()
+            .into_owned();
+        let term = env::var("TERM").ok();
+
+        let mut rpc = new_rpc_client()?;
+        let request = SpawnRequest::new()?;
+        let pid = request.send(|id| rpc.spawn_shell_in_tunnel_namespace(id, terminal, term))?;
+
+        // The shell reads from the same terminal, so signals from it are meant for the shell
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_IGN);
+            libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+            libc::signal(libc::SIGTSTP, libc::SIG_IGN);
+        }
+        let process_path = Path::new("/proc").join(pid.to_string());
+        while process_path.exists() {
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(())
+    }
+}
+
+/// A file in the spawn request directory, which proves to the daemon which user is asking it to
+/// start a process.
+struct SpawnRequest {
+    id: String,
+    path: PathBuf,
+}
+
+impl SpawnRequest {
+    fn new() -> Result<Self> {
+        let mut random_bytes = [0u8; 16];
+        File::open("/dev/urandom")?.read_exact(&mut random_bytes)?;
+        let id: String = random_bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let path = mullvad_paths::get_spawn_request_dir()?.join(&id);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(SpawnRequest { id, path })
+    }
+
+    /// Passes the request ID to `send`. The daemon removes the file when it handles the request,
+    /// but it is removed here as well in case the request never got that far.
+    fn send<T>(self, send: impl FnOnce(String) -> mullvad_ipc_client::Result<T>) -> Result<T> {
+        let result = send(self.id);
+        if result.is_err() {
+            let _ = fs::remove_file(&self.path);
+        }
+        Ok(result?)
+    }
+}
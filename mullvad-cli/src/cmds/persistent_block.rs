@@ -0,0 +1,63 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t_or_exit;
+
+pub struct PersistentBlock;
+
+impl Command for PersistentBlock {
+    fn name(&self) -> &'static str {
+        "persistent-block"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Control if network access should stay blocked when the system service is not running")
+            .setting(clap::AppSettings::SubcommandRequired)
+            .subcommand(
+                clap::SubCommand::with_name("set")
+                    .about("Change the persistent block setting")
+                    .arg(
+                        clap::Arg::with_name("policy")
+                            .required(true)
+                            .possible_values(&["on", "off"]),
+                    ),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("get")
+                    .about("Display the current persistent block setting"),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        if let Some(set_matches) = matches.subcommand_matches("set") {
+            let persistent_block = value_t_or_exit!(set_matches.value_of("policy"), String);
+            self.set(persistent_block == "on")
+        } else if let Some(_matches) = matches.subcommand_matches("get") {
+            self.get()
+        } else {
+            unreachable!("No persistent-block command given");
+        }
+    }
+}
+
+impl PersistentBlock {
+    fn set(&self, persistent_block: bool) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_persistent_block(persistent_block)?;
+        println!("Changed persistent block setting");
+        Ok(())
+    }
+
+    fn get(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        let persistent_block = rpc.get_settings()?.get_persistent_block();
+        println!(
+            "Network traffic will be {} when the system service is not running",
+            if persistent_block {
+                "blocked"
+            } else {
+                "allowed"
+            }
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{new_rpc_client, Command, Result};
+use clap::value_t;
+use mullvad_ipc_client::DaemonRpcClient;
+use mullvad_types::account::AccountToken;
+
+pub struct PortForward;
+
+impl Command for PortForward {
+    fn name(&self) -> &'static str {
+        "port-forward"
+    }
+
+    fn clap_subcommand(&self) -> clap::App<'static, 'static> {
+        clap::SubCommand::with_name(self.name())
+            .about("Manage ports forwarded to this device through the VPN tunnel")
+            .setting(clap::AppSettings::SubcommandRequired)
+            .subcommand(
+                clap::SubCommand::with_name("list")
+                    .about("Display the ports forwarded to the configured account"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("add")
+                    .about("Request a new forwarded port for the configured account"),
+            )
+            .subcommand(
+                clap::SubCommand::with_name("remove")
+                    .about("Remove a forwarded port from the configured account")
+                    .arg(
+                        clap::Arg::with_name("port")
+                            .help("The forwarded port to remove")
+                            .required(true),
+                    ),
+            )
+    }
+
+    fn run(&self, matches: &clap::ArgMatches) -> Result<()> {
+        if let Some(_matches) = matches.subcommand_matches("list") {
+            self.list()
+        } else if let Some(_matches) = matches.subcommand_matches("add") {
+            self.add()
+        } else if let Some(remove_matches) = matches.subcommand_matches("remove") {
+            let port = value_t!(remove_matches.value_of("port"), u16).unwrap_or_else(|e| e.exit());
+            self.remove(port)
+        } else {
+            unreachable!("No port-forward command given");
+        }
+    }
+}
+
+impl PortForward {
+    fn list(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        if let Some(account_token) = Self::get_account_token(&mut rpc)? {
+            let ports = rpc.get_forwarded_ports(account_token)?;
+            if ports.is_empty() {
+                println!("No forwarded ports");
+            } else {
+                println!("Forwarded ports:");
+                for port in ports {
+                    println!("\t{}", port);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn add(&self) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        if let Some(account_token) = Self::get_account_token(&mut rpc)? {
+            let port = rpc.add_forwarded_port(account_token)?;
+            println!("Added forwarded port {}", port);
+        }
+        Ok(())
+    }
+
+    fn remove(&self, port: u16) -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        if let Some(account_token) = Self::get_account_token(&mut rpc)? {
+            rpc.remove_forwarded_port(account_token, port)?;
+            println!("Removed forwarded port {}", port);
+        }
+        Ok(())
+    }
+
+    fn get_account_token(rpc: &mut DaemonRpcClient) -> Result<Option<AccountToken>> {
+        let account_token = rpc.get_settings()?.get_account_token();
+        if account_token.is_none() {
+            println!("No account configured");
+        }
+        Ok(account_token)
+    }
+}
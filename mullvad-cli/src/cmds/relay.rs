@@ -1,12 +1,15 @@
-use crate::{new_rpc_client, Command, Result, ResultExt};
+use crate::{new_rpc_client, Command, ErrorKind, Result, ResultExt};
 use clap::{value_t, values_t};
 use std::{
+    fs,
     io::{self, BufRead},
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     str::FromStr,
 };
 
+use ipnetwork::IpNetwork;
 use mullvad_types::{
+    config_file,
     endpoint::all_of_the_internet,
     relay_constraints::{
         Constraint, LocationConstraint, OpenVpnConstraints, RelayConstraintsUpdate,
@@ -62,6 +65,13 @@ impl Command for Relay {
                                         .index(4)
                                         .required(false),
                                 )
+                                .arg(
+                                    clap::Arg::with_name("ipv6-gateway")
+                                        .help("IPv6 gateway address, used as a DNS server when IPv6 is enabled")
+                                        .long("ipv6-gateway")
+                                        .takes_value(true)
+                                        .required(false),
+                                )
                                 .arg(
                                     clap::Arg::with_name("addr")
                                         .help("Local address of wireguard tunnel")
@@ -69,6 +79,32 @@ impl Command for Relay {
                                         .takes_value(true)
                                         .multiple(true)
                                         .required(false),
+                                )
+                                .arg(
+                                    clap::Arg::with_name("preshared-key")
+                                        .help("Read a base64 encoded preshared key for the peer from standard input, \
+                                               on the line after the private key")
+                                        .long("preshared-key")
+                                        .required(false),
+                                )
+                                .arg(
+                                    clap::Arg::with_name("allowed-ip")
+                                        .help("Network to route through the peer. Defaults to all of the internet")
+                                        .long("allowed-ip")
+                                        .takes_value(true)
+                                        .multiple(true)
+                                        .number_of_values(1)
+                                        .required(false),
+                                )
+                                .arg(
+                                    clap::Arg::with_name("peer")
+                                        .help("Additional peer, given as <public key>,<ip:port>,<allowed ip>[,<allowed ip>...]. \
+                                               The allowed IPs of all peers must be distinct")
+                                        .long("peer")
+                                        .takes_value(true)
+                                        .multiple(true)
+                                        .number_of_values(1)
+                                        .required(false),
                                 ),
                             )
                             .subcommand(clap::SubCommand::with_name("openvpn")
@@ -102,6 +138,27 @@ impl Command for Relay {
                                         .index(5),
                                 )
                             )
+                            .subcommand(clap::SubCommand::with_name("import")
+                                .about("Read the relay from a wg-quick or OpenVPN configuration file")
+                                .arg(
+                                    clap::Arg::with_name("file")
+                                        .help("Path to the configuration file")
+                                        .required(true)
+                                        .index(1),
+                                )
+                                .arg(
+                                    clap::Arg::with_name("username")
+                                        .help("Username to be used with an OpenVpn relay")
+                                        .long("username")
+                                        .takes_value(true),
+                                )
+                                .arg(
+                                    clap::Arg::with_name("password")
+                                        .help("Password to be used with an OpenVpn relay")
+                                        .long("password")
+                                        .takes_value(true),
+                                )
+                            )
                     )
                     .subcommand(
                         clap::SubCommand::with_name("location")
@@ -143,6 +200,25 @@ impl Command for Relay {
                     ),
             )
             .subcommand(clap::SubCommand::with_name("get"))
+            .subcommand(
+                clap::SubCommand::with_name("export")
+                    .about(
+                        "Print a wg-quick or OpenVPN configuration file for connecting to the \
+                         current relay without the daemon",
+                    )
+                    .arg(
+                        clap::Arg::with_name("hostname")
+                            .help("Hostname of the relay to export instead of the current one")
+                            .index(1),
+                    )
+                    .arg(
+                        clap::Arg::with_name("output")
+                            .help("Write the configuration to this file instead of standard output")
+                            .long("output")
+                            .short("o")
+                            .takes_value(true),
+                    ),
+            )
             .subcommand(
                 clap::SubCommand::with_name("list").about("List available countries and cities"),
             )
@@ -157,6 +233,8 @@ impl Command for Relay {
             self.set(set_matches)
         } else if matches.subcommand_matches("get").is_some() {
             self.get()
+        } else if let Some(export_matches) = matches.subcommand_matches("export") {
+            self.export(export_matches)
         } else if matches.subcommand_matches("list").is_some() {
             self.list()
         } else if matches.subcommand_matches("update").is_some() {
@@ -191,6 +269,7 @@ impl Relay {
         let custom_endpoint = match matches.subcommand() {
             ("openvpn", Some(openvpn_matches)) => Self::read_custom_openvpn_relay(openvpn_matches),
             ("wireguard", Some(wg_matches)) => Self::read_custom_wireguard_relay(wg_matches),
+            ("import", Some(import_matches)) => Self::import_custom_relay(import_matches)?,
             (_unknown_tunnel, _) => unreachable!("No set relay command given"),
         };
         self.update_constraints(RelaySettingsUpdate::CustomTunnelEndpoint(custom_endpoint))
@@ -221,6 +300,11 @@ impl Relay {
         let peer_key_str =
             value_t!(matches.value_of("peer-key"), String).unwrap_or_else(|e| e.exit());
         let gateway = value_t!(matches.value_of("gateway"), IpAddr).unwrap_or_else(|e| e.exit());
+        let ipv6_gateway = if matches.is_present("ipv6-gateway") {
+            Some(value_t!(matches, "ipv6-gateway", Ipv6Addr).unwrap_or_else(|e| e.exit()))
+        } else {
+            None
+        };
         let mut private_key_str = String::new();
         println!("Reading private key from standard input");
         let _ = io::stdin().lock().read_line(&mut private_key_str);
@@ -229,6 +313,23 @@ impl Relay {
         }
         let private_key = Self::validate_wireguard_key(&private_key_str).into();
         let peer_public_key = Self::validate_wireguard_key(&peer_key_str).into();
+        let preshared_key = if matches.is_present("preshared-key") {
+            let mut preshared_key_str = String::new();
+            println!("Reading preshared key from standard input");
+            let _ = io::stdin().lock().read_line(&mut preshared_key_str);
+            Some(Self::validate_wireguard_key(&preshared_key_str).into())
+        } else {
+            None
+        };
+        let allowed_ips = if matches.is_present("allowed-ip") {
+            values_t!(matches.values_of("allowed-ip"), IpNetwork).unwrap_or_else(|e| e.exit())
+        } else {
+            all_of_the_internet()
+        };
+        let additional_peers = matches
+            .values_of("peer")
+            .map(|peers| peers.map(Self::parse_wireguard_peer).collect())
+            .unwrap_or_default();
 
 
         CustomTunnelEndpoint::new(
@@ -240,14 +341,77 @@ impl Relay {
                 },
                 peer: wireguard::PeerConfig {
                     public_key: peer_public_key,
-                    allowed_ips: all_of_the_internet(),
+                    allowed_ips,
                     endpoint: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port),
+                    preshared_key,
                 },
                 gateway,
+                ipv6_gateway,
+                additional_peers,
             }),
         )
     }
 
+    /// Reads a relay from a `wg-quick` or OpenVPN configuration file. The keepalive interval of a
+    /// `wg-quick` configuration is applied to the WireGuard tunnel settings.
+    fn import_custom_relay(matches: &clap::ArgMatches) -> Result<CustomTunnelEndpoint> {
+        let path = matches.value_of("file").unwrap();
+        let contents = fs::read_to_string(path)?;
+        if config_file::is_wg_quick_config(&contents) {
+            let config = config_file::parse_wg_quick(&contents)
+                .chain_err(|| format!("Failed to import relay from {}", path))?;
+            if let Some(interval) = config.persistent_keepalive {
+                let mut rpc = new_rpc_client()?;
+                rpc.set_wireguard_persistent_keepalive(Some(interval))?;
+                println!("Wireguard keepalive interval has been set to {}", interval);
+            }
+            Ok(config.endpoint)
+        } else {
+            let username = matches
+                .value_of("username")
+                .ok_or(ErrorKind::UsernameRequired)?
+                .to_owned();
+            let password = matches.value_of("password").unwrap_or("").to_owned();
+            config_file::parse_openvpn(&contents, username, password)
+                .chain_err(|| format!("Failed to import relay from {}", path))
+        }
+    }
+
+    /// Parses a peer given as `<public key>,<ip:port>,<allowed ip>[,<allowed ip>...]`.
+    fn parse_wireguard_peer(peer_str: &str) -> wireguard::PeerConfig {
+        let mut parts = peer_str.split(',');
+        let public_key = Self::validate_wireguard_key(parts.next().unwrap_or("")).into();
+        let endpoint = parts
+            .next()
+            .and_then(|endpoint| endpoint.trim().parse::<SocketAddr>().ok())
+            .unwrap_or_else(|| {
+                eprintln!("Expected an IP and port for the peer: {}", peer_str);
+                ::std::process::exit(1);
+            });
+        let allowed_ips = parts
+            .map(|allowed_ip| {
+                allowed_ip.trim().parse::<IpNetwork>().unwrap_or_else(|e| {
+                    eprintln!("Invalid allowed IP {}: {}", allowed_ip, e);
+                    ::std::process::exit(1);
+                })
+            })
+            .collect::<Vec<_>>();
+        if allowed_ips.is_empty() {
+            eprintln!(
+                "Expected at least one allowed IP for the peer: {}",
+                peer_str
+            );
+            ::std::process::exit(1);
+        }
+
+        wireguard::PeerConfig {
+            public_key,
+            allowed_ips,
+            endpoint,
+            preshared_key: None,
+        }
+    }
+
     fn validate_wireguard_key(key_str: &str) -> [u8; 32] {
         let key_bytes = base64::decode(key_str.trim()).unwrap_or_else(|e| {
             eprintln!("Failed to decode wireguard key: {}", e);
@@ -326,6 +490,20 @@ impl Relay {
         Ok(())
     }
 
+    fn export(&self, matches: &clap::ArgMatches) -> Result<()> {
+        let hostname = matches.value_of("hostname").map(str::to_owned);
+        let mut rpc = new_rpc_client()?;
+        let config = rpc.export_tunnel_config(hostname)?;
+        match matches.value_of("output") {
+            Some(path) => {
+                fs::write(path, config)?;
+                println!("Configuration written to {}", path);
+            }
+            None => print!("{}", config),
+        }
+        Ok(())
+    }
+
     fn list(&self) -> Result<()> {
         let mut rpc = new_rpc_client()?;
         let mut locations = rpc.get_relay_locations()?;
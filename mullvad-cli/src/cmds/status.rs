@@ -1,7 +1,8 @@
 use crate::{new_rpc_client, Command, Result};
+use chrono::offset::Utc;
 use mullvad_ipc_client::DaemonRpcClient;
 use mullvad_types::auth_failed::AuthFailed;
-use talpid_types::tunnel::{BlockReason, TunnelStateTransition};
+use talpid_types::tunnel::{ActionAfterDisconnect, BlockReason, TunnelStateTransition};
 
 pub struct Status;
 
@@ -13,6 +14,17 @@ impl Command for Status {
     fn clap_subcommand(&self) -> clap::App<'static, 'static> {
         clap::SubCommand::with_name(self.name())
             .about("View the state of the VPN tunnel")
+            .arg(
+                clap::Arg::with_name("verbose")
+                    .short("v")
+                    .long("verbose")
+                    .help("Also show how the DNS settings are managed"),
+            )
+            .arg(
+                clap::Arg::with_name("stats")
+                    .long("stats")
+                    .help("Also show traffic statistics for the tunnel"),
+            )
             .subcommand(
                 clap::SubCommand::with_name("listen").about("Listen for VPN tunnel state changes"),
             )
@@ -24,6 +36,13 @@ impl Command for Status {
 
         print_state(&state);
         print_location(&mut rpc)?;
+        print_dns_content_blocking(&mut rpc)?;
+        if matches.is_present("verbose") {
+            print_dns_status(&mut rpc)?;
+        }
+        if matches.is_present("stats") {
+            print_tunnel_stats(&mut rpc)?;
+        }
         if matches.subcommand_matches("listen").is_some() {
             for new_state in rpc.new_state_subscribe()? {
                 print_state(&new_state);
@@ -47,6 +66,9 @@ fn print_state(state: &TunnelStateTransition) {
         Connected(_) => println!("Connected"),
         Connecting(_) => println!("Connecting..."),
         Disconnected => println!("Disconnected"),
+        Disconnecting(ActionAfterDisconnect::Reconnect(reason)) => {
+            println!("Reconnecting... ({})", reason)
+        }
         Disconnecting(_) => println!("Disconnecting..."),
     }
 }
@@ -64,6 +86,74 @@ fn print_blocked_reason(reason: &BlockReason) {
     }
 }
 
+fn print_dns_content_blocking(rpc: &mut DaemonRpcClient) -> Result<()> {
+    let settings = rpc.get_settings()?;
+    let content_blocking = settings.get_dns_content_blocking();
+    if content_blocking.resolver().is_some() {
+        if settings.get_custom_dns().is_empty() {
+            println!("Blocked through DNS: {}", content_blocking);
+        } else {
+            println!("Blocked through DNS: nothing (custom DNS servers are used)");
+        }
+    }
+    Ok(())
+}
+
+fn print_dns_status(rpc: &mut DaemonRpcClient) -> Result<()> {
+    let status = rpc.get_dns_status()?;
+    match status.backend {
+        Some(backend) => {
+            let servers = status
+                .servers
+                .iter()
+                .map(|server| server.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!("DNS managed via: {}", backend);
+            println!(
+                "DNS servers: {} on {}",
+                servers,
+                status
+                    .interface
+                    .unwrap_or_else(|| "unknown interface".to_owned())
+            );
+            if let Some(last_applied) = status.last_applied {
+                println!("DNS last applied at: {}", last_applied);
+            }
+        }
+        None => println!("DNS managed via: nothing, the system DNS settings are used"),
+    }
+    Ok(())
+}
+
+fn print_tunnel_stats(rpc: &mut DaemonRpcClient) -> Result<()> {
+    let stats = match rpc.get_tunnel_stats()? {
+        Some(stats) => stats,
+        None => {
+            println!("Tunnel statistics unavailable");
+            return Ok(());
+        }
+    };
+    match (stats.rx_packets, stats.tx_packets) {
+        (Some(rx_packets), Some(tx_packets)) => {
+            println!(
+                "Received: {} bytes ({} packets)",
+                stats.rx_bytes, rx_packets
+            );
+            println!("Sent: {} bytes ({} packets)", stats.tx_bytes, tx_packets);
+        }
+        _ => {
+            println!("Received: {} bytes", stats.rx_bytes);
+            println!("Sent: {} bytes", stats.tx_bytes);
+        }
+    }
+    if let Some(last_handshake) = stats.last_handshake {
+        let age = Utc::now().signed_duration_since(last_handshake);
+        println!("Latest handshake: {} seconds ago", age.num_seconds());
+    }
+    Ok(())
+}
+
 fn print_location(rpc: &mut DaemonRpcClient) -> Result<()> {
     let location = match rpc.get_current_location()? {
         Some(loc) => loc,
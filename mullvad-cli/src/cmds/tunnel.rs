@@ -3,6 +3,8 @@ use clap::value_t;
 
 use mullvad_types::settings::TunnelOptions;
 use talpid_types::net::openvpn;
+#[cfg(target_os = "linux")]
+use talpid_types::net::wireguard;
 
 use std::net::{IpAddr, SocketAddr};
 
@@ -38,9 +40,12 @@ fn create_wireguard_subcommand() -> clap::App<'static, 'static> {
     let app = clap::SubCommand::with_name("wireguard")
         .about("Manage options for Wireguard tunnels")
         .setting(clap::AppSettings::SubcommandRequired)
-        .subcommand(create_wireguard_mtu_subcommand());
+        .subcommand(create_wireguard_mtu_subcommand())
+        .subcommand(create_wireguard_keepalive_subcommand());
     if cfg!(target_os = "linux") {
         app.subcommand(create_wireguard_fwmark_subcommand())
+            .subcommand(create_wireguard_backend_subcommand())
+            .subcommand(create_wireguard_namespace_subcommand())
     } else {
         app
     }
@@ -53,7 +58,26 @@ fn create_wireguard_mtu_subcommand() -> clap::App<'static, 'static> {
         .subcommand(clap::SubCommand::with_name("get"))
         .subcommand(clap::SubCommand::with_name("unset"))
         .subcommand(
-            clap::SubCommand::with_name("set").arg(clap::Arg::with_name("mtu").required(true)),
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("mtu")
+                    .required(true)
+                    .help("The MTU to use, or \"auto\" to probe the path MTU to the relay"),
+            ),
+        )
+}
+
+fn create_wireguard_keepalive_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("keepalive")
+        .about("Configure the persistent keepalive interval of the wireguard tunnel")
+        .setting(clap::AppSettings::SubcommandRequired)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(clap::SubCommand::with_name("unset"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("interval")
+                    .required(true)
+                    .help("Interval in seconds between keepalive packets"),
+            ),
         )
 }
 
@@ -67,6 +91,41 @@ fn create_wireguard_fwmark_subcommand() -> clap::App<'static, 'static> {
         )
 }
 
+fn create_wireguard_backend_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("backend")
+        .about(
+            "Configure whether Wireguard tunnels use the kernel module or wireguard-go. By \
+             default the kernel module is used when it is available",
+        )
+        .setting(clap::AppSettings::SubcommandRequired)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("backend")
+                    .required(true)
+                    .possible_values(&["auto", "kernel", "userspace"]),
+            ),
+        )
+}
+
+fn create_wireguard_namespace_subcommand() -> clap::App<'static, 'static> {
+    clap::SubCommand::with_name("namespace")
+        .about(
+            "Configure a network namespace to move the Wireguard tunnel into. When set, only \
+             processes in the namespace use the tunnel, and the rest of the system is left alone",
+        )
+        .setting(clap::AppSettings::SubcommandRequired)
+        .subcommand(clap::SubCommand::with_name("get"))
+        .subcommand(clap::SubCommand::with_name("unset"))
+        .subcommand(
+            clap::SubCommand::with_name("set").arg(
+                clap::Arg::with_name("namespace")
+                    .required(true)
+                    .help("Name of the network namespace, as used by `ip netns`"),
+            ),
+        )
+}
+
 fn create_openvpn_subcommand() -> clap::App<'static, 'static> {
     clap::SubCommand::with_name("openvpn")
         .about("Manage options for OpenVPN tunnels")
@@ -198,44 +257,102 @@ impl Tunnel {
                 _ => unreachable!("unhandled command"),
             },
 
+            ("keepalive", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_keepalive_get(),
+                ("set", Some(matches)) => Self::process_wireguard_keepalive_set(matches),
+                ("unset", _) => Self::process_wireguard_keepalive_unset(),
+                _ => unreachable!("unhandled command"),
+            },
+
             #[cfg(target_os = "linux")]
             ("fwmark", Some(matches)) => match matches.subcommand() {
                 ("get", _) => Self::process_wireguard_fwmark_get(),
                 ("set", Some(fwmark_matches)) => Self::process_wireguard_fwmark_set(fwmark_matches),
                 _ => unreachable!("unhandled command"),
             },
+
+            #[cfg(target_os = "linux")]
+            ("backend", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_backend_get(),
+                ("set", Some(backend_matches)) => {
+                    Self::process_wireguard_backend_set(backend_matches)
+                }
+                _ => unreachable!("unhandled command"),
+            },
+
+            #[cfg(target_os = "linux")]
+            ("namespace", Some(matches)) => match matches.subcommand() {
+                ("get", _) => Self::process_wireguard_namespace_get(),
+                ("set", Some(matches)) => Self::process_wireguard_namespace_set(matches),
+                ("unset", _) => Self::process_wireguard_namespace_unset(),
+                _ => unreachable!("unhandled command"),
+            },
             _ => unreachable!("unhandled command"),
         }
     }
 
     fn process_wireguard_mtu_get() -> Result<()> {
         let tunnel_options = Self::get_tunnel_options()?;
-        println!(
-            "mtu: {}",
-            tunnel_options
-                .wireguard
-                .mtu
-                .map(|mtu| mtu.to_string())
-                .unwrap_or("unset".into())
-        );
+        let mtu = match tunnel_options.wireguard.mtu {
+            Some(mtu) => mtu.to_string(),
+            None if tunnel_options.wireguard.automatic_mtu => "auto".to_owned(),
+            None => "unset".to_owned(),
+        };
+        println!("mtu: {}", mtu);
         Ok(())
     }
 
     fn process_wireguard_mtu_set(matches: &clap::ArgMatches) -> Result<()> {
-        let mtu = value_t!(matches.value_of("mtu"), u16).unwrap_or_else(|e| e.exit());
         let mut rpc = new_rpc_client()?;
-        rpc.set_wireguard_mtu(Some(mtu))?;
-        println!("Wireguard MTU has been updated");
+        if matches.value_of("mtu") == Some("auto") {
+            rpc.set_wireguard_mtu(None)?;
+            rpc.set_wireguard_automatic_mtu(true)?;
+            println!("Wireguard MTU will be detected automatically");
+        } else {
+            let mtu = value_t!(matches.value_of("mtu"), u16).unwrap_or_else(|e| e.exit());
+            rpc.set_wireguard_mtu(Some(mtu))?;
+            rpc.set_wireguard_automatic_mtu(false)?;
+            println!("Wireguard MTU has been updated");
+        }
         Ok(())
     }
 
     fn process_wireguard_mtu_unset() -> Result<()> {
         let mut rpc = new_rpc_client()?;
         rpc.set_wireguard_mtu(None)?;
+        rpc.set_wireguard_automatic_mtu(false)?;
         println!("Wireguard MTU has been unset");
         Ok(())
     }
 
+    fn process_wireguard_keepalive_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options()?;
+        println!(
+            "keepalive: {}",
+            tunnel_options
+                .wireguard
+                .persistent_keepalive
+                .map(|interval| format!("{} seconds", interval))
+                .unwrap_or_else(|| "unset".to_owned())
+        );
+        Ok(())
+    }
+
+    fn process_wireguard_keepalive_set(matches: &clap::ArgMatches) -> Result<()> {
+        let interval = value_t!(matches.value_of("interval"), u16).unwrap_or_else(|e| e.exit());
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_persistent_keepalive(Some(interval))?;
+        println!("Wireguard keepalive interval has been updated");
+        Ok(())
+    }
+
+    fn process_wireguard_keepalive_unset() -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_persistent_keepalive(None)?;
+        println!("Wireguard keepalive interval has been unset");
+        Ok(())
+    }
+
     #[cfg(target_os = "linux")]
     fn process_wireguard_fwmark_get() -> Result<()> {
         let tunnel_options = Self::get_tunnel_options()?;
@@ -252,6 +369,63 @@ impl Tunnel {
         Ok(())
     }
 
+    #[cfg(target_os = "linux")]
+    fn process_wireguard_backend_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options()?;
+        println!(
+            "backend: {}",
+            tunnel_options
+                .wireguard
+                .backend
+                .map(|backend| backend.to_string())
+                .unwrap_or("auto".into())
+        );
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn process_wireguard_backend_set(matches: &clap::ArgMatches) -> Result<()> {
+        let backend = match matches.value_of("backend").unwrap() {
+            "kernel" => Some(wireguard::Backend::Kernel),
+            "userspace" => Some(wireguard::Backend::Userspace),
+            _ => None,
+        };
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_backend(backend)?;
+        println!("Wireguard backend has been updated");
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn process_wireguard_namespace_get() -> Result<()> {
+        let tunnel_options = Self::get_tunnel_options()?;
+        println!(
+            "namespace: {}",
+            tunnel_options
+                .wireguard
+                .namespace
+                .unwrap_or_else(|| "unset".to_owned())
+        );
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn process_wireguard_namespace_set(matches: &clap::ArgMatches) -> Result<()> {
+        let namespace = matches.value_of("namespace").unwrap().to_owned();
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_namespace(Some(namespace))?;
+        println!("Wireguard namespace has been updated");
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn process_wireguard_namespace_unset() -> Result<()> {
+        let mut rpc = new_rpc_client()?;
+        rpc.set_wireguard_namespace(None)?;
+        println!("Wireguard namespace has been unset");
+        Ok(())
+    }
+
     fn handle_ipv6_cmd(matches: &clap::ArgMatches) -> Result<()> {
         if matches.subcommand_matches("get").is_some() {
             Self::process_ipv6_get()
@@ -26,6 +26,12 @@ error_chain! {
             description("Failed to connect to daemon")
             display("Failed to connect to daemon: {}Is the daemon running?", err.display_chain())
         }
+        NotATerminal {
+            description("Standard input is not a terminal")
+        }
+        UsernameRequired {
+            description("A username is required for OpenVPN relays")
+        }
     }
 
     foreign_links {
@@ -35,6 +41,7 @@ error_chain! {
 
     links {
         RpcClientError(mullvad_ipc_client::Error, mullvad_ipc_client::ErrorKind);
+        PathsError(mullvad_paths::Error, mullvad_paths::ErrorKind);
     }
 }
 
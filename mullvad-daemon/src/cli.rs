@@ -9,6 +9,7 @@ pub struct Config {
     pub log_stdout_timestamps: bool,
     pub run_as_service: bool,
     pub register_service: bool,
+    pub install_boot_firewall: bool,
 }
 
 pub fn get_config() -> Config {
@@ -25,6 +26,8 @@ pub fn get_config() -> Config {
 
     let run_as_service = cfg!(windows) && matches.is_present("run_as_service");
     let register_service = cfg!(windows) && matches.is_present("register_service");
+    let install_boot_firewall =
+        cfg!(target_os = "linux") && matches.is_present("install_boot_firewall");
 
     Config {
         log_level,
@@ -32,6 +35,7 @@ pub fn get_config() -> Config {
         log_stdout_timestamps,
         run_as_service,
         register_service,
+        install_boot_firewall,
     }
 }
 
@@ -89,6 +93,12 @@ fn create_app() -> App<'static, 'static> {
                 .long("register-service")
                 .help("Register itself as a system service"),
         )
+    } else if cfg!(target_os = "linux") {
+        app.arg(
+            Arg::with_name("install_boot_firewall")
+                .long("install-boot-firewall")
+                .help("Apply the blocking firewall rules if persistent block is enabled, then exit. Meant to be run during early boot"),
+        )
     } else {
         app
     }
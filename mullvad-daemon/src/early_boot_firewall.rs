@@ -0,0 +1,25 @@
+use crate::{Result, ResultExt};
+use log::info;
+use mullvad_types::settings::Settings;
+use talpid_core::firewall::{Firewall, FirewallPolicy};
+
+/// Applies the blocking firewall policy if the persistent block setting is enabled. Meant to be
+/// run early during boot, before any network interfaces are brought up and before the daemon
+/// itself has been started.
+pub fn install() -> Result<()> {
+    let settings = Settings::load().chain_err(|| "Unable to read settings")?;
+    if !settings.get_persistent_block() {
+        info!("Persistent block is disabled, not applying any firewall rules");
+        return Ok(());
+    }
+
+    let mut firewall = Firewall::new().chain_err(|| "Unable to initialize firewall")?;
+    firewall
+        .apply_policy(FirewallPolicy::Blocked {
+            allow_lan: settings.get_allow_lan(),
+            allowed_endpoint: None,
+        })
+        .chain_err(|| "Unable to apply blocking firewall policy")?;
+    info!("Applied blocking firewall policy");
+    Ok(())
+}
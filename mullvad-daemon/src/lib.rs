@@ -15,6 +15,8 @@ extern crate serde;
 mod account_history;
 mod geoip;
 mod management_interface;
+#[cfg(target_os = "linux")]
+mod namespace;
 mod relays;
 mod rpc_uniqueness_check;
 
@@ -32,22 +34,25 @@ use mullvad_types::{
     endpoint::MullvadEndpoint,
     location::GeoIpLocation,
     relay_constraints::{
-        Constraint, OpenVpnConstraints, RelayConstraintsUpdate, RelaySettings, RelaySettingsUpdate,
-        TunnelConstraints,
+        Constraint, OpenVpnConstraints, RelayConstraints, RelayConstraintsUpdate, RelaySettings,
+        RelaySettingsUpdate, TunnelConstraints,
     },
     relay_list::{Relay, RelayList},
-    settings::{self, Settings},
+    settings::{self, DnsContentBlocking, Settings},
     states::TargetState,
     version::{AppVersion, AppVersionInfo},
 };
-use std::{mem, path::PathBuf, sync::mpsc, thread, time::Duration};
+use std::{mem, net::IpAddr, path::PathBuf, sync::mpsc, thread, time::Duration};
 use talpid_core::{
     mpsc::IntoSender,
     tunnel_state_machine::{self, TunnelCommand, TunnelParametersGenerator},
 };
 use talpid_types::{
-    net::{openvpn, TransportProtocol, TunnelParameters},
-    tunnel::{BlockReason, TunnelStateTransition},
+    net::{
+        dns::{DnsStatus, EncryptedDnsUpstream},
+        openvpn, Endpoint, TransportProtocol, TunnelParameters,
+    },
+    tunnel::{BlockReason, TunnelStateTransition, TunnelStats},
 };
 
 
@@ -86,6 +91,14 @@ pub enum DaemonEvent {
     ManagementInterfaceExited,
     /// Daemon shutdown triggered by a signal, ctrl-c or similar.
     TriggerShutdown,
+    /// The list of forwarded ports was fetched through the API.
+    ForwardedPorts(Vec<u16>),
+    /// A port was forwarded through the API.
+    ForwardedPortAdded(u16),
+    /// A forwarded port was removed through the API.
+    ForwardedPortRemoved(u16),
+    /// The IP address the API is reached at has changed.
+    ApiIpChanged(IpAddr),
 }
 
 impl From<TunnelStateTransition> for DaemonEvent {
@@ -162,6 +175,7 @@ pub struct Daemon {
     tokio_remote: tokio_core::reactor::Remote,
     relay_selector: relays::RelaySelector,
     last_generated_relay: Option<Relay>,
+    resource_dir: PathBuf,
     version: String,
 }
 
@@ -178,7 +192,15 @@ impl Daemon {
         );
         let ca_path = resource_dir.join(mullvad_paths::resources::API_CA_FILENAME);
 
+        let (tx, rx) = mpsc::channel();
+
         let mut rpc_manager = mullvad_rpc::MullvadRpcFactory::with_cache_dir(&cache_dir, &ca_path);
+        rpc_manager.set_mark_sockets(true);
+        let api_ip_tx = tx.clone();
+        rpc_manager.set_api_ip_listener(move |api_ip| {
+            let _ = api_ip_tx.send(DaemonEvent::ApiIpChanged(api_ip));
+        });
+        let api_endpoint = Self::api_endpoint(rpc_manager.api_ip());
 
         let (rpc_handle, https_handle, tokio_remote) =
             mullvad_rpc::event_loop::create(move |core| {
@@ -195,21 +217,36 @@ impl Daemon {
         let relay_selector =
             relays::RelaySelector::new(rpc_handle.clone(), &resource_dir, &cache_dir);
         let settings = Settings::load().chain_err(|| "Unable to read settings")?;
+        #[cfg(target_os = "linux")]
+        {
+            if let Err(error) = mullvad_paths::spawn_request_dir() {
+                warn!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to create spawn request directory")
+                );
+            }
+        }
 
-        let (tx, rx) = mpsc::channel();
         let tunnel_parameters_generator = MullvadTunnelParametersGenerator { tx: tx.clone() };
         let tunnel_command_tx = tunnel_state_machine::spawn(
             settings.get_allow_lan(),
             settings.get_block_when_disconnected(),
+            settings.get_persistent_block(),
+            Some(api_endpoint),
+            settings.get_forwarded_ports(),
+            settings.get_tunnel_dns_servers(),
+            settings.get_encrypted_dns(),
+            settings.get_split_dns(),
             tunnel_parameters_generator,
             log_dir,
-            resource_dir,
+            resource_dir.clone(),
             cache_dir.clone(),
             IntoSender::from(tx.clone()),
         )?;
 
         let target_state = TargetState::Unsecured;
-        let management_interface_result = Self::start_management_interface(tx.clone(), cache_dir)?;
+        let management_interface_result =
+            Self::start_management_interface(tx.clone(), cache_dir, tokio_remote.clone())?;
 
         // Attempt to download a fresh relay list
         relay_selector.update();
@@ -232,6 +269,7 @@ impl Daemon {
             tokio_remote,
             relay_selector,
             last_generated_relay: None,
+            resource_dir,
             version,
         })
     }
@@ -241,9 +279,11 @@ impl Daemon {
     fn start_management_interface(
         event_tx: mpsc::Sender<DaemonEvent>,
         cache_dir: PathBuf,
+        tokio_remote: tokio_core::reactor::Remote,
     ) -> Result<(management_interface::EventBroadcaster, String)> {
         let multiplex_event_tx = IntoSender::from(event_tx.clone());
-        let server = Self::start_management_interface_server(multiplex_event_tx, cache_dir)?;
+        let server =
+            Self::start_management_interface_server(multiplex_event_tx, cache_dir, tokio_remote)?;
         let event_broadcaster = server.event_broadcaster();
         let socket_path = server.socket_path().to_owned();
         Self::spawn_management_interface_wait_thread(server, event_tx);
@@ -253,8 +293,9 @@ impl Daemon {
     fn start_management_interface_server(
         event_tx: IntoSender<ManagementCommand, DaemonEvent>,
         cache_dir: PathBuf,
+        tokio_remote: tokio_core::reactor::Remote,
     ) -> Result<ManagementInterfaceServer> {
-        let server = ManagementInterfaceServer::start(event_tx, cache_dir)
+        let server = ManagementInterfaceServer::start(event_tx, cache_dir, tokio_remote)
             .chain_err(|| ErrorKind::ManagementInterfaceError("Failed to start server"))?;
         info!(
             "Mullvad management interface listening on {}",
@@ -305,6 +346,10 @@ impl Daemon {
                 );
             }
             TriggerShutdown => self.handle_trigger_shutdown_event(),
+            ForwardedPorts(forwarded_ports) => self.handle_forwarded_ports(forwarded_ports),
+            ForwardedPortAdded(port) => self.handle_forwarded_port_added(port),
+            ForwardedPortRemoved(port) => self.handle_forwarded_port_removed(port),
+            ApiIpChanged(api_ip) => self.handle_api_ip_changed(api_ip),
         }
         Ok(())
     }
@@ -419,7 +464,15 @@ impl Daemon {
             SetTargetState(tx, state) => self.on_set_target_state(tx, state),
             GetState(tx) => self.on_get_state(tx),
             GetCurrentLocation(tx) => self.on_get_current_location(tx),
+            GetDnsStatus(tx) => self.on_get_dns_status(tx),
+            GetTunnelStats(tx) => self.on_get_tunnel_stats(tx),
+            ExportTunnelConfig(tx, hostname) => self.on_export_tunnel_config(tx, hostname),
             GetAccountData(tx, account_token) => self.on_get_account_data(tx, account_token),
+            GetForwardedPorts(tx, account_token) => self.on_get_forwarded_ports(tx, account_token),
+            AddForwardedPort(tx, account_token) => self.on_add_forwarded_port(tx, account_token),
+            RemoveForwardedPort(tx, account_token, port) => {
+                self.on_remove_forwarded_port(tx, account_token, port)
+            }
             GetRelayLocations(tx) => self.on_get_relay_locations(tx),
             UpdateRelayLocations => self.on_update_relay_locations(),
             SetAccount(tx, account_token) => self.on_set_account(tx, account_token),
@@ -428,13 +481,40 @@ impl Daemon {
             SetBlockWhenDisconnected(tx, block_when_disconnected) => {
                 self.on_set_block_when_disconnected(tx, block_when_disconnected)
             }
+            SetPersistentBlock(tx, persistent_block) => {
+                self.on_set_persistent_block(tx, persistent_block)
+            }
+            SetCustomDns(tx, servers) => self.on_set_custom_dns(tx, servers),
+            SetDnsContentBlocking(tx, content_blocking) => {
+                self.on_set_dns_content_blocking(tx, content_blocking)
+            }
+            SetEncryptedDns(tx, upstream) => self.on_set_encrypted_dns(tx, upstream),
+            SetSplitDns(tx, domain, servers) => self.on_set_split_dns(tx, domain, servers),
             SetAutoConnect(tx, auto_connect) => self.on_set_auto_connect(tx, auto_connect),
             SetOpenVpnMssfix(tx, mssfix_arg) => self.on_set_openvpn_mssfix(tx, mssfix_arg),
             SetOpenVpnProxy(tx, proxy) => self.on_set_openvpn_proxy(tx, proxy),
             SetEnableIpv6(tx, enable_ipv6) => self.on_set_enable_ipv6(tx, enable_ipv6),
             #[cfg(target_os = "linux")]
             SetWireguardFwmark(tx, fwmark) => self.on_set_wireguard_fwmark(tx, fwmark),
+            #[cfg(target_os = "linux")]
+            SetWireguardBackend(tx, backend) => self.on_set_wireguard_backend(tx, backend),
+            #[cfg(target_os = "linux")]
+            SetWireguardNamespace(tx, namespace) => self.on_set_wireguard_namespace(tx, namespace),
+            #[cfg(target_os = "linux")]
+            SpawnInTunnelNamespace(tx, request_id, command) => {
+                self.on_spawn_in_tunnel_namespace(tx, request_id, command)
+            }
+            #[cfg(target_os = "linux")]
+            SpawnShellInTunnelNamespace(tx, request_id, terminal, term) => {
+                self.on_spawn_shell_in_tunnel_namespace(tx, request_id, terminal, term)
+            }
             SetWireguardMtu(tx, mtu) => self.on_set_wireguard_mtu(tx, mtu),
+            SetWireguardAutomaticMtu(tx, automatic_mtu) => {
+                self.on_set_wireguard_automatic_mtu(tx, automatic_mtu)
+            }
+            SetWireguardPersistentKeepalive(tx, interval) => {
+                self.on_set_wireguard_persistent_keepalive(tx, interval)
+            }
             GetSettings(tx) => self.on_get_settings(tx),
             GetVersionInfo(tx) => self.on_get_version_info(tx),
             GetCurrentVersion(tx) => self.on_get_current_version(tx),
@@ -516,6 +596,61 @@ impl Daemon {
         })
     }
 
+    fn on_get_dns_status(&mut self, tx: oneshot::Sender<DnsStatus>) {
+        self.send_tunnel_command(TunnelCommand::GetDnsStatus(tx));
+    }
+
+    fn on_get_tunnel_stats(&mut self, tx: oneshot::Sender<Option<TunnelStats>>) {
+        self.send_tunnel_command(TunnelCommand::GetTunnelStats(tx));
+    }
+
+    fn on_export_tunnel_config(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<String, String>>,
+        hostname: Option<String>,
+    ) {
+        let result = self.export_tunnel_config(hostname).map_err(|error| {
+            error!("{}", error.display_chain());
+            error.display_chain().to_string()
+        });
+        Self::oneshot_send(tx, result, "export_tunnel_config response");
+    }
+
+    fn export_tunnel_config(&mut self, hostname: Option<String>) -> Result<String> {
+        let tunnel_parameters = match (self.settings.get_relay_settings(), hostname) {
+            (RelaySettings::CustomTunnelEndpoint(custom_relay), None) => custom_relay
+                .to_tunnel_parameters(self.settings.get_tunnel_options().clone())
+                .chain_err(|| "Custom tunnel endpoint could not be resolved")?,
+            (relay_settings, hostname) => {
+                let account_token = self
+                    .settings
+                    .get_account_token()
+                    .ok_or_else(|| Error::from("No account token configured"))?;
+                let constraints = match relay_settings {
+                    RelaySettings::Normal(constraints) => constraints,
+                    RelaySettings::CustomTunnelEndpoint(_) => RelayConstraints::default(),
+                };
+                // Without a hostname, the relay that was last connected to is exported
+                let hostname = hostname.or_else(|| {
+                    self.last_generated_relay
+                        .as_ref()
+                        .map(|relay| relay.hostname.clone())
+                });
+                let (_, endpoint) = match hostname {
+                    Some(hostname) => self
+                        .relay_selector
+                        .get_tunnel_endpoint_on_relay(&hostname, &constraints),
+                    None => self.relay_selector.get_tunnel_endpoint(&constraints, 0),
+                }
+                .chain_err(|| "No valid relay servers match the current settings")?;
+                self.create_tunnel_parameters(endpoint, account_token)?
+            }
+        };
+        let dns_servers = self.settings.get_tunnel_dns_servers();
+        talpid_core::tunnel::export_config(&tunnel_parameters, &dns_servers, &self.resource_dir)
+            .chain_err(|| "Failed to export the tunnel configuration")
+    }
+
     fn on_get_account_data(
         &mut self,
         tx: oneshot::Sender<BoxFuture<AccountData, mullvad_rpc::Error>>,
@@ -528,6 +663,91 @@ impl Daemon {
         Self::oneshot_send(tx, Box::new(rpc_call), "account data")
     }
 
+    fn on_get_forwarded_ports(
+        &mut self,
+        tx: oneshot::Sender<BoxFuture<Vec<u16>, mullvad_rpc::Error>>,
+        account_token: AccountToken,
+    ) {
+        let daemon_tx = self.tx.clone();
+        let rpc_call = self
+            .accounts_proxy
+            .list_ports(account_token)
+            .map(move |forwarded_ports| {
+                let _ = daemon_tx.send(DaemonEvent::ForwardedPorts(forwarded_ports.clone()));
+                forwarded_ports
+            });
+        Self::oneshot_send(tx, Box::new(rpc_call), "forwarded ports")
+    }
+
+    fn on_add_forwarded_port(
+        &mut self,
+        tx: oneshot::Sender<BoxFuture<u16, mullvad_rpc::Error>>,
+        account_token: AccountToken,
+    ) {
+        let daemon_tx = self.tx.clone();
+        let rpc_call = self
+            .accounts_proxy
+            .add_port(account_token)
+            .map(move |port| {
+                let _ = daemon_tx.send(DaemonEvent::ForwardedPortAdded(port));
+                port
+            });
+        Self::oneshot_send(tx, Box::new(rpc_call), "add forwarded port response")
+    }
+
+    fn on_remove_forwarded_port(
+        &mut self,
+        tx: oneshot::Sender<BoxFuture<(), mullvad_rpc::Error>>,
+        account_token: AccountToken,
+        port: u16,
+    ) {
+        let daemon_tx = self.tx.clone();
+        let rpc_call = self
+            .accounts_proxy
+            .remove_port(account_token, port)
+            .map(move |()| {
+                let _ = daemon_tx.send(DaemonEvent::ForwardedPortRemoved(port));
+            });
+        Self::oneshot_send(tx, Box::new(rpc_call), "remove forwarded port response")
+    }
+
+    fn handle_forwarded_port_added(&mut self, port: u16) {
+        let mut forwarded_ports = self.settings.get_forwarded_ports();
+        forwarded_ports.push(port);
+        self.handle_forwarded_ports(forwarded_ports);
+    }
+
+    fn handle_forwarded_port_removed(&mut self, port: u16) {
+        let mut forwarded_ports = self.settings.get_forwarded_ports();
+        forwarded_ports.retain(|forwarded_port| *forwarded_port != port);
+        self.handle_forwarded_ports(forwarded_ports);
+    }
+
+    fn handle_api_ip_changed(&mut self, api_ip: IpAddr) {
+        info!("API address changed to {}", api_ip);
+        self.send_tunnel_command(TunnelCommand::AllowEndpoint(Self::api_endpoint(api_ip)));
+    }
+
+    fn api_endpoint(api_ip: IpAddr) -> Endpoint {
+        Endpoint::new(api_ip, mullvad_rpc::API_PORT, TransportProtocol::Tcp)
+    }
+
+    fn handle_forwarded_ports(&mut self, forwarded_ports: Vec<u16>) {
+        let save_result = self.settings.set_forwarded_ports(forwarded_ports);
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    self.send_tunnel_command(TunnelCommand::ForwardedPorts(
+                        self.settings.get_forwarded_ports(),
+                    ));
+                }
+            }
+            Err(e) => error!("{}", e.display_chain()),
+        }
+    }
+
     fn on_get_relay_locations(&mut self, tx: oneshot::Sender<RelayList>) {
         Self::oneshot_send(tx, self.relay_selector.get_locations(), "relay locations");
     }
@@ -546,6 +766,9 @@ impl Daemon {
                 if account_changed {
                     self.management_interface_broadcaster
                         .notify_settings(&self.settings);
+                    self.send_tunnel_command(TunnelCommand::ForwardedPorts(
+                        self.settings.get_forwarded_ports(),
+                    ));
                     if account_token_cleared {
                         info!("Disconnecting because account token was cleared");
                         self.set_target_state(TargetState::Unsecured);
@@ -594,18 +817,24 @@ impl Daemon {
         }
     }
 
-    fn on_set_allow_lan(&mut self, tx: oneshot::Sender<()>, allow_lan: bool) {
-        let save_result = self.settings.set_allow_lan(allow_lan);
-        match save_result.chain_err(|| "Unable to save settings") {
+    fn on_set_allow_lan(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        allow_lan: bool,
+    ) {
+        match self.settings.set_allow_lan(allow_lan) {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, (), "set_allow_lan response");
+                Self::oneshot_send(tx, Ok(()), "set_allow_lan response");
                 if settings_changed {
                     self.management_interface_broadcaster
                         .notify_settings(&self.settings);
                     self.send_tunnel_command(TunnelCommand::AllowLan(allow_lan));
                 }
             }
-            Err(e) => error!("{}", e.display_chain()),
+            Err(error) => {
+                error!("{}", error.display_chain());
+                Self::oneshot_send(tx, Err(error), "set_allow_lan response");
+            }
         }
     }
 
@@ -632,6 +861,108 @@ impl Daemon {
         }
     }
 
+    fn on_set_persistent_block(&mut self, tx: oneshot::Sender<()>, persistent_block: bool) {
+        let save_result = self.settings.set_persistent_block(persistent_block);
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_persistent_block response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    self.send_tunnel_command(TunnelCommand::PersistentBlock(persistent_block));
+                }
+            }
+            Err(e) => error!("{}", e.display_chain()),
+        }
+    }
+
+    fn on_set_custom_dns(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        servers: Vec<IpAddr>,
+    ) {
+        match self.settings.set_custom_dns(servers) {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_custom_dns response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    self.send_tunnel_dns_servers();
+                }
+            }
+            Err(error) => {
+                error!("{}", error.display_chain());
+                Self::oneshot_send(tx, Err(error), "set_custom_dns response");
+            }
+        }
+    }
+
+    fn on_set_dns_content_blocking(
+        &mut self,
+        tx: oneshot::Sender<()>,
+        content_blocking: DnsContentBlocking,
+    ) {
+        let save_result = self.settings.set_dns_content_blocking(content_blocking);
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_dns_content_blocking response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    self.send_tunnel_dns_servers();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain()),
+        }
+    }
+
+    fn on_set_encrypted_dns(
+        &mut self,
+        tx: oneshot::Sender<()>,
+        upstream: Option<EncryptedDnsUpstream>,
+    ) {
+        let save_result = self.settings.set_encrypted_dns(upstream.clone());
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_encrypted_dns response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    self.send_tunnel_command(TunnelCommand::EncryptedDns(upstream));
+                }
+            }
+            Err(e) => error!("{}", e.display_chain()),
+        }
+    }
+
+    fn on_set_split_dns(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<(), settings::Error>>,
+        domain: String,
+        servers: Vec<IpAddr>,
+    ) {
+        match self.settings.set_split_dns(&domain, servers) {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_split_dns response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    let split_dns = self.settings.get_split_dns();
+                    self.send_tunnel_command(TunnelCommand::SplitDns(split_dns));
+                }
+            }
+            Err(error) => {
+                error!("{}", error.display_chain());
+                Self::oneshot_send(tx, Err(error), "set_split_dns response");
+            }
+        }
+    }
+
+    fn send_tunnel_dns_servers(&mut self) {
+        let dns_servers = self.settings.get_tunnel_dns_servers();
+        self.send_tunnel_command(TunnelCommand::CustomDns(dns_servers));
+    }
+
     fn on_set_auto_connect(&mut self, tx: oneshot::Sender<()>, auto_connect: bool) {
         let save_result = self.settings.set_auto_connect(auto_connect);
         match save_result.chain_err(|| "Unable to save settings") {
@@ -746,6 +1077,89 @@ impl Daemon {
         }
     }
 
+    #[cfg(target_os = "linux")]
+    fn on_set_wireguard_backend(
+        &mut self,
+        tx: oneshot::Sender<()>,
+        backend: Option<talpid_types::net::wireguard::Backend>,
+    ) {
+        let save_result = self.settings.set_wireguard_backend(backend);
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_wireguard_backend response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    info!(
+                        "Initiating tunnel restart because the WireGuard backend setting changed"
+                    );
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain()),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_set_wireguard_namespace(&mut self, tx: oneshot::Sender<()>, namespace: Option<String>) {
+        let save_result = self.settings.set_wireguard_namespace(namespace);
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_wireguard_namespace response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    info!(
+                        "Initiating tunnel restart because the WireGuard namespace setting changed"
+                    );
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain()),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_spawn_in_tunnel_namespace(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<u32, String>>,
+        request_id: String,
+        command: Vec<String>,
+    ) {
+        let result = namespace::spawn_command(self.tunnel_namespace(), &request_id, command)
+            .map_err(|error| {
+                error!("{}", error.display_chain());
+                error.display_chain().to_string()
+            });
+        Self::oneshot_send(tx, result, "spawn_in_tunnel_namespace response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_spawn_shell_in_tunnel_namespace(
+        &mut self,
+        tx: oneshot::Sender<::std::result::Result<u32, String>>,
+        request_id: String,
+        terminal: String,
+        term: Option<String>,
+    ) {
+        let result = namespace::spawn_shell(self.tunnel_namespace(), &request_id, &terminal, term)
+            .map_err(|error| {
+                error!("{}", error.display_chain());
+                error.display_chain().to_string()
+            });
+        Self::oneshot_send(tx, result, "spawn_shell_in_tunnel_namespace response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn tunnel_namespace(&self) -> Option<&str> {
+        self.settings
+            .get_tunnel_options()
+            .wireguard
+            .namespace
+            .as_ref()
+            .map(String::as_str)
+    }
+
     fn on_set_wireguard_mtu(&mut self, tx: oneshot::Sender<()>, mtu: Option<u16>) {
         let save_result = self.settings.set_wireguard_mtu(mtu);
         match save_result.chain_err(|| "Unable to save settings") {
@@ -762,6 +1176,48 @@ impl Daemon {
         }
     }
 
+    fn on_set_wireguard_automatic_mtu(&mut self, tx: oneshot::Sender<()>, automatic_mtu: bool) {
+        let save_result = self.settings.set_wireguard_automatic_mtu(automatic_mtu);
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_wireguard_automatic_mtu response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    info!(
+                        "Initiating tunnel restart because the WireGuard automatic MTU setting \
+                         changed"
+                    );
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain()),
+        }
+    }
+
+    fn on_set_wireguard_persistent_keepalive(
+        &mut self,
+        tx: oneshot::Sender<()>,
+        interval: Option<u16>,
+    ) {
+        let save_result = self.settings.set_wireguard_persistent_keepalive(interval);
+        match save_result.chain_err(|| "Unable to save settings") {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, (), "set_wireguard_persistent_keepalive response");
+                if settings_changed {
+                    self.management_interface_broadcaster
+                        .notify_settings(&self.settings);
+                    info!(
+                        "Initiating tunnel restart because the WireGuard persistent keepalive \
+                         setting changed"
+                    );
+                    self.reconnect_tunnel();
+                }
+            }
+            Err(e) => error!("{}", e.display_chain()),
+        }
+    }
+
     fn on_get_settings(&self, tx: oneshot::Sender<Settings>) {
         Self::oneshot_send(tx, self.settings.clone(), "get_settings response");
     }
@@ -774,6 +1230,7 @@ impl Daemon {
 
     fn handle_trigger_shutdown_event(&mut self) {
         self.state.shutdown(&self.tunnel_state);
+        self.send_tunnel_command(TunnelCommand::PrepareShutdown);
         self.disconnect_tunnel();
     }
 
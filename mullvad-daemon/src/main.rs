@@ -16,6 +16,8 @@ use mullvad_daemon::Daemon;
 use std::{thread, time::Duration};
 
 mod cli;
+#[cfg(target_os = "linux")]
+mod early_boot_firewall;
 mod logging;
 mod shutdown;
 #[cfg(windows)]
@@ -97,7 +99,16 @@ fn run_platform(config: &cli::Config) -> Result<()> {
     }
 }
 
-#[cfg(not(windows))]
+#[cfg(target_os = "linux")]
+fn run_platform(config: &cli::Config) -> Result<()> {
+    if config.install_boot_firewall {
+        early_boot_firewall::install()
+    } else {
+        run_standalone(config)
+    }
+}
+
+#[cfg(target_os = "macos")]
 fn run_platform(config: &cli::Config) -> Result<()> {
     run_standalone(config)
 }
@@ -4,7 +4,7 @@ use jsonrpc_core::{
     futures::{
         future,
         sync::{self, oneshot::Sender as OneshotSender},
-        Future,
+        Future, Stream,
     },
     Error, ErrorCode, MetaIoHandler, Metadata,
 };
@@ -18,19 +18,30 @@ use mullvad_types::{
     location::GeoIpLocation,
     relay_constraints::RelaySettingsUpdate,
     relay_list::RelayList,
-    settings::{self, Settings},
+    settings::{self, DnsContentBlocking, Settings},
     states::TargetState,
     version,
 };
 use serde;
 use std::{
+    cmp,
     collections::{hash_map::Entry, HashMap},
+    net::IpAddr,
     path::PathBuf,
     sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
 };
 use talpid_core::mpsc::IntoSender;
 use talpid_ipc;
-use talpid_types::{net::openvpn, tunnel::TunnelStateTransition};
+use talpid_types::{
+    net::{
+        dns::{DnsStatus, EncryptedDnsUpstream},
+        openvpn, wireguard,
+    },
+    tunnel::{TunnelStateTransition, TunnelStats},
+};
+use tokio_core::reactor::Remote;
+use tokio_timer::Timer;
 use uuid;
 
 /// FIXME(linus): This is here just because the futures crate has deprecated it and jsonrpc_core
@@ -47,6 +58,18 @@ build_rpc_trait! {
         #[rpc(meta, name = "get_account_data")]
         fn get_account_data(&self, Self::Metadata, AccountToken) -> BoxFuture<AccountData, Error>;
 
+        /// Fetches the ports forwarded to the given account through the relays.
+        #[rpc(meta, name = "get_forwarded_ports")]
+        fn get_forwarded_ports(&self, Self::Metadata, AccountToken) -> BoxFuture<Vec<u16>, Error>;
+
+        /// Requests a new forwarded port for the given account and returns it.
+        #[rpc(meta, name = "add_forwarded_port")]
+        fn add_forwarded_port(&self, Self::Metadata, AccountToken) -> BoxFuture<u16, Error>;
+
+        /// Removes a forwarded port from the given account.
+        #[rpc(meta, name = "remove_forwarded_port")]
+        fn remove_forwarded_port(&self, Self::Metadata, AccountToken, u16) -> BoxFuture<(), Error>;
+
         /// Returns available countries.
         #[rpc(meta, name = "get_relay_locations")]
         fn get_relay_locations(&self, Self::Metadata) -> BoxFuture<RelayList, Error>;
@@ -74,6 +97,30 @@ build_rpc_trait! {
         #[rpc(meta, name = "set_block_when_disconnected")]
         fn set_block_when_disconnected(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
 
+        /// Set if the blocking firewall rules should be kept in place when the daemon exits.
+        #[rpc(meta, name = "set_persistent_block")]
+        fn set_persistent_block(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
+
+        /// Set the DNS servers to use inside the tunnel. An empty list means the default DNS
+        /// server is used.
+        #[rpc(meta, name = "set_custom_dns")]
+        fn set_custom_dns(&self, Self::Metadata, Vec<IpAddr>) -> BoxFuture<(), Error>;
+
+        /// Set which content should be blocked by using a filtering DNS resolver on the relay.
+        #[rpc(meta, name = "set_dns_content_blocking")]
+        fn set_dns_content_blocking(&self, Self::Metadata, DnsContentBlocking) -> BoxFuture<(), Error>;
+
+        /// Set an encrypted DNS server to forward all DNS queries to through a local stub
+        /// resolver, or `null` to use plain DNS. Only supported on Linux.
+        #[rpc(meta, name = "set_encrypted_dns")]
+        fn set_encrypted_dns(&self, Self::Metadata, Option<EncryptedDnsUpstream>) -> BoxFuture<(), Error>;
+
+        /// Set the DNS servers that resolve names within the given domain, instead of the DNS
+        /// servers used inside the tunnel. An empty list removes the domain. Only supported with
+        /// systemd-resolved on Linux.
+        #[rpc(meta, name = "set_split_dns")]
+        fn set_split_dns(&self, Self::Metadata, String, Vec<IpAddr>) -> BoxFuture<(), Error>;
+
         /// Set if the daemon should automatically establish a tunnel on start or not.
         #[rpc(meta, name = "set_auto_connect")]
         fn set_auto_connect(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
@@ -97,6 +144,20 @@ build_rpc_trait! {
         #[rpc(meta, name = "get_current_location")]
         fn get_current_location(&self, Self::Metadata) -> BoxFuture<Option<GeoIpLocation>, Error>;
 
+        /// Returns how the DNS settings are currently managed on the system.
+        #[rpc(meta, name = "get_dns_status")]
+        fn get_dns_status(&self, Self::Metadata) -> BoxFuture<DnsStatus, Error>;
+
+        /// Returns the traffic statistics of the tunnel, or `None` if not connected. Can also be
+        /// received periodically by subscribing to `tunnel_stats`.
+        #[rpc(meta, name = "get_tunnel_stats")]
+        fn get_tunnel_stats(&self, Self::Metadata) -> BoxFuture<Option<TunnelStats>, Error>;
+
+        /// Renders a wg-quick or OpenVPN configuration file for connecting to the current relay,
+        /// or the relay with the given hostname, without the daemon.
+        #[rpc(meta, name = "export_tunnel_config")]
+        fn export_tunnel_config(&self, Self::Metadata, Option<String>) -> BoxFuture<String, Error>;
+
         /// Makes the daemon exit its main loop and quit.
         #[rpc(meta, name = "shutdown")]
         fn shutdown(&self, Self::Metadata) -> BoxFuture<(), Error>;
@@ -125,10 +186,45 @@ build_rpc_trait! {
         #[rpc(meta, name = "set_wireguard_fwmark")]
         fn set_wireguard_fwmark(&self, Self::Metadata, i32) -> BoxFuture<(), Error>;
 
+        /// Force wireguard tunnels on Linux to use either the kernel module or wireguard-go.
+        /// `None` uses the kernel module when it's available.
+        #[rpc(meta, name = "set_wireguard_backend")]
+        fn set_wireguard_backend(&self, Self::Metadata, Option<wireguard::Backend>)
+            -> BoxFuture<(), Error>;
+
+        /// Run wireguard tunnels on Linux in the network namespace with the given name, leaving
+        /// the routes, firewall and DNS of the host untouched. `None` tunnels all traffic.
+        #[rpc(meta, name = "set_wireguard_namespace")]
+        fn set_wireguard_namespace(&self, Self::Metadata, Option<String>) -> BoxFuture<(), Error>;
+
+        /// Starts a command in the network namespace of the tunnel, as the user that created the
+        /// request file with the given name in the spawn request directory. Returns the PID of
+        /// the process.
+        #[rpc(meta, name = "spawn_in_tunnel_namespace")]
+        fn spawn_in_tunnel_namespace(&self, Self::Metadata, String, Vec<String>)
+            -> BoxFuture<u32, Error>;
+
+        /// Starts the shell of the user that created the request file with the given name in the
+        /// network namespace of the tunnel, attached to the given terminal device. The last
+        /// argument is the value of `TERM` for the shell. Returns the PID of the shell.
+        #[rpc(meta, name = "spawn_shell_in_tunnel_namespace")]
+        fn spawn_shell_in_tunnel_namespace(&self, Self::Metadata, String, String, Option<String>)
+            -> BoxFuture<u32, Error>;
+
         /// Set MTU for wireguard tunnels
         #[rpc(meta, name = "set_wireguard_mtu")]
         fn set_wireguard_mtu(&self, Self::Metadata, Option<u16>) -> BoxFuture<(), Error>;
 
+        /// Set whether the MTU for wireguard tunnels should be derived from the path MTU to the
+        /// relay when no MTU is set
+        #[rpc(meta, name = "set_wireguard_automatic_mtu")]
+        fn set_wireguard_automatic_mtu(&self, Self::Metadata, bool) -> BoxFuture<(), Error>;
+
+        /// Set the persistent keepalive interval, in seconds, for wireguard tunnels
+        #[rpc(meta, name = "set_wireguard_persistent_keepalive")]
+        fn set_wireguard_persistent_keepalive(&self, Self::Metadata, Option<u16>)
+            -> BoxFuture<(), Error>;
+
         /// Returns the current daemon settings
         #[rpc(meta, name = "get_settings")]
         fn get_settings(&self, Self::Metadata) -> BoxFuture<Settings, Error>;
@@ -165,6 +261,22 @@ build_rpc_trait! {
             #[rpc(name = "settings_unsubscribe")]
             fn settings_unsubscribe(&self, SubscriptionId) -> BoxFuture<(), Error>;
         }
+
+        #[pubsub(name = "tunnel_stats")] {
+            /// Subscribes to the `tunnel_stats` event notifications. The traffic statistics of
+            /// the tunnel are sent every given number of seconds while connected.
+            #[rpc(name = "tunnel_stats_subscribe")]
+            fn tunnel_stats_subscribe(
+                &self,
+                Self::Metadata,
+                pubsub::Subscriber<TunnelStats>,
+                u64
+            );
+
+            /// Unsubscribes from the `tunnel_stats` event notifications.
+            #[rpc(name = "tunnel_stats_unsubscribe")]
+            fn tunnel_stats_unsubscribe(&self, SubscriptionId) -> BoxFuture<(), Error>;
+        }
     }
 }
 
@@ -177,11 +289,33 @@ pub enum ManagementCommand {
     GetState(OneshotSender<TunnelStateTransition>),
     /// Get the current geographical location.
     GetCurrentLocation(OneshotSender<Option<GeoIpLocation>>),
+    /// Request how the DNS settings are currently managed.
+    GetDnsStatus(OneshotSender<DnsStatus>),
+    /// Request the traffic statistics of the tunnel.
+    GetTunnelStats(OneshotSender<Option<TunnelStats>>),
+    /// Render a configuration file for connecting to the current or the given relay.
+    ExportTunnelConfig(OneshotSender<Result<String, String>>, Option<String>),
     /// Request the metadata for an account.
     GetAccountData(
         OneshotSender<BoxFuture<AccountData, mullvad_rpc::Error>>,
         AccountToken,
     ),
+    /// Request the ports forwarded to an account.
+    GetForwardedPorts(
+        OneshotSender<BoxFuture<Vec<u16>, mullvad_rpc::Error>>,
+        AccountToken,
+    ),
+    /// Request a new forwarded port for an account.
+    AddForwardedPort(
+        OneshotSender<BoxFuture<u16, mullvad_rpc::Error>>,
+        AccountToken,
+    ),
+    /// Remove a forwarded port from an account.
+    RemoveForwardedPort(
+        OneshotSender<BoxFuture<(), mullvad_rpc::Error>>,
+        AccountToken,
+        u16,
+    ),
     /// Get the list of countries and cities where there are relays.
     GetRelayLocations(OneshotSender<RelayList>),
     /// Trigger an asynchronous relay list update. This returns before the relay list is actually
@@ -192,9 +326,23 @@ pub enum ManagementCommand {
     /// Place constraints on the type of tunnel and relay
     UpdateRelaySettings(OneshotSender<()>, RelaySettingsUpdate),
     /// Set the allow LAN setting.
-    SetAllowLan(OneshotSender<()>, bool),
+    SetAllowLan(OneshotSender<Result<(), settings::Error>>, bool),
     /// Set the block_when_disconnected setting.
     SetBlockWhenDisconnected(OneshotSender<()>, bool),
+    /// Set the persistent_block setting.
+    SetPersistentBlock(OneshotSender<()>, bool),
+    /// Set the DNS servers to use inside the tunnel.
+    SetCustomDns(OneshotSender<Result<(), settings::Error>>, Vec<IpAddr>),
+    /// Set which content should be blocked through DNS.
+    SetDnsContentBlocking(OneshotSender<()>, DnsContentBlocking),
+    /// Set the encrypted DNS server to forward DNS queries to.
+    SetEncryptedDns(OneshotSender<()>, Option<EncryptedDnsUpstream>),
+    /// Set the DNS servers to use for a domain.
+    SetSplitDns(
+        OneshotSender<Result<(), settings::Error>>,
+        String,
+        Vec<IpAddr>,
+    ),
     /// Set the auto-connect setting.
     SetAutoConnect(OneshotSender<()>, bool),
     /// Set the mssfix argument for OpenVPN
@@ -209,8 +357,29 @@ pub enum ManagementCommand {
     #[cfg(target_os = "linux")]
     /// Set wireguard firewall mark
     SetWireguardFwmark(OneshotSender<()>, i32),
+    #[cfg(target_os = "linux")]
+    /// Set which wireguard implementation to use
+    SetWireguardBackend(OneshotSender<()>, Option<wireguard::Backend>),
+    #[cfg(target_os = "linux")]
+    /// Set the network namespace wireguard tunnels run in
+    SetWireguardNamespace(OneshotSender<()>, Option<String>),
+    #[cfg(target_os = "linux")]
+    /// Start a command in the tunnel namespace for the user behind a spawn request
+    SpawnInTunnelNamespace(OneshotSender<Result<u32, String>>, String, Vec<String>),
+    #[cfg(target_os = "linux")]
+    /// Start a shell on a terminal in the tunnel namespace for the user behind a spawn request
+    SpawnShellInTunnelNamespace(
+        OneshotSender<Result<u32, String>>,
+        String,
+        String,
+        Option<String>,
+    ),
     /// Set MTU for wireguard tunnels
     SetWireguardMtu(OneshotSender<()>, Option<u16>),
+    /// Set whether the MTU for wireguard tunnels should be detected automatically
+    SetWireguardAutomaticMtu(OneshotSender<()>, bool),
+    /// Set the persistent keepalive interval for wireguard tunnels
+    SetWireguardPersistentKeepalive(OneshotSender<()>, Option<u16>),
     /// Get the daemon settings
     GetSettings(OneshotSender<Settings>),
     /// Get information about the currently running and latest app versions
@@ -221,10 +390,23 @@ pub enum ManagementCommand {
     Shutdown,
 }
 
+/// How often the shared timer checks whether any `tunnel_stats` subscriber is due.
+const TUNNEL_STATS_TICK: Duration = Duration::from_secs(1);
+
 #[derive(Default)]
 struct ActiveSubscriptions {
     new_state_subscriptions: RwLock<HashMap<SubscriptionId, pubsub::Sink<TunnelStateTransition>>>,
     settings_subscriptions: RwLock<HashMap<SubscriptionId, pubsub::Sink<Settings>>>,
+    tunnel_stats_subscriptions: RwLock<HashMap<SubscriptionId, pubsub::Sink<TunnelStats>>>,
+    tunnel_stats_schedule: Mutex<TunnelStatsSchedule>,
+}
+
+/// The interval and next due time of each `tunnel_stats` subscriber, which are all served by the
+/// same timer.
+#[derive(Default)]
+struct TunnelStatsSchedule {
+    subscribers: HashMap<SubscriptionId, (Duration, Instant)>,
+    is_timer_running: bool,
 }
 
 pub struct ManagementInterfaceServer {
@@ -236,11 +418,12 @@ impl ManagementInterfaceServer {
     pub fn start<T>(
         tunnel_tx: IntoSender<ManagementCommand, T>,
         cache_dir: PathBuf,
+        tokio_remote: Remote,
     ) -> talpid_ipc::Result<Self>
     where
         T: From<ManagementCommand> + 'static + Send,
     {
-        let rpc = ManagementInterface::new(tunnel_tx, cache_dir);
+        let rpc = ManagementInterface::new(tunnel_tx, cache_dir, tokio_remote);
         let subscriptions = rpc.subscriptions.clone();
 
         let mut io = PubSubHandler::default();
@@ -308,23 +491,30 @@ impl EventBroadcaster {
 
 struct ManagementInterface<T: From<ManagementCommand> + 'static + Send> {
     subscriptions: Arc<ActiveSubscriptions>,
-    tx: Mutex<IntoSender<ManagementCommand, T>>,
+    tx: Arc<Mutex<IntoSender<ManagementCommand, T>>>,
     cache_dir: PathBuf,
+    tokio_remote: Remote,
 }
 
 impl<T: From<ManagementCommand> + 'static + Send> ManagementInterface<T> {
-    pub fn new(tx: IntoSender<ManagementCommand, T>, cache_dir: PathBuf) -> Self {
+    pub fn new(
+        tx: IntoSender<ManagementCommand, T>,
+        cache_dir: PathBuf,
+        tokio_remote: Remote,
+    ) -> Self {
         ManagementInterface {
             subscriptions: Default::default(),
-            tx: Mutex::new(tx),
+            tx: Arc::new(Mutex::new(tx)),
             cache_dir,
+            tokio_remote,
         }
     }
 
+    /// Adds the subscriber to the given subscriptions, returning the id it was assigned.
     fn subscribe<V>(
         subscriber: pubsub::Subscriber<V>,
         subscriptions_lock: &RwLock<HashMap<SubscriptionId, pubsub::Sink<V>>>,
-    ) {
+    ) -> Option<SubscriptionId> {
         let mut subscriptions = subscriptions_lock.write().unwrap();
         loop {
             let id = SubscriptionId::String(uuid::Uuid::new_v4().to_string());
@@ -332,12 +522,53 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterface<T> {
                 if let Ok(sink) = subscriber.assign_id(id.clone()) {
                     log::debug!("Accepting new subscription with id {:?}", id);
                     entry.insert(sink);
+                    return Some(id);
                 }
-                break;
+                return None;
             }
         }
     }
 
+    /// Spawns the timer that periodically fetches the tunnel statistics from the daemon and
+    /// sends them to the `tunnel_stats` subscribers that are due. The timer runs on the event loop
+    /// and stops once there are no subscribers left.
+    fn spawn_tunnel_stats_timer(&self) {
+        let subscriptions = self.subscriptions.clone();
+        let tx = self.tx.clone();
+        self.tokio_remote.spawn(move |_| {
+            Timer::default()
+                .interval(TUNNEL_STATS_TICK)
+                .map_err(|error| log::error!("Tunnel stats timer failed: {}", error))
+                .for_each(move |_| -> BoxFuture<(), ()> {
+                    let due_sinks = match take_due_tunnel_stats_sinks(&subscriptions) {
+                        Some(due_sinks) => due_sinks,
+                        // Ends the stream, and with it the timer
+                        None => return Box::new(future::err(())),
+                    };
+                    if due_sinks.is_empty() {
+                        return Box::new(future::ok(()));
+                    }
+
+                    let (stats_tx, stats_rx) = sync::oneshot::channel();
+                    if tx
+                        .lock()
+                        .unwrap()
+                        .send(ManagementCommand::GetTunnelStats(stats_tx))
+                        .is_err()
+                    {
+                        return Box::new(future::ok(()));
+                    }
+                    let subscriptions = subscriptions.clone();
+                    Box::new(stats_rx.then(move |stats| {
+                        if let Ok(Some(stats)) = stats {
+                            notify_tunnel_stats(&subscriptions, due_sinks, stats);
+                        }
+                        Ok(())
+                    }))
+                })
+        });
+    }
+
     fn unsubscribe<V>(
         id: &SubscriptionId,
         subscriptions_lock: &RwLock<HashMap<SubscriptionId, pubsub::Sink<V>>>,
@@ -416,6 +647,71 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
+    fn get_forwarded_ports(
+        &self,
+        _: Self::Metadata,
+        account_token: AccountToken,
+    ) -> BoxFuture<Vec<u16>, Error> {
+        log::debug!("get_forwarded_ports");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::GetForwardedPorts(tx, account_token))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|rpc_future| {
+                rpc_future.map_err(|error: mullvad_rpc::Error| {
+                    log::error!(
+                        "Unable to get forwarded ports from API: {}",
+                        error.display_chain()
+                    );
+                    Self::map_rpc_error(&error)
+                })
+            });
+        Box::new(future)
+    }
+
+    fn add_forwarded_port(
+        &self,
+        _: Self::Metadata,
+        account_token: AccountToken,
+    ) -> BoxFuture<u16, Error> {
+        log::debug!("add_forwarded_port");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::AddForwardedPort(tx, account_token))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|rpc_future| {
+                rpc_future.map_err(|error: mullvad_rpc::Error| {
+                    log::error!("Unable to add forwarded port: {}", error.display_chain());
+                    Self::map_rpc_error(&error)
+                })
+            });
+        Box::new(future)
+    }
+
+    fn remove_forwarded_port(
+        &self,
+        _: Self::Metadata,
+        account_token: AccountToken,
+        port: u16,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("remove_forwarded_port({})", port);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::RemoveForwardedPort(
+                tx,
+                account_token,
+                port,
+            ))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|rpc_future| {
+                rpc_future.map_err(|error: mullvad_rpc::Error| {
+                    log::error!("Unable to remove forwarded port: {}", error.display_chain());
+                    Self::map_rpc_error(&error)
+                })
+            });
+        Box::new(future)
+    }
+
     fn get_relay_locations(&self, _: Self::Metadata) -> BoxFuture<RelayList, Error> {
         log::debug!("get_relay_locations");
         let (tx, rx) = sync::oneshot::channel();
@@ -475,7 +771,15 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         let (tx, rx) = sync::oneshot::channel();
         let future = self
             .send_command_to_daemon(ManagementCommand::SetAllowLan(tx, allow_lan))
-            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|settings_result| {
+                settings_result.map_err(|error| match error.kind() {
+                    settings::ErrorKind::LocalDnsServerWithoutLan(..) => {
+                        Error::invalid_params(error.to_string())
+                    }
+                    _ => Error::internal_error(),
+                })
+            });
         Box::new(future)
     }
 
@@ -495,6 +799,88 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
+    fn set_persistent_block(
+        &self,
+        _: Self::Metadata,
+        persistent_block: bool,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_persistent_block({})", persistent_block);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetPersistentBlock(tx, persistent_block))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn set_custom_dns(&self, _: Self::Metadata, servers: Vec<IpAddr>) -> BoxFuture<(), Error> {
+        log::debug!("set_custom_dns({:?})", servers);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetCustomDns(tx, servers))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|settings_result| {
+                settings_result.map_err(|error| match error.kind() {
+                    settings::ErrorKind::LocalDnsServerWithoutLan(..) => {
+                        Error::invalid_params(error.to_string())
+                    }
+                    _ => Error::internal_error(),
+                })
+            });
+        Box::new(future)
+    }
+
+    fn set_dns_content_blocking(
+        &self,
+        _: Self::Metadata,
+        content_blocking: DnsContentBlocking,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_dns_content_blocking({:?})", content_blocking);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetDnsContentBlocking(
+                tx,
+                content_blocking,
+            ))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn set_encrypted_dns(
+        &self,
+        _: Self::Metadata,
+        upstream: Option<EncryptedDnsUpstream>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_encrypted_dns({:?})", upstream);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetEncryptedDns(tx, upstream))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn set_split_dns(
+        &self,
+        _: Self::Metadata,
+        domain: String,
+        servers: Vec<IpAddr>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_split_dns({}, {:?})", domain, servers);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetSplitDns(tx, domain, servers))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|settings_result| {
+                settings_result.map_err(|error| match error.kind() {
+                    settings::ErrorKind::InvalidSplitDnsDomain(..)
+                    | settings::ErrorKind::LocalDnsServerWithoutLan(..) => {
+                        Error::invalid_params(error.to_string())
+                    }
+                    _ => Error::internal_error(),
+                })
+            });
+        Box::new(future)
+    }
+
     fn set_auto_connect(&self, _: Self::Metadata, auto_connect: bool) -> BoxFuture<(), Error> {
         log::debug!("set_auto_connect({})", auto_connect);
         let (tx, rx) = sync::oneshot::channel();
@@ -551,6 +937,44 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
+    fn get_dns_status(&self, _: Self::Metadata) -> BoxFuture<DnsStatus, Error> {
+        log::debug!("get_dns_status");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::GetDnsStatus(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn get_tunnel_stats(&self, _: Self::Metadata) -> BoxFuture<Option<TunnelStats>, Error> {
+        log::debug!("get_tunnel_stats");
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::GetTunnelStats(tx))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn export_tunnel_config(
+        &self,
+        _: Self::Metadata,
+        hostname: Option<String>,
+    ) -> BoxFuture<String, Error> {
+        log::debug!("export_tunnel_config({:?})", hostname);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::ExportTunnelConfig(tx, hostname))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()))
+            .and_then(|result| {
+                result.map_err(|message| Error {
+                    code: ErrorCode::InternalError,
+                    message,
+                    data: None,
+                })
+            });
+        Box::new(future)
+    }
+
     fn shutdown(&self, _: Self::Metadata) -> BoxFuture<(), Error> {
         log::debug!("shutdown");
         self.send_command_to_daemon(ManagementCommand::Shutdown)
@@ -647,6 +1071,118 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         }
     }
 
+    /// Set which wireguard implementation to use on Linux
+    fn set_wireguard_backend(
+        &self,
+        _: Self::Metadata,
+        backend: Option<wireguard::Backend>,
+    ) -> BoxFuture<(), Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("set_wireguard_backend({:?})", backend);
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(ManagementCommand::SetWireguardBackend(tx, backend))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()));
+
+            Box::new(future)
+        }
+        #[cfg(any(windows, target_os = "macos"))]
+        {
+            return Box::new(future::err(Error::method_not_found()));
+        }
+    }
+
+    /// Set the network namespace wireguard tunnels run in on Linux
+    fn set_wireguard_namespace(
+        &self,
+        _: Self::Metadata,
+        namespace: Option<String>,
+    ) -> BoxFuture<(), Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("set_wireguard_namespace({:?})", namespace);
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(ManagementCommand::SetWireguardNamespace(tx, namespace))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()));
+
+            Box::new(future)
+        }
+        #[cfg(any(windows, target_os = "macos"))]
+        {
+            return Box::new(future::err(Error::method_not_found()));
+        }
+    }
+
+    fn spawn_in_tunnel_namespace(
+        &self,
+        _: Self::Metadata,
+        request_id: String,
+        command: Vec<String>,
+    ) -> BoxFuture<u32, Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!("spawn_in_tunnel_namespace({}, {:?})", request_id, command);
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(ManagementCommand::SpawnInTunnelNamespace(
+                    tx, request_id, command,
+                ))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()))
+                .and_then(|result| {
+                    result.map_err(|message| Error {
+                        code: ErrorCode::InternalError,
+                        message,
+                        data: None,
+                    })
+                });
+
+            Box::new(future)
+        }
+        #[cfg(any(windows, target_os = "macos"))]
+        {
+            return Box::new(future::err(Error::method_not_found()));
+        }
+    }
+
+    fn spawn_shell_in_tunnel_namespace(
+        &self,
+        _: Self::Metadata,
+        request_id: String,
+        terminal: String,
+        term: Option<String>,
+    ) -> BoxFuture<u32, Error> {
+        #[cfg(target_os = "linux")]
+        {
+            log::debug!(
+                "spawn_shell_in_tunnel_namespace({}, {}, {:?})",
+                request_id,
+                terminal,
+                term
+            );
+            let (tx, rx) = sync::oneshot::channel();
+            let future = self
+                .send_command_to_daemon(ManagementCommand::SpawnShellInTunnelNamespace(
+                    tx, request_id, terminal, term,
+                ))
+                .and_then(|_| rx.map_err(|_| Error::internal_error()))
+                .and_then(|result| {
+                    result.map_err(|message| Error {
+                        code: ErrorCode::InternalError,
+                        message,
+                        data: None,
+                    })
+                });
+
+            Box::new(future)
+        }
+        #[cfg(any(windows, target_os = "macos"))]
+        {
+            return Box::new(future::err(Error::method_not_found()));
+        }
+    }
+
     /// Set MTU for wireguard tunnels
     fn set_wireguard_mtu(&self, _: Self::Metadata, mtu: Option<u16>) -> BoxFuture<(), Error> {
         log::debug!("set_wireguard_mtu({:?})", mtu);
@@ -657,6 +1193,37 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         Box::new(future)
     }
 
+    fn set_wireguard_automatic_mtu(
+        &self,
+        _: Self::Metadata,
+        automatic_mtu: bool,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_wireguard_automatic_mtu({})", automatic_mtu);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetWireguardAutomaticMtu(
+                tx,
+                automatic_mtu,
+            ))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
+    fn set_wireguard_persistent_keepalive(
+        &self,
+        _: Self::Metadata,
+        interval: Option<u16>,
+    ) -> BoxFuture<(), Error> {
+        log::debug!("set_wireguard_persistent_keepalive({:?})", interval);
+        let (tx, rx) = sync::oneshot::channel();
+        let future = self
+            .send_command_to_daemon(ManagementCommand::SetWireguardPersistentKeepalive(
+                tx, interval,
+            ))
+            .and_then(|_| rx.map_err(|_| Error::internal_error()));
+        Box::new(future)
+    }
+
     fn get_settings(&self, _: Self::Metadata) -> BoxFuture<Settings, Error> {
         log::debug!("get_settings");
         let (tx, rx) = sync::oneshot::channel();
@@ -718,6 +1285,79 @@ impl<T: From<ManagementCommand> + 'static + Send> ManagementInterfaceApi
         log::debug!("settings_unsubscribe");
         Self::unsubscribe(&id, &self.subscriptions.settings_subscriptions)
     }
+
+    fn tunnel_stats_subscribe(
+        &self,
+        _: Self::Metadata,
+        subscriber: pubsub::Subscriber<TunnelStats>,
+        interval_secs: u64,
+    ) {
+        log::debug!("tunnel_stats_subscribe({})", interval_secs);
+        if let Some(id) =
+            Self::subscribe(subscriber, &self.subscriptions.tunnel_stats_subscriptions)
+        {
+            let interval = Duration::from_secs(cmp::max(interval_secs, 1));
+            let mut schedule = self.subscriptions.tunnel_stats_schedule.lock().unwrap();
+            schedule
+                .subscribers
+                .insert(id, (interval, Instant::now() + interval));
+            if !schedule.is_timer_running {
+                schedule.is_timer_running = true;
+                self.spawn_tunnel_stats_timer();
+            }
+        }
+    }
+
+    fn tunnel_stats_unsubscribe(&self, id: SubscriptionId) -> BoxFuture<(), Error> {
+        log::debug!("tunnel_stats_unsubscribe");
+        Self::unsubscribe(&id, &self.subscriptions.tunnel_stats_subscriptions)
+    }
+}
+
+/// Returns the sinks of the `tunnel_stats` subscribers that are due for a notification, and
+/// schedules their next one. Returns `None` and marks the timer as stopped if there are no
+/// subscribers left.
+fn take_due_tunnel_stats_sinks(
+    subscriptions: &ActiveSubscriptions,
+) -> Option<Vec<(SubscriptionId, pubsub::Sink<TunnelStats>)>> {
+    let mut schedule = subscriptions.tunnel_stats_schedule.lock().unwrap();
+    let sinks = subscriptions.tunnel_stats_subscriptions.read().unwrap();
+    schedule.subscribers.retain(|id, _| sinks.contains_key(id));
+    if schedule.subscribers.is_empty() {
+        schedule.is_timer_running = false;
+        return None;
+    }
+
+    let now = Instant::now();
+    let mut due_sinks = Vec::new();
+    for (id, (interval, next_notification)) in schedule.subscribers.iter_mut() {
+        if *next_notification <= now {
+            *next_notification = now + *interval;
+            due_sinks.push((id.clone(), sinks[id].clone()));
+        }
+    }
+    Some(due_sinks)
+}
+
+/// Sends the statistics to the given subscribers without holding the subscriptions lock, and
+/// removes the ones that can't be reached afterwards.
+fn notify_tunnel_stats(
+    subscriptions: &ActiveSubscriptions,
+    sinks: Vec<(SubscriptionId, pubsub::Sink<TunnelStats>)>,
+    stats: TunnelStats,
+) {
+    let unreachable_ids: Vec<_> = sinks
+        .into_iter()
+        .filter(|(_, sink)| sink.notify(Ok(stats.clone())).wait().is_err())
+        .map(|(id, _)| id)
+        .collect();
+    if !unreachable_ids.is_empty() {
+        let mut subscriptions = subscriptions.tunnel_stats_subscriptions.write().unwrap();
+        for id in unreachable_ids {
+            log::debug!("Removing unreachable tunnel stats subscriber {:?}", id);
+            subscriptions.remove(&id);
+        }
+    }
 }
 
 
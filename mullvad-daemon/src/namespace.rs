@@ -0,0 +1,237 @@
+//! Starts processes in the network namespace of the tunnel on behalf of local users.
+//!
+//! Anyone can talk to the daemon, so the caller has to prove who they are before anything is
+//! started for them. The caller creates an empty file with a random name in the spawn request
+//! directory and passes that name along. Only the daemon can list the directory, and nobody can
+//! create files owned by someone else, so the owner of the file is who made the request.
+
+use std::{
+    ffi::{CStr, CString},
+    fs, mem,
+    os::unix::{
+        fs::{FileTypeExt, MetadataExt, OpenOptionsExt},
+        io::AsRawFd,
+    },
+    process::{Command, Stdio},
+    ptr, thread,
+};
+use talpid_core::netns::NetworkNamespace;
+
+error_chain! {
+    errors {
+        NoNamespace {
+            description("Tunnels are not configured to run in a network namespace")
+        }
+        EmptyCommand {
+            description("No command given")
+        }
+        InvalidRequest(request_id: String) {
+            description("Invalid spawn request")
+            display("Invalid spawn request: {}", request_id)
+        }
+        UnknownUser(uid: u32) {
+            description("Unknown user")
+            display("No user with UID {}", uid)
+        }
+        InvalidTerminal(path: String) {
+            description("Invalid terminal")
+            display("{} is not a terminal owned by the requesting user", path)
+        }
+        SpawnError {
+            description("Failed to start process in the tunnel namespace")
+        }
+    }
+}
+
+/// `PATH` given to spawned processes, since the daemon's environment is not passed on.
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// The user that made a spawn request, as found in the password database.
+struct Requester {
+    uid: u32,
+    gid: u32,
+    groups: Vec<u32>,
+    name: String,
+    home: String,
+    shell: String,
+}
+
+/// Starts `command` in the namespace, detached from any terminal. Returns the PID of the process.
+pub fn spawn_command(
+    namespace: Option<&str>,
+    request_id: &str,
+    command: Vec<String>,
+) -> Result<u32> {
+    let namespace = namespace.ok_or(ErrorKind::NoNamespace)?;
+    let requester = take_requester(request_id)?;
+    let (program, args) = command.split_first().ok_or(ErrorKind::EmptyCommand)?;
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    spawn(namespace, &requester, command, None)
+}
+
+/// Starts the shell of the requesting user in the namespace, with `terminal` as its
+/// standard input and output. Returns the PID of the shell.
+pub fn spawn_shell(
+    namespace: Option<&str>,
+    request_id: &str,
+    terminal: &str,
+    term: Option<String>,
+) -> Result<u32> {
+    let namespace = namespace.ok_or(ErrorKind::NoNamespace)?;
+    let requester = take_requester(request_id)?;
+    let terminal_file = open_terminal(terminal, requester.uid)?;
+
+    let mut command = Command::new(&requester.shell);
+    command
+        .stdin(
+            terminal_file
+                .try_clone()
+                .chain_err(|| ErrorKind::SpawnError)?,
+        )
+        .stdout(
+            terminal_file
+                .try_clone()
+                .chain_err(|| ErrorKind::SpawnError)?,
+        )
+        .stderr(terminal_file);
+    spawn(namespace, &requester, command, term)
+}
+
+fn spawn(
+    namespace: &str,
+    requester: &Requester,
+    mut command: Command,
+    term: Option<String>,
+) -> Result<u32> {
+    command
+        .env_clear()
+        .env("HOME", &requester.home)
+        .env("USER", &requester.name)
+        .env("LOGNAME", &requester.name)
+        .env("SHELL", &requester.shell)
+        .env("PATH", DEFAULT_PATH)
+        .current_dir(&requester.home);
+    if let Some(term) = term {
+        command.env("TERM", term);
+    }
+
+    let namespace = NetworkNamespace::create(namespace).chain_err(|| ErrorKind::SpawnError)?;
+    let mut child = namespace
+        .spawn(
+            command,
+            requester.uid,
+            requester.gid,
+            requester.groups.clone(),
+        )
+        .chain_err(|| ErrorKind::SpawnError)?;
+    let pid = child.id();
+    log::info!(
+        "Started process {} in network namespace {} for {}",
+        pid,
+        namespace.name(),
+        requester.name
+    );
+
+    // Reap the process once it exits
+    thread::spawn(move || {
+        let _ = child.wait();
+    });
+    Ok(pid)
+}
+
+/// Finds the user that created the request file named `request_id`, and removes the file so it
+/// can't be used again.
+fn take_requester(request_id: &str) -> Result<Requester> {
+    let invalid_request = || ErrorKind::InvalidRequest(request_id.to_owned());
+    ensure!(
+        !request_id.is_empty() && request_id.chars().all(|c| c.is_ascii_alphanumeric()),
+        invalid_request()
+    );
+    let path = mullvad_paths::get_spawn_request_dir()
+        .chain_err(invalid_request)?
+        .join(request_id);
+    let metadata = fs::symlink_metadata(&path).chain_err(invalid_request)?;
+    fs::remove_file(&path).chain_err(invalid_request)?;
+    // A hard link to someone else's file would otherwise pass as a request made by them
+    ensure!(
+        metadata.file_type().is_file() && metadata.nlink() == 1,
+        invalid_request()
+    );
+    lookup_user(metadata.uid())
+}
+
+fn lookup_user(uid: u32) -> Result<Requester> {
+    let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut result = ptr::null_mut();
+    let error = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if error != 0 || result.is_null() {
+        bail!(ErrorKind::UnknownUser(uid));
+    }
+
+    let name = unsafe { CStr::from_ptr(passwd.pw_name) }.to_owned();
+    let field = |value: *const libc::c_char| {
+        unsafe { CStr::from_ptr(value) }
+            .to_string_lossy()
+            .into_owned()
+    };
+    Ok(Requester {
+        uid,
+        gid: passwd.pw_gid,
+        groups: lookup_groups(&name, passwd.pw_gid),
+        name: name.to_string_lossy().into_owned(),
+        home: field(passwd.pw_dir),
+        shell: field(passwd.pw_shell),
+    })
+}
+
+/// Returns the supplementary groups of a user.
+fn lookup_groups(name: &CString, gid: u32) -> Vec<u32> {
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        let result =
+            unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+        if result >= 0 {
+            groups.truncate(count as usize);
+            return groups;
+        }
+        // The list was too small, and `count` is now the number of groups the user is in
+        let new_len = (count as usize).max(groups.len() * 2);
+        groups.resize(new_len, 0);
+    }
+}
+
+fn open_terminal(path: &str, uid: u32) -> Result<fs::File> {
+    let invalid_terminal = || ErrorKind::InvalidTerminal(path.to_owned());
+    let is_user_device =
+        |metadata: fs::Metadata| metadata.file_type().is_char_device() && metadata.uid() == uid;
+    // Checked before opening as well, since opening some devices has side effects
+    let metadata = fs::metadata(path).chain_err(invalid_terminal)?;
+    ensure!(is_user_device(metadata), invalid_terminal());
+
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(path)
+        .chain_err(invalid_terminal)?;
+    let metadata = file.metadata().chain_err(invalid_terminal)?;
+    let is_terminal = unsafe { libc::isatty(file.as_raw_fd()) } == 1;
+    ensure!(is_user_device(metadata) && is_terminal, invalid_terminal());
+    Ok(file)
+}
@@ -216,6 +216,36 @@ impl RelaySelector {
         }
     }
 
+    /// Returns a random endpoint on the relay with the given hostname, matching the tunnel
+    /// constraints. The location constraint is not used, since the relay is already given.
+    pub fn get_tunnel_endpoint_on_relay(
+        &mut self,
+        hostname: &str,
+        constraints: &RelayConstraints,
+    ) -> Result<(Relay, MullvadEndpoint)> {
+        let relay = self
+            .lock_parsed_relays()
+            .relays()
+            .iter()
+            .find(|relay| relay.hostname == hostname)
+            .cloned()
+            .ok_or(ErrorKind::NoRelay)?;
+        let constraints = RelayConstraints {
+            location: Constraint::Any,
+            tunnel: constraints.tunnel.clone(),
+        };
+        Self::matching_relay(&relay, &constraints)
+            .and_then(|relay| {
+                self.get_random_tunnel(&relay.tunnels)
+                    .map(|tunnel_parameters| {
+                        let endpoint =
+                            tunnel_parameters.to_mullvad_endpoint(relay.ipv4_addr_in.into());
+                        (relay, endpoint)
+                    })
+            })
+            .ok_or_else(|| ErrorKind::NoRelay.into())
+    }
+
     fn preferred_constraints(
         original_constraints: &RelayConstraints,
         retry_attempt: u32,
@@ -12,12 +12,18 @@ use mullvad_types::{
     location::GeoIpLocation,
     relay_constraints::{RelaySettings, RelaySettingsUpdate},
     relay_list::RelayList,
-    settings::{Settings, TunnelOptions},
+    settings::{DnsContentBlocking, Settings, TunnelOptions},
     version::AppVersionInfo,
 };
 use serde::{Deserialize, Serialize};
-use std::{path::Path, sync::mpsc, thread, time::Duration};
-use talpid_types::{net::openvpn, tunnel::TunnelStateTransition};
+use std::{net::IpAddr, path::Path, sync::mpsc, thread, time::Duration};
+use talpid_types::{
+    net::{
+        dns::{DnsStatus, EncryptedDnsUpstream},
+        openvpn, wireguard,
+    },
+    tunnel::{TunnelStateTransition, TunnelStats},
+};
 
 pub use jsonrpc_client_core::{Error as RpcError, ErrorKind as RpcErrorKind};
 
@@ -125,6 +131,18 @@ impl DaemonRpcClient {
         self.call("get_account_data", &[account])
     }
 
+    pub fn get_forwarded_ports(&mut self, account: AccountToken) -> Result<Vec<u16>> {
+        self.call("get_forwarded_ports", &[account])
+    }
+
+    pub fn add_forwarded_port(&mut self, account: AccountToken) -> Result<u16> {
+        self.call("add_forwarded_port", &[account])
+    }
+
+    pub fn remove_forwarded_port(&mut self, account: AccountToken, port: u16) -> Result<()> {
+        self.call("remove_forwarded_port", &(account, port))
+    }
+
     pub fn set_allow_lan(&mut self, allow_lan: bool) -> Result<()> {
         self.call("set_allow_lan", &[allow_lan])
     }
@@ -133,6 +151,26 @@ impl DaemonRpcClient {
         self.call("set_block_when_disconnected", &[block_when_disconnected])
     }
 
+    pub fn set_persistent_block(&mut self, persistent_block: bool) -> Result<()> {
+        self.call("set_persistent_block", &[persistent_block])
+    }
+
+    pub fn set_custom_dns(&mut self, servers: Vec<IpAddr>) -> Result<()> {
+        self.call("set_custom_dns", &[servers])
+    }
+
+    pub fn set_dns_content_blocking(&mut self, content_blocking: DnsContentBlocking) -> Result<()> {
+        self.call("set_dns_content_blocking", &[content_blocking])
+    }
+
+    pub fn set_encrypted_dns(&mut self, upstream: Option<EncryptedDnsUpstream>) -> Result<()> {
+        self.call("set_encrypted_dns", &[upstream])
+    }
+
+    pub fn set_split_dns(&mut self, domain: String, servers: Vec<IpAddr>) -> Result<()> {
+        self.call("set_split_dns", &(domain, servers))
+    }
+
     pub fn get_allow_lan(&mut self) -> Result<bool> {
         self.call("get_allow_lan", &NO_ARGS)
     }
@@ -149,6 +187,18 @@ impl DaemonRpcClient {
         self.call("get_current_location", &NO_ARGS)
     }
 
+    pub fn get_dns_status(&mut self) -> Result<DnsStatus> {
+        self.call("get_dns_status", &NO_ARGS)
+    }
+
+    pub fn get_tunnel_stats(&mut self) -> Result<Option<TunnelStats>> {
+        self.call("get_tunnel_stats", &NO_ARGS)
+    }
+
+    pub fn export_tunnel_config(&mut self, hostname: Option<String>) -> Result<String> {
+        self.call("export_tunnel_config", &[hostname])
+    }
+
     pub fn get_current_version(&mut self) -> Result<String> {
         self.call("get_current_version", &NO_ARGS)
     }
@@ -193,10 +243,46 @@ impl DaemonRpcClient {
         self.call("set_wireguard_mtu", &[mtu])
     }
 
+    pub fn set_wireguard_automatic_mtu(&mut self, automatic_mtu: bool) -> Result<()> {
+        self.call("set_wireguard_automatic_mtu", &[automatic_mtu])
+    }
+
+    pub fn set_wireguard_persistent_keepalive(&mut self, interval: Option<u16>) -> Result<()> {
+        self.call("set_wireguard_persistent_keepalive", &[interval])
+    }
+
     pub fn set_wireguard_fwmark(&mut self, fwmark: i32) -> Result<()> {
         self.call("set_wireguard_fwmark", &[fwmark])
     }
 
+    pub fn set_wireguard_backend(&mut self, backend: Option<wireguard::Backend>) -> Result<()> {
+        self.call("set_wireguard_backend", &[backend])
+    }
+
+    pub fn set_wireguard_namespace(&mut self, namespace: Option<String>) -> Result<()> {
+        self.call("set_wireguard_namespace", &[namespace])
+    }
+
+    pub fn spawn_in_tunnel_namespace(
+        &mut self,
+        request_id: String,
+        command: Vec<String>,
+    ) -> Result<u32> {
+        self.call("spawn_in_tunnel_namespace", &(request_id, command))
+    }
+
+    pub fn spawn_shell_in_tunnel_namespace(
+        &mut self,
+        request_id: String,
+        terminal: String,
+        term: Option<String>,
+    ) -> Result<u32> {
+        self.call(
+            "spawn_shell_in_tunnel_namespace",
+            &(request_id, terminal, term),
+        )
+    }
+
     pub fn set_openvpn_mssfix(&mut self, mssfix: Option<u16>) -> Result<()> {
         self.call("set_openvpn_mssfix", &[mssfix])
     }
@@ -62,3 +62,10 @@ pub use crate::rpc_socket::{get_default_rpc_socket_path, get_rpc_socket_path};
 
 mod settings;
 pub use crate::settings::{get_default_settings_dir, settings_dir};
+
+#[cfg(target_os = "linux")]
+mod spawn_requests;
+#[cfg(target_os = "linux")]
+pub use crate::spawn_requests::{
+    get_default_spawn_request_dir, get_spawn_request_dir, spawn_request_dir,
+};
@@ -0,0 +1,26 @@
+use crate::Result;
+
+use std::{env, os::unix::fs::PermissionsExt, path::PathBuf};
+
+/// Creates and returns the directory pointed to by `MULLVAD_SPAWN_REQUEST_DIR`, or the default
+/// one if that variable is unset. Users create a file in it to prove who they are when asking the
+/// daemon to start processes for them. Anyone can create files there, but only the owner of the
+/// directory can list them.
+pub fn spawn_request_dir() -> Result<PathBuf> {
+    crate::create_and_return(
+        get_spawn_request_dir,
+        Some(PermissionsExt::from_mode(0o1733)),
+    )
+}
+
+/// Get the spawn request directory, but don't try to create it.
+pub fn get_spawn_request_dir() -> Result<PathBuf> {
+    match env::var_os("MULLVAD_SPAWN_REQUEST_DIR") {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => get_default_spawn_request_dir(),
+    }
+}
+
+pub fn get_default_spawn_request_dir() -> Result<PathBuf> {
+    Ok(PathBuf::from("/var/run").join(format!("{}-spawn", crate::PRODUCT_NAME)))
+}
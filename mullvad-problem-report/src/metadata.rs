@@ -11,9 +11,32 @@ pub fn collect() -> HashMap<String, String> {
         PRODUCT_VERSION.to_owned(),
     );
     metadata.insert("os".to_owned(), os::version());
+    add_dns_status(&mut metadata);
     metadata
 }
 
+/// Adds how the daemon manages DNS, if the daemon is running. The server addresses are left out,
+/// since IP addresses are redacted from the rest of the report.
+fn add_dns_status(metadata: &mut HashMap<String, String>) {
+    let rpc_socket_path = mullvad_paths::get_rpc_socket_path();
+    let status = match mullvad_ipc_client::new_standalone_ipc_client(&rpc_socket_path)
+        .and_then(|mut rpc| rpc.get_dns_status())
+    {
+        Ok(status) => status,
+        Err(_) => return,
+    };
+    metadata.insert(
+        "dns-backend".to_owned(),
+        status.backend.unwrap_or_else(|| "none".to_owned()),
+    );
+    if let Some(interface) = status.interface {
+        metadata.insert("dns-interface".to_owned(), interface);
+    }
+    if let Some(last_applied) = status.last_applied {
+        metadata.insert("dns-last-applied".to_owned(), last_applied.to_string());
+    }
+}
+
 #[cfg(target_os = "linux")]
 mod os {
     pub fn version() -> String {
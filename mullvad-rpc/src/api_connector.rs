@@ -0,0 +1,105 @@
+use crate::cached_dns_resolver::CachedDnsResolver;
+use futures::Future;
+use hyper::Uri;
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio_core::{net::TcpStream, reactor::Handle};
+use tokio_service::Service;
+
+#[cfg(target_os = "linux")]
+use futures::future;
+#[cfg(target_os = "linux")]
+use std::{mem, os::unix::io::AsRawFd};
+#[cfg(target_os = "linux")]
+use talpid_types::net::OWN_TRAFFIC_FWMARK;
+
+type ConnectFuture = Box<dyn Future<Item = TcpStream, Error = io::Error>>;
+
+/// A connector for the `http` scheme that connects to the current address of the API, ignoring
+/// the host in the URI. The address is looked up for every connection, so a refreshed address is
+/// picked up without recreating the client.
+#[derive(Clone)]
+pub struct ApiConnector {
+    handle: Handle,
+    resolver: Arc<Mutex<CachedDnsResolver>>,
+    mark_sockets: bool,
+}
+
+impl ApiConnector {
+    /// Creates a connector that looks up the API address in `resolver`. If `mark_sockets` is set,
+    /// the sockets are marked with `OWN_TRAFFIC_FWMARK` on Linux, so the firewall lets them reach
+    /// the API. Marking requires `CAP_NET_ADMIN`.
+    pub fn new(
+        handle: &Handle,
+        resolver: Arc<Mutex<CachedDnsResolver>>,
+        mark_sockets: bool,
+    ) -> Self {
+        ApiConnector {
+            handle: handle.clone(),
+            resolver,
+            mark_sockets,
+        }
+    }
+}
+
+impl Service for ApiConnector {
+    type Request = Uri;
+    type Response = TcpStream;
+    type Error = io::Error;
+    type Future = ConnectFuture;
+
+    fn call(&self, uri: Uri) -> Self::Future {
+        let ip = self
+            .resolver
+            .lock()
+            .expect("API address resolver lock is poisoned")
+            .resolve();
+        let address = SocketAddr::new(ip, uri.port().unwrap_or(crate::API_PORT));
+        connect(address, self.mark_sockets, &self.handle)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn connect(address: SocketAddr, mark_sockets: bool, handle: &Handle) -> ConnectFuture {
+    if !mark_sockets {
+        return Box::new(TcpStream::connect(&address, handle));
+    }
+    match create_marked_stream(&address) {
+        Ok(stream) => TcpStream::connect_stream(stream, &address, handle),
+        Err(error) => Box::new(future::err(error)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect(address: SocketAddr, _mark_sockets: bool, handle: &Handle) -> ConnectFuture {
+    Box::new(TcpStream::connect(&address, handle))
+}
+
+/// Creates an unconnected TCP socket marked with `OWN_TRAFFIC_FWMARK`.
+#[cfg(target_os = "linux")]
+fn create_marked_stream(address: &SocketAddr) -> io::Result<std::net::TcpStream> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    let domain = match address {
+        SocketAddr::V4(_) => Domain::ipv4(),
+        SocketAddr::V6(_) => Domain::ipv6(),
+    };
+    let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+    let mark = OWN_TRAFFIC_FWMARK;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_MARK,
+            &mark as *const u32 as *const libc::c_void,
+            mem::size_of_val(&mark) as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(socket.into_tcp_stream())
+}
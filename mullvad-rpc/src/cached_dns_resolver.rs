@@ -86,6 +86,7 @@ pub struct CachedDnsResolver<R: DnsResolver = SystemDnsResolver> {
     cache_file: Option<PathBuf>,
     cached_address: IpAddr,
     last_updated: SystemTime,
+    address_change_listener: Option<Box<dyn Fn(IpAddr) + Send>>,
 }
 
 impl CachedDnsResolver<SystemDnsResolver> {
@@ -112,9 +113,16 @@ impl<R: DnsResolver> CachedDnsResolver<R> {
             cache_file,
             cached_address,
             last_updated,
+            address_change_listener: None,
         }
     }
 
+    /// Sets a callback that is called with the new address whenever a resolution changes the
+    /// cached address.
+    pub fn set_address_change_listener(&mut self, listener: Box<dyn Fn(IpAddr) + Send>) {
+        self.address_change_listener = Some(listener);
+    }
+
     pub fn resolve(&mut self) -> IpAddr {
         if let Ok(cache_age) = self.last_updated.elapsed() {
             if cache_age > MAX_CACHE_AGE {
@@ -182,9 +190,16 @@ impl<R: DnsResolver> CachedDnsResolver<R> {
                 }
 
                 debug!("Updating DNS cache for {} with {}", self.hostname, address);
+                let address_changed = self.cached_address != address;
                 self.cached_address = address;
                 self.last_updated = SystemTime::now();
 
+                if address_changed {
+                    if let Some(listener) = &self.address_change_listener {
+                        listener(address);
+                    }
+                }
+
                 if let Err(error) = self.update_cache_file() {
                     warn!("Failed to update cache file with new IP address: {}", error);
                 }
@@ -369,6 +384,26 @@ mod tests {
         assert!(!cache_file_path.exists());
     }
 
+    #[test]
+    fn notifies_listener_when_address_changes() {
+        let (_temp_dir, cache_dir) = create_test_dirs();
+        let cached_address = "80.10.20.30".parse().unwrap();
+        let mock_address = "90.168.1.206".parse().unwrap();
+        let mock_resolver = MockDnsResolver::with_address(mock_address);
+        let (changes_tx, changes_rx) = mpsc::channel();
+
+        let cache_file_path = write_address(&cache_dir, cached_address);
+        make_file_old(&cache_file_path);
+
+        let mut cache = create_cached_dns_resolver(mock_resolver, &cache_dir, None);
+        cache.set_address_change_listener(Box::new(move |address| {
+            let _ = changes_tx.send(address);
+        }));
+        cache.resolve();
+
+        assert_eq!(changes_rx.try_recv(), Ok(mock_address));
+    }
+
     fn create_test_dirs() -> (TempDir, PathBuf) {
         let temp_dir = TempDir::new().expect("Failed to create a temporary cache directory");
         let cache_dir = temp_dir.path().join("cache");
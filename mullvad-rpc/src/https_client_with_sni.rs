@@ -1,3 +1,4 @@
+use crate::{api_connector::ApiConnector, cached_dns_resolver::CachedDnsResolver};
 use futures::{Future, Poll};
 use hyper::{
     client::{Client, Connect, HttpConnector},
@@ -9,7 +10,7 @@ use std::{
     fmt, io,
     path::{Path, PathBuf},
     str,
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 use tokio_core::reactor::Handle;
 use tokio_openssl::{SslConnectorExt, SslStream};
@@ -20,23 +21,34 @@ pub use hyper_openssl::openssl::error::ErrorStack;
 pub struct HttpsClientWithSni {
     sni_hostname: String,
     ca_path: Box<Path>,
+    resolver: Arc<Mutex<CachedDnsResolver>>,
+    mark_sockets: bool,
 }
 
 impl HttpsClientWithSni {
-    pub fn new<P: Into<PathBuf>>(sni_hostname: String, ca_path: P) -> Self {
+    pub fn new<P: Into<PathBuf>>(
+        sni_hostname: String,
+        ca_path: P,
+        resolver: Arc<Mutex<CachedDnsResolver>>,
+        mark_sockets: bool,
+    ) -> Self {
         HttpsClientWithSni {
             sni_hostname,
             ca_path: ca_path.into().into_boxed_path(),
+            resolver,
+            mark_sockets,
         }
     }
 }
 
 impl ClientCreator for HttpsClientWithSni {
-    type Connect = HttpsConnectorWithSni<HttpConnector>;
+    type Connect = HttpsConnectorWithSni<ApiConnector>;
     type Error = ErrorStack;
 
     fn create(&self, handle: &Handle) -> Result<Client<Self::Connect, Body>, Self::Error> {
-        let mut connector = HttpsConnectorWithSni::new(&self.ca_path, handle)?;
+        let ssl = create_ssl_connector(&self.ca_path)?;
+        let api_connector = ApiConnector::new(handle, self.resolver.clone(), self.mark_sockets);
+        let mut connector = HttpsConnectorWithSni::from((api_connector, ssl));
         connector.set_sni_hostname(Some(self.sni_hostname.clone()));
         let client = Client::configure()
             .keep_alive(false)
@@ -64,14 +76,18 @@ impl HttpsConnectorWithSni<HttpConnector> {
     pub fn new<P: AsRef<Path>>(ca_path: P, handle: &Handle) -> Result<Self, ErrorStack> {
         let mut http = HttpConnector::new(crate::DNS_THREADS, handle);
         http.enforce_http(false);
-        let mut ssl_builder = SslConnector::builder(SslMethod::tls())?;
-        ssl_builder.set_ca_file(ca_path)?;
-        let ssl = ssl_builder.build();
+        let ssl = create_ssl_connector(ca_path)?;
 
         Ok(HttpsConnectorWithSni::from((http, ssl)))
     }
 }
 
+fn create_ssl_connector<P: AsRef<Path>>(ca_path: P) -> Result<SslConnector, ErrorStack> {
+    let mut ssl_builder = SslConnector::builder(SslMethod::tls())?;
+    ssl_builder.set_ca_file(ca_path)?;
+    Ok(ssl_builder.build())
+}
+
 impl<T> HttpsConnectorWithSni<T>
 where
     T: Connect,
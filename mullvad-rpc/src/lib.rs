@@ -19,6 +19,7 @@ use std::{
     collections::HashMap,
     net::{IpAddr, Ipv4Addr},
     path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
     time::Duration,
 };
 use tokio_core::reactor::Handle;
@@ -29,6 +30,8 @@ pub use jsonrpc_client_http::{Error as HttpError, HttpHandle};
 pub mod event_loop;
 pub mod rest;
 
+mod api_connector;
+
 mod cached_dns_resolver;
 use crate::cached_dns_resolver::CachedDnsResolver;
 
@@ -40,6 +43,7 @@ use crate::https_client_with_sni::{HttpsClientWithSni, HttpsConnectorWithSni};
 const DNS_THREADS: usize = 2;
 
 const API_HOST: &str = "api.mullvad.net";
+pub const API_PORT: u16 = 443;
 const RPC_TIMEOUT: Duration = Duration::from_secs(5);
 pub const API_IP_CACHE_FILENAME: &str = "api-ip-address.txt";
 lazy_static! {
@@ -49,16 +53,20 @@ lazy_static! {
 
 /// A type that helps with the creation of RPC connections.
 pub struct MullvadRpcFactory {
-    cached_dns_resolver: CachedDnsResolver,
+    cached_dns_resolver: Arc<Mutex<CachedDnsResolver>>,
     ca_path: PathBuf,
+    mark_sockets: bool,
 }
 
 impl MullvadRpcFactory {
     /// Create a new `MullvadRpcFactory`.
     pub fn new<P: Into<PathBuf>>(ca_path: P) -> Self {
+        let cached_dns_resolver = CachedDnsResolver::new(API_HOST.to_owned(), None, *API_IP);
+
         MullvadRpcFactory {
-            cached_dns_resolver: CachedDnsResolver::new(API_HOST.to_owned(), None, *API_IP),
+            cached_dns_resolver: Arc::new(Mutex::new(cached_dns_resolver)),
             ca_path: ca_path.into(),
+            mark_sockets: false,
         }
     }
 
@@ -69,11 +77,36 @@ impl MullvadRpcFactory {
             CachedDnsResolver::new(API_HOST.to_owned(), Some(cache_file), *API_IP);
 
         MullvadRpcFactory {
-            cached_dns_resolver,
+            cached_dns_resolver: Arc::new(Mutex::new(cached_dns_resolver)),
             ca_path: ca_path.into(),
+            mark_sockets: false,
         }
     }
 
+    /// Mark the sockets of connections created after this call with the firewall mark of the
+    /// daemon, so they can reach the API while the firewall blocks other traffic. Only has an
+    /// effect on Linux, where it requires `CAP_NET_ADMIN`. Disabled by default.
+    pub fn set_mark_sockets(&mut self, mark_sockets: bool) {
+        self.mark_sockets = mark_sockets;
+    }
+
+    /// Set a callback that is called with the new IP address of the API whenever it changes.
+    pub fn set_api_ip_listener<F: Fn(IpAddr) + Send + 'static>(&mut self, listener: F) {
+        self.lock_resolver()
+            .set_address_change_listener(Box::new(listener));
+    }
+
+    /// Returns the IP address the API is reached at.
+    pub fn api_ip(&mut self) -> IpAddr {
+        self.lock_resolver().resolve()
+    }
+
+    fn lock_resolver(&self) -> MutexGuard<'_, CachedDnsResolver> {
+        self.cached_dns_resolver
+            .lock()
+            .expect("API address resolver lock is poisoned")
+    }
+
     /// Create and returns a `HttpHandle` running on the given core handle.
     pub fn new_connection_on_event_loop(
         &mut self,
@@ -88,27 +121,30 @@ impl MullvadRpcFactory {
             HttpTransportBuilder<HttpsClientWithSni>,
         ) -> jsonrpc_client_http::Result<HttpTransport>,
     {
-        let client = HttpsClientWithSni::new(API_HOST.to_owned(), self.ca_path.clone());
+        let client = HttpsClientWithSni::new(
+            API_HOST.to_owned(),
+            self.ca_path.clone(),
+            self.cached_dns_resolver.clone(),
+            self.mark_sockets,
+        );
         let transport_builder = HttpTransportBuilder::with_client(client).timeout(RPC_TIMEOUT);
 
         let transport = create_transport(transport_builder)?;
-        let api_uri = self.api_uri();
-        log::debug!("Using API URI {}", api_uri);
-        let mut handle = transport.handle(&api_uri)?;
+        // The connector connects to the resolved address of the API, so the host in the URI is
+        // only used for the `Host` header.
+        let mut handle = transport.handle(&format!("https://{}/rpc/", API_HOST))?;
 
         handle.set_header(Host::new(API_HOST, None));
 
         Ok(handle)
     }
-
-    fn api_uri(&mut self) -> String {
-        let ip = self.cached_dns_resolver.resolve().to_string();
-        format!("https://{}/rpc/", ip)
-    }
 }
 
 jsonrpc_client!(pub struct AccountsProxy {
     pub fn get_expiry(&mut self, account_token: AccountToken) -> RpcRequest<DateTime<Utc>>;
+    pub fn list_ports(&mut self, account_token: AccountToken) -> RpcRequest<Vec<u16>>;
+    pub fn add_port(&mut self, account_token: AccountToken) -> RpcRequest<u16>;
+    pub fn remove_port(&mut self, account_token: AccountToken, port: u16) -> RpcRequest<()>;
 });
 
 jsonrpc_client!(pub struct ProblemReportProxy {
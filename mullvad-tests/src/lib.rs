@@ -25,6 +25,8 @@ pub mod mock_openvpn;
 type Result<T> = ::std::result::Result<T, String>;
 
 pub const ASSETS_DIR: &str = "../dist-assets";
+const API_IP: &str = "193.138.218.73";
+const API_IP_CACHE_FILENAME: &str = "api-ip-address.txt";
 
 #[cfg(unix)]
 mod platform_specific {
@@ -204,6 +206,7 @@ fn prepare_test_dirs() -> (TempDir, PathBuf, PathBuf, PathBuf) {
     fs::create_dir(&settings_dir).expect("Failed to create settings directory");
 
     prepare_resource_dir(&resource_dir);
+    prepare_api_ip_cache(&cache_dir);
 
     (temp_dir, cache_dir, resource_dir, settings_dir)
 }
@@ -223,6 +226,13 @@ fn prepare_resource_dir(resource_dir: &Path) {
     prepare_relay_list(resource_dir.join("relays.json"));
 }
 
+/// Caches the API IP address, so the daemon doesn't resolve it and the recorded firewall
+/// policies are predictable.
+fn prepare_api_ip_cache(cache_dir: &Path) {
+    fs::write(cache_dir.join(API_IP_CACHE_FILENAME), API_IP)
+        .expect("Failed to create API IP address cache file");
+}
+
 fn prepare_relay_list<T: AsRef<Path>>(path: T) {
     fs::write(
         path,
@@ -256,6 +266,7 @@ fn prepare_relay_list<T: AsRef<Path>>(path: T) {
 pub struct DaemonRunner {
     process: Option<duct::Handle>,
     mock_openvpn_args_file: PathBuf,
+    dry_run_file: PathBuf,
     rpc_socket_path: PathBuf,
     _temp_dir: TempDir,
 }
@@ -268,6 +279,7 @@ impl DaemonRunner {
     fn spawn_internal() -> Self {
         let (temp_dir, cache_dir, resource_dir, settings_dir) = prepare_test_dirs();
         let mock_openvpn_args_file = temp_dir.path().join(MOCK_OPENVPN_ARGS_FILE);
+        let dry_run_file = temp_dir.path().join("dry_run");
 
         let rpc_socket_path = temp_dir.path().join("rpc_socket");
 
@@ -278,6 +290,8 @@ impl DaemonRunner {
             .env("MULLVAD_RESOURCE_DIR", resource_dir)
             .env("MULLVAD_SETTINGS_DIR", settings_dir)
             .env("MOCK_OPENVPN_ARGS_FILE", mock_openvpn_args_file.clone())
+            .env("TALPID_DRY_RUN_FILE", dry_run_file.clone())
+            .env("TALPID_FIREWALL_BACKEND", "iptables")
             .stdout_null()
             .stderr_null();
 
@@ -287,6 +301,7 @@ impl DaemonRunner {
         DaemonRunner {
             process: Some(process),
             mock_openvpn_args_file,
+            dry_run_file,
             rpc_socket_path,
             _temp_dir: temp_dir,
         }
@@ -296,6 +311,11 @@ impl DaemonRunner {
         &self.mock_openvpn_args_file
     }
 
+    /// The file where the daemon records firewall and DNS changes instead of applying them.
+    pub fn dry_run_file(&self) -> &Path {
+        &self.dry_run_file
+    }
+
     pub fn rpc_client(&mut self) -> Result<DaemonRpcClient> {
         wait_for_file(&self.rpc_socket_path);
         let socket_path: String = self.rpc_socket_path.to_string_lossy().to_string();
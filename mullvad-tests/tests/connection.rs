@@ -154,6 +154,130 @@ fn disconnects() {
     );
 }
 
+#[test]
+fn records_firewall_and_dns_changes() {
+    let mut daemon = DaemonRunner::spawn();
+    let mut rpc_client = daemon.rpc_client().unwrap();
+    let openvpn_args_file = daemon.mock_openvpn_args_file();
+    let mut openvpn_args_file_events = PathWatcher::watch(&openvpn_args_file).unwrap();
+    let state_events = rpc_client.new_state_subscribe().unwrap();
+
+    rpc_client.set_account(Some("123456".to_owned())).unwrap();
+    rpc_client.connect().unwrap();
+
+    assert_state_event(&state_events, TunnelStateTransition::Connecting);
+    openvpn_args_file_events.assert_create_write_close_sequence();
+
+    let mut mock_plugin_client = create_mock_openvpn_plugin_client(openvpn_args_file);
+
+    mock_plugin_client.up().unwrap();
+
+    assert_state_event(&state_events, TunnelStateTransition::Connected);
+
+    rpc_client.disconnect().unwrap();
+
+    assert_state_event(&state_events, TunnelStateTransition::Disconnected);
+
+    let recorded_changes =
+        fs::read_to_string(daemon.dry_run_file()).expect("Failed to read dry-run file");
+    let connecting_policy = "firewall apply_policy: Connecting to 192.168.0.100:1000 over UDP, \
+                             Blocking LAN, allowing own traffic to 193.138.218.73:443 over TCP";
+    let connected_policy = "firewall apply_policy: Connected to 192.168.0.100:1000 over UDP over \
+                            \"lo\" (ip: 10.0.0.10, gw: 10.0.0.1, dns: 10.0.0.1, split dns: , dns \
+                            upstream: none, forwarded ports: []), Blocking LAN";
+    let expected_changes = [
+        "firewall reset_policy".to_owned(),
+        connecting_policy.to_owned() + CONNECTING_RULES,
+        connected_policy.to_owned() + CONNECTED_RULES,
+        "dns encrypted upstream: none".to_owned(),
+        "dns set lo: 10.0.0.1".to_owned(),
+        "dns reset".to_owned(),
+        "firewall reset_policy".to_owned(),
+    ];
+
+    assert_eq!(
+        recorded_changes.lines().collect::<Vec<_>>(),
+        expected_changes
+            .iter()
+            .flat_map(|change| change.lines())
+            .collect::<Vec<_>>()
+    );
+}
+
+#[cfg(target_os = "linux")]
+const CONNECTING_RULES: &str = "
+# IPv4
+*filter
+:mullvad-in - [0:0]
+:mullvad-out - [0:0]
+-A mullvad-out -o lo -j ACCEPT
+-A mullvad-in -i lo -j ACCEPT
+-A mullvad-out -p udp --sport 68 -d 255.255.255.255 --dport 67 -j ACCEPT
+-A mullvad-in -p udp --sport 67 --dport 68 -j ACCEPT
+-A mullvad-in -s 192.168.0.100 -p udp --sport 1000 -m conntrack --ctstate ESTABLISHED -j ACCEPT
+-A mullvad-out -d 192.168.0.100 -p udp --dport 1000 -j ACCEPT
+-A mullvad-in -s 193.138.218.73 -p tcp --sport 443 -m conntrack --ctstate ESTABLISHED -j ACCEPT
+-A mullvad-out -d 193.138.218.73 -p tcp --dport 443 -m mark --mark 0x6d617069 -j ACCEPT
+-A mullvad-in -j DROP
+-A mullvad-out -j DROP
+COMMIT
+# IPv6
+*filter
+:mullvad-in - [0:0]
+:mullvad-out - [0:0]
+-A mullvad-out -o lo -j ACCEPT
+-A mullvad-in -i lo -j ACCEPT
+-A mullvad-out -s fe80::/10 -p udp --sport 546 -d ff02::1:2 --dport 547 -j ACCEPT
+-A mullvad-out -s fe80::/10 -p udp --sport 546 -d ff05::1:3 --dport 547 -j ACCEPT
+-A mullvad-in -s fe80::/10 -p udp --sport 547 -d fe80::/10 --dport 546 -j ACCEPT
+-A mullvad-in -j DROP
+-A mullvad-out -j DROP
+COMMIT";
+
+#[cfg(target_os = "linux")]
+const CONNECTED_RULES: &str = "
+# IPv4
+*filter
+:mullvad-in - [0:0]
+:mullvad-out - [0:0]
+-A mullvad-out -o lo -j ACCEPT
+-A mullvad-in -i lo -j ACCEPT
+-A mullvad-out -p udp --sport 68 -d 255.255.255.255 --dport 67 -j ACCEPT
+-A mullvad-in -p udp --sport 67 --dport 68 -j ACCEPT
+-A mullvad-in -s 192.168.0.100 -p udp --sport 1000 -m conntrack --ctstate ESTABLISHED -j ACCEPT
+-A mullvad-out -d 192.168.0.100 -p udp --dport 1000 -j ACCEPT
+-A mullvad-out -o lo -d 10.0.0.1 -p udp --dport 53 -j ACCEPT
+-A mullvad-out -p udp --dport 53 -j DROP
+-A mullvad-out -o lo -d 10.0.0.1 -p tcp --dport 53 -j ACCEPT
+-A mullvad-out -p tcp --dport 53 -j DROP
+-A mullvad-out -o lo -j ACCEPT
+-A mullvad-in -i lo -m conntrack --ctstate ESTABLISHED,RELATED -j ACCEPT
+-A mullvad-in -j DROP
+-A mullvad-out -j DROP
+COMMIT
+# IPv6
+*filter
+:mullvad-in - [0:0]
+:mullvad-out - [0:0]
+-A mullvad-out -o lo -j ACCEPT
+-A mullvad-in -i lo -j ACCEPT
+-A mullvad-out -s fe80::/10 -p udp --sport 546 -d ff02::1:2 --dport 547 -j ACCEPT
+-A mullvad-out -s fe80::/10 -p udp --sport 546 -d ff05::1:3 --dport 547 -j ACCEPT
+-A mullvad-in -s fe80::/10 -p udp --sport 547 -d fe80::/10 --dport 546 -j ACCEPT
+-A mullvad-out -p udp --dport 53 -j DROP
+-A mullvad-out -p tcp --dport 53 -j DROP
+-A mullvad-out -o lo -j ACCEPT
+-A mullvad-in -i lo -m conntrack --ctstate ESTABLISHED,RELATED -j ACCEPT
+-A mullvad-in -j DROP
+-A mullvad-out -j DROP
+COMMIT";
+
+// Firewall rules are only rendered on Linux.
+#[cfg(not(target_os = "linux"))]
+const CONNECTING_RULES: &str = "";
+#[cfg(not(target_os = "linux"))]
+const CONNECTED_RULES: &str = "";
+
 fn assert_state_event(
     receiver: &mpsc::Receiver<TunnelStateTransition>,
     expected_state: TunnelStateTransition,
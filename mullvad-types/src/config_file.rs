@@ -0,0 +1,529 @@
+//! Reads configuration files written for other VPN clients, so that a server that is already set
+//! up for `wg-quick` or OpenVPN can be used as a custom relay.
+
+use crate::{ConnectionConfig, CustomTunnelEndpoint};
+use ipnetwork::IpNetwork;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use talpid_types::net::{openvpn, wireguard, Endpoint, TransportProtocol};
+
+error_chain! {
+    errors {
+        SyntaxError(line: usize) {
+            description("Syntax error")
+            display("Syntax error on line {}", line)
+        }
+        UnexpectedSection(line: usize, section: String) {
+            description("Unexpected section")
+            display("Unexpected section on line {}: [{}]", line, section)
+        }
+        UnsupportedDirective(line: usize, directive: String) {
+            description("Unsupported directive")
+            display("Unsupported directive on line {}: {}", line, directive)
+        }
+        InvalidValue(line: usize, directive: String) {
+            description("Invalid value")
+            display("Invalid value for {} on line {}", directive, line)
+        }
+        MissingDirective(directive: &'static str) {
+            description("Missing directive")
+            display("Missing required directive: {}", directive)
+        }
+        MultipleRemotes(line: usize) {
+            description("Only one remote is supported")
+            display("Only one remote is supported, found another one on line {}", line)
+        }
+        IncompatibleDirective(line: usize, directive: String, reason: &'static str) {
+            description("Directive is incompatible with how the daemon connects to relays")
+            display("Unsupported directive on line {}: {}. {}", line, directive, reason)
+        }
+        PeerHostnameUnsupported(host: String) {
+            description("Only the first peer can have a hostname as its endpoint")
+            display("Only the first peer can have a hostname as its endpoint, got {}", host)
+        }
+    }
+}
+
+/// OpenVPN directives that are accepted but ignored, since the daemon always passes its own
+/// values for them.
+const IGNORED_OPENVPN_DIRECTIVES: &[&str] = &[
+    "auth-user-pass",
+    "client",
+    "down",
+    "mute-replay-warnings",
+    "nobind",
+    "persist-key",
+    "persist-tun",
+    "remote-cert-tls",
+    "resolv-retry",
+    "script-security",
+    "tls-cipher",
+    "tun-ipv6",
+    "up",
+    "verb",
+];
+
+const DEFAULT_OPENVPN_PORT: u16 = 1194;
+
+/// Returns true if the contents look like a `wg-quick` configuration rather than an OpenVPN one.
+pub fn is_wg_quick_config(contents: &str) -> bool {
+    contents
+        .lines()
+        .map(|line| strip_comment(line, &['#']))
+        .find(|line| !line.is_empty())
+        .map(|line| line.starts_with('['))
+        .unwrap_or(false)
+}
+
+/// A relay read from a `wg-quick` configuration.
+#[derive(Debug, PartialEq)]
+pub struct WgQuickConfig {
+    pub endpoint: CustomTunnelEndpoint,
+    /// The `PersistentKeepalive` of the first peer. The daemon applies keepalive to the whole
+    /// tunnel, so this should be used as the WireGuard keepalive setting. Is `None` if the
+    /// directive is missing or `off`.
+    pub persistent_keepalive: Option<u16>,
+}
+
+/// Parses a `wg-quick` configuration with one `[Interface]` section and at least one `[Peer]`
+/// section. The first peer becomes the relay, the others become additional peers. The first `DNS`
+/// server is used as the gateway, since that is what the tunnel resolves names through.
+pub fn parse_wg_quick(contents: &str) -> Result<WgQuickConfig> {
+    let mut interface = None;
+    let mut peers = Vec::new();
+
+    for (line, key, value) in ini_entries(contents)? {
+        match key {
+            IniEntry::Section(section) => match section.to_lowercase().as_str() {
+                "interface" if interface.is_none() => interface = Some(WgInterface::default()),
+                "peer" if interface.is_some() => peers.push(WgPeer::default()),
+                _ => bail!(ErrorKind::UnexpectedSection(line, section.to_owned())),
+            },
+            IniEntry::Value(key) => match peers.last_mut() {
+                Some(peer) => peer.set(line, key, value)?,
+                None => interface
+                    .as_mut()
+                    .ok_or(ErrorKind::SyntaxError(line))?
+                    .set(line, key, value)?,
+            },
+        }
+    }
+
+    let interface = interface.ok_or(ErrorKind::MissingDirective("[Interface]"))?;
+    let mut peers = peers.into_iter();
+    let first_peer = peers.next().ok_or(ErrorKind::MissingDirective("[Peer]"))?;
+
+    let private_key = interface
+        .private_key
+        .ok_or(ErrorKind::MissingDirective("PrivateKey"))?;
+    if interface.addresses.is_empty() {
+        bail!(ErrorKind::MissingDirective("Address"));
+    }
+    let gateway = interface
+        .dns
+        .iter()
+        .find(|ip| ip.is_ipv4())
+        .or_else(|| interface.dns.first())
+        .cloned()
+        .ok_or(ErrorKind::MissingDirective("DNS"))?;
+    let ipv6_gateway = interface.dns.iter().find_map(|ip| match ip {
+        IpAddr::V6(ip) if gateway.is_ipv4() => Some(*ip),
+        _ => None,
+    });
+
+    let (host, port) = first_peer
+        .endpoint
+        .clone()
+        .ok_or(ErrorKind::MissingDirective("Endpoint"))?;
+    let persistent_keepalive = first_peer.persistent_keepalive;
+    let peer = first_peer.into_peer_config(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), port))?;
+    let additional_peers = peers
+        .map(|peer| {
+            let endpoint = peer.socket_addr()?;
+            peer.into_peer_config(endpoint)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let endpoint = CustomTunnelEndpoint::new(
+        host,
+        ConnectionConfig::Wireguard(wireguard::ConnectionConfig {
+            tunnel: wireguard::TunnelConfig {
+                private_key: private_key.into(),
+                addresses: interface.addresses,
+            },
+            peer,
+            gateway,
+            ipv6_gateway,
+            additional_peers,
+        }),
+    );
+    Ok(WgQuickConfig {
+        endpoint,
+        persistent_keepalive,
+    })
+}
+
+/// Parses the connection details out of an OpenVPN client configuration. Only `remote`, `port`
+/// and `proto` are used, along with a few directives that are ignored since the daemon sets them
+/// on its own. Anything else, like certificates, ciphers and inline blocks, is rejected, as is
+/// more than one `remote`.
+pub fn parse_openvpn(
+    contents: &str,
+    username: String,
+    password: String,
+) -> Result<CustomTunnelEndpoint> {
+    let mut remote: Option<(String, Option<u16>, Option<TransportProtocol>)> = None;
+    let mut port = None;
+    let mut protocol = None;
+
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        let mut words = strip_comment(line, &['#', ';']).split_whitespace();
+        let directive = match words.next() {
+            Some(directive) => directive,
+            None => continue,
+        };
+        let arguments = words.collect::<Vec<_>>();
+        let invalid_value = || ErrorKind::InvalidValue(line_number, directive.to_owned());
+
+        match directive {
+            "remote" if remote.is_none() => {
+                let host = arguments.first().ok_or_else(invalid_value)?;
+                let remote_port = match arguments.get(1) {
+                    Some(port) => Some(port.parse().map_err(|_| invalid_value())?),
+                    None => None,
+                };
+                let remote_protocol = match arguments.get(2) {
+                    Some(protocol) => {
+                        Some(parse_openvpn_protocol(protocol).ok_or_else(invalid_value)?)
+                    }
+                    None => None,
+                };
+                ensure!(arguments.len() <= 3, invalid_value());
+                remote = Some((host.to_string(), remote_port, remote_protocol));
+            }
+            "remote" => bail!(ErrorKind::MultipleRemotes(line_number)),
+            "ca" => bail!(ErrorKind::IncompatibleDirective(
+                line_number,
+                directive.to_owned(),
+                "Relays are always verified with the CA certificate bundled with the app",
+            )),
+            "cipher" => bail!(ErrorKind::IncompatibleDirective(
+                line_number,
+                directive.to_owned(),
+                "The cipher is chosen by the app",
+            )),
+            "port" => {
+                ensure!(arguments.len() == 1, invalid_value());
+                port = Some(arguments[0].parse().map_err(|_| invalid_value())?);
+            }
+            "proto" => {
+                ensure!(arguments.len() == 1, invalid_value());
+                protocol = Some(parse_openvpn_protocol(arguments[0]).ok_or_else(invalid_value)?);
+            }
+            "dev" => ensure!(
+                arguments.first().map(|dev| dev.starts_with("tun")) == Some(true),
+                invalid_value()
+            ),
+            directive if IGNORED_OPENVPN_DIRECTIVES.contains(&directive) => (),
+            directive => bail!(ErrorKind::UnsupportedDirective(
+                line_number,
+                directive.to_owned()
+            )),
+        }
+    }
+
+    let (host, remote_port, remote_protocol) =
+        remote.ok_or(ErrorKind::MissingDirective("remote"))?;
+    let port = remote_port.or(port).unwrap_or(DEFAULT_OPENVPN_PORT);
+    let protocol = remote_protocol
+        .or(protocol)
+        .unwrap_or(TransportProtocol::Udp);
+
+    Ok(CustomTunnelEndpoint::new(
+        host,
+        ConnectionConfig::OpenVpn(openvpn::ConnectionConfig {
+            endpoint: Endpoint::new(Ipv4Addr::UNSPECIFIED, port, protocol),
+            username,
+            password,
+        }),
+    ))
+}
+
+#[derive(Default)]
+struct WgInterface {
+    private_key: Option<[u8; 32]>,
+    addresses: Vec<IpAddr>,
+    dns: Vec<IpAddr>,
+}
+
+impl WgInterface {
+    fn set(&mut self, line: usize, key: &str, value: &str) -> Result<()> {
+        let invalid_value = || ErrorKind::InvalidValue(line, key.to_owned());
+        match key.to_lowercase().as_str() {
+            "privatekey" => self.private_key = Some(parse_key(value).ok_or_else(invalid_value)?),
+            "address" => {
+                for address in split_list(value) {
+                    // Addresses are usually written with a prefix length, which the tunnel
+                    // doesn't need since all traffic is routed through the peers anyway
+                    let network = address.parse::<IpNetwork>().map_err(|_| invalid_value())?;
+                    self.addresses.push(network.ip());
+                }
+            }
+            "dns" => {
+                for server in split_list(value) {
+                    self.dns.push(server.parse().map_err(|_| invalid_value())?);
+                }
+            }
+            _ => bail!(ErrorKind::UnsupportedDirective(line, key.to_owned())),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct WgPeer {
+    public_key: Option<[u8; 32]>,
+    preshared_key: Option<[u8; 32]>,
+    allowed_ips: Vec<IpNetwork>,
+    endpoint: Option<(String, u16)>,
+    persistent_keepalive: Option<u16>,
+}
+
+impl WgPeer {
+    fn set(&mut self, line: usize, key: &str, value: &str) -> Result<()> {
+        let invalid_value = || ErrorKind::InvalidValue(line, key.to_owned());
+        match key.to_lowercase().as_str() {
+            "publickey" => self.public_key = Some(parse_key(value).ok_or_else(invalid_value)?),
+            "presharedkey" => {
+                self.preshared_key = Some(parse_key(value).ok_or_else(invalid_value)?)
+            }
+            "allowedips" => {
+                for allowed_ip in split_list(value) {
+                    self.allowed_ips
+                        .push(allowed_ip.parse().map_err(|_| invalid_value())?);
+                }
+            }
+            "endpoint" => self.endpoint = Some(split_host_port(value).ok_or_else(invalid_value)?),
+            "persistentkeepalive" => {
+                self.persistent_keepalive = match value.to_lowercase().as_str() {
+                    "off" | "0" => None,
+                    interval => Some(interval.parse().map_err(|_| invalid_value())?),
+                }
+            }
+            _ => bail!(ErrorKind::UnsupportedDirective(line, key.to_owned())),
+        }
+        Ok(())
+    }
+
+    /// Additional peers are stored with their address, so their endpoints can't be hostnames.
+    fn socket_addr(&self) -> Result<SocketAddr> {
+        let (host, port) = self
+            .endpoint
+            .as_ref()
+            .ok_or(ErrorKind::MissingDirective("Endpoint"))?;
+        let ip = host
+            .parse::<IpAddr>()
+            .map_err(|_| ErrorKind::PeerHostnameUnsupported(host.to_owned()))?;
+        Ok(SocketAddr::new(ip, *port))
+    }
+
+    fn into_peer_config(self, endpoint: SocketAddr) -> Result<wireguard::PeerConfig> {
+        let public_key = self
+            .public_key
+            .ok_or(ErrorKind::MissingDirective("PublicKey"))?;
+        ensure!(
+            !self.allowed_ips.is_empty(),
+            ErrorKind::MissingDirective("AllowedIPs")
+        );
+        Ok(wireguard::PeerConfig {
+            public_key: public_key.into(),
+            allowed_ips: self.allowed_ips,
+            endpoint,
+            preshared_key: self.preshared_key.map(Into::into),
+        })
+    }
+}
+
+enum IniEntry<'a> {
+    Section(&'a str),
+    Value(&'a str),
+}
+
+/// Splits an INI style file into sections and `key = value` entries, along with their line
+/// numbers. Sections have an empty value.
+fn ini_entries(contents: &str) -> Result<Vec<(usize, IniEntry<'_>, &str)>> {
+    let mut entries = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line_number = index + 1;
+        let line = strip_comment(line, &['#']);
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            entries.push((
+                line_number,
+                IniEntry::Section(line[1..line.len() - 1].trim()),
+                "",
+            ));
+        } else {
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts
+                .next()
+                .ok_or(ErrorKind::SyntaxError(line_number))?
+                .trim();
+            ensure!(!key.is_empty(), ErrorKind::SyntaxError(line_number));
+            entries.push((line_number, IniEntry::Value(key), value));
+        }
+    }
+    Ok(entries)
+}
+
+fn strip_comment<'a>(line: &'a str, comment_chars: &[char]) -> &'a str {
+    match line.find(comment_chars) {
+        Some(index) => line[..index].trim(),
+        None => line.trim(),
+    }
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+/// Splits `host:port` or `[ipv6]:port`.
+fn split_host_port(value: &str) -> Option<(String, u16)> {
+    let separator = value.rfind(':')?;
+    let (host, port) = (&value[..separator], &value[separator + 1..]);
+    let host = if host.starts_with('[') && host.ends_with(']') {
+        &host[1..host.len() - 1]
+    } else if host.contains(':') {
+        return None;
+    } else {
+        host
+    };
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_owned(), port.parse().ok()?))
+}
+
+fn parse_key(value: &str) -> Option<[u8; 32]> {
+    let bytes = base64::decode(value).ok()?;
+    if bytes.len() != 32 {
+        return None;
+    }
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&bytes);
+    Some(key)
+}
+
+fn parse_openvpn_protocol(protocol: &str) -> Option<TransportProtocol> {
+    match protocol {
+        "udp" | "udp4" | "udp6" => Some(TransportProtocol::Udp),
+        "tcp" | "tcp4" | "tcp6" | "tcp-client" | "tcp4-client" | "tcp6-client" => {
+            Some(TransportProtocol::Tcp)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WG_QUICK_CONFIG: &str = "
+        [Interface]
+        # Device: Some device
+        PrivateKey = AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=
+        Address = 10.64.0.2/32, fc00:bbbb:bbbb:bb01::2/128
+        DNS = 10.64.0.1
+
+        [Peer]
+        PublicKey = AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=
+        AllowedIPs = 0.0.0.0/0, ::0/0
+        Endpoint = se4-wireguard.mullvad.net:51820
+        PersistentKeepalive = 25
+
+        [Peer]
+        PublicKey = AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=
+        AllowedIPs = 192.168.10.0/24
+        Endpoint = 192.168.1.1:51820
+        PersistentKeepalive = off
+    ";
+
+    #[test]
+    fn parses_wg_quick_config() {
+        assert!(is_wg_quick_config(WG_QUICK_CONFIG));
+        let config = parse_wg_quick(WG_QUICK_CONFIG).unwrap();
+        let expected = CustomTunnelEndpoint::new(
+            "se4-wireguard.mullvad.net".to_owned(),
+            ConnectionConfig::Wireguard(wireguard::ConnectionConfig {
+                tunnel: wireguard::TunnelConfig {
+                    private_key: [0; 32].into(),
+                    addresses: vec![
+                        "10.64.0.2".parse().unwrap(),
+                        "fc00:bbbb:bbbb:bb01::2".parse().unwrap(),
+                    ],
+                },
+                peer: wireguard::PeerConfig {
+                    public_key: [1; 32].into(),
+                    allowed_ips: crate::endpoint::all_of_the_internet(),
+                    endpoint: "0.0.0.0:51820".parse().unwrap(),
+                    preshared_key: None,
+                },
+                gateway: "10.64.0.1".parse().unwrap(),
+                ipv6_gateway: None,
+                additional_peers: vec![wireguard::PeerConfig {
+                    public_key: [2; 32].into(),
+                    allowed_ips: vec!["192.168.10.0/24".parse().unwrap()],
+                    endpoint: "192.168.1.1:51820".parse().unwrap(),
+                    preshared_key: None,
+                }],
+            }),
+        );
+        assert_eq!(config.endpoint, expected);
+        assert_eq!(config.persistent_keepalive, Some(25));
+
+        let config = WG_QUICK_CONFIG.replace("PersistentKeepalive = 25", "");
+        assert_eq!(parse_wg_quick(&config).unwrap().persistent_keepalive, None);
+    }
+
+    #[test]
+    fn rejects_unsupported_directives() {
+        let config = WG_QUICK_CONFIG.replace("DNS = 10.64.0.1", "DNS = 10.64.0.1\nPostUp = true");
+        match parse_wg_quick(&config).unwrap_err().kind() {
+            ErrorKind::UnsupportedDirective(7, directive) => assert_eq!(directive, "PostUp"),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+
+        let config = "client\nremote 185.65.135.1 1301\nproto tcp\n<ca>\n";
+        match parse_openvpn(config, "user".to_owned(), "pass".to_owned())
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::UnsupportedDirective(4, directive) => assert_eq!(directive, "<ca>"),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+
+        let config = "client\nremote 185.65.135.1 1301\ncipher AES-256-CBC\n";
+        match parse_openvpn(config, "user".to_owned(), "pass".to_owned())
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::IncompatibleDirective(3, directive, _) => assert_eq!(directive, "cipher"),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+
+        let config = "client\nremote 185.65.135.1 1301\nremote 185.65.135.2 1301\n";
+        match parse_openvpn(config, "user".to_owned(), "pass".to_owned())
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::MultipleRemotes(3) => (),
+            kind => panic!("Unexpected error: {:?}", kind),
+        }
+    }
+}
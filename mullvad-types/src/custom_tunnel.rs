@@ -62,11 +62,27 @@ impl fmt::Display for CustomTunnelEndpoint {
                 config.endpoint.address.port(),
                 config.endpoint.protocol
             ),
-            ConnectionConfig::Wireguard(connection) => write!(
-                f,
-                "WireGuard relay - {} with public key {}",
-                connection.peer.endpoint, connection.peer.public_key
-            ),
+            ConnectionConfig::Wireguard(connection) => {
+                write!(
+                    f,
+                    "WireGuard relay - {} with public key {}",
+                    connection.peer.endpoint, connection.peer.public_key
+                )?;
+                for peer in &connection.additional_peers {
+                    write!(
+                        f,
+                        ", {} with public key {} for {}",
+                        peer.endpoint,
+                        peer.public_key,
+                        peer.allowed_ips
+                            .iter()
+                            .map(|allowed_ip| allowed_ip.to_string())
+                            .collect::<Vec<_>>()
+                            .join(",")
+                    )?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -80,6 +80,7 @@ impl TunnelEndpointData {
                     public_key: metadata.peer_public_key,
                     endpoint: SocketAddr::new(host, metadata.port),
                     allowed_ips: all_of_the_internet(),
+                    preshared_key: None,
                 };
                 MullvadEndpoint::Wireguard {
                     peer: peer_config,
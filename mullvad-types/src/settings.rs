@@ -4,8 +4,17 @@ use crate::relay_constraints::{
 use log::{debug, info};
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::{fs::File, io, path::PathBuf};
-use talpid_types::net::{openvpn, wireguard, GenericTunnelOptions};
+use std::{
+    fmt,
+    fs::File,
+    io,
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+};
+use talpid_types::net::{
+    dns::{EncryptedDnsUpstream, SplitDns},
+    is_reached_outside_tunnel, openvpn, wireguard, GenericTunnelOptions,
+};
 
 error_chain! {
     errors {
@@ -27,6 +36,17 @@ error_chain! {
             description("Invalid proxy configuration was rejected")
             display("Invalid proxy configuration was rejected: {}", reason)
         }
+        LocalDnsServerWithoutLan(server: IpAddr) {
+            description("DNS server on the local network was rejected since LAN access is blocked")
+            display(
+                "DNS server {} is on the local network, but local network sharing is blocked",
+                server
+            )
+        }
+        InvalidSplitDnsDomain(domain: String) {
+            description("Invalid split DNS domain was rejected")
+            display("Invalid split DNS domain was rejected: \"{}\"", domain)
+        }
     }
 }
 
@@ -44,6 +64,23 @@ pub struct Settings {
     /// Extra level of kill switch. When this setting is on, the disconnected state will block
     /// the firewall to not allow any traffic in or out.
     block_when_disconnected: bool,
+    /// Keep the blocking firewall rules in place after the daemon exits, and apply them during
+    /// early boot before the daemon has been started.
+    persistent_block: bool,
+    /// Ports on the relays that are forwarded to this device through the tunnel.
+    forwarded_ports: Vec<u16>,
+    /// DNS servers to use inside the tunnel instead of the default one. Empty means the default
+    /// DNS server is used.
+    custom_dns: Vec<IpAddr>,
+    /// Content that should be blocked by using a filtering DNS resolver on the relay. Not used
+    /// when custom DNS servers are set.
+    dns_content_blocking: DnsContentBlocking,
+    /// Encrypted DNS server that all DNS queries are forwarded to through a local stub resolver,
+    /// instead of being sent as plain DNS.
+    encrypted_dns: Option<EncryptedDnsUpstream>,
+    /// DNS servers that resolve names within specific domains, instead of the DNS servers used
+    /// inside the tunnel. Only supported with systemd-resolved.
+    split_dns: SplitDns,
     /// If the daemon should connect the VPN tunnel directly on start or not.
     auto_connect: bool,
     /// Options that should be applied to tunnels of a specific type regardless of where the relays
@@ -61,6 +98,12 @@ impl Default for Settings {
             }),
             allow_lan: false,
             block_when_disconnected: false,
+            persistent_block: false,
+            forwarded_ports: Vec::new(),
+            custom_dns: Vec::new(),
+            dns_content_blocking: DnsContentBlocking::default(),
+            encrypted_dns: None,
+            split_dns: SplitDns::new(),
             auto_connect: false,
             tunnel_options: TunnelOptions::default(),
         }
@@ -113,6 +156,7 @@ impl Settings {
     }
 
     /// Changes account number to the one given. Also saves the new settings to disk.
+    /// The forwarded ports belong to the previous account and are cleared when it changes.
     /// The boolean in the Result indicates if the account token changed or not
     pub fn set_account_token(&mut self, mut account_token: Option<String>) -> Result<bool> {
         if account_token.as_ref().map(String::len) == Some(0) {
@@ -128,6 +172,7 @@ impl Settings {
                 info!("Changing account token")
             }
             self.account_token = account_token;
+            self.forwarded_ports.clear();
             self.save().map(|_| true)
         } else {
             Ok(false)
@@ -157,7 +202,21 @@ impl Settings {
         self.allow_lan
     }
 
+    /// Changes whether LAN access is allowed. LAN access can't be blocked while a custom or split
+    /// DNS server is on the local network, since it could then not be reached.
     pub fn set_allow_lan(&mut self, allow_lan: bool) -> Result<bool> {
+        if !allow_lan {
+            let split_dns_servers = self.split_dns.values().flatten();
+            if let Some(server) = self
+                .custom_dns
+                .iter()
+                .chain(split_dns_servers)
+                .find(|server| is_reached_outside_tunnel(**server))
+            {
+                bail!(ErrorKind::LocalDnsServerWithoutLan(*server));
+            }
+        }
+
         if allow_lan != self.allow_lan {
             self.allow_lan = allow_lan;
             self.save().map(|_| true)
@@ -179,6 +238,139 @@ impl Settings {
         }
     }
 
+    pub fn get_persistent_block(&self) -> bool {
+        self.persistent_block
+    }
+
+    pub fn set_persistent_block(&mut self, persistent_block: bool) -> Result<bool> {
+        if persistent_block != self.persistent_block {
+            self.persistent_block = persistent_block;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_forwarded_ports(&self) -> Vec<u16> {
+        self.forwarded_ports.clone()
+    }
+
+    pub fn set_forwarded_ports(&mut self, mut forwarded_ports: Vec<u16>) -> Result<bool> {
+        forwarded_ports.sort();
+        forwarded_ports.dedup();
+        if forwarded_ports != self.forwarded_ports {
+            self.forwarded_ports = forwarded_ports;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_custom_dns(&self) -> Vec<IpAddr> {
+        self.custom_dns.clone()
+    }
+
+    /// Changes the custom DNS servers. Servers on the local network are only accepted when LAN
+    /// access is allowed, since they can not be reached through the tunnel. Private addresses
+    /// inside the tunnel, like the DNS server of the relay, are always accepted.
+    pub fn set_custom_dns(&mut self, custom_dns: Vec<IpAddr>) -> Result<bool> {
+        if !self.allow_lan {
+            if let Some(server) = custom_dns
+                .iter()
+                .find(|server| is_reached_outside_tunnel(**server))
+            {
+                bail!(ErrorKind::LocalDnsServerWithoutLan(*server));
+            }
+        }
+
+        if custom_dns != self.custom_dns {
+            self.custom_dns = custom_dns;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_dns_content_blocking(&self) -> DnsContentBlocking {
+        self.dns_content_blocking
+    }
+
+    pub fn set_dns_content_blocking(
+        &mut self,
+        content_blocking: DnsContentBlocking,
+    ) -> Result<bool> {
+        if content_blocking != self.dns_content_blocking {
+            self.dns_content_blocking = content_blocking;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_encrypted_dns(&self) -> Option<EncryptedDnsUpstream> {
+        self.encrypted_dns.clone()
+    }
+
+    pub fn set_encrypted_dns(
+        &mut self,
+        encrypted_dns: Option<EncryptedDnsUpstream>,
+    ) -> Result<bool> {
+        if encrypted_dns != self.encrypted_dns {
+            self.encrypted_dns = encrypted_dns;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn get_split_dns(&self) -> SplitDns {
+        self.split_dns.clone()
+    }
+
+    /// Sets the DNS servers to use for the given domain, or stops resolving it with other servers
+    /// than the rest of the domains if `servers` is empty. Wildcards and trailing dots are
+    /// stripped from the domain. Like custom DNS servers, servers on the local network are only
+    /// accepted when LAN access is allowed.
+    pub fn set_split_dns(&mut self, domain: &str, servers: Vec<IpAddr>) -> Result<bool> {
+        let domain = domain
+            .trim_start_matches("*.")
+            .trim_matches('.')
+            .to_lowercase();
+        if domain.is_empty() || domain.contains(|c: char| c.is_whitespace() || c == '*') {
+            bail!(ErrorKind::InvalidSplitDnsDomain(domain));
+        }
+        if !self.allow_lan {
+            if let Some(server) = servers
+                .iter()
+                .find(|server| is_reached_outside_tunnel(**server))
+            {
+                bail!(ErrorKind::LocalDnsServerWithoutLan(*server));
+            }
+        }
+
+        let changed = if servers.is_empty() {
+            self.split_dns.remove(&domain).is_some()
+        } else {
+            self.split_dns.insert(domain, servers.clone()) != Some(servers)
+        };
+        if changed {
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Returns the DNS servers that should be used inside the tunnel. Custom DNS servers take
+    /// precedence over the content blocking resolver. An empty list means the default DNS server
+    /// should be used.
+    pub fn get_tunnel_dns_servers(&self) -> Vec<IpAddr> {
+        if !self.custom_dns.is_empty() {
+            self.custom_dns.clone()
+        } else {
+            self.dns_content_blocking.resolver().into_iter().collect()
+        }
+    }
+
     pub fn get_auto_connect(&self) -> bool {
         self.auto_connect
     }
@@ -235,6 +427,26 @@ impl Settings {
         }
     }
 
+    #[cfg(target_os = "linux")]
+    pub fn set_wireguard_backend(&mut self, backend: Option<wireguard::Backend>) -> Result<bool> {
+        if self.tunnel_options.wireguard.backend != backend {
+            self.tunnel_options.wireguard.backend = backend;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn set_wireguard_namespace(&mut self, namespace: Option<String>) -> Result<bool> {
+        if self.tunnel_options.wireguard.namespace != namespace {
+            self.tunnel_options.wireguard.namespace = namespace;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
     pub fn set_wireguard_mtu(&mut self, mtu: Option<u16>) -> Result<bool> {
         if self.tunnel_options.wireguard.mtu != mtu {
             self.tunnel_options.wireguard.mtu = mtu;
@@ -244,11 +456,83 @@ impl Settings {
         }
     }
 
+    pub fn set_wireguard_automatic_mtu(&mut self, automatic_mtu: bool) -> Result<bool> {
+        if self.tunnel_options.wireguard.automatic_mtu != automatic_mtu {
+            self.tunnel_options.wireguard.automatic_mtu = automatic_mtu;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn set_wireguard_persistent_keepalive(
+        &mut self,
+        persistent_keepalive: Option<u16>,
+    ) -> Result<bool> {
+        if self.tunnel_options.wireguard.persistent_keepalive != persistent_keepalive {
+            self.tunnel_options.wireguard.persistent_keepalive = persistent_keepalive;
+            self.save().map(|_| true)
+        } else {
+            Ok(false)
+        }
+    }
+
     pub fn get_tunnel_options(&self) -> &TunnelOptions {
         &self.tunnel_options
     }
 }
 
+/// Content that can be blocked by the filtering DNS resolvers on the relays.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DnsContentBlocking {
+    pub block_ads: bool,
+    pub block_trackers: bool,
+    pub block_malware: bool,
+}
+
+impl DnsContentBlocking {
+    /// Returns the address of the in-tunnel resolver that blocks the selected content, or `None`
+    /// if nothing should be blocked. Each kind of content is one bit in the last octet.
+    pub fn resolver(&self) -> Option<IpAddr> {
+        let mut last_octet = 0;
+        if self.block_ads {
+            last_octet |= 1;
+        }
+        if self.block_trackers {
+            last_octet |= 1 << 1;
+        }
+        if self.block_malware {
+            last_octet |= 1 << 2;
+        }
+        if last_octet == 0 {
+            None
+        } else {
+            Some(IpAddr::V4(Ipv4Addr::new(100, 64, 0, last_octet)))
+        }
+    }
+}
+
+impl fmt::Display for DnsContentBlocking {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let blocked = [
+            (self.block_ads, "ads"),
+            (self.block_trackers, "trackers"),
+            (self.block_malware, "malware"),
+        ]
+        .iter()
+        .filter(|(is_blocked, _)| *is_blocked)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>();
+
+        if blocked.is_empty() {
+            write!(f, "nothing")
+        } else {
+            write!(f, "{}", blocked.join(", "))
+        }
+    }
+}
+
 /// TunnelOptions holds configuration data that applies to all kinds of tunnels.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(default)]
@@ -267,8 +551,14 @@ impl Default for TunnelOptions {
             openvpn: openvpn::TunnelOptions::default(),
             wireguard: wireguard::TunnelOptions {
                 mtu: None,
+                automatic_mtu: false,
+                persistent_keepalive: None,
                 #[cfg(target_os = "linux")]
                 fwmark: 78_78_78,
+                #[cfg(target_os = "linux")]
+                backend: None,
+                #[cfg(target_os = "linux")]
+                namespace: None,
             },
             generic: GenericTunnelOptions { enable_ipv6: false },
         }
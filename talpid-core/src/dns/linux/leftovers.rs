@@ -0,0 +1,178 @@
+//! Keeps track of the changes made to the system DNS settings in a file, so that they can be
+//! undone if the process stopped without resetting them, e.g. because it crashed.
+
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    net::IpAddr,
+    path::Path,
+};
+
+/// A change made to the system DNS settings.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Leftover {
+    /// `/etc/resolv.conf` was overwritten after making a backup of it.
+    StaticResolvConf,
+    /// A record was added with `resolvconf`.
+    ResolvconfRecord(String),
+    /// The global DNS configuration of NetworkManager was set.
+    NetworkManager,
+    /// The DNS settings of a systemd-resolved link were set, and should be reverted.
+    SystemdResolvedLink(String),
+    /// The DNS settings of a systemd-resolved link were changed, and should be set back to the
+    /// given servers and domains.
+    SystemdResolvedSavedLink {
+        interface_name: String,
+        servers: Vec<IpAddr>,
+        domains: Vec<(String, bool)>,
+    },
+}
+
+impl fmt::Display for Leftover {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Leftover::StaticResolvConf => write!(f, "static-file"),
+            Leftover::ResolvconfRecord(record_name) => write!(f, "resolvconf {}", record_name),
+            Leftover::NetworkManager => write!(f, "network-manager"),
+            Leftover::SystemdResolvedLink(interface_name) => {
+                write!(f, "systemd-resolved {}", interface_name)
+            }
+            Leftover::SystemdResolvedSavedLink {
+                interface_name,
+                servers,
+                domains,
+            } => {
+                let servers = servers
+                    .iter()
+                    .map(|server| server.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",");
+                // Routing-only domains are prefixed with '~', like in `resolvectl`.
+                let domains = domains
+                    .iter()
+                    .map(|(domain, routing_only)| {
+                        if *routing_only {
+                            format!("~{}", domain)
+                        } else {
+                            domain.clone()
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",");
+                write!(
+                    f,
+                    "systemd-resolved-saved {} servers={} domains={}",
+                    interface_name, servers, domains
+                )
+            }
+        }
+    }
+}
+
+impl Leftover {
+    fn parse(line: &str) -> Option<Self> {
+        let mut words = line.split_whitespace();
+        let leftover = match words.next()? {
+            "static-file" => Leftover::StaticResolvConf,
+            "resolvconf" => Leftover::ResolvconfRecord(words.next()?.to_owned()),
+            "network-manager" => Leftover::NetworkManager,
+            "systemd-resolved" => Leftover::SystemdResolvedLink(words.next()?.to_owned()),
+            "systemd-resolved-saved" => {
+                let interface_name = words.next()?.to_owned();
+                let servers = split_list(parse_field(words.next()?, "servers")?)
+                    .map(|server| server.parse().ok())
+                    .collect::<Option<Vec<_>>>()?;
+                let domains = split_list(parse_field(words.next()?, "domains")?)
+                    .map(|domain| {
+                        if domain.starts_with('~') {
+                            (domain[1..].to_owned(), true)
+                        } else {
+                            (domain.to_owned(), false)
+                        }
+                    })
+                    .collect();
+                Leftover::SystemdResolvedSavedLink {
+                    interface_name,
+                    servers,
+                    domains,
+                }
+            }
+            _ => return None,
+        };
+        Some(leftover)
+    }
+}
+
+fn parse_field<'a>(word: &'a str, name: &str) -> Option<&'a str> {
+    let mut parts = word.splitn(2, '=');
+    if parts.next()? == name {
+        parts.next()
+    } else {
+        None
+    }
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').filter(|item| !item.is_empty())
+}
+
+/// Writes the changes to the given file, replacing any changes that were stored there before.
+pub fn write(path: &Path, leftovers: &[Leftover]) -> io::Result<()> {
+    let mut file = fs::File::create(path)?;
+    for leftover in leftovers {
+        writeln!(file, "{}", leftover)?;
+    }
+    file.sync_all()
+}
+
+/// Reads the changes stored in the given file. Lines that can't be parsed are skipped.
+pub fn read(path: &Path) -> io::Result<Vec<Leftover>> {
+    let contents = fs::read_to_string(path)?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| {
+            let leftover = Leftover::parse(line);
+            if leftover.is_none() {
+                log::warn!("Ignoring invalid DNS state backup entry: {}", line);
+            }
+            leftover
+        })
+        .collect())
+}
+
+/// Removes the file with the stored changes, if it exists.
+pub fn remove(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_formatted_leftovers() {
+        let leftovers = vec![
+            Leftover::StaticResolvConf,
+            Leftover::ResolvconfRecord("wg0-mullvad.mullvad".to_owned()),
+            Leftover::NetworkManager,
+            Leftover::SystemdResolvedLink("wg0-mullvad".to_owned()),
+            Leftover::SystemdResolvedSavedLink {
+                interface_name: "eth0".to_owned(),
+                servers: vec!["192.168.1.1".parse().unwrap(), "fd00::1".parse().unwrap()],
+                domains: vec![("lan".to_owned(), false), ("corp".to_owned(), true)],
+            },
+            Leftover::SystemdResolvedSavedLink {
+                interface_name: "eth1".to_owned(),
+                servers: vec![],
+                domains: vec![],
+            },
+        ];
+
+        for leftover in leftovers {
+            assert_eq!(Leftover::parse(&leftover.to_string()), Some(leftover));
+        }
+    }
+}
@@ -1,22 +1,39 @@
+mod leftovers;
 mod network_manager;
 mod resolvconf;
 mod static_resolv_conf;
+mod stub;
 mod systemd_resolved;
 
 use self::{
-    network_manager::NetworkManager, resolvconf::Resolvconf, static_resolv_conf::StaticResolvConf,
-    systemd_resolved::SystemdResolved,
+    leftovers::Leftover, network_manager::NetworkManager, resolvconf::Resolvconf,
+    static_resolv_conf::StaticResolvConf, stub::StubResolver, systemd_resolved::SystemdResolved,
 };
-use std::{env, fmt, net::IpAddr, path::Path};
+use super::LastApplied;
+use error_chain::ChainedError;
+use std::{
+    env, fmt, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+use talpid_types::net::dns::{EncryptedDnsUpstream, SplitDns};
 
 
 const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+const DNS_STATE_FILENAME: &str = "dns-state-backup";
 
 error_chain! {
     errors {
         NoDnsMonitor {
             description("No suitable DNS monitor implementation detected")
         }
+        SplitDnsUnsupported(dns_monitor: String) {
+            description("Split DNS is not supported by the DNS monitor")
+            display("Split DNS is only supported with systemd-resolved, not {}", dns_monitor)
+        }
+        RestoreLeftoversError {
+            description("Failed to restore DNS settings left by a previous run")
+        }
     }
 
     links {
@@ -24,25 +41,51 @@ error_chain! {
         StaticResolvConf(static_resolv_conf::Error, static_resolv_conf::ErrorKind);
         SystemdResolved(systemd_resolved::Error, systemd_resolved::ErrorKind);
         NetworkManager(network_manager::Error, network_manager::ErrorKind);
+        Stub(stub::Error, stub::ErrorKind);
     }
 }
 
 pub struct DnsMonitor {
     inner: Option<DnsMonitorHolder>,
+    encrypted_upstream: Option<EncryptedDnsUpstream>,
+    last_applied: LastApplied,
+    /// File where the changes made to the system DNS settings are stored while they are applied.
+    state_path: PathBuf,
 }
 
 impl super::DnsMonitorT for DnsMonitor {
     type Error = Error;
 
-    fn new(_cache_dir: impl AsRef<Path>) -> Result<Self> {
-        Ok(DnsMonitor { inner: None })
+    fn new(cache_dir: impl AsRef<Path>, last_applied: LastApplied) -> Result<Self> {
+        let state_path = cache_dir.as_ref().join(DNS_STATE_FILENAME);
+        if let Err(error) = restore_leftovers(&state_path) {
+            log::error!("{}", error.display_chain());
+        }
+        Ok(DnsMonitor {
+            inner: None,
+            encrypted_upstream: None,
+            last_applied,
+            state_path,
+        })
     }
 
-    fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
+    fn set(&mut self, interface: &str, servers: &[IpAddr], split_dns: &SplitDns) -> Result<()> {
         self.reset()?;
         // Creating a new DNS monitor for each set, in case the system changed how it manages DNS.
-        let mut inner = DnsMonitorHolder::new()?;
-        inner.set(interface, servers)?;
+        let mut inner = match self.encrypted_upstream {
+            Some(ref upstream) => DnsMonitorHolder::Stub(
+                StubResolver::start(upstream.clone())?,
+                Box::new(DnsMonitorHolder::new(&self.last_applied)?),
+            ),
+            None => DnsMonitorHolder::new(&self.last_applied)?,
+        };
+        inner.set(interface, servers, split_dns)?;
+        if let Err(error) = leftovers::write(&self.state_path, &inner.leftovers()) {
+            log::error!(
+                "{}",
+                Error::with_chain(error, "Failed to save the DNS state backup").display_chain()
+            );
+        }
         self.inner = Some(inner);
         Ok(())
     }
@@ -50,9 +93,25 @@ impl super::DnsMonitorT for DnsMonitor {
     fn reset(&mut self) -> Result<()> {
         if let Some(mut inner) = self.inner.take() {
             inner.reset()?;
+            if let Err(error) = leftovers::remove(&self.state_path) {
+                log::warn!("Failed to remove DNS state backup file: {}", error);
+            }
         }
         Ok(())
     }
+
+    fn set_encrypted_upstream(&mut self, upstream: Option<EncryptedDnsUpstream>) -> Result<()> {
+        // Takes effect the next time DNS is set, since that recreates the DNS monitor.
+        self.encrypted_upstream = upstream;
+        Ok(())
+    }
+
+    fn backend_name(&self) -> String {
+        match self.inner {
+            Some(ref inner) => inner.to_string(),
+            None => "none".to_owned(),
+        }
+    }
 }
 
 pub enum DnsMonitorHolder {
@@ -60,6 +119,8 @@ pub enum DnsMonitorHolder {
     StaticResolvConf(StaticResolvConf),
     SystemdResolved(SystemdResolved),
     NetworkManager(NetworkManager),
+    /// Runs a local stub resolver and points the system DNS at it, via another DNS monitor.
+    Stub(StubResolver, Box<DnsMonitorHolder>),
 }
 
 impl fmt::Display for DnsMonitorHolder {
@@ -70,50 +131,95 @@ impl fmt::Display for DnsMonitorHolder {
             StaticResolvConf(..) => "/etc/resolv.conf",
             SystemdResolved(..) => "systemd-resolved",
             NetworkManager(..) => "network manager",
+            Stub(stub, system) => {
+                return write!(
+                    f,
+                    "stub resolver forwarding to {} via {}",
+                    stub.upstream(),
+                    system
+                );
+            }
         };
         f.write_str(name)
     }
 }
 
 impl DnsMonitorHolder {
-    fn new() -> Result<Self> {
+    fn new(last_applied: &LastApplied) -> Result<Self> {
         let dns_module = env::var_os("TALPID_DNS_MODULE");
 
         let manager = match dns_module.as_ref().and_then(|value| value.to_str()) {
-            Some("static-file") => DnsMonitorHolder::StaticResolvConf(StaticResolvConf::new()?),
-            Some("resolvconf") => DnsMonitorHolder::Resolvconf(Resolvconf::new()?),
+            Some("static-file") => {
+                DnsMonitorHolder::StaticResolvConf(StaticResolvConf::new(last_applied.clone())?)
+            }
+            Some("resolvconf") => {
+                DnsMonitorHolder::Resolvconf(Resolvconf::new(last_applied.clone())?)
+            }
             Some("systemd") => DnsMonitorHolder::SystemdResolved(SystemdResolved::new()?),
-            Some("network-manager") => DnsMonitorHolder::NetworkManager(NetworkManager::new()?),
-            Some(_) | None => Self::with_detected_dns_manager()?,
+            Some("network-manager") => {
+                DnsMonitorHolder::NetworkManager(NetworkManager::new(last_applied.clone())?)
+            }
+            Some(_) | None => Self::with_detected_dns_manager(last_applied)?,
         };
         log::debug!("Managing DNS via {}", manager);
         Ok(manager)
     }
 
-    fn with_detected_dns_manager() -> Result<Self> {
+    fn with_detected_dns_manager(last_applied: &LastApplied) -> Result<Self> {
         SystemdResolved::new()
             .map(DnsMonitorHolder::SystemdResolved)
-            .or_else(|_| NetworkManager::new().map(DnsMonitorHolder::NetworkManager))
-            .or_else(|_| Resolvconf::new().map(DnsMonitorHolder::Resolvconf))
-            .or_else(|_| StaticResolvConf::new().map(DnsMonitorHolder::StaticResolvConf))
+            .or_else(|_| {
+                NetworkManager::new(last_applied.clone()).map(DnsMonitorHolder::NetworkManager)
+            })
+            .or_else(|_| Resolvconf::new(last_applied.clone()).map(DnsMonitorHolder::Resolvconf))
+            .or_else(|_| {
+                StaticResolvConf::new(last_applied.clone()).map(DnsMonitorHolder::StaticResolvConf)
+            })
             .chain_err(|| ErrorKind::NoDnsMonitor)
     }
 
-    fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
+    fn set(&mut self, interface: &str, servers: &[IpAddr], split_dns: &SplitDns) -> Result<()> {
         use self::DnsMonitorHolder::*;
+        if !split_dns.is_empty() && !self.supports_split_dns() {
+            bail!(ErrorKind::SplitDnsUnsupported(self.to_string()));
+        }
         match self {
             Resolvconf(ref mut resolvconf) => resolvconf.set_dns(interface, servers)?,
             StaticResolvConf(ref mut static_resolv_conf) => {
                 static_resolv_conf.set_dns(servers.to_vec())?
             }
             SystemdResolved(ref mut systemd_resolved) => {
-                systemd_resolved.set_dns(interface, &servers)?
+                systemd_resolved.set_dns(interface, &servers, split_dns)?
             }
             NetworkManager(ref mut network_manager) => network_manager.set_dns(servers)?,
+            // The stub resolver forwards all queries to its upstream, so the given servers are
+            // only allowed through the firewall.
+            Stub(_, ref mut system) => system.set(interface, &[stub::address()], split_dns)?,
         }
         Ok(())
     }
 
+    /// Only systemd-resolved can route queries for some domains to other DNS servers.
+    fn supports_split_dns(&self) -> bool {
+        match self {
+            DnsMonitorHolder::SystemdResolved(..) => true,
+            DnsMonitorHolder::Stub(_, system) => system.supports_split_dns(),
+            _ => false,
+        }
+    }
+
+    /// The changes made to the system DNS settings by this monitor.
+    fn leftovers(&self) -> Vec<Leftover> {
+        use self::DnsMonitorHolder::*;
+        match self {
+            Resolvconf(ref resolvconf) => resolvconf.leftovers(),
+            StaticResolvConf(..) => vec![Leftover::StaticResolvConf],
+            SystemdResolved(ref systemd_resolved) => systemd_resolved.leftovers(),
+            NetworkManager(..) => vec![Leftover::NetworkManager],
+            Stub(_, ref system) => system.leftovers(),
+        }
+    }
+
     fn reset(&mut self) -> Result<()> {
         use self::DnsMonitorHolder::*;
         match self {
@@ -121,7 +227,47 @@ impl DnsMonitorHolder {
             StaticResolvConf(ref mut static_resolv_conf) => static_resolv_conf.reset()?,
             SystemdResolved(ref mut systemd_resolved) => systemd_resolved.reset()?,
             NetworkManager(ref mut network_manager) => network_manager.reset()?,
+            Stub(_, ref mut system) => system.reset()?,
         }
         Ok(())
     }
 }
+
+/// Undoes the changes to the system DNS settings that a previous run stored in the state file,
+/// since they were never reset.
+fn restore_leftovers(state_path: &Path) -> Result<()> {
+    let leftovers = match leftovers::read(state_path) {
+        Ok(leftovers) => leftovers,
+        Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(Error::with_chain(error, ErrorKind::RestoreLeftoversError)),
+    };
+
+    log::info!("Restoring DNS settings left by a previous run");
+    for leftover in leftovers {
+        if let Err(error) = restore_leftover(&leftover) {
+            let chained_error =
+                error.chain_err(|| format!("Failed to undo DNS change: {}", leftover));
+            log::error!("{}", chained_error.display_chain());
+        }
+    }
+    leftovers::remove(state_path).chain_err(|| ErrorKind::RestoreLeftoversError)
+}
+
+fn restore_leftover(leftover: &Leftover) -> Result<()> {
+    match leftover {
+        Leftover::StaticResolvConf => static_resolv_conf::restore_from_backup()?,
+        Leftover::ResolvconfRecord(record_name) => {
+            Resolvconf::new(LastApplied::default())?.delete_record(record_name)?
+        }
+        Leftover::NetworkManager => network_manager::reset_global_dns()?,
+        Leftover::SystemdResolvedLink(interface_name) => {
+            SystemdResolved::new()?.revert_interface(interface_name)?
+        }
+        Leftover::SystemdResolvedSavedLink {
+            interface_name,
+            servers,
+            domains,
+        } => SystemdResolved::new()?.restore_interface(interface_name, servers, domains)?,
+    }
+    Ok(())
+}
@@ -1,7 +1,8 @@
+use super::LastApplied;
 use dbus::{
     arg::{RefArg, Variant},
     stdintf::*,
-    BusType,
+    BusType, ConnectionItem,
 };
 use error_chain::ChainedError;
 use std::{
@@ -10,6 +11,11 @@ use std::{
     io::{BufRead, BufReader},
     net::IpAddr,
     path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
 };
 
 error_chain! {
@@ -38,16 +44,27 @@ const NM_OBJECT_PATH: &str = "/org/freedesktop/NetworkManager";
 const RPC_TIMEOUT_MS: i32 = 1000;
 const GLOBAL_DNS_CONF_KEY: &str = "GlobalDnsConfiguration";
 const RC_MANAGEMENT_MODE_KEY: &str = "RcManager";
+const PROPERTIES_CHANGED_MATCH: &str = "type='signal',member='PropertiesChanged',\
+                                        path='/org/freedesktop/NetworkManager'";
+/// How long the watchdog waits for a signal before checking if it should stop.
+const WATCHDOG_POLL_TIMEOUT_MS: i32 = 1000;
 
 pub struct NetworkManager {
     dbus_connection: dbus::Connection,
+    desired_dns: Arc<Mutex<Option<Vec<IpAddr>>>>,
+    _watchdog: DnsWatchdog,
 }
 
 
 impl NetworkManager {
-    pub fn new() -> Result<Self> {
+    pub fn new(last_applied: LastApplied) -> Result<Self> {
         let dbus_connection = dbus::Connection::get_private(BusType::System)?;
-        let manager = NetworkManager { dbus_connection };
+        let desired_dns = Arc::new(Mutex::new(None));
+        let manager = NetworkManager {
+            dbus_connection,
+            desired_dns: desired_dns.clone(),
+            _watchdog: DnsWatchdog::start(desired_dns, last_applied),
+        };
         manager.ensure_network_manager_exists()?;
         manager.ensure_resolv_conf_is_managed()?;
         Ok(manager)
@@ -92,23 +109,137 @@ impl NetworkManager {
     }
 
     fn as_manager(&self) -> dbus::ConnPath<&dbus::Connection> {
-        self.dbus_connection
-            .with_path(NM_BUS, NM_OBJECT_PATH, RPC_TIMEOUT_MS)
+        as_manager(&self.dbus_connection)
     }
 
     pub fn set_dns(&mut self, servers: &[IpAddr]) -> Result<()> {
-        self.set_global_dns(create_global_settings(servers))
+        let mut desired_dns = self
+            .desired_dns
+            .lock()
+            .expect("DNS watchdog thread panicked");
+        set_global_dns(&self.dbus_connection, create_global_settings(servers))?;
+        *desired_dns = Some(servers.to_vec());
+        Ok(())
     }
 
-    fn set_global_dns(&mut self, config: GlobalDnsConfig) -> Result<()> {
-        self.as_manager()
-            .set(NM_TOP_OBJECT, GLOBAL_DNS_CONF_KEY, config)
-            .map_err(|e| e.into())
+    pub fn reset(&mut self) -> Result<()> {
+        let mut desired_dns = self
+            .desired_dns
+            .lock()
+            .expect("DNS watchdog thread panicked");
+        *desired_dns = None;
+        set_global_dns(&self.dbus_connection, create_empty_global_settings())
     }
+}
 
-    pub fn reset(&mut self) -> Result<()> {
-        self.set_global_dns(create_empty_global_settings())
+/// Listens for changes to the NetworkManager DNS configuration and re-applies the desired DNS
+/// servers if another program changed them.
+struct DnsWatchdog {
+    stop: Arc<AtomicBool>,
+}
+
+impl DnsWatchdog {
+    fn start(desired_dns: Arc<Mutex<Option<Vec<IpAddr>>>>, last_applied: LastApplied) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        thread::spawn(move || {
+            if let Err(error) = Self::run(&desired_dns, &last_applied, &thread_stop) {
+                let chained_error = error.chain_err(|| "NetworkManager DNS watchdog failed");
+                log::error!("{}", chained_error.display_chain());
+            }
+        });
+        DnsWatchdog { stop }
+    }
+
+    fn run(
+        desired_dns: &Mutex<Option<Vec<IpAddr>>>,
+        last_applied: &LastApplied,
+        stop: &AtomicBool,
+    ) -> Result<()> {
+        let dbus_connection = dbus::Connection::get_private(BusType::System)?;
+        dbus_connection.add_match(PROPERTIES_CHANGED_MATCH)?;
+
+        let mut reapply_count = 0u64;
+        for item in dbus_connection.iter(WATCHDOG_POLL_TIMEOUT_MS) {
+            if stop.load(Ordering::SeqCst) {
+                break;
+            }
+            if let ConnectionItem::Signal(_) = item {
+                let desired_dns = desired_dns.lock().expect("DNS monitor thread panicked");
+                if let Some(ref servers) = *desired_dns {
+                    if Self::reassert(&dbus_connection, servers)? {
+                        last_applied.update();
+                        reapply_count += 1;
+                        log::warn!(
+                            "DNS settings were changed by another program, re-applied them via \
+                             NetworkManager ({} times so far)",
+                            reapply_count
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the desired servers again if the current global DNS configuration differs. Returns
+    /// whether the configuration was re-applied.
+    fn reassert(dbus_connection: &dbus::Connection, servers: &[IpAddr]) -> Result<bool> {
+        let config: Box<RefArg> =
+            as_manager(dbus_connection).get(NM_TOP_OBJECT, GLOBAL_DNS_CONF_KEY)?;
+        let desired_servers = servers.iter().map(ToString::to_string).collect::<Vec<_>>();
+        if get_global_servers(&*config).as_ref() == Some(&desired_servers) {
+            return Ok(false);
+        }
+        set_global_dns(dbus_connection, create_global_settings(servers))?;
+        Ok(true)
+    }
+}
+
+impl Drop for DnsWatchdog {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Clears the global DNS configuration, which may have been set by another instance.
+pub fn reset_global_dns() -> Result<()> {
+    let dbus_connection = dbus::Connection::get_private(BusType::System)?;
+    set_global_dns(&dbus_connection, create_empty_global_settings())
+}
+
+fn as_manager(dbus_connection: &dbus::Connection) -> dbus::ConnPath<&dbus::Connection> {
+    dbus_connection.with_path(NM_BUS, NM_OBJECT_PATH, RPC_TIMEOUT_MS)
+}
+
+fn set_global_dns(dbus_connection: &dbus::Connection, config: GlobalDnsConfig) -> Result<()> {
+    as_manager(dbus_connection)
+        .set(NM_TOP_OBJECT, GLOBAL_DNS_CONF_KEY, config)
+        .map_err(|e| e.into())
+}
+
+/// Returns the servers used for all domains in a global DNS configuration.
+fn get_global_servers(config: &RefArg) -> Option<Vec<String>> {
+    let servers = get_dict_value(
+        get_dict_value(get_dict_value(config, "domains")?, "*")?,
+        "servers",
+    )?;
+    servers
+        .as_iter()?
+        .map(|server| server.as_str().map(str::to_owned))
+        .collect()
+}
+
+/// Returns the value inside the variant stored under `key` in a dictionary of variants.
+fn get_dict_value<'a>(dict: &'a RefArg, key: &str) -> Option<&'a RefArg> {
+    let mut items = dict.as_iter()?;
+    while let Some(item_key) = items.next() {
+        let value = items.next()?;
+        if item_key.as_str() == Some(key) {
+            return value.as_iter()?.next();
+        }
     }
+    None
 }
 
 type GlobalDnsConfig = HashMap<&'static str, Variant<Box<RefArg>>>;
@@ -1,12 +1,20 @@
+use super::{leftovers::Leftover, LastApplied};
+use error_chain::ChainedError;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use std::{
-    collections::HashSet,
+    collections::HashMap,
     ffi::OsStr,
     fs,
     net::IpAddr,
     path::{Path, PathBuf},
+    sync::{mpsc, Arc, Mutex, MutexGuard},
+    thread,
 };
 use which::which;
 
+/// Directories where the different `resolvconf` implementations store the added records.
+const RECORD_DIRS: &[&str] = &["/run/resolvconf/interface", "/run/resolvconf/interfaces"];
+
 error_chain! {
     errors {
         NoResolvconf {
@@ -25,24 +33,34 @@ error_chain! {
         DeleteRecordError {
             description("Using 'resolvconf' to delete a record failed")
         }
+        WatchRecord {
+            description("Failed to watch 'resolvconf' records for changes")
+        }
     }
 }
 
+/// The servers in each record added by this instance.
+type Records = Arc<Mutex<HashMap<String, Vec<IpAddr>>>>;
+
 pub struct Resolvconf {
-    record_names: HashSet<String>,
+    records: Records,
     resolvconf: PathBuf,
+    last_applied: LastApplied,
+    watcher: Option<DnsWatcher>,
 }
 
 impl Resolvconf {
-    pub fn new() -> Result<Self> {
+    pub fn new(last_applied: LastApplied) -> Result<Self> {
         let resolvconf_path =
             which("resolvconf").map_err(|_| Error::from(ErrorKind::NoResolvconf))?;
         if Self::resolvconf_is_resolved_symlink(&resolvconf_path) {
             bail!(ErrorKind::ResolvconfUsesResolved);
         }
         Ok(Resolvconf {
-            record_names: HashSet::new(),
+            records: Arc::new(Mutex::new(HashMap::new())),
             resolvconf: resolvconf_path,
+            last_applied,
+            watcher: None,
         })
     }
 
@@ -56,51 +74,203 @@ impl Resolvconf {
 
     pub fn set_dns(&mut self, interface: &str, servers: &[IpAddr]) -> Result<()> {
         let record_name = format!("{}.mullvad", interface);
-        let mut record_contents = String::new();
+        let mut records = lock_records(&self.records);
+
+        add_record(&self.resolvconf, &record_name, servers)?;
+
+        if self.watcher.is_none() {
+            self.watcher = DnsWatcher::start(
+                &record_name,
+                &self.resolvconf,
+                self.records.clone(),
+                self.last_applied.clone(),
+            );
+        }
+        records.insert(record_name, servers.to_vec());
+
+        Ok(())
+    }
+
+    pub fn reset(&mut self) -> Result<()> {
+        let mut result = Ok(());
 
-        for address in servers {
-            record_contents.push_str("nameserver ");
-            record_contents.push_str(&address.to_string());
-            record_contents.push('\n');
+        for (record_name, _) in lock_records(&self.records).drain() {
+            if let Err(error) = self.delete_record(&record_name) {
+                result = Err(error);
+            }
         }
 
-        let output = duct::cmd!(&self.resolvconf, "-a", &record_name)
-            .input(record_contents)
+        result
+    }
+
+    /// Deletes a record, which may have been added by another instance.
+    pub fn delete_record(&self, record_name: &str) -> Result<()> {
+        let output = duct::cmd!(&self.resolvconf, "-d", record_name)
             .stderr_capture()
             .unchecked()
             .run()
             .chain_err(|| ErrorKind::RunResolvconf)?;
 
-        ensure!(
-            output.status.success(),
-            ErrorKind::AddRecordError(String::from_utf8_lossy(&output.stderr).to_string())
-        );
+        if !output.status.success() {
+            log::error!(
+                "Failed to delete 'resolvconf' record '{}':\n{}",
+                record_name,
+                String::from_utf8_lossy(&output.stderr)
+            );
+            bail!(ErrorKind::DeleteRecordError);
+        }
+        Ok(())
+    }
+
+    pub fn leftovers(&self) -> Vec<Leftover> {
+        lock_records(&self.records)
+            .keys()
+            .map(|record_name| Leftover::ResolvconfRecord(record_name.clone()))
+            .collect()
+    }
+}
 
-        self.record_names.insert(record_name);
+/// Watches the records added with `resolvconf` and adds them again if another program removed
+/// or changed them.
+struct DnsWatcher {
+    _watcher: RecommendedWatcher,
+}
 
-        Ok(())
+impl DnsWatcher {
+    /// Starts watching the directory containing the given record. Failures are logged, since
+    /// DNS is still set without the watcher.
+    fn start(
+        record_name: &str,
+        resolvconf: &Path,
+        records: Records,
+        last_applied: LastApplied,
+    ) -> Option<Self> {
+        let record_dir = match RECORD_DIRS
+            .iter()
+            .map(Path::new)
+            .find(|dir| dir.join(record_name).exists())
+        {
+            Some(dir) => dir,
+            None => {
+                log::warn!("Unable to find 'resolvconf' records, not watching them for changes");
+                return None;
+            }
+        };
+
+        match Self::watch(record_dir, resolvconf.to_owned(), records, last_applied) {
+            Ok(watcher) => Some(watcher),
+            Err(error) => {
+                log::error!("{}", error.display_chain());
+                None
+            }
+        }
     }
 
-    pub fn reset(&mut self) -> Result<()> {
-        let mut result = Ok(());
+    fn watch(
+        record_dir: &Path,
+        resolvconf: PathBuf,
+        records: Records,
+        last_applied: LastApplied,
+    ) -> Result<Self> {
+        let (event_tx, event_rx) = mpsc::channel();
+        let mut watcher = notify::raw_watcher(event_tx).chain_err(|| ErrorKind::WatchRecord)?;
 
-        for record_name in self.record_names.drain() {
-            let output = duct::cmd!(&self.resolvconf, "-d", &record_name)
-                .stderr_capture()
-                .unchecked()
-                .run()
-                .chain_err(|| ErrorKind::RunResolvconf)?;
-
-            if !output.status.success() {
-                log::error!(
-                    "Failed to delete 'resolvconf' record '{}':\n{}",
-                    record_name,
-                    String::from_utf8_lossy(&output.stderr)
-                );
-                result = Err(Error::from(ErrorKind::DeleteRecordError));
+        watcher
+            .watch(record_dir, RecursiveMode::NonRecursive)
+            .chain_err(|| ErrorKind::WatchRecord)?;
+
+        thread::spawn(move || Self::event_loop(event_rx, &resolvconf, &records, &last_applied));
+
+        Ok(DnsWatcher { _watcher: watcher })
+    }
+
+    fn event_loop(
+        events: mpsc::Receiver<notify::RawEvent>,
+        resolvconf: &Path,
+        records: &Records,
+        last_applied: &LastApplied,
+    ) {
+        let mut reapply_count = 0u64;
+        for event in events {
+            let path = match event.path {
+                Some(path) => path,
+                None => continue,
+            };
+            let record_name = match path.file_name().and_then(OsStr::to_str) {
+                Some(record_name) => record_name,
+                None => continue,
+            };
+
+            let records = lock_records(records);
+            if let Some(servers) = records.get(record_name) {
+                if read_record_servers(&path) == *servers {
+                    continue;
+                }
+                match add_record(resolvconf, record_name, servers) {
+                    Ok(()) => {
+                        last_applied.update();
+                        reapply_count += 1;
+                        log::warn!(
+                            "DNS settings were changed by another program, re-applied them via \
+                             resolvconf ({} times so far)",
+                            reapply_count
+                        );
+                    }
+                    Err(error) => {
+                        let chained_error = error
+                            .chain_err(|| "Failed to re-apply DNS settings after they changed");
+                        log::error!("{}", chained_error.display_chain());
+                    }
+                }
             }
         }
+    }
+}
 
-        result
+fn lock_records(records: &Records) -> MutexGuard<HashMap<String, Vec<IpAddr>>> {
+    records
+        .lock()
+        .expect("a thread panicked while using the DNS configuration state")
+}
+
+fn add_record(resolvconf: &Path, record_name: &str, servers: &[IpAddr]) -> Result<()> {
+    let mut record_contents = String::new();
+
+    for address in servers {
+        record_contents.push_str("nameserver ");
+        record_contents.push_str(&address.to_string());
+        record_contents.push('\n');
     }
+
+    let output = duct::cmd!(resolvconf, "-a", record_name)
+        .input(record_contents)
+        .stderr_capture()
+        .unchecked()
+        .run()
+        .chain_err(|| ErrorKind::RunResolvconf)?;
+
+    ensure!(
+        output.status.success(),
+        ErrorKind::AddRecordError(String::from_utf8_lossy(&output.stderr).to_string())
+    );
+
+    Ok(())
+}
+
+/// Returns the servers in a record file, or nothing if it could not be read.
+fn read_record_servers(path: &Path) -> Vec<IpAddr> {
+    fs::read_to_string(path)
+        .map(|contents| {
+            contents
+                .lines()
+                .filter_map(|line| {
+                    let mut words = line.split_whitespace();
+                    match (words.next(), words.next()) {
+                        (Some("nameserver"), Some(address)) => address.parse().ok(),
+                        _ => None,
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_else(|_| Vec::new())
 }
@@ -1,4 +1,4 @@
-use super::RESOLV_CONF_PATH;
+use super::{LastApplied, RESOLV_CONF_PATH};
 use error_chain::ChainedError;
 use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use resolv_conf::{Config, ScopedIp};
@@ -37,11 +37,11 @@ pub struct StaticResolvConf {
 }
 
 impl StaticResolvConf {
-    pub fn new() -> Result<Self> {
+    pub fn new(last_applied: LastApplied) -> Result<Self> {
         restore_from_backup().chain_err(|| ErrorKind::RestoreResolvConf)?;
 
         let state = Arc::new(Mutex::new(None));
-        let watcher = DnsWatcher::start(state.clone())?;
+        let watcher = DnsWatcher::start(state.clone(), last_applied)?;
 
         Ok(StaticResolvConf {
             state,
@@ -114,7 +114,7 @@ struct DnsWatcher {
 }
 
 impl DnsWatcher {
-    fn start(state: Arc<Mutex<Option<State>>>) -> Result<Self> {
+    fn start(state: Arc<Mutex<Option<State>>>, last_applied: LastApplied) -> Result<Self> {
         let (event_tx, event_rx) = mpsc::channel();
         let mut watcher = notify::raw_watcher(event_tx).chain_err(|| ErrorKind::WatchResolvConf)?;
 
@@ -122,18 +122,22 @@ impl DnsWatcher {
             .watch(RESOLV_CONF_PATH, RecursiveMode::NonRecursive)
             .chain_err(|| ErrorKind::WatchResolvConf)?;
 
-        thread::spawn(move || Self::event_loop(event_rx, &state));
+        thread::spawn(move || Self::event_loop(event_rx, &state, &last_applied));
 
         Ok(DnsWatcher { _watcher: watcher })
     }
 
-    fn event_loop(events: mpsc::Receiver<notify::RawEvent>, state: &Arc<Mutex<Option<State>>>) {
+    fn event_loop(
+        events: mpsc::Receiver<notify::RawEvent>,
+        state: &Arc<Mutex<Option<State>>>,
+        last_applied: &LastApplied,
+    ) {
         for _ in events {
             let mut locked_state = state
                 .lock()
                 .expect("a thread panicked while using the DNS configuration state");
 
-            if let Err(error) = Self::update(locked_state.as_mut()) {
+            if let Err(error) = Self::update(locked_state.as_mut(), last_applied) {
                 let chained_error = error
                     .chain_err(|| "Failed to update DNS state after DNS settings have changed.");
                 log::error!("{}", chained_error.display_chain());
@@ -141,7 +145,7 @@ impl DnsWatcher {
         }
     }
 
-    fn update(state: Option<&mut State>) -> Result<()> {
+    fn update(state: Option<&mut State>, last_applied: &LastApplied) -> Result<()> {
         if let Some(state) = state {
             let mut new_config = read_config()?;
             let desired_nameservers = state
@@ -154,7 +158,9 @@ impl DnsWatcher {
                 state.backup = new_config.clone();
                 new_config.nameservers = desired_nameservers;
 
-                write_config(&new_config)
+                write_config(&new_config)?;
+                last_applied.update();
+                Ok(())
             } else {
                 new_config.nameservers.clear();
                 new_config.nameservers.append(&mut state.backup.nameservers);
@@ -187,7 +193,8 @@ fn write_backup(backup: &Config) -> Result<()> {
         .chain_err(|| "Failed to write to /etc/resolv.conf backup file")
 }
 
-fn restore_from_backup() -> Result<()> {
+/// Restores `/etc/resolv.conf` from the backup, if there is one.
+pub fn restore_from_backup() -> Result<()> {
     match fs::read_to_string(RESOLV_CONF_BACKUP_PATH) {
         Ok(backup) => {
             log::info!("Restoring DNS state from backup");
@@ -0,0 +1,729 @@
+use futures::{
+    future::{self, Either},
+    Future, Stream,
+};
+use hyper::{client::Client, Body, Method, Request, StatusCode, Uri};
+use openssl::ssl::{SslConnector, SslMethod, SslStream};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::{
+    io::{self, Read, Write},
+    mem,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket},
+    os::unix::io::AsRawFd,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use talpid_types::net::{dns::EncryptedDnsUpstream, OWN_TRAFFIC_FWMARK};
+use tokio_core::{
+    net::TcpStream as AsyncTcpStream,
+    reactor::{Core, Handle, Timeout},
+};
+use tokio_openssl::SslConnectorExt;
+use tokio_service::Service;
+
+const DNS_PORT: u16 = 53;
+const MAX_MESSAGE_SIZE: usize = 65535;
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the listening threads check if they should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Number of threads forwarding queries, each with its own connection to the upstream server.
+const WORKER_COUNT: usize = 4;
+/// How many queries can wait for a worker. Queries received while the queue is full are dropped.
+const MAX_QUEUED_QUERIES: usize = 64;
+/// How many TCP clients can be connected at once. Clients only fall back to TCP for responses that
+/// don't fit in a UDP message, so few connections are expected.
+const MAX_TCP_CONNECTIONS: usize = 16;
+/// How long a TCP client can be connected without sending a query.
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest response that is sent over UDP to clients that don't advertise a size with EDNS.
+const MIN_UDP_PAYLOAD_SIZE: usize = 512;
+const DNS_HEADER_SIZE: usize = 12;
+const OPT_RECORD_TYPE: u16 = 41;
+/// The TC bit in the third byte of the header.
+const TRUNCATED_FLAG: u8 = 0x02;
+const DNS_MESSAGE_CONTENT_TYPE: &str = "application/dns-message";
+
+error_chain! {
+    errors {
+        BindError {
+            description("Failed to bind the DNS stub resolver sockets")
+        }
+        TlsError {
+            description("Failed to initialize TLS for the DNS stub resolver")
+        }
+        EventLoopError {
+            description("Failed to create the event loop for DNS-over-HTTPS requests")
+        }
+        InvalidUri(uri: String) {
+            description("Invalid DNS-over-HTTPS URI")
+            display("Invalid DNS-over-HTTPS URI: {}", uri)
+        }
+    }
+}
+
+/// Returns the address that the stub resolver listens on.
+pub fn address() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(127, 0, 0, 60))
+}
+
+/// A DNS stub resolver that listens for plain DNS queries over UDP and TCP on the loopback
+/// interface, and forwards them to an upstream server over DNS-over-HTTPS or DNS-over-TLS.
+pub struct StubResolver {
+    upstream: EncryptedDnsUpstream,
+    stop: Arc<AtomicBool>,
+    tcp_listener: TcpListener,
+    listener_threads: Vec<thread::JoinHandle<()>>,
+    worker_threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl StubResolver {
+    pub fn start(upstream: EncryptedDnsUpstream) -> Result<Self> {
+        let listen_address = SocketAddr::new(address(), DNS_PORT);
+        let udp_socket = UdpSocket::bind(listen_address).chain_err(|| ErrorKind::BindError)?;
+        udp_socket
+            .set_read_timeout(Some(POLL_INTERVAL))
+            .chain_err(|| ErrorKind::BindError)?;
+        let tcp_listener = TcpListener::bind(listen_address).chain_err(|| ErrorKind::BindError)?;
+        let connector = SslConnector::builder(SslMethod::tls())
+            .chain_err(|| ErrorKind::TlsError)?
+            .build();
+
+        let (query_tx, query_rx) = mpsc::sync_channel(MAX_QUEUED_QUERIES);
+        let query_rx = Arc::new(Mutex::new(query_rx));
+        // The HTTPS client runs on an event loop that can't be moved between threads, so each
+        // worker creates its own forwarder and reports back whether that succeeded.
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let mut worker_threads = Vec::with_capacity(WORKER_COUNT);
+        for _ in 0..WORKER_COUNT {
+            let response_socket = udp_socket.try_clone().chain_err(|| ErrorKind::BindError)?;
+            let worker_upstream = upstream.clone();
+            let connector = connector.clone();
+            let queries = query_rx.clone();
+            let ready_tx = ready_tx.clone();
+            worker_threads.push(thread::spawn(move || {
+                match Forwarder::new(&worker_upstream, connector) {
+                    Ok(forwarder) => {
+                        let _ = ready_tx.send(Ok(()));
+                        work(queries, forwarder, response_socket, worker_upstream)
+                    }
+                    Err(error) => {
+                        let _ = ready_tx.send(Err(error));
+                    }
+                }
+            }));
+        }
+        mem::drop(ready_tx);
+        for result in ready_rx.iter().take(WORKER_COUNT) {
+            result?;
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let udp_stop = stop.clone();
+        let udp_queries = query_tx.clone();
+        let tcp_stop = stop.clone();
+        let accepting_listener = tcp_listener
+            .try_clone()
+            .chain_err(|| ErrorKind::BindError)?;
+        let listener_threads = vec![
+            thread::spawn(move || listen_udp(udp_socket, udp_queries, udp_stop)),
+            thread::spawn(move || listen_tcp(accepting_listener, query_tx, tcp_stop)),
+        ];
+
+        log::info!(
+            "Started DNS stub resolver on {}, forwarding to {}",
+            address(),
+            upstream
+        );
+        Ok(StubResolver {
+            upstream,
+            stop,
+            tcp_listener,
+            listener_threads,
+            worker_threads,
+        })
+    }
+
+    pub fn upstream(&self) -> &EncryptedDnsUpstream {
+        &self.upstream
+    }
+}
+
+impl Drop for StubResolver {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Shutting down the listening socket wakes up the thread blocked on accepting connections
+        if unsafe { libc::shutdown(self.tcp_listener.as_raw_fd(), libc::SHUT_RD) } != 0 {
+            log::error!(
+                "Failed to shut down the DNS stub resolver TCP listener: {}",
+                io::Error::last_os_error()
+            );
+        }
+        // Wait for the sockets to be closed, so that a new stub resolver can bind to the address.
+        // The workers stop once the listener threads and TCP connections have stopped and they
+        // have no queries left.
+        let threads = self.listener_threads.drain(..);
+        for thread in threads.chain(self.worker_threads.drain(..)) {
+            if thread.join().is_err() {
+                log::error!("DNS stub resolver thread panicked");
+            }
+        }
+        log::info!("Stopped DNS stub resolver");
+    }
+}
+
+struct Query {
+    message: Vec<u8>,
+    source: Source,
+}
+
+/// Where a query came from, and so where to send its response.
+enum Source {
+    Udp(SocketAddr),
+    /// Queries received over the same connection can be answered by different workers, so the
+    /// stream is locked while a response is written.
+    Tcp(SocketAddr, Arc<Mutex<TcpStream>>),
+}
+
+impl Source {
+    fn address(&self) -> SocketAddr {
+        match *self {
+            Source::Udp(address) | Source::Tcp(address, _) => address,
+        }
+    }
+}
+
+/// Queues a query for the workers. Returns false if the workers have stopped.
+fn enqueue(queries: &SyncSender<Query>, query: Query) -> bool {
+    let client = query.source.address();
+    match queries.try_send(query) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            log::debug!(
+                "Dropping DNS query from {}, too many pending queries",
+                client
+            );
+            true
+        }
+        Err(TrySendError::Disconnected(_)) => {
+            log::error!("All DNS stub resolver workers have stopped");
+            false
+        }
+    }
+}
+
+fn listen_udp(socket: UdpSocket, queries: SyncSender<Query>, stop: Arc<AtomicBool>) {
+    let mut buffer = vec![0u8; MAX_MESSAGE_SIZE];
+    while !stop.load(Ordering::SeqCst) {
+        let (length, client) = match socket.recv_from(&mut buffer) {
+            Ok(result) => result,
+            Err(ref error) if is_timeout(error) => continue,
+            Err(error) => {
+                log::error!("Failed to receive DNS query: {}", error);
+                continue;
+            }
+        };
+        let query = Query {
+            message: buffer[..length].to_vec(),
+            source: Source::Udp(client),
+        };
+        if !enqueue(&queries, query) {
+            return;
+        }
+    }
+}
+
+fn listen_tcp(listener: TcpListener, queries: SyncSender<Query>, stop: Arc<AtomicBool>) {
+    let connection_count = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        if stop.load(Ordering::SeqCst) {
+            return;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                log::error!("Failed to accept DNS client connection: {}", error);
+                continue;
+            }
+        };
+        if connection_count.fetch_add(1, Ordering::SeqCst) >= MAX_TCP_CONNECTIONS {
+            connection_count.fetch_sub(1, Ordering::SeqCst);
+            log::debug!("Closing DNS client connection, too many open connections");
+            continue;
+        }
+
+        let connection_count = connection_count.clone();
+        let queries = queries.clone();
+        let stop = stop.clone();
+        thread::spawn(move || {
+            if let Err(error) = serve_tcp_client(stream, queries, stop) {
+                log::debug!("DNS client connection failed: {}", error);
+            }
+            connection_count.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Reads length prefixed queries from a TCP client until it disconnects or has been idle for too
+/// long.
+fn serve_tcp_client(
+    mut stream: TcpStream,
+    queries: SyncSender<Query>,
+    stop: Arc<AtomicBool>,
+) -> io::Result<()> {
+    let client = stream.peer_addr()?;
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    let response_stream = Arc::new(Mutex::new(stream.try_clone()?));
+
+    let mut received = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let mut last_activity = Instant::now();
+    while !stop.load(Ordering::SeqCst) {
+        match stream.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(length) => {
+                received.extend_from_slice(&buffer[..length]);
+                last_activity = Instant::now();
+                while let Some(message) = take_tcp_message(&mut received) {
+                    let query = Query {
+                        message,
+                        source: Source::Tcp(client, response_stream.clone()),
+                    };
+                    if !enqueue(&queries, query) {
+                        return Ok(());
+                    }
+                }
+            }
+            Err(ref error) if is_timeout(error) => {
+                if last_activity.elapsed() >= TCP_IDLE_TIMEOUT {
+                    return Ok(());
+                }
+            }
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::WouldBlock || error.kind() == io::ErrorKind::TimedOut
+}
+
+fn work(
+    queries: Arc<Mutex<Receiver<Query>>>,
+    mut forwarder: Forwarder,
+    socket: UdpSocket,
+    upstream: EncryptedDnsUpstream,
+) {
+    loop {
+        let query = match queries.lock().map(|queries| queries.recv()) {
+            Ok(Ok(query)) => query,
+            // The listeners have stopped, or another worker panicked
+            _ => return,
+        };
+        match forwarder.forward(&query.message) {
+            Ok(response) => {
+                if let Err(error) = respond(&socket, &query, &response) {
+                    log::warn!(
+                        "Failed to send DNS response to {}: {}",
+                        query.source.address(),
+                        error
+                    );
+                }
+            }
+            Err(error) => log::warn!("Failed to forward DNS query to {}: {}", upstream, error),
+        }
+    }
+}
+
+fn respond(socket: &UdpSocket, query: &Query, response: &[u8]) -> io::Result<()> {
+    match query.source {
+        Source::Udp(address) => {
+            if response.len() > max_udp_response_size(&query.message) {
+                let truncated = truncate_response(response)
+                    .ok_or_else(|| invalid_data("Malformed DNS response"))?;
+                socket.send_to(&truncated, address)?;
+            } else {
+                socket.send_to(response, address)?;
+            }
+            Ok(())
+        }
+        Source::Tcp(_, ref stream) => stream
+            .lock()
+            .expect("DNS client stream lock is poisoned")
+            .write_all(&length_prefixed(response)?),
+    }
+}
+
+/// Forwards queries to the upstream server, keeping connections open between queries.
+enum Forwarder {
+    Https(HttpsForwarder),
+    Tls(TlsForwarder),
+}
+
+impl Forwarder {
+    fn new(upstream: &EncryptedDnsUpstream, connector: SslConnector) -> Result<Self> {
+        let address = upstream.endpoint().address;
+        match upstream {
+            EncryptedDnsUpstream::Https { hostname, path, .. } => {
+                HttpsForwarder::new(address, hostname, path, connector).map(Forwarder::Https)
+            }
+            EncryptedDnsUpstream::Tls { hostname, .. } => Ok(Forwarder::Tls(TlsForwarder {
+                address,
+                hostname: hostname.to_owned(),
+                connector,
+                connection: None,
+            })),
+        }
+    }
+
+    fn forward(&mut self, query: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Forwarder::Https(forwarder) => forwarder.forward(query),
+            Forwarder::Tls(forwarder) => forwarder.forward(query),
+        }
+    }
+}
+
+/// Posts queries to a DNS-over-HTTPS server, with a client that runs on an event loop owned by
+/// the worker.
+struct HttpsForwarder {
+    core: Core,
+    client: Client<UpstreamConnector, Body>,
+    uri: Uri,
+}
+
+impl HttpsForwarder {
+    fn new(
+        address: SocketAddr,
+        hostname: &str,
+        path: &str,
+        connector: SslConnector,
+    ) -> Result<Self> {
+        let uri_str = format!("https://{}{}", hostname, path);
+        let uri = uri_str
+            .parse::<Uri>()
+            .chain_err(|| ErrorKind::InvalidUri(uri_str.clone()))?;
+        let core = Core::new().chain_err(|| ErrorKind::EventLoopError)?;
+        let handle = core.handle();
+        let client = Client::configure()
+            .connector(UpstreamConnector {
+                handle: handle.clone(),
+                address,
+                hostname: hostname.to_owned(),
+                tls: connector,
+            })
+            .build(&handle);
+        Ok(HttpsForwarder { core, client, uri })
+    }
+
+    fn forward(&mut self, query: &[u8]) -> io::Result<Vec<u8>> {
+        // The server may have closed an idle connection that the client reuses, which only shows
+        // once the request fails.
+        self.post(query).or_else(|error| {
+            if error.kind() == io::ErrorKind::TimedOut {
+                Err(error)
+            } else {
+                self.post(query)
+            }
+        })
+    }
+
+    fn post(&mut self, query: &[u8]) -> io::Result<Vec<u8>> {
+        let mut request = Request::new(Method::Post, self.uri.clone());
+        request
+            .headers_mut()
+            .set_raw("Content-Type", DNS_MESSAGE_CONTENT_TYPE);
+        request
+            .headers_mut()
+            .set_raw("Accept", DNS_MESSAGE_CONTENT_TYPE);
+        request.set_body(query.to_vec());
+
+        let response = self
+            .client
+            .request(request)
+            .map_err(http_error)
+            .and_then(|response| {
+                let status = response.status();
+                if status == StatusCode::Ok {
+                    Either::A(response.body().concat2().map_err(http_error))
+                } else {
+                    Either::B(future::err(invalid_data(format!(
+                        "Unexpected HTTP status: {}",
+                        status
+                    ))))
+                }
+            })
+            .map(|body| body.to_vec());
+        let timeout = Timeout::new(UPSTREAM_TIMEOUT, &self.core.handle())?.and_then(|()| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "DNS-over-HTTPS request timed out",
+            ))
+        });
+        self.core
+            .run(response.select(timeout))
+            .map(|(body, _)| body)
+            .map_err(|(error, _)| error)
+    }
+}
+
+fn http_error(error: hyper::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, error.to_string())
+}
+
+/// A connector for the HTTPS client that always connects to the upstream server, with a socket
+/// that the firewall lets through. The hostname is used to verify the certificate.
+#[derive(Clone)]
+struct UpstreamConnector {
+    handle: Handle,
+    address: SocketAddr,
+    hostname: String,
+    tls: SslConnector,
+}
+
+impl Service for UpstreamConnector {
+    type Request = Uri;
+    type Response = tokio_openssl::SslStream<AsyncTcpStream>;
+    type Error = io::Error;
+    type Future = Box<dyn Future<Item = Self::Response, Error = io::Error>>;
+
+    fn call(&self, _uri: Uri) -> Self::Future {
+        let stream = match create_marked_socket(&self.address) {
+            Ok(socket) => socket.into_tcp_stream(),
+            Err(error) => return Box::new(future::err(error)),
+        };
+        let tls = self.tls.clone();
+        let hostname = self.hostname.clone();
+        let connecting = AsyncTcpStream::connect_stream(stream, &self.address, &self.handle)
+            .and_then(move |stream| {
+                tls.connect_async(&hostname, stream)
+                    .map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+            });
+        Box::new(connecting)
+    }
+}
+
+/// Sends queries to a DNS-over-TLS server over a blocking connection.
+struct TlsForwarder {
+    address: SocketAddr,
+    hostname: String,
+    connector: SslConnector,
+    connection: Option<SslStream<TcpStream>>,
+}
+
+impl TlsForwarder {
+    fn forward(&mut self, query: &[u8]) -> io::Result<Vec<u8>> {
+        if let Some(mut stream) = self.connection.take() {
+            // The server may have closed the connection while it was idle, so failures on a
+            // reused connection are retried on a new one.
+            if let Ok(response) = tls_exchange(&mut stream, query) {
+                self.connection = Some(stream);
+                return Ok(response);
+            }
+        }
+        let mut stream = self.connect()?;
+        let response = tls_exchange(&mut stream, query)?;
+        self.connection = Some(stream);
+        Ok(response)
+    }
+
+    fn connect(&self) -> io::Result<SslStream<TcpStream>> {
+        let socket = create_marked_socket(&self.address)?;
+        socket.connect_timeout(&SockAddr::from(self.address), UPSTREAM_TIMEOUT)?;
+        let stream = socket.into_tcp_stream();
+        stream.set_read_timeout(Some(UPSTREAM_TIMEOUT))?;
+        stream.set_write_timeout(Some(UPSTREAM_TIMEOUT))?;
+        self.connector
+            .connect(&self.hostname, stream)
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error.to_string()))
+    }
+}
+
+/// Creates an unconnected TCP socket, marked so that the firewall lets it reach the upstream
+/// server.
+fn create_marked_socket(address: &SocketAddr) -> io::Result<Socket> {
+    let domain = match address {
+        SocketAddr::V4(_) => Domain::ipv4(),
+        SocketAddr::V6(_) => Domain::ipv6(),
+    };
+    let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+    let mark = OWN_TRAFFIC_FWMARK;
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_MARK,
+            &mark as *const u32 as *const libc::c_void,
+            mem::size_of_val(&mark) as libc::socklen_t,
+        )
+    };
+    if result == 0 {
+        Ok(socket)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Sends a query over DNS-over-TLS, where messages are prefixed with their length like in DNS
+/// over TCP.
+fn tls_exchange(stream: &mut SslStream<TcpStream>, query: &[u8]) -> io::Result<Vec<u8>> {
+    stream.write_all(&length_prefixed(query)?)?;
+
+    let mut length = [0u8; 2];
+    stream.read_exact(&mut length)?;
+    let mut response = vec![0u8; usize::from(u16::from_be_bytes(length))];
+    stream.read_exact(&mut response)?;
+    Ok(response)
+}
+
+/// Prefixes a message with its length, as it is sent over TCP.
+fn length_prefixed(message: &[u8]) -> io::Result<Vec<u8>> {
+    if message.len() > usize::from(u16::max_value()) {
+        return Err(invalid_data("DNS message is too large"));
+    }
+    let mut prefixed = Vec::with_capacity(message.len() + 2);
+    prefixed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+    prefixed.extend_from_slice(message);
+    Ok(prefixed)
+}
+
+/// Removes the first length prefixed message from data received over TCP. Returns `None` if the
+/// message hasn't been received in full yet.
+fn take_tcp_message(received: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let length = usize::from(read_u16(received, 0)?);
+    if received.len() < length + 2 {
+        return None;
+    }
+    let message = received[2..length + 2].to_vec();
+    received.drain(..length + 2);
+    Some(message)
+}
+
+/// Returns the largest response that the client accepts over UDP, which is the payload size in
+/// the EDNS OPT record of the query, if it has one.
+fn max_udp_response_size(query: &[u8]) -> usize {
+    edns_payload_size(query)
+        .map(usize::from)
+        .unwrap_or(0)
+        .max(MIN_UDP_PAYLOAD_SIZE)
+}
+
+fn edns_payload_size(query: &[u8]) -> Option<u16> {
+    let question_count = read_u16(query, 4)?;
+    let record_count = usize::from(read_u16(query, 6)?)
+        + usize::from(read_u16(query, 8)?)
+        + usize::from(read_u16(query, 10)?);
+
+    let mut offset = skip_questions(query, question_count)?;
+    for _ in 0..record_count {
+        offset = skip_name(query, offset)?;
+        let record_type = read_u16(query, offset)?;
+        // The class of an OPT record is the payload size
+        let class = read_u16(query, offset + 2)?;
+        if record_type == OPT_RECORD_TYPE {
+            return Some(class);
+        }
+        let data_length = read_u16(query, offset + 8)?;
+        offset += 10 + usize::from(data_length);
+    }
+    None
+}
+
+/// Shortens a response to its header and question, and sets the TC flag so that the client
+/// retries the query over TCP. Returns `None` if the response is malformed.
+fn truncate_response(response: &[u8]) -> Option<Vec<u8>> {
+    let question_count = read_u16(response, 4)?;
+    let question_end = skip_questions(response, question_count)?;
+    if question_end > response.len() {
+        return None;
+    }
+    let mut truncated = response[..question_end].to_vec();
+    truncated[2] |= TRUNCATED_FLAG;
+    // Zero the answer, authority and additional record counts
+    for count in &mut truncated[6..DNS_HEADER_SIZE] {
+        *count = 0;
+    }
+    Some(truncated)
+}
+
+/// Returns the offset of the first record after the question section.
+fn skip_questions(message: &[u8], question_count: u16) -> Option<usize> {
+    let mut offset = DNS_HEADER_SIZE;
+    for _ in 0..question_count {
+        // Each question ends with its type and class
+        offset = skip_name(message, offset)? + 4;
+    }
+    Some(offset)
+}
+
+/// Returns the offset after the domain name that starts at `offset`.
+fn skip_name(message: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        match *message.get(offset)? {
+            0 => return Some(offset + 1),
+            // A compression pointer ends the name
+            length if length & 0xc0 == 0xc0 => return Some(offset + 2),
+            length => offset += 1 + usize::from(length),
+        }
+    }
+}
+
+fn read_u16(message: &[u8], offset: usize) -> Option<u16> {
+    let bytes = message.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A query for `example.com`, with an OPT record advertising a payload size of 1232 bytes.
+    const QUERY: &[u8] = &[
+        0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // header
+        7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'c', b'o', b'm', 0, // name
+        0x00, 0x01, 0x00, 0x01, // type A, class IN
+        0, 0x00, 0x29, 0x04, 0xd0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // OPT record
+    ];
+
+    #[test]
+    fn truncates_responses_larger_than_the_payload_size() {
+        assert_eq!(max_udp_response_size(QUERY), 1232);
+        let mut query_without_edns = QUERY[..QUERY.len() - 11].to_vec();
+        query_without_edns[11] = 0;
+        assert_eq!(
+            max_udp_response_size(&query_without_edns),
+            MIN_UDP_PAYLOAD_SIZE
+        );
+
+        // Answer with a pointer to the question name, type A, class IN, TTL and 4 bytes of data
+        let mut response = query_without_edns.clone();
+        response[2] |= 0x80;
+        response[7] = 1;
+        response.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 0, 1]);
+
+        let truncated = truncate_response(&response).unwrap();
+        assert_eq!(truncated.len(), query_without_edns.len());
+        assert_eq!(truncated[2], 0x80 | TRUNCATED_FLAG | 0x01);
+        assert_eq!(&truncated[6..], &query_without_edns[6..]);
+    }
+
+    #[test]
+    fn takes_complete_tcp_messages() {
+        let mut received = vec![0, 3, 1, 2, 3, 0, 2, 4];
+
+        assert_eq!(take_tcp_message(&mut received), Some(vec![1, 2, 3]));
+        assert_eq!(take_tcp_message(&mut received), None);
+        received.push(5);
+        assert_eq!(take_tcp_message(&mut received), Some(vec![4, 5]));
+        assert!(received.is_empty());
+    }
+}
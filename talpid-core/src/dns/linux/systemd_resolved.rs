@@ -1,4 +1,4 @@
-use super::RESOLV_CONF_PATH;
+use super::{leftovers::Leftover, RESOLV_CONF_PATH};
 use crate::linux::iface_index;
 use dbus::{
     arg::RefArg, stdintf::*, BusType, Interface, Member, Message, MessageItem, MessageItemArray,
@@ -8,10 +8,12 @@ use error_chain::ChainedError;
 use lazy_static::lazy_static;
 use libc::{AF_INET, AF_INET6};
 use std::{
-    fs,
-    net::{IpAddr, Ipv4Addr},
+    collections::BTreeMap,
+    fs, mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
     path::Path,
 };
+use talpid_types::net::dns::SplitDns;
 
 
 error_chain! {
@@ -34,6 +36,13 @@ error_chain! {
         SetDomainsError {
             description("Failed to configure DNS domains")
         }
+        GetLinkSettingsError {
+            description("Failed to read the DNS settings of a link")
+        }
+        RouteLookupError(server: IpAddr) {
+            description("Failed to find the interface routing to a DNS server")
+            display("Failed to find the interface routing to DNS server {}", server)
+        }
         RevertDnsError {
             description("Failed to revert DNS configuration")
         }
@@ -63,6 +72,15 @@ lazy_static! {
 pub struct SystemdResolved {
     dbus_connection: dbus::Connection,
     interface_link: Option<(String, dbus::Path<'static>)>,
+    split_dns_links: Vec<SavedLinkSettings>,
+}
+
+/// The settings of a link that was configured with split DNS domains, so they can be restored.
+struct SavedLinkSettings {
+    interface_name: String,
+    link_object_path: dbus::Path<'static>,
+    servers: Vec<IpAddr>,
+    domains: Vec<(String, bool)>,
 }
 
 impl SystemdResolved {
@@ -72,6 +90,7 @@ impl SystemdResolved {
         let systemd_resolved = SystemdResolved {
             dbus_connection,
             interface_link: None,
+            split_dns_links: Vec::new(),
         };
 
         SystemdResolved::ensure_resolved_is_active()?;
@@ -131,7 +150,12 @@ impl SystemdResolved {
             .with_path(RESOLVED_BUS, link_object_path, RPC_TIMEOUT_MS)
     }
 
-    pub fn set_dns(&mut self, interface_name: &str, servers: &[IpAddr]) -> Result<()> {
+    pub fn set_dns(
+        &mut self,
+        interface_name: &str,
+        servers: &[IpAddr],
+        split_dns: &SplitDns,
+    ) -> Result<()> {
         let link_object_path = self.fetch_link(interface_name)?;
         if let Err(e) = self.reset() {
             log::debug!(
@@ -142,10 +166,74 @@ impl SystemdResolved {
 
         self.set_link_dns(&link_object_path, servers)?;
         self.interface_link = Some((interface_name.to_string(), link_object_path));
+        self.set_split_dns(interface_name, split_dns)
+    }
+
+    /// Makes the links that route to the split DNS servers resolve the split domains. The tunnel
+    /// link resolves all other domains, since its catch-all domain is less specific.
+    fn set_split_dns(&mut self, tunnel_interface_name: &str, split_dns: &SplitDns) -> Result<()> {
+        let mut link_configs: BTreeMap<String, (Vec<IpAddr>, Vec<String>)> = BTreeMap::new();
+        for (domain, servers) in split_dns {
+            for server in servers {
+                let interface_name = route_interface(*server)?;
+                if interface_name == tunnel_interface_name {
+                    log::warn!(
+                        "Not using {} for split DNS since it is only reachable through the tunnel",
+                        server
+                    );
+                    continue;
+                }
+                let (link_servers, link_domains) = link_configs.entry(interface_name).or_default();
+                if !link_servers.contains(server) {
+                    link_servers.push(*server);
+                }
+                if !link_domains.contains(domain) {
+                    link_domains.push(domain.clone());
+                }
+            }
+        }
+
+        for (interface_name, (servers, domains)) in link_configs {
+            let link_object_path = self.fetch_link(&interface_name)?;
+            let saved_settings = self.get_link_settings(interface_name, link_object_path)?;
 
+            // The regular DNS servers of the link are blocked by the firewall while connected,
+            // so only the split DNS servers are set.
+            let mut link_domains = saved_settings.domains.clone();
+            link_domains.extend(domains.into_iter().map(|domain| (domain, true)));
+            let link_object_path = saved_settings.link_object_path.clone();
+            self.split_dns_links.push(saved_settings);
+
+            self.set_link_servers(&link_object_path, &servers)?;
+            self.set_link_domains(&link_object_path, &link_domains)?;
+        }
         Ok(())
     }
 
+    fn get_link_settings(
+        &self,
+        interface_name: String,
+        link_object_path: dbus::Path<'static>,
+    ) -> Result<SavedLinkSettings> {
+        let link = self.as_link_object(link_object_path.clone());
+        let servers: Vec<(i32, Vec<u8>)> = link
+            .get(&LINK_INTERFACE, "DNS")
+            .chain_err(|| ErrorKind::GetLinkSettingsError)?;
+        let domains: Vec<(String, bool)> = link
+            .get(&LINK_INTERFACE, "Domains")
+            .chain_err(|| ErrorKind::GetLinkSettingsError)?;
+
+        Ok(SavedLinkSettings {
+            interface_name,
+            link_object_path,
+            servers: servers
+                .into_iter()
+                .filter_map(|(protocol, octets)| message_item_to_ip_address(protocol, &octets))
+                .collect(),
+            domains,
+        })
+    }
+
     fn fetch_link(&self, interface_name: &str) -> Result<dbus::Path<'static>> {
         let interface_index =
             iface_index(interface_name).chain_err(|| ErrorKind::InvalidInterfaceName)?;
@@ -162,7 +250,19 @@ impl SystemdResolved {
         result.read1().chain_err(|| ErrorKind::GetLinkError)
     }
 
-    fn set_link_dns<'a, 'b: 'a>(
+    fn set_link_dns(
+        &self,
+        link_object_path: &dbus::Path<'static>,
+        servers: &[IpAddr],
+    ) -> Result<()> {
+        self.set_link_servers(link_object_path, servers)?;
+
+        // set the search domain to catch all DNS requests, forces the link to be the prefered
+        // resolver, otherwise systemd-resolved will use other interfaces to do DNS lookups
+        self.set_link_domains(link_object_path, &[(".".to_owned(), true)])
+    }
+
+    fn set_link_servers<'a, 'b: 'a>(
         &'a self,
         link_object_path: &'b dbus::Path<'static>,
         servers: &[IpAddr],
@@ -179,12 +279,16 @@ impl SystemdResolved {
         reply
             .as_result()
             .map(|_| ())
-            .chain_err(|| ErrorKind::SetDnsError)?;
-
-        // set the search domain to catch all DNS requests, forces the link to be the prefered
-        // resolver, otherwise systemd-resolved will use other interfaces to do DNS lookups
-        let dns_domains: &[_] = &[(&".", true)];
+            .chain_err(|| ErrorKind::SetDnsError)
+    }
 
+    /// Sets the domains of a link. Domains marked `true` are only used for routing queries to the
+    /// link, not as search domains.
+    fn set_link_domains(
+        &self,
+        link_object_path: &dbus::Path<'static>,
+        dns_domains: &[(String, bool)],
+    ) -> Result<()> {
         let msg = Message::new_method_call(
             RESOLVED_BUS,
             link_object_path as &str,
@@ -205,6 +309,20 @@ impl SystemdResolved {
     }
 
     pub fn reset(&mut self) -> Result<()> {
+        for saved_settings in mem::replace(&mut self.split_dns_links, Vec::new()) {
+            if let Err(error) = self.restore_link_settings(&saved_settings) {
+                log::error!(
+                    "{}",
+                    error
+                        .chain_err(|| format!(
+                            "Failed to restore DNS settings of interface: {}",
+                            saved_settings.interface_name
+                        ))
+                        .display_chain()
+                );
+            }
+        }
+
         if let Some((interface_name, link_object_path)) = self.interface_link.take() {
             self.revert_link(link_object_path, &interface_name)
                 .chain_err(|| {
@@ -219,6 +337,56 @@ impl SystemdResolved {
         Ok(())
     }
 
+    /// Returns the changes made to the link settings, so that they can be undone if this
+    /// instance is stopped without resetting them.
+    pub fn leftovers(&self) -> Vec<Leftover> {
+        let mut leftovers: Vec<Leftover> = self
+            .split_dns_links
+            .iter()
+            .map(|saved_settings| Leftover::SystemdResolvedSavedLink {
+                interface_name: saved_settings.interface_name.clone(),
+                servers: saved_settings.servers.clone(),
+                domains: saved_settings.domains.clone(),
+            })
+            .collect();
+        if let Some((ref interface_name, _)) = self.interface_link {
+            leftovers.push(Leftover::SystemdResolvedLink(interface_name.clone()));
+        }
+        leftovers
+    }
+
+    /// Reverts the DNS settings of an interface that were set by another instance.
+    pub fn revert_interface(&mut self, interface_name: &str) -> Result<()> {
+        match self.fetch_link(interface_name) {
+            Ok(link_object_path) => self.revert_link(link_object_path, interface_name),
+            Err(_) => {
+                log::info!(
+                    "Not reseting DNS of interface {} because it no longer exists",
+                    interface_name
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Sets the DNS settings of an interface back to what they were before another instance
+    /// changed them.
+    pub fn restore_interface(
+        &mut self,
+        interface_name: &str,
+        servers: &[IpAddr],
+        domains: &[(String, bool)],
+    ) -> Result<()> {
+        let link_object_path = self.fetch_link(interface_name)?;
+        self.set_link_servers(&link_object_path, servers)?;
+        self.set_link_domains(&link_object_path, domains)
+    }
+
+    fn restore_link_settings(&self, saved_settings: &SavedLinkSettings) -> Result<()> {
+        self.set_link_servers(&saved_settings.link_object_path, &saved_settings.servers)?;
+        self.set_link_domains(&saved_settings.link_object_path, &saved_settings.domains)
+    }
+
     fn revert_link(
         &mut self,
         link_object_path: dbus::Path<'static>,
@@ -270,6 +438,34 @@ fn ip_address_to_message_item(address: &IpAddr) -> MessageItem {
     ])
 }
 
+fn message_item_to_ip_address(protocol: i32, octets: &[u8]) -> Option<IpAddr> {
+    match (protocol, octets.len()) {
+        (AF_INET, 4) => Some(IpAddr::V4(Ipv4Addr::new(
+            octets[0], octets[1], octets[2], octets[3],
+        ))),
+        (AF_INET6, 16) => {
+            let mut ipv6_octets = [0u8; 16];
+            ipv6_octets.copy_from_slice(octets);
+            Some(IpAddr::V6(Ipv6Addr::from(ipv6_octets)))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the name of the interface that traffic to the given address is routed through.
+fn route_interface(address: IpAddr) -> Result<String> {
+    let output = duct::cmd!("ip", "route", "get", address.to_string())
+        .stderr_null()
+        .read()
+        .chain_err(|| ErrorKind::RouteLookupError(address))?;
+    let mut words = output.split_whitespace();
+    words
+        .find(|word| *word == "dev")
+        .and_then(|_| words.next())
+        .map(|interface_name| interface_name.to_owned())
+        .ok_or_else(|| ErrorKind::RouteLookupError(address).into())
+}
+
 fn bytes_to_message_item_array(bytes: &[u8]) -> MessageItemArray {
     MessageItemArray::new(
         bytes.iter().cloned().map(MessageItem::Byte).collect(),
@@ -1,3 +1,4 @@
+use super::LastApplied;
 use error_chain::ChainedError;
 use log::{debug, trace};
 use std::{
@@ -20,11 +21,13 @@ use system_configuration::{
     dynamic_store::{SCDynamicStore, SCDynamicStoreBuilder, SCDynamicStoreCallBackContext},
     sys::schema_definitions::kSCPropNetDNSServerAddresses,
 };
+use talpid_types::net::dns::SplitDns;
 
 error_chain! {
     errors {
         SettingDnsFailed { description("Error while setting DNS servers") }
         DynamicStoreInitError { description("Failed to initialize dynamic store") }
+        SplitDnsUnsupported { description("Split DNS is not supported on macOS") }
     }
 }
 
@@ -39,6 +42,8 @@ struct State {
     dns_settings: DnsSettings,
     /// The backup of all DNS settings. These are being applied back on reset.
     backup: HashMap<ServicePath, Option<DnsSettings>>,
+    /// Updated whenever the settings are re-applied after being changed by another program.
+    last_applied: LastApplied,
 }
 
 /// Holds the configuration for one service.
@@ -124,6 +129,8 @@ pub struct DnsMonitor {
     /// When it's `Some(state)` we are actively making sure `state.dns_settings` is configured
     /// on all network interfaces.
     state: Arc<Mutex<Option<State>>>,
+
+    last_applied: LastApplied,
 }
 
 impl super::DnsMonitorT for DnsMonitor {
@@ -133,16 +140,18 @@ impl super::DnsMonitorT for DnsMonitor {
     /// DNS settings for all network interfaces. If any changes occur it will instantly reset
     /// the DNS settings for that interface back to the last server list set to this instance
     /// with `set_dns`.
-    fn new(_cache_dir: impl AsRef<Path>) -> Result<Self> {
+    fn new(_cache_dir: impl AsRef<Path>, last_applied: LastApplied) -> Result<Self> {
         let state = Arc::new(Mutex::new(None));
         Self::spawn(state.clone())?;
         Ok(DnsMonitor {
             store: SCDynamicStoreBuilder::new("mullvad-dns").build(),
             state,
+            last_applied,
         })
     }
 
-    fn set(&mut self, _interface: &str, servers: &[IpAddr]) -> Result<()> {
+    fn set(&mut self, _interface: &str, servers: &[IpAddr], split_dns: &SplitDns) -> Result<()> {
+        ensure!(split_dns.is_empty(), ErrorKind::SplitDnsUnsupported);
         let servers: Vec<DnsServer> = servers.iter().map(|ip| ip.to_string()).collect();
         let settings = DnsSettings::from_server_addresses(&servers);
         let mut state_lock = self.state.lock().unwrap();
@@ -156,6 +165,7 @@ impl super::DnsMonitorT for DnsMonitor {
                 State {
                     dns_settings: settings,
                     backup,
+                    last_applied: self.last_applied.clone(),
                 }
             }
             Some(state) => {
@@ -166,6 +176,7 @@ impl super::DnsMonitorT for DnsMonitor {
                     State {
                         dns_settings: settings,
                         backup: state.backup,
+                        last_applied: state.last_applied,
                     }
                 } else {
                     debug!("No change, new DNS same as the one already set");
@@ -193,6 +204,10 @@ impl super::DnsMonitorT for DnsMonitor {
         }
         Ok(())
     }
+
+    fn backend_name(&self) -> String {
+        "SystemConfiguration".to_owned()
+    }
 }
 
 impl DnsMonitor {
@@ -308,6 +323,7 @@ fn dns_change_callback_internal(
                     .save(&store, setup_path.clone())
                     .chain_err(|| format!("Failed changing DNS for {}", setup_path))?;
             }
+            state.last_applied.update();
         }
     }
     Ok(())
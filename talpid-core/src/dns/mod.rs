@@ -1,4 +1,11 @@
-use std::{net::IpAddr, path::Path};
+use crate::dry_run::Recorder;
+use chrono::{offset::Utc, DateTime};
+use std::{
+    net::IpAddr,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+use talpid_types::net::dns::{DnsStatus, EncryptedDnsUpstream, SplitDns};
 
 #[cfg(target_os = "macos")]
 #[path = "macos.rs"]
@@ -12,47 +19,208 @@ mod imp;
 #[path = "windows/mod.rs"]
 mod imp;
 
-pub use self::imp::Error;
+error_chain! {
+    errors {
+        /// Failure to record a DNS change in dry-run mode
+        RecordDnsError {
+            description("Failed to record DNS change")
+        }
+    }
+    foreign_links {
+        Platform(imp::Error) #[doc = "Error in the platform specific DNS implementation"];
+    }
+}
 
 /// Sets and monitors system DNS settings. Makes sure the desired DNS servers are being used.
 pub struct DnsMonitor {
-    inner: imp::DnsMonitor,
+    inner: DnsMonitorBackend,
+    /// The interface and servers that DNS is currently set to, if any.
+    current: Option<(String, Vec<IpAddr>)>,
+    last_applied: LastApplied,
+}
+
+enum DnsMonitorBackend {
+    System(imp::DnsMonitor),
+    Recording(Recorder),
 }
 
 impl DnsMonitor {
-    /// Returns a new `DnsMonitor` that can set and monitor the system DNS.
-    pub fn new(cache_dir: impl AsRef<Path>) -> Result<Self, Error> {
-        Ok(DnsMonitor {
-            inner: imp::DnsMonitor::new(cache_dir)?,
-        })
-    }
-
-    /// Set DNS to the given servers. And start monitoring the system for changes.
-    pub fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Error> {
-        log::info!(
-            "Setting DNS servers to {}",
-            servers
-                .iter()
-                .map(|ip| ip.to_string())
-                .collect::<Vec<String>>()
-                .join(", ")
-        );
-        self.inner.set(interface, servers)
+    /// Returns a new `DnsMonitor` that can set and monitor the system DNS. If dry-run mode is
+    /// enabled through the environment, DNS changes are recorded instead of applied.
+    pub fn new(cache_dir: impl AsRef<Path>) -> Result<Self> {
+        match Recorder::from_env() {
+            Some(recorder) => Ok(Self::with_recorder(recorder)),
+            None => {
+                let last_applied = LastApplied::default();
+                Ok(DnsMonitor {
+                    inner: DnsMonitorBackend::System(imp::DnsMonitor::new(
+                        cache_dir,
+                        last_applied.clone(),
+                    )?),
+                    current: None,
+                    last_applied,
+                })
+            }
+        }
+    }
+
+    /// Returns a new `DnsMonitor` that records DNS changes with the given `Recorder` instead of
+    /// applying them to the system.
+    pub fn with_recorder(recorder: Recorder) -> Self {
+        log::debug!("Recording DNS changes instead of applying them");
+        DnsMonitor {
+            inner: DnsMonitorBackend::Recording(recorder),
+            current: None,
+            last_applied: LastApplied::default(),
+        }
+    }
+
+    /// Set DNS to the given servers. And start monitoring the system for changes. Names within
+    /// the domains in `split_dns` are resolved by their own servers instead.
+    pub fn set(&mut self, interface: &str, servers: &[IpAddr], split_dns: &SplitDns) -> Result<()> {
+        let servers_str = format_servers(servers);
+        log::info!("Setting DNS servers to {}", servers_str);
+        for (domain, domain_servers) in split_dns {
+            log::info!(
+                "Resolving {} with {}",
+                domain,
+                format_servers(domain_servers)
+            );
+        }
+        self.current = None;
+        match self.inner {
+            DnsMonitorBackend::System(ref mut monitor) => {
+                monitor.set(interface, servers, split_dns)?
+            }
+            DnsMonitorBackend::Recording(ref recorder) => {
+                recorder
+                    .record(&format!("dns set {}: {}", interface, servers_str))
+                    .chain_err(|| ErrorKind::RecordDnsError)?;
+                for (domain, domain_servers) in split_dns {
+                    recorder
+                        .record(&format!(
+                            "dns split {}: {}",
+                            domain,
+                            format_servers(domain_servers)
+                        ))
+                        .chain_err(|| ErrorKind::RecordDnsError)?;
+                }
+            }
+        }
+        self.current = Some((interface.to_owned(), servers.to_vec()));
+        self.last_applied.update();
+        Ok(())
+    }
+
+    /// Set an encrypted DNS server to forward all queries to, through a local stub resolver. Takes
+    /// effect the next time DNS is set. Only supported on Linux.
+    pub fn set_encrypted_upstream(&mut self, upstream: Option<EncryptedDnsUpstream>) -> Result<()> {
+        match self.inner {
+            DnsMonitorBackend::System(ref mut monitor) => {
+                Ok(monitor.set_encrypted_upstream(upstream)?)
+            }
+            DnsMonitorBackend::Recording(ref recorder) => {
+                let upstream_str = upstream
+                    .map(|upstream| upstream.to_string())
+                    .unwrap_or_else(|| "none".to_owned());
+                recorder
+                    .record(&format!("dns encrypted upstream: {}", upstream_str))
+                    .chain_err(|| ErrorKind::RecordDnsError)
+            }
+        }
     }
 
     /// Reset system DNS settings to what it was before being set by this instance.
-    pub fn reset(&mut self) -> Result<(), Error> {
+    pub fn reset(&mut self) -> Result<()> {
         log::info!("Resetting DNS");
-        self.inner.reset()
+        self.current = None;
+        self.last_applied.clear();
+        match self.inner {
+            DnsMonitorBackend::System(ref mut monitor) => Ok(monitor.reset()?),
+            DnsMonitorBackend::Recording(ref recorder) => recorder
+                .record("dns reset")
+                .chain_err(|| ErrorKind::RecordDnsError),
+        }
+    }
+
+    /// Returns how the DNS settings are currently managed.
+    pub fn status(&self) -> DnsStatus {
+        match self.current {
+            Some((ref interface, ref servers)) => DnsStatus {
+                backend: Some(match self.inner {
+                    DnsMonitorBackend::System(ref monitor) => monitor.backend_name(),
+                    DnsMonitorBackend::Recording(_) => "dry-run recording".to_owned(),
+                }),
+                servers: servers.clone(),
+                interface: Some(interface.clone()),
+                last_applied: self.last_applied.get(),
+            },
+            None => DnsStatus::default(),
+        }
+    }
+}
+
+/// The time when the DNS settings were last applied. Shared with the platform specific monitors,
+/// so that they can update it when they re-apply settings that were changed by another program.
+#[derive(Clone, Default)]
+pub struct LastApplied(Arc<Mutex<Option<DateTime<Utc>>>>);
+
+impl LastApplied {
+    /// Sets the time to now.
+    pub fn update(&self) {
+        *self.lock() = Some(Utc::now());
+    }
+
+    fn clear(&self) {
+        *self.lock() = None;
+    }
+
+    fn get(&self) -> Option<DateTime<Utc>> {
+        *self.lock()
     }
+
+    fn lock(&self) -> MutexGuard<'_, Option<DateTime<Utc>>> {
+        self.0
+            .lock()
+            .expect("a thread panicked while updating the DNS application time")
+    }
+}
+
+fn format_servers(servers: &[IpAddr]) -> String {
+    servers
+        .iter()
+        .map(|ip| ip.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
 }
 
 trait DnsMonitorT: Sized {
     type Error: ::std::error::Error;
 
-    fn new(cache_dir: impl AsRef<Path>) -> Result<Self, Self::Error>;
+    fn new(
+        cache_dir: impl AsRef<Path>,
+        last_applied: LastApplied,
+    ) -> ::std::result::Result<Self, Self::Error>;
 
-    fn set(&mut self, interface: &str, servers: &[IpAddr]) -> Result<(), Self::Error>;
+    fn set(
+        &mut self,
+        interface: &str,
+        servers: &[IpAddr],
+        split_dns: &SplitDns,
+    ) -> ::std::result::Result<(), Self::Error>;
 
-    fn reset(&mut self) -> Result<(), Self::Error>;
+    fn reset(&mut self) -> ::std::result::Result<(), Self::Error>;
+
+    /// Name of the mechanism used to manage the DNS settings.
+    fn backend_name(&self) -> String;
+
+    fn set_encrypted_upstream(
+        &mut self,
+        upstream: Option<EncryptedDnsUpstream>,
+    ) -> ::std::result::Result<(), Self::Error> {
+        if upstream.is_some() {
+            log::warn!("Encrypted DNS is not supported on this platform");
+        }
+        Ok(())
+    }
 }
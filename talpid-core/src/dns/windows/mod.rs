@@ -6,9 +6,11 @@ use std::{
     path::Path,
     ptr, slice,
 };
+use talpid_types::net::dns::SplitDns;
 
 mod system_state;
 use self::system_state::SystemStateWriter;
+use super::LastApplied;
 
 use error_chain::ChainedError;
 use widestring::WideCString;
@@ -42,6 +44,11 @@ error_chain! {
         Recovery{
             description("Failed to recover to backed up system state")
         }
+
+        /// Split DNS domains were given, but are not supported
+        SplitDnsUnsupported{
+            description("Split DNS is not supported on Windows")
+        }
     }
 }
 
@@ -52,7 +59,7 @@ pub struct DnsMonitor {
 impl super::DnsMonitorT for DnsMonitor {
     type Error = Error;
 
-    fn new(cache_dir: impl AsRef<Path>) -> Result<Self> {
+    fn new(cache_dir: impl AsRef<Path>, _last_applied: LastApplied) -> Result<Self> {
         unsafe { WinDns_Initialize(Some(log_sink), ptr::null_mut()).into_result()? };
 
         let backup_writer = SystemStateWriter::new(
@@ -71,7 +78,8 @@ impl super::DnsMonitorT for DnsMonitor {
         Ok(dns)
     }
 
-    fn set(&mut self, _interface: &str, servers: &[IpAddr]) -> Result<()> {
+    fn set(&mut self, _interface: &str, servers: &[IpAddr], split_dns: &SplitDns) -> Result<()> {
+        ensure!(split_dns.is_empty(), ErrorKind::SplitDnsUnsupported);
         let ipv4 = servers
             .iter()
             .filter(|ip| ip.is_ipv4())
@@ -120,6 +128,10 @@ impl super::DnsMonitorT for DnsMonitor {
         }
         Ok(())
     }
+
+    fn backend_name(&self) -> String {
+        "WinDns".to_owned()
+    }
 }
 
 impl DnsMonitor {
@@ -0,0 +1,37 @@
+use std::{
+    env,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+/// Name of the environment variable that, when set to a file path, makes the firewall and DNS
+/// backends record the changes they would make to that file instead of applying them.
+pub const DRY_RUN_ENV_VAR: &str = "TALPID_DRY_RUN_FILE";
+
+/// Records changes to the system as lines of text appended to a file.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    path: PathBuf,
+}
+
+impl Recorder {
+    /// Creates a recorder that appends to the given file.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Recorder { path: path.into() }
+    }
+
+    /// Returns a recorder if dry-run mode has been enabled through `DRY_RUN_ENV_VAR`.
+    pub fn from_env() -> Option<Self> {
+        env::var_os(DRY_RUN_ENV_VAR).map(Self::new)
+    }
+
+    /// Appends the given entry to the file, followed by a newline.
+    pub fn record(&self, entry: &str) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", entry)
+    }
+}
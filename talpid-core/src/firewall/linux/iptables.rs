@@ -0,0 +1,604 @@
+use crate::firewall::{
+    FirewallPolicy, FirewallT, DHCPV6_SERVER_ADDRS, LOCAL_INET6_NET, MULTICAST_INET6_NET,
+    MULTICAST_NET, PRIVATE_NETS, SSDP_IP,
+};
+use crate::tunnel;
+use ipnetwork::IpNetwork;
+use std::{
+    fmt::Write,
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+};
+use talpid_types::net::{
+    is_reached_outside_tunnel, Endpoint, TransportProtocol, OWN_TRAFFIC_FWMARK,
+};
+use which::which;
+
+error_chain! {
+    errors {
+        /// The iptables-restore or ip6tables-restore programs could not be found
+        NoIptables {
+            description("Failed to detect 'iptables-restore' and 'ip6tables-restore' programs")
+        }
+        /// Failed to execute an iptables program
+        RunIptables(program: String) {
+            description("Failed to execute iptables program")
+            display("Failed to execute '{}'", program)
+        }
+        /// An iptables program rejected the rules
+        ApplyRulesError(program: String, stderr: String) {
+            description("Failed to apply iptables rules")
+            display("'{}' failed to apply rules: {}", program, stderr)
+        }
+    }
+}
+
+const IN_CHAIN_NAME: &str = "mullvad-in";
+const OUT_CHAIN_NAME: &str = "mullvad-out";
+
+/// The legacy iptables based Linux firewall implementation. Rules are added to dedicated chains
+/// that are jumped to from the builtin `INPUT` and `OUTPUT` chains of the `filter` table.
+pub struct Firewall {
+    families: [Family; 2],
+}
+
+struct Family {
+    iptables: PathBuf,
+    iptables_restore: PathBuf,
+    is_ipv4: bool,
+}
+
+impl FirewallT for Firewall {
+    type Error = Error;
+
+    fn new() -> Result<Self> {
+        let find = |program| which(program).map_err(|_| Error::from(ErrorKind::NoIptables));
+        Ok(Firewall {
+            families: [
+                Family {
+                    iptables: find("iptables")?,
+                    iptables_restore: find("iptables-restore")?,
+                    is_ipv4: true,
+                },
+                Family {
+                    iptables: find("ip6tables")?,
+                    iptables_restore: find("ip6tables-restore")?,
+                    is_ipv4: false,
+                },
+            ],
+        })
+    }
+
+    fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
+        let rules = RuleSet::from_policy(&policy);
+        for family in &self.families {
+            family.restore(&rules.render(family.is_ipv4, !family.has_jump_rules()))?;
+        }
+        Ok(())
+    }
+
+    fn reset_policy(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for family in &self.families {
+            if let Err(error) = family.remove_chains() {
+                result = Err(error);
+            }
+        }
+        result
+    }
+}
+
+impl Family {
+    fn restore(&self, rules: &str) -> Result<()> {
+        let program = self.iptables_restore.display().to_string();
+        let output = duct::cmd!(&self.iptables_restore, "--noflush")
+            .input(rules)
+            .stderr_capture()
+            .unchecked()
+            .run()
+            .chain_err(|| ErrorKind::RunIptables(program.clone()))?;
+
+        ensure!(
+            output.status.success(),
+            ErrorKind::ApplyRulesError(
+                program,
+                String::from_utf8_lossy(&output.stderr).to_string()
+            )
+        );
+        Ok(())
+    }
+
+    fn has_jump_rules(&self) -> bool {
+        self.iptables(&["-C", "INPUT", "-j", IN_CHAIN_NAME])
+            && self.iptables(&["-C", "OUTPUT", "-j", OUT_CHAIN_NAME])
+    }
+
+    fn remove_chains(&self) -> Result<()> {
+        // Deleting a chain that is not in use or does not exist fails, so the results of the
+        // individual commands are ignored. The chains are verified to be gone at the end.
+        while self.iptables(&["-D", "INPUT", "-j", IN_CHAIN_NAME]) {}
+        while self.iptables(&["-D", "OUTPUT", "-j", OUT_CHAIN_NAME]) {}
+        for chain in &[IN_CHAIN_NAME, OUT_CHAIN_NAME] {
+            self.iptables(&["-F", chain]);
+            self.iptables(&["-X", chain]);
+        }
+
+        let program = self.iptables.display().to_string();
+        ensure!(
+            !self.iptables(&["-n", "-L", IN_CHAIN_NAME])
+                && !self.iptables(&["-n", "-L", OUT_CHAIN_NAME]),
+            ErrorKind::ApplyRulesError(program, "Unable to remove chains".to_owned())
+        );
+        Ok(())
+    }
+
+    /// Runs the iptables program with the given arguments, returning whether it succeeded.
+    fn iptables(&self, args: &[&str]) -> bool {
+        duct::cmd(&self.iptables, args)
+            .stdout_null()
+            .stderr_null()
+            .unchecked()
+            .run()
+            .map(|output| output.status.success())
+            .unwrap_or(false)
+    }
+}
+
+/// Renders the rules for the given policy as `iptables-restore` input, first for IPv4 and then for
+/// IPv6.
+pub fn render_policy(policy: &FirewallPolicy) -> String {
+    let rule_set = RuleSet::from_policy(policy);
+    format!(
+        "# IPv4\n{}# IPv6\n{}",
+        rule_set.render(true, false),
+        rule_set.render(false, false)
+    )
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Chain {
+    In,
+    Out,
+}
+
+impl Chain {
+    fn name(self) -> &'static str {
+        match self {
+            Chain::In => IN_CHAIN_NAME,
+            Chain::Out => OUT_CHAIN_NAME,
+        }
+    }
+}
+
+/// Which IP versions a rule applies to.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Scope {
+    Both,
+    Ipv4,
+    Ipv6,
+}
+
+impl Scope {
+    fn of_ip(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => Scope::Ipv4,
+            IpAddr::V6(_) => Scope::Ipv6,
+        }
+    }
+
+    fn includes(self, is_ipv4: bool) -> bool {
+        match self {
+            Scope::Both => true,
+            Scope::Ipv4 => is_ipv4,
+            Scope::Ipv6 => !is_ipv4,
+        }
+    }
+}
+
+/// The rules for a policy, in the order they should be matched. Each rule is a list of
+/// iptables match arguments, and a final `DROP` rule is implied at the end of each chain.
+struct RuleSet {
+    rules: Vec<(Chain, Scope, String)>,
+}
+
+impl RuleSet {
+    fn from_policy(policy: &FirewallPolicy) -> Self {
+        let mut rule_set = RuleSet { rules: Vec::new() };
+        rule_set.add_loopback_rules();
+        rule_set.add_dhcp_rules();
+        rule_set.add_policy_specific_rules(policy);
+        rule_set
+    }
+
+    /// Renders the rules as `iptables-restore` input for one IP version.
+    fn render(&self, is_ipv4: bool, add_jump_rules: bool) -> String {
+        let mut output = String::from("*filter\n");
+        for chain in &[Chain::In, Chain::Out] {
+            // Declaring the chains creates them, or flushes them if they exist.
+            writeln!(output, ":{} - [0:0]", chain.name()).unwrap();
+        }
+        if add_jump_rules {
+            writeln!(output, "-I INPUT 1 -j {}", IN_CHAIN_NAME).unwrap();
+            writeln!(output, "-I OUTPUT 1 -j {}", OUT_CHAIN_NAME).unwrap();
+        }
+        for (chain, scope, rule) in &self.rules {
+            if scope.includes(is_ipv4) {
+                writeln!(output, "-A {} {}", chain.name(), rule).unwrap();
+            }
+        }
+        for chain in &[Chain::In, Chain::Out] {
+            writeln!(output, "-A {} -j DROP", chain.name()).unwrap();
+        }
+        output.push_str("COMMIT\n");
+        output
+    }
+
+    fn add(&mut self, chain: Chain, scope: Scope, rule: String) {
+        self.rules.push((chain, scope, rule));
+    }
+
+    fn add_loopback_rules(&mut self) {
+        self.add(Chain::Out, Scope::Both, "-o lo -j ACCEPT".to_owned());
+        self.add(Chain::In, Scope::Both, "-i lo -j ACCEPT".to_owned());
+    }
+
+    fn add_dhcp_rules(&mut self) {
+        self.add(
+            Chain::Out,
+            Scope::Ipv4,
+            format!(
+                "-p udp --sport 68 -d {} --dport 67 -j ACCEPT",
+                Ipv4Addr::BROADCAST
+            ),
+        );
+        self.add(
+            Chain::In,
+            Scope::Ipv4,
+            "-p udp --sport 67 --dport 68 -j ACCEPT".to_owned(),
+        );
+        for dhcpv6_server in &*DHCPV6_SERVER_ADDRS {
+            self.add(
+                Chain::Out,
+                Scope::Ipv6,
+                format!(
+                    "-s {} -p udp --sport 546 -d {} --dport 547 -j ACCEPT",
+                    *LOCAL_INET6_NET, dhcpv6_server
+                ),
+            );
+        }
+        self.add(
+            Chain::In,
+            Scope::Ipv6,
+            format!(
+                "-s {net} -p udp --sport 547 -d {net} --dport 546 -j ACCEPT",
+                net = *LOCAL_INET6_NET
+            ),
+        );
+    }
+
+    fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) {
+        let allow_lan = match policy {
+            FirewallPolicy::Connecting {
+                peer_endpoint,
+                allow_lan,
+                allowed_endpoint,
+            } => {
+                self.add_allow_endpoint_rules(peer_endpoint, None, "");
+                if let Some(endpoint) = allowed_endpoint {
+                    self.add_allow_endpoint_rules(endpoint, None, &own_traffic_match());
+                }
+                *allow_lan
+            }
+            FirewallPolicy::Connected {
+                peer_endpoints,
+                tunnel,
+                allow_lan,
+                forwarded_ports,
+                dns_servers,
+                split_dns_servers,
+                dns_upstream,
+            } => {
+                for peer_endpoint in peer_endpoints {
+                    self.add_allow_endpoint_rules(peer_endpoint, None, "");
+                }
+                if let Some(endpoint) = dns_upstream {
+                    self.add_dns_upstream_rules(endpoint, tunnel);
+                }
+                self.add_dns_rules(tunnel, dns_servers, split_dns_servers, *allow_lan);
+                self.add_allow_tunnel_rules(tunnel, forwarded_ports);
+                *allow_lan
+            }
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_endpoint,
+            } => {
+                if let Some(endpoint) = allowed_endpoint {
+                    self.add_allow_endpoint_rules(endpoint, None, &own_traffic_match());
+                }
+                *allow_lan
+            }
+        };
+
+        if allow_lan {
+            self.add_allow_lan_rules();
+        }
+    }
+
+    /// Allows traffic to and from the endpoint, only on `interface` if given.
+    fn add_allow_endpoint_rules(
+        &mut self,
+        endpoint: &Endpoint,
+        interface: Option<&str>,
+        extra_out_matches: &str,
+    ) {
+        let ip = endpoint.address.ip();
+        let port = endpoint.address.port();
+        let protocol = protocol_name(endpoint.protocol);
+        let (in_interface, out_interface) = match interface {
+            Some(interface) => (format!("-i {} ", interface), format!("-o {} ", interface)),
+            None => (String::new(), String::new()),
+        };
+        self.add(
+            Chain::In,
+            Scope::of_ip(ip),
+            format!(
+                "{}-s {} -p {} --sport {} -m conntrack --ctstate ESTABLISHED -j ACCEPT",
+                in_interface, ip, protocol, port
+            ),
+        );
+        self.add(
+            Chain::Out,
+            Scope::of_ip(ip),
+            format!(
+                "{}-d {} -p {} --dport {}{} -j ACCEPT",
+                out_interface, ip, protocol, port, extra_out_matches
+            ),
+        );
+    }
+
+    /// Allows only this process to reach the encrypted DNS upstream, and only through the tunnel,
+    /// so that other programs can't bypass the stub resolver by talking to it directly.
+    fn add_dns_upstream_rules(&mut self, endpoint: &Endpoint, tunnel: &tunnel::TunnelMetadata) {
+        self.add_allow_endpoint_rules(endpoint, Some(&tunnel.interface), &own_traffic_match());
+        let ip = endpoint.address.ip();
+        self.add(
+            Chain::Out,
+            Scope::of_ip(ip),
+            format!(
+                "-d {} -p {} --dport {} -j DROP",
+                ip,
+                protocol_name(endpoint.protocol),
+                endpoint.address.port()
+            ),
+        );
+    }
+
+    fn add_dns_rules(
+        &mut self,
+        tunnel: &tunnel::TunnelMetadata,
+        dns_servers: &[IpAddr],
+        split_dns_servers: &[IpAddr],
+        allow_lan: bool,
+    ) {
+        for protocol in &[TransportProtocol::Udp, TransportProtocol::Tcp] {
+            let protocol = protocol_name(*protocol);
+            for server in dns_servers.iter().chain(split_dns_servers) {
+                self.add(
+                    Chain::Out,
+                    Scope::of_ip(*server),
+                    format!(
+                        "-o {} -d {} -p {} --dport 53 -j ACCEPT",
+                        tunnel.interface, server, protocol
+                    ),
+                );
+                // Resolvers on the local network are reached outside the tunnel
+                if allow_lan && is_reached_outside_tunnel(*server) {
+                    self.add(
+                        Chain::Out,
+                        Scope::of_ip(*server),
+                        format!("-d {} -p {} --dport 53 -j ACCEPT", server, protocol),
+                    );
+                }
+            }
+            self.add(
+                Chain::Out,
+                Scope::Both,
+                format!("-p {} --dport 53 -j DROP", protocol),
+            );
+        }
+    }
+
+    fn add_allow_tunnel_rules(&mut self, tunnel: &tunnel::TunnelMetadata, forwarded_ports: &[u16]) {
+        self.add(
+            Chain::Out,
+            Scope::Both,
+            format!("-o {} -j ACCEPT", tunnel.interface),
+        );
+        self.add(
+            Chain::In,
+            Scope::Both,
+            format!(
+                "-i {} -m conntrack --ctstate ESTABLISHED,RELATED -j ACCEPT",
+                tunnel.interface
+            ),
+        );
+        for port in forwarded_ports {
+            for protocol in &[TransportProtocol::Tcp, TransportProtocol::Udp] {
+                self.add(
+                    Chain::In,
+                    Scope::Both,
+                    format!(
+                        "-i {} -p {} --dport {} -j ACCEPT",
+                        tunnel.interface,
+                        protocol_name(*protocol),
+                        port
+                    ),
+                );
+            }
+        }
+    }
+
+    fn add_allow_lan_rules(&mut self) {
+        // LAN -> LAN
+        for chain in &[Chain::In, Chain::Out] {
+            for net in &*PRIVATE_NETS {
+                self.add_net_rule(*chain, *net, *net);
+            }
+            self.add_net_rule(*chain, *LOCAL_INET6_NET, *LOCAL_INET6_NET);
+        }
+        // LAN -> multicast
+        for net in &*PRIVATE_NETS {
+            self.add_net_rule(Chain::Out, *net, *MULTICAST_NET);
+            // LAN -> SSDP + WS-Discovery protocols
+            self.add_net_rule(Chain::Out, *net, IpNetwork::from(*SSDP_IP));
+        }
+        self.add_net_rule(Chain::Out, *LOCAL_INET6_NET, *MULTICAST_INET6_NET);
+    }
+
+    fn add_net_rule(&mut self, chain: Chain, source: IpNetwork, destination: IpNetwork) {
+        self.add(
+            chain,
+            Scope::of_ip(source.ip()),
+            format!("-s {} -d {} -j ACCEPT", source, destination),
+        );
+    }
+}
+
+fn protocol_name(protocol: TransportProtocol) -> &'static str {
+    match protocol {
+        TransportProtocol::Udp => "udp",
+        TransportProtocol::Tcp => "tcp",
+    }
+}
+
+/// Matches traffic from sockets marked with `OWN_TRAFFIC_FWMARK` by this process.
+fn own_traffic_match() -> String {
+    format!(" -m mark --mark {:#x}", OWN_TRAFFIC_FWMARK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocked_policy_allows_own_traffic_to_endpoint() {
+        let endpoint = Endpoint::new(Ipv4Addr::new(10, 0, 0, 1), 443, TransportProtocol::Tcp);
+        let policy = FirewallPolicy::Blocked {
+            allow_lan: false,
+            allowed_endpoint: Some(endpoint),
+        };
+
+        let rules = RuleSet::from_policy(&policy).render(true, false);
+
+        assert!(rules.starts_with("*filter\n:mullvad-in - [0:0]\n:mullvad-out - [0:0]\n"));
+        assert!(rules.contains(
+            "-A mullvad-out -d 10.0.0.1 -p tcp --dport 443 -m mark --mark 0x6d617069 -j ACCEPT\n"
+        ));
+        assert!(rules.ends_with("-A mullvad-in -j DROP\n-A mullvad-out -j DROP\nCOMMIT\n"));
+        assert!(!rules.contains("-I INPUT"));
+    }
+
+    #[test]
+    fn connecting_policy_allows_only_own_traffic_to_endpoint() {
+        let peer_endpoint = Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 1194, TransportProtocol::Udp);
+        let endpoint = Endpoint::new(Ipv4Addr::new(10, 0, 0, 1), 443, TransportProtocol::Tcp);
+        let policy = FirewallPolicy::Connecting {
+            peer_endpoint,
+            allow_lan: false,
+            allowed_endpoint: Some(endpoint),
+        };
+
+        let rules = RuleSet::from_policy(&policy).render(true, false);
+
+        assert!(rules.contains("-A mullvad-out -d 1.2.3.4 -p udp --dport 1194 -j ACCEPT\n"));
+        assert!(rules.contains(
+            "-A mullvad-out -d 10.0.0.1 -p tcp --dport 443 -m mark --mark 0x6d617069 -j ACCEPT\n"
+        ));
+        assert!(!rules.contains("-A mullvad-out -d 10.0.0.1 -p tcp --dport 443 -j ACCEPT\n"));
+    }
+
+    #[test]
+    fn ipv4_rules_are_not_rendered_for_ipv6() {
+        let policy = FirewallPolicy::Blocked {
+            allow_lan: true,
+            allowed_endpoint: None,
+        };
+
+        let rules = RuleSet::from_policy(&policy).render(false, true);
+
+        assert!(rules.contains("-I INPUT 1 -j mullvad-in\n"));
+        assert!(!rules.contains("192.168.0.0/16"));
+        assert!(rules.contains("-A mullvad-in -s fe80::/10 -d fe80::/10 -j ACCEPT\n"));
+    }
+
+    #[test]
+    fn local_dns_servers_are_only_allowed_outside_tunnel_with_lan() {
+        let policy = |allow_lan| FirewallPolicy::Connected {
+            peer_endpoints: vec![Endpoint::new(
+                Ipv4Addr::new(1, 2, 3, 4),
+                1194,
+                TransportProtocol::Udp,
+            )],
+            tunnel: tunnel::TunnelMetadata {
+                interface: "tun0".to_owned(),
+                ips: vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 2))],
+                gateway: IpAddr::V4(Ipv4Addr::new(10, 8, 0, 1)),
+                ipv6_gateway: None,
+            },
+            allow_lan,
+            forwarded_ports: vec![],
+            dns_servers: vec![
+                IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)),
+                IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)),
+                IpAddr::V4(Ipv4Addr::new(10, 64, 0, 1)),
+            ],
+            split_dns_servers: vec![IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1))],
+            dns_upstream: None,
+        };
+
+        let rules = RuleSet::from_policy(&policy(false)).render(true, false);
+        assert!(
+            rules.contains("-A mullvad-out -o tun0 -d 192.168.1.1 -p udp --dport 53 -j ACCEPT\n")
+        );
+        assert!(rules.contains("-A mullvad-out -o tun0 -d 8.8.8.8 -p udp --dport 53 -j ACCEPT\n"));
+        assert!(!rules.contains("-A mullvad-out -d 192.168.1.1 -p udp --dport 53 -j ACCEPT\n"));
+
+        let rules = RuleSet::from_policy(&policy(true)).render(true, false);
+        assert!(rules.contains("-A mullvad-out -d 192.168.1.1 -p udp --dport 53 -j ACCEPT\n"));
+        assert!(!rules.contains("-A mullvad-out -d 8.8.8.8 -p udp --dport 53 -j ACCEPT\n"));
+        assert!(rules.contains("-A mullvad-out -o tun0 -d 1.1.1.1 -p udp --dport 53 -j ACCEPT\n"));
+        assert!(!rules.contains("-A mullvad-out -d 1.1.1.1 -p udp --dport 53 -j ACCEPT\n"));
+        assert!(rules.contains("-A mullvad-out -o tun0 -d 10.64.0.1 -p udp --dport 53 -j ACCEPT\n"));
+        assert!(!rules.contains("-A mullvad-out -d 10.64.0.1 -p udp --dport 53 -j ACCEPT\n"));
+    }
+
+    #[test]
+    fn only_forwarded_ports_accept_new_connections_over_tunnel() {
+        let policy = FirewallPolicy::Connected {
+            peer_endpoints: vec![Endpoint::new(
+                Ipv4Addr::new(1, 2, 3, 4),
+                1194,
+                TransportProtocol::Udp,
+            )],
+            tunnel: tunnel::TunnelMetadata {
+                interface: "tun0".to_owned(),
+                ips: vec![IpAddr::V4(Ipv4Addr::new(10, 8, 0, 2))],
+                gateway: IpAddr::V4(Ipv4Addr::new(10, 8, 0, 1)),
+                ipv6_gateway: None,
+            },
+            allow_lan: false,
+            forwarded_ports: vec![56789],
+            dns_servers: vec![],
+            split_dns_servers: vec![],
+            dns_upstream: None,
+        };
+
+        let rules = RuleSet::from_policy(&policy).render(true, false);
+        assert!(rules.contains(
+            "-A mullvad-in -i tun0 -m conntrack --ctstate ESTABLISHED,RELATED -j ACCEPT\n"
+        ));
+        assert!(rules.contains("-A mullvad-in -i tun0 -p tcp --dport 56789 -j ACCEPT\n"));
+        assert!(rules.contains("-A mullvad-in -i tun0 -p udp --dport 56789 -j ACCEPT\n"));
+        assert!(!rules.contains("-A mullvad-in -i tun0 -j ACCEPT\n"));
+    }
+}
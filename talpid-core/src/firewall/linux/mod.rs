@@ -0,0 +1,119 @@
+mod iptables;
+mod nftables;
+
+use super::{FirewallPolicy, FirewallT};
+use error_chain::ChainedError;
+use lazy_static::lazy_static;
+use std::{env, fmt};
+
+error_chain! {
+    errors {
+        /// No supported firewall backend could be initialized
+        NoFirewallBackend {
+            description("No supported firewall backend could be initialized")
+        }
+    }
+    links {
+        Nftables(nftables::Error, nftables::ErrorKind) #[doc = "Error in the nftables backend"];
+        Iptables(iptables::Error, iptables::ErrorKind) #[doc = "Error in the iptables backend"];
+    }
+}
+
+lazy_static! {
+    /// The backend that policies are rendered for in dry-run mode. The backend in use is not
+    /// detected, since probing for nftables support modifies the system.
+    static ref DRY_RUN_BACKEND: Backend = Backend::from_env().unwrap_or(Backend::Iptables);
+}
+
+/// Returns the rules for the given policy in the format of the backend selected with
+/// `TALPID_FIREWALL_BACKEND`, `nft -f` input for nftables and `iptables-restore` input for
+/// iptables, which is the default. Used to record policies in dry-run mode.
+pub fn describe_policy(policy: &FirewallPolicy) -> Option<String> {
+    Some(match *DRY_RUN_BACKEND {
+        Backend::Nftables => nftables::render_policy(policy),
+        Backend::Iptables => iptables::render_policy(policy),
+    })
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Backend {
+    Nftables,
+    Iptables,
+}
+
+impl Backend {
+    /// Returns the backend selected with the `TALPID_FIREWALL_BACKEND` environment variable, if
+    /// any.
+    fn from_env() -> Option<Self> {
+        let firewall_backend = env::var_os("TALPID_FIREWALL_BACKEND");
+        match firewall_backend.as_ref().and_then(|value| value.to_str()) {
+            Some("nftables") => Some(Backend::Nftables),
+            Some("iptables") => Some(Backend::Iptables),
+            Some(_) | None => None,
+        }
+    }
+}
+
+/// The Linux implementation for the firewall. Uses nftables when available and falls back to
+/// legacy iptables otherwise.
+pub enum Firewall {
+    /// Firewall rules are applied in a dedicated nftables table.
+    Nftables(nftables::Firewall),
+    /// Firewall rules are applied in dedicated iptables and ip6tables chains.
+    Iptables(iptables::Firewall),
+}
+
+impl fmt::Display for Firewall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Firewall::Nftables(..) => "nftables",
+            Firewall::Iptables(..) => "iptables",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FirewallT for Firewall {
+    type Error = Error;
+
+    fn new() -> Result<Self> {
+        let firewall = match Backend::from_env() {
+            Some(Backend::Nftables) => Firewall::Nftables(nftables::Firewall::new()?),
+            Some(Backend::Iptables) => Firewall::Iptables(iptables::Firewall::new()?),
+            None => Self::with_detected_backend()?,
+        };
+        log::debug!("Managing firewall via {}", firewall);
+        Ok(firewall)
+    }
+
+    fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
+        match self {
+            Firewall::Nftables(ref mut nftables) => nftables.apply_policy(policy)?,
+            Firewall::Iptables(ref mut iptables) => iptables.apply_policy(policy)?,
+        }
+        Ok(())
+    }
+
+    fn reset_policy(&mut self) -> Result<()> {
+        match self {
+            Firewall::Nftables(ref mut nftables) => nftables.reset_policy()?,
+            Firewall::Iptables(ref mut iptables) => iptables.reset_policy()?,
+        }
+        Ok(())
+    }
+}
+
+impl Firewall {
+    fn with_detected_backend() -> Result<Self> {
+        nftables::Firewall::new()
+            .map(Firewall::Nftables)
+            .or_else(|error| {
+                log::warn!(
+                    "Unable to use nftables, trying iptables: {}",
+                    error.display_chain()
+                );
+                iptables::Firewall::new().map(Firewall::Iptables)
+            })
+            .chain_err(|| ErrorKind::NoFirewallBackend)
+    }
+}
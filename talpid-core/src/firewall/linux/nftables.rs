@@ -0,0 +1,809 @@
+use crate::firewall::{
+    FirewallPolicy, FirewallT, DHCPV6_SERVER_ADDRS, LOCAL_INET6_NET, MULTICAST_INET6_NET,
+    MULTICAST_NET, PRIVATE_NETS, SSDP_IP,
+};
+use crate::tunnel;
+use ipnetwork::IpNetwork;
+use lazy_static::lazy_static;
+use libc;
+use nftnl::{
+    expr::{self, Verdict},
+    nft_expr, nft_expr_bitwise, nft_expr_cmp, nft_expr_ct, nft_expr_meta, nft_expr_payload, table,
+    Batch, Chain, FinalizedBatch, ProtoFamily, Rule, Table,
+};
+use std::{
+    collections::HashSet,
+    env,
+    ffi::{CStr, CString},
+    fmt::{self, Write},
+    net::{IpAddr, Ipv4Addr},
+};
+use talpid_types::net::{
+    is_reached_outside_tunnel, Endpoint, TransportProtocol, OWN_TRAFFIC_FWMARK,
+};
+
+error_chain! {
+    errors {
+        /// Unable to open netlink socket to netfilter
+        NetlinkOpenError { description("Unable to open netlink socket to netfilter") }
+        /// Unable to send netlink command to netfilter
+        NetlinkSendError { description("Unable to send netlink command to netfilter") }
+        /// Error while reading from netlink socket
+        NetlinkRecvError { description("Error while reading from netlink socket") }
+        /// Error while processing an incoming netlink message
+        ProcessNetlinkError { description("Error while processing an incoming netlink message") }
+        /// Failed to verify that our tables are set. Probably means that
+        /// it's the host does not support nftables properly.
+        NetfilterTableNotSetError{ description("Failed to set firewall rules") }
+    }
+    links {
+        Nftnl(nftnl::Error, nftnl::ErrorKind) #[doc = "Error in nftnl"];
+    }
+    foreign_links {
+        IfaceIndexLookupError(crate::linux::IfaceIndexLookupError);
+    }
+}
+
+lazy_static! {
+    /// TODO(linus): This crate is not supposed to be Mullvad-aware. So at some point this should be
+    /// replaced by allowing the table name to be configured from the public API of this crate.
+    static ref TABLE_NAME: CString = CString::new("mullvad").unwrap();
+    static ref IN_CHAIN_NAME: CString = CString::new("in").unwrap();
+    static ref OUT_CHAIN_NAME: CString = CString::new("out").unwrap();
+
+    /// Allows controlling whether firewall rules should have packet counters or not from an env
+    /// variable. Useful for debugging the rules.
+    static ref ADD_COUNTERS: bool = env::var("TALPID_FIREWALL_DEBUG")
+        .map(|v| v == "1")
+        .unwrap_or(false);
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+enum Direction {
+    In,
+    Out,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+enum End {
+    Src,
+    Dst,
+}
+
+impl End {
+    /// The name of the address at this end in nft syntax.
+    fn addr(self) -> &'static str {
+        match self {
+            End::Src => "saddr",
+            End::Dst => "daddr",
+        }
+    }
+
+    /// The name of the port at this end in nft syntax.
+    fn port(self) -> &'static str {
+        match self {
+            End::Src => "sport",
+            End::Dst => "dport",
+        }
+    }
+}
+
+/// The nftables based Linux firewall implementation.
+pub struct Firewall {
+    table_name: CString,
+}
+
+impl FirewallT for Firewall {
+    type Error = Error;
+
+    fn new() -> Result<Self> {
+        let firewall = Firewall {
+            table_name: TABLE_NAME.clone(),
+        };
+        firewall.probe()?;
+        Ok(firewall)
+    }
+
+    fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
+        let table = Table::new(&self.table_name, ProtoFamily::Inet)?;
+        let batch = PolicyBatch::new(&table)?.finalize(&policy)?;
+        self.send_and_process(&batch)?;
+        self.verify_tables(&[&TABLE_NAME])
+    }
+
+    fn reset_policy(&mut self) -> Result<()> {
+        let table = Table::new(&self.table_name, ProtoFamily::Inet)?;
+        let batch = {
+            let mut batch = Batch::new()?;
+            // Our batch will add and remove the table even though the goal is just to remove it.
+            // This because only removing it throws a strange error if the table does not exist.
+            batch.add(&table, nftnl::MsgType::Add)?;
+            batch.add(&table, nftnl::MsgType::Del)?;
+            batch.finalize()?
+        };
+
+        log::debug!("Removing table and chain from netfilter");
+        self.send_and_process(&batch)
+    }
+}
+
+impl Firewall {
+    /// Checks that netfilter accepts our table, without leaving anything behind. If the table
+    /// already exists it is left untouched, along with any policy in it. Otherwise it is added and
+    /// removed again in the same batch.
+    fn probe(&self) -> Result<()> {
+        if self.list_tables()?.contains(self.table_name.as_c_str()) {
+            return Ok(());
+        }
+        let table = Table::new(&self.table_name, ProtoFamily::Inet)?;
+        let batch = {
+            let mut batch = Batch::new()?;
+            batch.add(&table, nftnl::MsgType::Add)?;
+            batch.add(&table, nftnl::MsgType::Del)?;
+            batch.finalize()?
+        };
+        self.send_and_process(&batch)
+    }
+
+    fn send_and_process(&self, batch: &FinalizedBatch) -> Result<()> {
+        let socket =
+            mnl::Socket::new(mnl::Bus::Netfilter).chain_err(|| ErrorKind::NetlinkOpenError)?;
+        socket
+            .send_all(batch)
+            .chain_err(|| ErrorKind::NetlinkSendError)?;
+
+        let portid = socket.portid();
+        let mut buffer = vec![0; nftnl::nft_nlmsg_maxsize() as usize];
+
+        let seq = 0;
+        while let Some(message) = Self::socket_recv(&socket, &mut buffer[..])? {
+            match mnl::cb_run(message, seq, portid).chain_err(|| ErrorKind::ProcessNetlinkError)? {
+                mnl::CbResult::Stop => {
+                    log::trace!("cb_run STOP");
+                    break;
+                }
+                mnl::CbResult::Ok => log::trace!("cb_run OK"),
+            };
+        }
+        Ok(())
+    }
+
+    fn verify_tables(&self, expected_tables: &[&CStr]) -> Result<()> {
+        let table_set = self.list_tables()?;
+        for expected_table in expected_tables {
+            if !table_set.contains(*expected_table) {
+                log::error!(
+                    "Expected '{}' netfilter table to be set, but it is not",
+                    expected_table.to_string_lossy()
+                );
+                bail!(ErrorKind::NetfilterTableNotSetError)
+            }
+        }
+        Ok(())
+    }
+
+    fn list_tables(&self) -> Result<HashSet<CString>> {
+        let socket =
+            mnl::Socket::new(mnl::Bus::Netfilter).chain_err(|| ErrorKind::NetlinkOpenError)?;
+        let portid = socket.portid();
+        let seq = 0;
+
+        let get_tables_msg = table::get_tables_nlmsg(seq);
+        socket
+            .send(&get_tables_msg)
+            .chain_err(|| ErrorKind::NetlinkSendError)?;
+
+        let mut table_set = HashSet::new();
+        let mut msg_buffer = vec![0; nftnl::nft_nlmsg_maxsize() as usize];
+
+        while let Some(message) = Self::socket_recv(&socket, &mut msg_buffer)? {
+            match mnl::cb_run2(message, seq, portid, table::get_tables_cb, &mut table_set)
+                .chain_err(|| ErrorKind::ProcessNetlinkError)?
+            {
+                mnl::CbResult::Stop => {
+                    log::trace!("cb_run STOP");
+                    break;
+                }
+                mnl::CbResult::Ok => log::trace!("cb_run OK"),
+            }
+        }
+        Ok(table_set)
+    }
+
+    fn socket_recv<'a>(socket: &mnl::Socket, buf: &'a mut [u8]) -> Result<Option<&'a [u8]>> {
+        let ret = socket.recv(buf).chain_err(|| ErrorKind::NetlinkRecvError)?;
+        log::trace!("Read {} bytes from netlink", ret);
+        if ret > 0 {
+            Ok(Some(&buf[..ret]))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+struct PolicyBatch<'a> {
+    batch: Batch,
+    in_chain: Chain<'a>,
+    out_chain: Chain<'a>,
+}
+
+impl<'a> PolicyBatch<'a> {
+    /// Bootstrap a new nftnl message batch object and add the initial messages creating the
+    /// table and chains.
+    pub fn new(table: &'a Table) -> Result<Self> {
+        let mut batch = Batch::new()?;
+        let mut out_chain = Chain::new(&*OUT_CHAIN_NAME, table)?;
+        let mut in_chain = Chain::new(&*IN_CHAIN_NAME, table)?;
+        out_chain.set_hook(nftnl::Hook::Out, 0);
+        in_chain.set_hook(nftnl::Hook::In, 0);
+        out_chain.set_policy(nftnl::Policy::Drop);
+        in_chain.set_policy(nftnl::Policy::Drop);
+
+        batch.add(table, nftnl::MsgType::Add)?;
+        batch.add(table, nftnl::MsgType::Del)?;
+        batch.add(table, nftnl::MsgType::Add)?;
+        batch.add(&out_chain, nftnl::MsgType::Add)?;
+        batch.add(&in_chain, nftnl::MsgType::Add)?;
+
+        Ok(PolicyBatch {
+            batch,
+            in_chain,
+            out_chain,
+        })
+    }
+
+    /// Finalize the nftnl message batch by adding every firewall rule needed to satisfy the given
+    /// policy.
+    pub fn finalize(mut self, policy: &FirewallPolicy) -> Result<FinalizedBatch> {
+        for spec in RuleSet::from_policy(policy).rules {
+            let chain = match spec.direction {
+                Direction::In => &self.in_chain,
+                Direction::Out => &self.out_chain,
+            };
+            self.batch.add(&spec.to_rule(chain)?, nftnl::MsgType::Add)?;
+        }
+
+        Ok(self.batch.finalize()?)
+    }
+}
+
+/// Renders the rules for the given policy as `nft -f` input. Used to record policies in dry-run
+/// mode.
+pub fn render_policy(policy: &FirewallPolicy) -> String {
+    let rule_set = RuleSet::from_policy(policy);
+    let mut output = String::new();
+    writeln!(output, "table inet {} {{", TABLE_NAME.to_string_lossy()).unwrap();
+    for (direction, chain_name, hook) in &[
+        (Direction::In, &*IN_CHAIN_NAME, "input"),
+        (Direction::Out, &*OUT_CHAIN_NAME, "output"),
+    ] {
+        writeln!(output, "\tchain {} {{", chain_name.to_string_lossy()).unwrap();
+        writeln!(
+            output,
+            "\t\ttype filter hook {} priority 0; policy drop;",
+            hook
+        )
+        .unwrap();
+        for spec in &rule_set.rules {
+            if spec.direction == *direction {
+                writeln!(output, "\t\t{}", spec).unwrap();
+            }
+        }
+        output.push_str("\t}\n");
+    }
+    output.push_str("}\n");
+    output
+}
+
+/// A single condition that a packet must fulfill for a rule to apply to it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+enum Match {
+    Iface(Direction, String),
+    Net(End, IpNetwork),
+    Ip(End, IpAddr),
+    Port(TransportProtocol, End, u16),
+    Established,
+    EstablishedOrRelated,
+    Mark(u32),
+}
+
+impl fmt::Display for Match {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Match::Iface(Direction::In, iface) => write!(f, "iif \"{}\"", iface),
+            Match::Iface(Direction::Out, iface) => write!(f, "oif \"{}\"", iface),
+            Match::Net(end, net) => write!(f, "{} {} {}", family_name(net.ip()), end.addr(), net),
+            Match::Ip(end, ip) => write!(f, "{} {} {}", family_name(*ip), end.addr(), ip),
+            Match::Port(protocol, end, port) => {
+                write!(f, "{} {} {}", protocol_name(*protocol), end.port(), port)
+            }
+            Match::Established => f.write_str("ct state established"),
+            Match::EstablishedOrRelated => f.write_str("ct state established,related"),
+            Match::Mark(mark) => write!(f, "meta mark {:#x}", mark),
+        }
+    }
+}
+
+/// A rule in one of the chains, accepting or dropping the packets that fulfill all its matches.
+struct RuleSpec {
+    direction: Direction,
+    matches: Vec<Match>,
+    verdict: Verdict,
+}
+
+impl RuleSpec {
+    fn accept(direction: Direction, matches: Vec<Match>) -> Self {
+        RuleSpec {
+            direction,
+            matches,
+            verdict: Verdict::Accept,
+        }
+    }
+
+    fn drop(direction: Direction, matches: Vec<Match>) -> Self {
+        RuleSpec {
+            direction,
+            matches,
+            verdict: Verdict::Drop,
+        }
+    }
+
+    fn to_rule<'a>(&self, chain: &'a Chain) -> Result<Rule<'a>> {
+        let mut rule = Rule::new(chain)?;
+        for rule_match in &self.matches {
+            match rule_match {
+                Match::Iface(direction, iface) => check_iface(&mut rule, *direction, iface)?,
+                Match::Net(end, net) => check_net(&mut rule, *end, *net)?,
+                Match::Ip(end, ip) => check_ip(&mut rule, *end, *ip)?,
+                Match::Port(protocol, end, port) => check_port(&mut rule, *protocol, *end, *port)?,
+                Match::Established => {
+                    check_ct_state(&mut rule, nftnl::expr::ct::States::ESTABLISHED)?
+                }
+                Match::EstablishedOrRelated => check_ct_state(
+                    &mut rule,
+                    nftnl::expr::ct::States::ESTABLISHED | nftnl::expr::ct::States::RELATED,
+                )?,
+                Match::Mark(mark) => check_mark(&mut rule, *mark)?,
+            }
+        }
+        add_verdict(&mut rule, &self.verdict)?;
+        Ok(rule)
+    }
+}
+
+impl fmt::Display for RuleSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for rule_match in &self.matches {
+            write!(f, "{} ", rule_match)?;
+        }
+        if *ADD_COUNTERS {
+            f.write_str("counter ")?;
+        }
+        f.write_str(match self.verdict {
+            Verdict::Drop => "drop",
+            _ => "accept",
+        })
+    }
+}
+
+/// The rules for a policy, in the order they are added to their chains.
+struct RuleSet {
+    rules: Vec<RuleSpec>,
+}
+
+impl RuleSet {
+    fn from_policy(policy: &FirewallPolicy) -> Self {
+        let mut rule_set = RuleSet { rules: Vec::new() };
+        rule_set.add_loopback_rules();
+        rule_set.add_dhcp_rules();
+        rule_set.add_policy_specific_rules(policy);
+        rule_set
+    }
+
+    fn add_loopback_rules(&mut self) {
+        const LOOPBACK_IFACE_NAME: &str = "lo";
+        self.add_allow_interface_rule(Direction::Out, LOOPBACK_IFACE_NAME);
+        self.add_allow_interface_rule(Direction::In, LOOPBACK_IFACE_NAME);
+    }
+
+    fn add_dhcp_rules(&mut self) {
+        use self::TransportProtocol::Udp;
+        const SERVER_PORT_V4: u16 = 67;
+        const CLIENT_PORT_V4: u16 = 68;
+        const SERVER_PORT_V6: u16 = 547;
+        const CLIENT_PORT_V6: u16 = 546;
+
+        self.rules.push(RuleSpec::accept(
+            Direction::Out,
+            vec![
+                Match::Port(Udp, End::Src, CLIENT_PORT_V4),
+                Match::Ip(End::Dst, IpAddr::V4(Ipv4Addr::BROADCAST)),
+                Match::Port(Udp, End::Dst, SERVER_PORT_V4),
+            ],
+        ));
+        self.rules.push(RuleSpec::accept(
+            Direction::In,
+            vec![
+                Match::Port(Udp, End::Src, SERVER_PORT_V4),
+                Match::Port(Udp, End::Dst, CLIENT_PORT_V4),
+            ],
+        ));
+        for dhcpv6_server in &*DHCPV6_SERVER_ADDRS {
+            self.rules.push(RuleSpec::accept(
+                Direction::Out,
+                vec![
+                    Match::Net(End::Src, *LOCAL_INET6_NET),
+                    Match::Port(Udp, End::Src, CLIENT_PORT_V6),
+                    Match::Ip(End::Dst, *dhcpv6_server),
+                    Match::Port(Udp, End::Dst, SERVER_PORT_V6),
+                ],
+            ));
+        }
+        self.rules.push(RuleSpec::accept(
+            Direction::In,
+            vec![
+                Match::Net(End::Src, *LOCAL_INET6_NET),
+                Match::Port(Udp, End::Src, SERVER_PORT_V6),
+                Match::Net(End::Dst, *LOCAL_INET6_NET),
+                Match::Port(Udp, End::Dst, CLIENT_PORT_V6),
+            ],
+        ));
+    }
+
+    fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) {
+        let allow_lan = match policy {
+            FirewallPolicy::Connecting {
+                peer_endpoint,
+                allow_lan,
+                allowed_endpoint,
+            } => {
+                self.add_allow_endpoint_rules(peer_endpoint);
+                if let Some(endpoint) = allowed_endpoint {
+                    self.add_allow_own_endpoint_rules(endpoint, None);
+                }
+                *allow_lan
+            }
+            FirewallPolicy::Connected {
+                peer_endpoints,
+                tunnel,
+                allow_lan,
+                forwarded_ports,
+                dns_servers,
+                split_dns_servers,
+                dns_upstream,
+            } => {
+                for peer_endpoint in peer_endpoints {
+                    self.add_allow_endpoint_rules(peer_endpoint);
+                }
+                if let Some(endpoint) = dns_upstream {
+                    self.add_dns_upstream_rules(endpoint, tunnel);
+                }
+                for protocol in &[TransportProtocol::Udp, TransportProtocol::Tcp] {
+                    self.add_dns_rule(
+                        tunnel,
+                        dns_servers,
+                        split_dns_servers,
+                        *allow_lan,
+                        *protocol,
+                    );
+                }
+                self.add_allow_tunnel_rules(tunnel, forwarded_ports);
+                *allow_lan
+            }
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_endpoint,
+            } => {
+                if let Some(endpoint) = allowed_endpoint {
+                    self.add_allow_own_endpoint_rules(endpoint, None);
+                }
+                *allow_lan
+            }
+        };
+
+        if allow_lan {
+            self.add_allow_lan_rules();
+        }
+    }
+
+    fn add_allow_endpoint_rules(&mut self, endpoint: &Endpoint) {
+        let mut in_matches = endpoint_matches(End::Src, endpoint);
+        in_matches.push(Match::Established);
+        self.rules.push(RuleSpec::accept(Direction::In, in_matches));
+
+        self.rules.push(RuleSpec::accept(
+            Direction::Out,
+            endpoint_matches(End::Dst, endpoint),
+        ));
+    }
+
+    /// Allows traffic to the given endpoint, but only from sockets marked with
+    /// `OWN_TRAFFIC_FWMARK` by this process. Only on `interface` if one is given.
+    fn add_allow_own_endpoint_rules(&mut self, endpoint: &Endpoint, interface: Option<&str>) {
+        let iface_matches = |direction| {
+            interface
+                .map(|interface| Match::Iface(direction, interface.to_owned()))
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+
+        let mut in_matches = iface_matches(Direction::In);
+        in_matches.extend(endpoint_matches(End::Src, endpoint));
+        in_matches.push(Match::Established);
+        self.rules.push(RuleSpec::accept(Direction::In, in_matches));
+
+        let mut out_matches = iface_matches(Direction::Out);
+        out_matches.extend(endpoint_matches(End::Dst, endpoint));
+        out_matches.push(Match::Mark(OWN_TRAFFIC_FWMARK));
+        self.rules
+            .push(RuleSpec::accept(Direction::Out, out_matches));
+    }
+
+    /// Allows only this process to reach the encrypted DNS upstream, and only through the tunnel,
+    /// so that other programs can't bypass the stub resolver by talking to it directly.
+    fn add_dns_upstream_rules(&mut self, endpoint: &Endpoint, tunnel: &tunnel::TunnelMetadata) {
+        self.add_allow_own_endpoint_rules(endpoint, Some(&tunnel.interface[..]));
+        self.rules.push(RuleSpec::drop(
+            Direction::Out,
+            endpoint_matches(End::Dst, endpoint),
+        ));
+    }
+
+    fn add_dns_rule(
+        &mut self,
+        tunnel: &tunnel::TunnelMetadata,
+        dns_servers: &[IpAddr],
+        split_dns_servers: &[IpAddr],
+        allow_lan: bool,
+        protocol: TransportProtocol,
+    ) {
+        for server in dns_servers.iter().chain(split_dns_servers) {
+            // allow DNS traffic to the server through the tunnel
+            self.rules.push(RuleSpec::accept(
+                Direction::Out,
+                vec![
+                    Match::Iface(Direction::Out, tunnel.interface.clone()),
+                    Match::Port(protocol, End::Dst, 53),
+                    Match::Ip(End::Dst, *server),
+                ],
+            ));
+
+            // resolvers on the local network are reached outside the tunnel
+            if allow_lan && is_reached_outside_tunnel(*server) {
+                self.rules.push(RuleSpec::accept(
+                    Direction::Out,
+                    vec![
+                        Match::Port(protocol, End::Dst, 53),
+                        Match::Ip(End::Dst, *server),
+                    ],
+                ));
+            }
+        }
+
+        self.rules.push(RuleSpec::drop(
+            Direction::Out,
+            vec![Match::Port(protocol, End::Dst, 53)],
+        ));
+    }
+
+    fn add_allow_tunnel_rules(&mut self, tunnel: &tunnel::TunnelMetadata, forwarded_ports: &[u16]) {
+        self.add_allow_interface_rule(Direction::Out, &tunnel.interface);
+
+        // Only accept incoming traffic belonging to connections we initiated, or to ports that
+        // are explicitly forwarded to us.
+        self.rules.push(RuleSpec::accept(
+            Direction::In,
+            vec![
+                Match::Iface(Direction::In, tunnel.interface.clone()),
+                Match::EstablishedOrRelated,
+            ],
+        ));
+        for port in forwarded_ports {
+            for protocol in &[TransportProtocol::Tcp, TransportProtocol::Udp] {
+                self.rules.push(RuleSpec::accept(
+                    Direction::In,
+                    vec![
+                        Match::Iface(Direction::In, tunnel.interface.clone()),
+                        Match::Port(*protocol, End::Dst, *port),
+                    ],
+                ));
+            }
+        }
+    }
+
+    fn add_allow_lan_rules(&mut self) {
+        // LAN -> LAN
+        for direction in &[Direction::In, Direction::Out] {
+            for net in &*PRIVATE_NETS {
+                self.add_allow_net_rule(*direction, *net, *net);
+            }
+            self.add_allow_net_rule(*direction, *LOCAL_INET6_NET, *LOCAL_INET6_NET);
+        }
+        // LAN -> multicast
+        for net in &*PRIVATE_NETS {
+            self.add_allow_net_rule(Direction::Out, *net, *MULTICAST_NET);
+            // LAN -> SSDP + WS-Discovery protocols
+            self.rules.push(RuleSpec::accept(
+                Direction::Out,
+                vec![Match::Net(End::Src, *net), Match::Ip(End::Dst, *SSDP_IP)],
+            ));
+        }
+        self.add_allow_net_rule(Direction::Out, *LOCAL_INET6_NET, *MULTICAST_INET6_NET);
+    }
+
+    fn add_allow_interface_rule(&mut self, direction: Direction, iface: &str) {
+        self.rules.push(RuleSpec::accept(
+            direction,
+            vec![Match::Iface(direction, iface.to_owned())],
+        ));
+    }
+
+    fn add_allow_net_rule(
+        &mut self,
+        direction: Direction,
+        source: IpNetwork,
+        destination: IpNetwork,
+    ) {
+        self.rules.push(RuleSpec::accept(
+            direction,
+            vec![
+                Match::Net(End::Src, source),
+                Match::Net(End::Dst, destination),
+            ],
+        ));
+    }
+}
+
+fn endpoint_matches(end: End, endpoint: &Endpoint) -> Vec<Match> {
+    vec![
+        Match::Ip(end, endpoint.address.ip()),
+        Match::Port(endpoint.protocol, end, endpoint.address.port()),
+    ]
+}
+
+fn check_iface(rule: &mut Rule, direction: Direction, iface: &str) -> Result<()> {
+    let iface_index = crate::linux::iface_index(iface)?;
+    rule.add_expr(&match direction {
+        Direction::In => nft_expr!(meta iif),
+        Direction::Out => nft_expr!(meta oif),
+    })?;
+    rule.add_expr(&nft_expr!(cmp == iface_index))?;
+    Ok(())
+}
+
+fn check_net(rule: &mut Rule, end: End, net: IpNetwork) -> Result<()> {
+    // Must check network layer protocol before loading network layer payload
+    check_l3proto(rule, net.ip())?;
+
+    rule.add_expr(&match (net, end) {
+        (IpNetwork::V4(_), End::Src) => nft_expr!(payload ipv4 saddr),
+        (IpNetwork::V4(_), End::Dst) => nft_expr!(payload ipv4 daddr),
+        (IpNetwork::V6(_), End::Src) => nft_expr!(payload ipv6 saddr),
+        (IpNetwork::V6(_), End::Dst) => nft_expr!(payload ipv6 daddr),
+    })?;
+    match net {
+        IpNetwork::V4(_) => rule.add_expr(&nft_expr!(bitwise mask net.mask(), xor 0u32))?,
+        IpNetwork::V6(_) => {
+            rule.add_expr(&nft_expr!(bitwise mask net.mask(), xor &[0u16; 8][..]))?
+        }
+    };
+    rule.add_expr(&nft_expr!(cmp == net.ip()))?;
+
+    Ok(())
+}
+
+fn check_ip(rule: &mut Rule, end: End, ip: IpAddr) -> Result<()> {
+    // Must check network layer protocol before loading network layer payload
+    check_l3proto(rule, ip)?;
+
+    rule.add_expr(&match (ip, end) {
+        (IpAddr::V4(..), End::Src) => nft_expr!(payload ipv4 saddr),
+        (IpAddr::V4(..), End::Dst) => nft_expr!(payload ipv4 daddr),
+        (IpAddr::V6(..), End::Src) => nft_expr!(payload ipv6 saddr),
+        (IpAddr::V6(..), End::Dst) => nft_expr!(payload ipv6 daddr),
+    })?;
+    match ip {
+        IpAddr::V4(addr) => rule.add_expr(&nft_expr!(cmp == addr))?,
+        IpAddr::V6(addr) => rule.add_expr(&nft_expr!(cmp == addr))?,
+    }
+    Ok(())
+}
+
+fn check_port(rule: &mut Rule, protocol: TransportProtocol, end: End, port: u16) -> Result<()> {
+    // Must check transport layer protocol before loading transport layer payload
+    check_l4proto(rule, protocol)?;
+
+    rule.add_expr(&match (protocol, end) {
+        (TransportProtocol::Udp, End::Src) => nft_expr!(payload udp sport),
+        (TransportProtocol::Udp, End::Dst) => nft_expr!(payload udp dport),
+        (TransportProtocol::Tcp, End::Src) => nft_expr!(payload tcp sport),
+        (TransportProtocol::Tcp, End::Dst) => nft_expr!(payload tcp dport),
+    })?;
+    rule.add_expr(&nft_expr!(cmp == port.to_be()))?;
+    Ok(())
+}
+
+fn check_ct_state(rule: &mut Rule, states: nftnl::expr::ct::States) -> Result<()> {
+    rule.add_expr(&nft_expr!(ct state))?;
+    let allowed_states = states.bits();
+    rule.add_expr(&nft_expr!(bitwise mask allowed_states, xor 0u32))?;
+    rule.add_expr(&nft_expr!(cmp != 0u32))?;
+    Ok(())
+}
+
+fn check_mark(rule: &mut Rule, mark: u32) -> Result<()> {
+    rule.add_expr(&nft_expr!(meta mark))?;
+    rule.add_expr(&nft_expr!(cmp == mark))?;
+    Ok(())
+}
+
+fn check_l3proto(rule: &mut Rule, ip: IpAddr) -> Result<()> {
+    rule.add_expr(&nft_expr!(meta nfproto))?;
+    rule.add_expr(&nft_expr!(cmp == l3proto(ip)))?;
+    Ok(())
+}
+
+fn l3proto(addr: IpAddr) -> u8 {
+    match addr {
+        IpAddr::V4(_) => libc::NFPROTO_IPV4 as u8,
+        IpAddr::V6(_) => libc::NFPROTO_IPV6 as u8,
+    }
+}
+
+fn check_l4proto(rule: &mut Rule, protocol: TransportProtocol) -> Result<()> {
+    rule.add_expr(&nft_expr!(meta l4proto))?;
+    rule.add_expr(&nft_expr!(cmp == l4proto(protocol)))?;
+    Ok(())
+}
+
+fn l4proto(protocol: TransportProtocol) -> u8 {
+    match protocol {
+        TransportProtocol::Udp => libc::IPPROTO_UDP as u8,
+        TransportProtocol::Tcp => libc::IPPROTO_TCP as u8,
+    }
+}
+
+fn family_name(addr: IpAddr) -> &'static str {
+    match addr {
+        IpAddr::V4(_) => "ip",
+        IpAddr::V6(_) => "ip6",
+    }
+}
+
+fn protocol_name(protocol: TransportProtocol) -> &'static str {
+    match protocol {
+        TransportProtocol::Udp => "udp",
+        TransportProtocol::Tcp => "tcp",
+    }
+}
+
+fn add_verdict(rule: &mut Rule, verdict: &expr::Verdict) -> Result<()> {
+    if *ADD_COUNTERS {
+        rule.add_expr(&nft_expr!(counter))?;
+    }
+    rule.add_expr(verdict)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connecting_policy_allows_only_own_traffic_to_endpoint() {
+        let peer_endpoint = Endpoint::new(Ipv4Addr::new(1, 2, 3, 4), 1194, TransportProtocol::Udp);
+        let endpoint = Endpoint::new(Ipv4Addr::new(10, 0, 0, 1), 443, TransportProtocol::Tcp);
+        let policy = FirewallPolicy::Connecting {
+            peer_endpoint,
+            allow_lan: false,
+            allowed_endpoint: Some(endpoint),
+        };
+
+        let rules = render_policy(&policy);
+
+        assert!(rules.starts_with("table inet mullvad {\n\tchain in {\n"));
+        assert!(rules.contains("\t\tip daddr 1.2.3.4 udp dport 1194 accept\n"));
+        assert!(rules.contains("\t\tip daddr 10.0.0.1 tcp dport 443 meta mark 0x6d617069 accept\n"));
+        assert!(!rules.contains("\t\tip daddr 10.0.0.1 tcp dport 443 accept\n"));
+    }
+}
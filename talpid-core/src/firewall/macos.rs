@@ -1,7 +1,10 @@
 use super::{FirewallPolicy, FirewallT};
 use pfctl::FilterRuleAction;
-use std::{env, net::Ipv4Addr};
-use talpid_types::net;
+use std::{
+    env,
+    net::{IpAddr, Ipv4Addr},
+};
+use talpid_types::net::{self, is_reached_outside_tunnel};
 
 pub use pfctl::Error;
 
@@ -11,6 +14,12 @@ type Result<T> = ::std::result::Result<T, Error>;
 /// replaced by allowing the anchor name to be configured from the public API of this crate.
 const ANCHOR_NAME: &'static str = "mullvad";
 
+/// Returns a textual list of the rules applied for the given policy. Not available on this
+/// platform, where only the policy itself is recorded in dry-run mode.
+pub fn describe_policy(_policy: &FirewallPolicy) -> Option<String> {
+    None
+}
+
 /// The macOS firewall and DNS implementation.
 pub struct Firewall {
     pf: pfctl::PfCtl,
@@ -85,34 +94,37 @@ impl Firewall {
             FirewallPolicy::Connecting {
                 peer_endpoint,
                 allow_lan,
+                allowed_endpoint,
             } => {
                 let mut rules = vec![self.get_allow_relay_rule(peer_endpoint)?];
+                if let Some(endpoint) = allowed_endpoint {
+                    rules.push(self.get_allow_own_endpoint_rule(endpoint)?);
+                }
                 if allow_lan {
                     rules.append(&mut self.get_allow_lan_rules()?);
                 }
                 Ok(rules)
             }
             FirewallPolicy::Connected {
-                peer_endpoint,
+                peer_endpoints,
                 tunnel,
                 allow_lan,
+                forwarded_ports,
+                dns_servers,
+                split_dns_servers,
+                // The stub resolver using the upstream is only available on Linux
+                dns_upstream: _,
             } => {
-                let allow_tcp_dns_to_relay_rule = self
-                    .create_rule_builder(FilterRuleAction::Pass)
-                    .direction(pfctl::Direction::Out)
-                    .quick(true)
-                    .interface(&tunnel.interface)
-                    .proto(pfctl::Proto::Tcp)
-                    .to(pfctl::Endpoint::new(tunnel.gateway, 53))
-                    .build()?;
-                let allow_udp_dns_to_relay_rule = self
-                    .create_rule_builder(FilterRuleAction::Pass)
-                    .direction(pfctl::Direction::Out)
-                    .quick(true)
-                    .interface(&tunnel.interface)
-                    .proto(pfctl::Proto::Udp)
-                    .to(pfctl::Endpoint::new(tunnel.gateway, 53))
-                    .build()?;
+                let mut rules = vec![];
+                for server in dns_servers.into_iter().chain(split_dns_servers) {
+                    rules.append(
+                        &mut self.get_allow_dns_rules(Some(tunnel.interface.as_str()), server)?,
+                    );
+                    // Resolvers on the local network are reached outside the tunnel
+                    if allow_lan && is_reached_outside_tunnel(server) {
+                        rules.append(&mut self.get_allow_dns_rules(None, server)?);
+                    }
+                }
                 let block_tcp_dns_rule = self
                     .create_rule_builder(FilterRuleAction::Drop)
                     .direction(pfctl::Direction::Out)
@@ -128,22 +140,34 @@ impl Firewall {
                     .to(pfctl::Port::from(53))
                     .build()?;
 
-                let mut rules = vec![
-                    allow_tcp_dns_to_relay_rule,
-                    allow_udp_dns_to_relay_rule,
+                rules.extend(vec![
                     block_tcp_dns_rule,
                     block_udp_dns_rule,
-                    self.get_allow_relay_rule(peer_endpoint)?,
                     self.get_allow_tunnel_rule(tunnel.interface.as_str())?,
-                ];
+                ]);
+                for peer_endpoint in peer_endpoints {
+                    rules.push(self.get_allow_relay_rule(peer_endpoint)?);
+                }
+                for port in forwarded_ports {
+                    rules.append(
+                        &mut self
+                            .get_allow_forwarded_port_rules(tunnel.interface.as_str(), port)?,
+                    );
+                }
 
                 if allow_lan {
                     rules.append(&mut self.get_allow_lan_rules()?);
                 }
                 Ok(rules)
             }
-            FirewallPolicy::Blocked { allow_lan } => {
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_endpoint,
+            } => {
                 let mut rules = Vec::new();
+                if let Some(endpoint) = allowed_endpoint {
+                    rules.push(self.get_allow_own_endpoint_rule(endpoint)?);
+                }
                 if allow_lan {
                     rules.append(&mut self.get_allow_lan_rules()?);
                 }
@@ -166,9 +190,50 @@ impl Firewall {
             .build()?)
     }
 
+    /// Allows DNS traffic to the given server, optionally only over the given interface.
+    fn get_allow_dns_rules(
+        &self,
+        interface: Option<&str>,
+        server: IpAddr,
+    ) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = Vec::with_capacity(2);
+        for proto in &[pfctl::Proto::Tcp, pfctl::Proto::Udp] {
+            let mut rule_builder = self.create_rule_builder(FilterRuleAction::Pass);
+            rule_builder
+                .direction(pfctl::Direction::Out)
+                .quick(true)
+                .proto(*proto)
+                .to(pfctl::Endpoint::new(server, 53));
+            if let Some(interface) = interface {
+                rule_builder.interface(interface);
+            }
+            rules.push(rule_builder.build()?);
+        }
+        Ok(rules)
+    }
+
+    /// Allows traffic to the given endpoint, but only from sockets owned by the same user as
+    /// this process.
+    fn get_allow_own_endpoint_rule(&self, endpoint: net::Endpoint) -> Result<pfctl::FilterRule> {
+        let pfctl_proto = as_pfctl_proto(endpoint.protocol);
+        let uid = unsafe { libc::getuid() };
+
+        Ok(self
+            .create_rule_builder(FilterRuleAction::Pass)
+            .direction(pfctl::Direction::Out)
+            .to(endpoint.address)
+            .proto(pfctl_proto)
+            .user(pfctl::Uid::from(uid))
+            .keep_state(pfctl::StatePolicy::Keep)
+            .tcp_flags(Self::get_tcp_flags())
+            .quick(true)
+            .build()?)
+    }
+
     fn get_allow_tunnel_rule(&self, tunnel_interface: &str) -> Result<pfctl::FilterRule> {
         Ok(self
             .create_rule_builder(FilterRuleAction::Pass)
+            .direction(pfctl::Direction::Out)
             .interface(tunnel_interface)
             .keep_state(pfctl::StatePolicy::Keep)
             .tcp_flags(Self::get_tcp_flags())
@@ -176,6 +241,27 @@ impl Firewall {
             .build()?)
     }
 
+    fn get_allow_forwarded_port_rules(
+        &self,
+        tunnel_interface: &str,
+        port: u16,
+    ) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = vec![];
+        for proto in &[pfctl::Proto::Tcp, pfctl::Proto::Udp] {
+            let rule = self
+                .create_rule_builder(FilterRuleAction::Pass)
+                .direction(pfctl::Direction::In)
+                .interface(tunnel_interface)
+                .proto(*proto)
+                .to(pfctl::Port::from(port))
+                .keep_state(pfctl::StatePolicy::Keep)
+                .quick(true)
+                .build()?;
+            rules.push(rule);
+        }
+        Ok(rules)
+    }
+
     fn get_allow_loopback_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
         let lo0_rule = self
             .create_rule_builder(FilterRuleAction::Pass)
@@ -1,27 +1,36 @@
+use crate::dry_run::Recorder;
 #[cfg(unix)]
 use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
 #[cfg(unix)]
 use lazy_static::lazy_static;
-use std::fmt;
 #[cfg(unix)]
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::{fmt, net::IpAddr};
 use talpid_types::net::Endpoint;
 
-
 #[cfg(target_os = "macos")]
 #[path = "macos.rs"]
 mod imp;
 
 #[cfg(target_os = "linux")]
-#[path = "linux.rs"]
+#[path = "linux/mod.rs"]
 mod imp;
 
 #[cfg(windows)]
 #[path = "windows.rs"]
 mod imp;
 
-pub use self::imp::Error;
-
+error_chain! {
+    errors {
+        /// Failure to record a firewall policy in dry-run mode
+        RecordPolicyError {
+            description("Failed to record firewall policy")
+        }
+    }
+    foreign_links {
+        Platform(imp::Error) #[doc = "Error in the platform specific firewall implementation"];
+    }
+}
 
 #[cfg(unix)]
 lazy_static! {
@@ -53,45 +62,82 @@ pub enum FirewallPolicy {
         peer_endpoint: Endpoint,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// An endpoint that the process applying the policy is still allowed to communicate
+        /// with while the tunnel is being established. Traffic from other processes to this
+        /// endpoint is blocked.
+        allowed_endpoint: Option<Endpoint>,
     },
 
     /// Allow traffic only to server and over tunnel interface
     Connected {
-        /// The peer endpoint that should be allowed.
-        peer_endpoint: Endpoint,
+        /// The peer endpoints that should be allowed. Tunnels with more than one peer have one
+        /// endpoint for each peer.
+        peer_endpoints: Vec<Endpoint>,
         /// Metadata about the tunnel and tunnel interface.
         tunnel: crate::tunnel::TunnelMetadata,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Ports that should accept incoming connections over the tunnel interface.
+        forwarded_ports: Vec<u16>,
+        /// DNS servers that may be reached through the tunnel. DNS traffic to any other server
+        /// is blocked. Servers on the local network are also reachable outside the tunnel when
+        /// `allow_lan` is set.
+        dns_servers: Vec<IpAddr>,
+        /// DNS servers for split DNS domains. These are reachable like the `dns_servers`, through
+        /// the tunnel, and outside of it only if they are on the local network and `allow_lan` is
+        /// set.
+        split_dns_servers: Vec<IpAddr>,
+        /// Encrypted DNS server used by the local stub resolver. Only the process applying the
+        /// policy is allowed to communicate with it.
+        dns_upstream: Option<Endpoint>,
     },
 
     /// Block all network traffic in and out from the computer.
     Blocked {
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// An endpoint that the process applying the policy is still allowed to communicate
+        /// with. Traffic from other processes to this endpoint is blocked.
+        allowed_endpoint: Option<Endpoint>,
     },
 }
 
 impl fmt::Display for FirewallPolicy {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             FirewallPolicy::Connecting {
                 peer_endpoint,
                 allow_lan,
-            } => write!(
-                f,
-                "Connecting to {}, {} LAN",
-                peer_endpoint,
-                if *allow_lan { "Allowing" } else { "Blocking" }
-            ),
+                allowed_endpoint,
+            } => {
+                write!(
+                    f,
+                    "Connecting to {}, {} LAN",
+                    peer_endpoint,
+                    if *allow_lan { "Allowing" } else { "Blocking" }
+                )?;
+                if let Some(endpoint) = allowed_endpoint {
+                    write!(f, ", allowing own traffic to {}", endpoint)?;
+                }
+                Ok(())
+            }
             FirewallPolicy::Connected {
-                peer_endpoint,
+                peer_endpoints,
                 tunnel,
                 allow_lan,
+                forwarded_ports,
+                dns_servers,
+                split_dns_servers,
+                dns_upstream,
             } => write!(
                 f,
-                "Connected to {} over \"{}\" (ip: {}, gw: {}), {} LAN",
-                peer_endpoint,
+                "Connected to {} over \"{}\" (ip: {}, gw: {}, dns: {}, split dns: {}, dns upstream: \
+                 {}, forwarded ports: [{}]), {} LAN",
+                peer_endpoints
+                    .iter()
+                    .map(|endpoint| endpoint.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", "),
                 tunnel.interface,
                 tunnel
                     .ips
@@ -100,13 +146,41 @@ impl fmt::Display for FirewallPolicy {
                     .collect::<Vec<_>>()
                     .join(","),
                 tunnel.gateway,
+                dns_servers
+                    .iter()
+                    .map(|ip| ip.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                split_dns_servers
+                    .iter()
+                    .map(|ip| ip.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                dns_upstream
+                    .as_ref()
+                    .map(|endpoint| endpoint.to_string())
+                    .unwrap_or_else(|| "none".to_owned()),
+                forwarded_ports
+                    .iter()
+                    .map(|port| port.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
                 if *allow_lan { "Allowing" } else { "Blocking" }
             ),
-            FirewallPolicy::Blocked { allow_lan } => write!(
-                f,
-                "Blocked, {} LAN",
-                if *allow_lan { "Allowing" } else { "Blocking" }
-            ),
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_endpoint,
+            } => {
+                write!(
+                    f,
+                    "Blocked, {} LAN",
+                    if *allow_lan { "Allowing" } else { "Blocking" }
+                )?;
+                if let Some(endpoint) = allowed_endpoint {
+                    write!(f, ", allowing own traffic to {}", endpoint)?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -114,29 +188,64 @@ impl fmt::Display for FirewallPolicy {
 /// Manages network security of the computer/device. Can apply and enforce firewall policies
 /// by manipulating the OS firewall and DNS settings.
 pub struct Firewall {
-    inner: imp::Firewall,
+    inner: FirewallBackend,
+}
+
+enum FirewallBackend {
+    System(imp::Firewall),
+    Recording(Recorder),
 }
 
 impl Firewall {
-    /// Returns a new `Firewall`, ready to apply policies.
-    pub fn new() -> Result<Self, Error> {
-        Ok(Firewall {
-            inner: imp::Firewall::new()?,
-        })
+    /// Returns a new `Firewall`, ready to apply policies. If dry-run mode is enabled through the
+    /// environment, policies are recorded instead of applied.
+    pub fn new() -> Result<Self> {
+        match Recorder::from_env() {
+            Some(recorder) => Ok(Self::with_recorder(recorder)),
+            None => Ok(Firewall {
+                inner: FirewallBackend::System(imp::Firewall::new()?),
+            }),
+        }
+    }
+
+    /// Returns a new `Firewall` that records policies with the given `Recorder` instead of
+    /// applying them to the system.
+    pub fn with_recorder(recorder: Recorder) -> Self {
+        log::debug!("Recording firewall policies instead of applying them");
+        Firewall {
+            inner: FirewallBackend::Recording(recorder),
+        }
     }
 
     /// Applies and starts enforcing the given `FirewallPolicy` Makes sure it is being kept in place
     /// until this method is called again with another policy, or until `reset_policy` is called.
-    pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Error> {
+    pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
         log::info!("Applying firewall policy: {}", policy);
-        self.inner.apply_policy(policy)
+        match self.inner {
+            FirewallBackend::System(ref mut firewall) => Ok(firewall.apply_policy(policy)?),
+            FirewallBackend::Recording(ref recorder) => {
+                let mut entry = format!("firewall apply_policy: {}", policy);
+                if let Some(rules) = imp::describe_policy(&policy) {
+                    entry.push('\n');
+                    entry.push_str(rules.trim_end());
+                }
+                recorder
+                    .record(&entry)
+                    .chain_err(|| ErrorKind::RecordPolicyError)
+            }
+        }
     }
 
     /// Resets/removes any currently enforced `FirewallPolicy`. Returns the system to the same state
     /// it had before any policy was applied through this `Firewall` instance.
-    pub fn reset_policy(&mut self) -> Result<(), Error> {
+    pub fn reset_policy(&mut self) -> Result<()> {
         log::info!("Resetting firewall policy");
-        self.inner.reset_policy()
+        match self.inner {
+            FirewallBackend::System(ref mut firewall) => Ok(firewall.reset_policy()?),
+            FirewallBackend::Recording(ref recorder) => recorder
+                .record("firewall reset_policy")
+                .chain_err(|| ErrorKind::RecordPolicyError),
+        }
     }
 }
 
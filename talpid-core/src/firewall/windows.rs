@@ -3,7 +3,7 @@ use std::{net::IpAddr, ptr};
 use self::winfw::*;
 use super::{FirewallPolicy, FirewallT};
 use crate::winnet;
-use log::{debug, error, trace};
+use log::{debug, error, trace, warn};
 use talpid_types::net::Endpoint;
 use widestring::WideCString;
 
@@ -48,6 +48,12 @@ error_chain! {
 
 const WINFW_TIMEOUT_SECONDS: u32 = 2;
 
+/// Returns a textual list of the rules applied for the given policy. Not available on this
+/// platform, where only the policy itself is recorded in dry-run mode.
+pub fn describe_policy(_policy: &FirewallPolicy) -> Option<String> {
+    None
+}
+
 /// The Windows implementation for the firewall and DNS.
 pub struct Firewall(());
 
@@ -72,19 +78,45 @@ impl FirewallT for Firewall {
             FirewallPolicy::Connecting {
                 peer_endpoint,
                 allow_lan,
+                allowed_endpoint,
             } => {
+                if let Some(endpoint) = allowed_endpoint {
+                    warn!(
+                        "Traffic to {} can not be allowed in the connecting state on Windows",
+                        endpoint
+                    );
+                }
                 let cfg = &WinFwSettings::new(allow_lan);
                 self.set_connecting_state(&peer_endpoint, &cfg)
             }
             FirewallPolicy::Connected {
-                peer_endpoint,
+                peer_endpoints,
                 tunnel,
                 allow_lan,
+                // All incoming traffic on the tunnel interface is permitted by the connected policy
+                forwarded_ports: _,
+                dns_servers,
+                // Split DNS is not supported on Windows
+                split_dns_servers: _,
+                // The stub resolver using the upstream is only available on Linux
+                dns_upstream: _,
             } => {
+                // Only WireGuard tunnels can have more than one peer, and they are not supported
+                // on Windows
+                let peer_endpoint = &peer_endpoints[0];
                 let cfg = &WinFwSettings::new(allow_lan);
-                self.set_connected_state(&peer_endpoint, &cfg, &tunnel)
+                self.set_connected_state(peer_endpoint, &cfg, &tunnel, &dns_servers)
             }
-            FirewallPolicy::Blocked { allow_lan } => {
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_endpoint,
+            } => {
+                if let Some(endpoint) = allowed_endpoint {
+                    warn!(
+                        "Traffic to {} can not be allowed in the blocked state on Windows",
+                        endpoint
+                    );
+                }
                 let cfg = &WinFwSettings::new(allow_lan);
                 self.set_blocked_state(&cfg)
             }
@@ -136,15 +168,27 @@ impl Firewall {
         endpoint: &Endpoint,
         winfw_settings: &WinFwSettings,
         tunnel_metadata: &crate::tunnel::TunnelMetadata,
+        dns_servers: &[IpAddr],
     ) -> Result<()> {
         trace!("Applying 'connected' firewall policy");
         let ip_str = Self::widestring_ip(&endpoint.address.ip());
-        let gateway_str = Self::widestring_ip(&tunnel_metadata.gateway.into());
+        // The firewall module only accepts a single DNS server
+        if dns_servers.len() > 1 {
+            warn!(
+                "Only allowing DNS traffic to the first of {} DNS servers",
+                dns_servers.len()
+            );
+        }
+        let dns_server = dns_servers
+            .first()
+            .cloned()
+            .unwrap_or(tunnel_metadata.gateway);
+        let dns_str = Self::widestring_ip(&dns_server);
 
         let tunnel_alias =
             WideCString::new(tunnel_metadata.interface.encode_utf16().collect::<Vec<_>>()).unwrap();
 
-        // ip_str, gateway_str and tunnel_alias have to outlive winfw_relay
+        // ip_str, dns_str and tunnel_alias have to outlive winfw_relay
         let winfw_relay = WinFwRelay {
             ip: ip_str.as_wide_c_str().as_ptr(),
             port: endpoint.address.port(),
@@ -165,7 +209,7 @@ impl Firewall {
                 winfw_settings,
                 &winfw_relay,
                 tunnel_alias.as_wide_c_str().as_ptr(),
-                gateway_str.as_wide_c_str().as_ptr(),
+                dns_str.as_wide_c_str().as_ptr(),
             )
             .into_result()
         }
@@ -28,6 +28,10 @@ pub mod network_interface;
 /// Abstraction over operating system routing table.
 pub mod routing;
 
+/// Network namespaces for running processes through the tunnel.
+#[cfg(target_os = "linux")]
+pub mod netns;
+
 mod offline;
 
 /// Working with processes.
@@ -42,6 +46,9 @@ pub mod logging;
 /// Abstractions and extra features on `std::mpsc`
 pub mod mpsc;
 
+/// Recording of firewall and DNS changes instead of applying them.
+pub mod dry_run;
+
 /// Abstractions over operating system firewalls.
 pub mod firewall;
 
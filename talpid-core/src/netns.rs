@@ -0,0 +1,287 @@
+use crate::network_interface::{self, NetworkInterface};
+use std::{
+    ffi::CString,
+    fs,
+    io::{self, Write},
+    net::IpAddr,
+    os::unix::{io::AsRawFd, process::CommandExt},
+    path::{Path, PathBuf},
+    process::{Child, Command},
+};
+
+error_chain! {
+    errors {
+        /// The name can't be used for a network namespace
+        InvalidNameError(name: String) {
+            description("Invalid network namespace name")
+            display("Invalid network namespace name: {}", name)
+        }
+        /// Failed to create the network namespace
+        CreateNamespaceError(name: String) {
+            description("Failed to create network namespace")
+            display("Failed to create network namespace {}", name)
+        }
+        /// Failed to move a network interface between namespaces
+        MoveLinkError(interface: String) {
+            description("Failed to move network interface")
+            display("Failed to move network interface {}", interface)
+        }
+        /// Failed to write the DNS configuration of the namespace
+        WriteResolvConfError {
+            description("Failed to write resolv.conf for network namespace")
+        }
+        /// Failed to open the namespace file
+        OpenNamespaceError(name: String) {
+            description("Failed to open network namespace")
+            display("Failed to open network namespace {}", name)
+        }
+        /// Failed to make the current thread enter the namespace
+        EnterNamespaceError(name: String) {
+            description("Failed to enter network namespace")
+            display("Failed to enter network namespace {}", name)
+        }
+        /// Failed to move the current thread back to the namespace it was in
+        LeaveNamespaceError(name: String) {
+            description("Failed to leave network namespace")
+            display("Failed to leave network namespace {}", name)
+        }
+        /// Failed to start a process in the namespace
+        SpawnError {
+            description("Failed to start process in network namespace")
+        }
+    }
+}
+
+/// Directory where `ip netns` keeps the bind mounts of named network namespaces.
+const NETNS_RUN_DIR: &str = "/var/run/netns";
+/// Network namespace of the calling thread.
+const CURRENT_THREAD_NETNS: &str = "/proc/thread-self/ns/net";
+/// Directory with per namespace configuration files, which `ip netns exec` bind mounts over the
+/// ones in `/etc`.
+const NETNS_ETC_DIR: &str = "/etc/netns";
+
+/// A named network namespace, as managed by `ip netns`.
+///
+/// The namespace is never deleted, so processes started in it stay there when the tunnel goes
+/// down. Without the tunnel interface they have no route out of the namespace.
+pub struct NetworkNamespace {
+    name: String,
+}
+
+impl NetworkNamespace {
+    /// Opens the namespace with the given name, creating it if it doesn't exist yet.
+    pub fn create(name: &str) -> Result<Self> {
+        ensure!(
+            !name.is_empty() && name != "." && name != ".." && !name.contains('/'),
+            ErrorKind::InvalidNameError(name.to_owned())
+        );
+        let namespace = NetworkNamespace {
+            name: name.to_owned(),
+        };
+        if !namespace.path().exists() {
+            duct::cmd!("ip", "netns", "add", name)
+                .run()
+                .chain_err(|| ErrorKind::CreateNamespaceError(name.to_owned()))?;
+            duct::cmd!("ip", "-n", name, "link", "set", "dev", "lo", "up")
+                .run()
+                .chain_err(|| ErrorKind::CreateNamespaceError(name.to_owned()))?;
+        }
+        Ok(namespace)
+    }
+
+    /// Name of the namespace.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn path(&self) -> PathBuf {
+        Path::new(NETNS_RUN_DIR).join(&self.name)
+    }
+
+    fn resolv_conf_path(&self) -> PathBuf {
+        Path::new(NETNS_ETC_DIR)
+            .join(&self.name)
+            .join("resolv.conf")
+    }
+
+    /// Moves an interface from the daemon's namespace into this one. Moving an interface removes
+    /// its addresses and brings it down, so it has to be configured again afterwards.
+    pub fn move_link(&self, interface: &str) -> Result<NamespacedInterface> {
+        duct::cmd!("ip", "link", "set", "dev", interface, "netns", &self.name)
+            .run()
+            .chain_err(|| ErrorKind::MoveLinkError(interface.to_owned()))?;
+        Ok(NamespacedInterface {
+            namespace: self.name.clone(),
+            name: interface.to_owned(),
+        })
+    }
+
+    /// Moves an interface from this namespace back into the namespace of init.
+    pub fn return_link(&self, interface: &str) -> Result<()> {
+        duct::cmd!("ip", "-n", &self.name, "link", "set", "dev", interface, "netns", "1")
+            .run()
+            .map(|_| ())
+            .chain_err(|| ErrorKind::MoveLinkError(interface.to_owned()))
+    }
+
+    /// Sets the DNS servers used by processes in the namespace. The host's DNS configuration is
+    /// left as it is.
+    pub fn set_dns(&self, servers: &[IpAddr]) -> Result<()> {
+        let path = self.resolv_conf_path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).chain_err(|| ErrorKind::WriteResolvConfError)?;
+        }
+        let mut file = fs::File::create(&path).chain_err(|| ErrorKind::WriteResolvConfError)?;
+        for server in servers {
+            writeln!(file, "nameserver {}", server)
+                .chain_err(|| ErrorKind::WriteResolvConfError)?;
+        }
+        Ok(())
+    }
+
+    /// Moves the calling thread into the namespace. Sockets the thread opens afterwards belong to
+    /// the namespace.
+    pub fn enter(&self) -> Result<()> {
+        let file = fs::File::open(self.path())
+            .chain_err(|| ErrorKind::OpenNamespaceError(self.name.clone()))?;
+        let result = unsafe { libc::setns(file.as_raw_fd(), libc::CLONE_NEWNET) };
+        if result < 0 {
+            return Err(io::Error::last_os_error())
+                .chain_err(|| ErrorKind::EnterNamespaceError(self.name.clone()));
+        }
+        Ok(())
+    }
+
+    /// Runs `f` on the calling thread from within the namespace, and moves the thread back to the
+    /// namespace it was in afterwards.
+    pub fn run<T>(&self, f: impl FnOnce() -> T) -> Result<T> {
+        let original_namespace = fs::File::open(CURRENT_THREAD_NETNS)
+            .chain_err(|| ErrorKind::EnterNamespaceError(self.name.clone()))?;
+        self.enter()?;
+        let result = f();
+        if unsafe { libc::setns(original_namespace.as_raw_fd(), libc::CLONE_NEWNET) } < 0 {
+            return Err(io::Error::last_os_error())
+                .chain_err(|| ErrorKind::LeaveNamespaceError(self.name.clone()));
+        }
+        Ok(result)
+    }
+
+    /// Starts `command` in the namespace as the given user and groups, in a new session. Like
+    /// `ip netns exec`, the process sees the namespace's resolv.conf as `/etc/resolv.conf`.
+    pub fn spawn(
+        &self,
+        mut command: Command,
+        uid: u32,
+        gid: u32,
+        groups: Vec<u32>,
+    ) -> Result<Child> {
+        let file = fs::File::open(self.path())
+            .chain_err(|| ErrorKind::OpenNamespaceError(self.name.clone()))?;
+        let namespace_fd = file.as_raw_fd();
+        let resolv_conf = self.resolv_conf_path();
+        let resolv_conf = if resolv_conf.exists() {
+            Some(
+                CString::new(resolv_conf.to_string_lossy().into_owned())
+                    .chain_err(|| ErrorKind::SpawnError)?,
+            )
+        } else {
+            None
+        };
+        let root = CString::new("/").unwrap();
+        let system_resolv_conf = CString::new("/etc/resolv.conf").unwrap();
+
+        let setup = move || {
+            check_result(unsafe { libc::setsid() })?;
+            check_result(unsafe { libc::unshare(libc::CLONE_NEWNS) })?;
+            // Keep the bind mount below from propagating back to the daemon's mount namespace
+            check_result(unsafe {
+                libc::mount(
+                    std::ptr::null(),
+                    root.as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_REC | libc::MS_SLAVE,
+                    std::ptr::null(),
+                )
+            })?;
+            if let Some(resolv_conf) = &resolv_conf {
+                check_result(unsafe {
+                    libc::mount(
+                        resolv_conf.as_ptr(),
+                        system_resolv_conf.as_ptr(),
+                        std::ptr::null(),
+                        libc::MS_BIND,
+                        std::ptr::null(),
+                    )
+                })?;
+            }
+            check_result(unsafe { libc::setns(namespace_fd, libc::CLONE_NEWNET) })?;
+            check_result(unsafe { libc::setgroups(groups.len(), groups.as_ptr()) })?;
+            check_result(unsafe { libc::setgid(gid) })?;
+            check_result(unsafe { libc::setuid(uid) })?;
+            Ok(())
+        };
+        // Only async-signal-safe calls are made between fork and exec
+        unsafe { command.pre_exec(setup) };
+        let child = command.spawn().chain_err(|| ErrorKind::SpawnError)?;
+        // The namespace file has to stay open until the child has entered the namespace
+        drop(file);
+        Ok(child)
+    }
+}
+
+/// A network interface inside a network namespace other than the daemon's own.
+pub struct NamespacedInterface {
+    namespace: String,
+    name: String,
+}
+
+impl NamespacedInterface {
+    /// Runs `ip` with the given arguments from within the namespace of the interface.
+    fn run_ip(
+        &self,
+        args: &[&str],
+        error: network_interface::ErrorKind,
+    ) -> network_interface::Result<()> {
+        let mut ip_args = vec!["-n", self.namespace.as_str()];
+        ip_args.extend_from_slice(args);
+        let result = duct::cmd("ip", &ip_args).run().map(|_| ());
+        // The `ResultExt` of this module would turn the error into a namespace error
+        network_interface::ResultExt::chain_err(result, || error)
+    }
+}
+
+impl NetworkInterface for NamespacedInterface {
+    fn set_up(&mut self, up: bool) -> network_interface::Result<()> {
+        let state = if up { "up" } else { "down" };
+        self.run_ip(
+            &["link", "set", "dev", &self.name, state],
+            network_interface::ErrorKind::ToggleDeviceError,
+        )
+    }
+
+    fn set_ip(&mut self, ip: IpAddr) -> network_interface::Result<()> {
+        self.run_ip(
+            &["addr", "add", &ip.to_string(), "dev", &self.name],
+            network_interface::ErrorKind::SetIpError,
+        )
+    }
+
+    fn set_mtu(&mut self, mtu: u16) -> network_interface::Result<()> {
+        self.run_ip(
+            &["link", "set", "dev", &self.name, "mtu", &mtu.to_string()],
+            network_interface::ErrorKind::ToggleDeviceError,
+        )
+    }
+
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+}
+
+fn check_result(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
@@ -2,11 +2,11 @@ use crate::tunnel_state_machine::TunnelCommand;
 use error_chain::ChainedError;
 use futures::{future::Either, sync::mpsc::UnboundedSender, Future, Stream};
 use iproute2::Link;
-use log::{error, trace, warn};
+use log::{debug, error, trace, warn};
 use netlink_socket::{Protocol, SocketAddr, TokioSocket};
 use rtnetlink::{
     LinkFlags, LinkHeader, LinkLayerType, LinkMessage, NetlinkCodec, NetlinkFramed, NetlinkMessage,
-    RtnlMessage,
+    RouteMessage, RtnlMessage,
 };
 use std::{collections::BTreeSet, thread};
 
@@ -32,6 +32,12 @@ error_chain! {
 
 const RTMGRP_NOTIFY: u32 = 1;
 const RTMGRP_LINK: u32 = 2;
+const RTMGRP_IPV4_ROUTE: u32 = 0x40;
+const RTMGRP_IPV6_ROUTE: u32 = 0x400;
+
+/// The routing table with the default routes of the host. Routes added for the tunnel are kept in
+/// other tables or namespaces.
+const RT_TABLE_MAIN: u8 = 254;
 
 pub struct MonitorHandle;
 
@@ -39,7 +45,10 @@ pub fn spawn_monitor(sender: UnboundedSender<TunnelCommand>) -> Result<MonitorHa
     let mut socket =
         TokioSocket::new(Protocol::Route).chain_err(|| ErrorKind::NetlinkConnectionError)?;
     socket
-        .bind(&SocketAddr::new(0, RTMGRP_NOTIFY | RTMGRP_LINK))
+        .bind(&SocketAddr::new(
+            0,
+            RTMGRP_NOTIFY | RTMGRP_LINK | RTMGRP_IPV4_ROUTE | RTMGRP_IPV6_ROUTE,
+        ))
         .chain_err(|| ErrorKind::NetlinkBindError)?;
 
     let channel = NetlinkFramed::new(socket, NetlinkCodec::<NetlinkMessage>::new());
@@ -105,6 +114,9 @@ fn monitor_event_loop(
             match payload {
                 RtnlMessage::NewLink(link_message) => link_monitor.new_link(link_message),
                 RtnlMessage::DelLink(link_message) => link_monitor.del_link(link_message),
+                RtnlMessage::NewRoute(route_message) | RtnlMessage::DelRoute(route_message) => {
+                    link_monitor.route_changed(&route_message)
+                }
                 _ => trace!("Ignoring unknown link message"),
             }
 
@@ -157,6 +169,19 @@ impl LinkMonitor {
         }
     }
 
+    /// Tells the tunnel state machine that the network changed when a default route of the main
+    /// routing table is added or removed while online. Other route changes are ignored.
+    pub fn route_changed(&mut self, route_message: &RouteMessage) {
+        let header = &route_message.header;
+        if header.destination_length != 0 || header.table != RT_TABLE_MAIN {
+            return;
+        }
+        if !self.is_offline {
+            debug!("Default route changed");
+            let _ = self.sender.unbounded_send(TunnelCommand::IsOffline(false));
+        }
+    }
+
     fn set_is_offline(&mut self, is_offline: bool) {
         if self.is_offline != is_offline {
             self.is_offline = is_offline;
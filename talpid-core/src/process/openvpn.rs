@@ -42,6 +42,14 @@ static BASE_ARGUMENTS: &[&[&str]] = &[
     ],
 ];
 
+/// Options that only make sense when the daemon supervises OpenVPN and reconnects by restarting
+/// it. They are left out of configuration files.
+static DAEMON_ONLY_OPTIONS: &[&str] = &["--ping-exit", "--connect-retry", "--connect-retry-max"];
+
+/// Options that make OpenVPN reconnect by itself, replacing the daemon only ones in
+/// configuration files.
+static CONFIG_FILE_ARGUMENTS: &[&[&str]] = &[&["--ping-restart", "20"]];
+
 static ALLOWED_TLS_CIPHERS: &[&str] = &[
     "TLS-DHE-RSA-WITH-AES-256-GCM-SHA384",
     "TLS-DHE-RSA-WITH-AES-256-CBC-SHA",
@@ -231,6 +239,36 @@ impl OpenVpnCommand {
         args
     }
 
+    /// Returns the arguments formatted as an OpenVPN configuration file, with one option per
+    /// line. Options that rely on the daemon to restart OpenVPN are replaced, so that the file
+    /// can be used on its own.
+    pub fn to_config_file(&self) -> String {
+        let mut arguments = self.get_arguments();
+        for arglist in CONFIG_FILE_ARGUMENTS.iter() {
+            arguments.extend(arglist.iter().map(OsString::from));
+        }
+        let mut config = String::new();
+        let mut is_skipped = false;
+        for arg in arguments {
+            let arg = arg.to_string_lossy();
+            if arg.starts_with("--") {
+                is_skipped = DAEMON_ONLY_OPTIONS.iter().any(|option| *option == arg);
+                if is_skipped {
+                    continue;
+                }
+                if !config.is_empty() {
+                    config.push('\n');
+                }
+                config.push_str(&arg[2..]);
+            } else if !is_skipped {
+                config.push(' ');
+                config.push_str(&arg);
+            }
+        }
+        config.push('\n');
+        config
+    }
+
     fn base_arguments() -> Vec<&'static str> {
         let mut args = vec![];
         for arglist in BASE_ARGUMENTS.iter() {
@@ -391,6 +429,20 @@ mod tests {
         assert!(testee_args.contains(&OsString::from("3333")));
     }
 
+    #[test]
+    fn formats_config_file() {
+        let remote = Endpoint::new(Ipv4Addr::new(127, 0, 0, 1), 3333, TransportProtocol::Tcp);
+
+        let config = OpenVpnCommand::new("").remote(remote).to_config_file();
+
+        assert!(config.starts_with("client\nnobind\n"));
+        assert!(config.contains("\nproto tcp-client\nremote 127.0.0.1 3333\n"));
+        assert!(config.contains("\nping 4\n"));
+        assert!(config.ends_with("\nping-restart 20\n"));
+        assert!(!config.contains("ping-exit"));
+        assert!(!config.contains("connect-retry"));
+    }
+
     #[test]
     fn passes_plugin_path() {
         let path = "./a/path";
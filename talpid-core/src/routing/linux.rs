@@ -2,6 +2,7 @@ use super::{NetNode, RequiredRoutes};
 
 use super::subprocess::{Exec, RunExpr};
 use std::{collections::HashSet, net::IpAddr};
+use talpid_types::net::OWN_TRAFFIC_FWMARK;
 
 
 error_chain! {
@@ -80,6 +81,8 @@ struct Table {
 }
 
 pub struct RouteManager {
+    // routes are managed in this network namespace instead of the daemon's own, if set
+    namespace: Option<String>,
     added_routes: HashSet<super::Route>,
     added_tables: HashSet<Table>,
     // the main routing table only has to be adjusted for default routes
@@ -88,6 +91,26 @@ pub struct RouteManager {
 }
 
 impl RouteManager {
+    /// Creates a route manager that manages the routes of the given network namespace.
+    pub fn new_in_namespace(namespace: &str) -> Self {
+        RouteManager {
+            namespace: Some(namespace.to_owned()),
+            added_routes: HashSet::new(),
+            added_tables: HashSet::new(),
+            main_table_suppress_by_prefix_set_v4: false,
+            main_table_suppress_by_prefix_set_v6: false,
+        }
+    }
+
+    fn ip_cmd(&self, version: &IpVersion) -> Exec {
+        let cmd = Exec::cmd("ip");
+        let cmd = match &self.namespace {
+            Some(namespace) => cmd.arg("-n").arg(namespace),
+            None => cmd,
+        };
+        cmd.arg(version.as_ref())
+    }
+
     // This function adjusts main routing table to not make any routing decisions based on rules
     // with a prefix of 0. This is to bypass the main table for default routes.
     fn set_suppress_prefix_length_on_main_routing_table(
@@ -100,18 +123,16 @@ impl RouteManager {
         {
             return Ok(());
         }
-        duct::cmd!(
-            "ip",
-            version.as_ref(),
-            "rule",
-            if set_rule { "add" } else { "delete" },
-            "table",
-            "main",
-            "suppress_prefixlength",
-            "0"
-        )
-        .run_expr()
-        .chain_err(|| ErrorKind::FailedToAdjustMainRoutingTable)?;
+        self.ip_cmd(&version)
+            .arg("rule")
+            .arg(if set_rule { "add" } else { "delete" })
+            .arg("table")
+            .arg("main")
+            .arg("suppress_prefixlength")
+            .arg("0")
+            .into_expr()
+            .run_expr()
+            .chain_err(|| ErrorKind::FailedToAdjustMainRoutingTable)?;
         if version.is_ipv4() {
             self.main_table_suppress_by_prefix_set_v4 = set_rule;
         } else {
@@ -121,14 +142,15 @@ impl RouteManager {
     }
 
     fn add_route(&mut self, route: super::Route, fwmark: &Option<String>) -> Result<()> {
-        if route.prefix.prefix() == 0 {
+        // The main table only needs to be bypassed when the routes go into a separate table
+        if route.prefix.prefix() == 0 && fwmark.is_some() {
             self.set_suppress_prefix_length_on_main_routing_table(route.prefix.ip().into(), true)?;
         }
 
         let version = IpVersion::new(route.prefix.ip());
 
-        let mut cmd = Exec::cmd("ip")
-            .arg(version.as_ref())
+        let mut cmd = self
+            .ip_cmd(&version)
             .arg("route")
             .arg("add")
             .arg(route.prefix.to_string());
@@ -161,20 +183,41 @@ impl RouteManager {
         if self.added_tables.contains(&added_table) {
             return Ok(());
         }
-        duct::cmd!(
-            "ip",
-            added_table.version.as_ref(),
-            "rule",
-            "add",
-            "not",
-            "fwmark",
-            &added_table.fwmark,
-            "table",
-            &added_table.fwmark
-        )
-        .run_expr()
-        .chain_err(|| ErrorKind::FailedToSetRuleForFwmark)?;
-
+        self.ip_cmd(&added_table.version)
+            .arg("rule")
+            .arg("add")
+            .arg("not")
+            .arg("fwmark")
+            .arg(&added_table.fwmark)
+            .arg("table")
+            .arg(&added_table.fwmark)
+            .into_expr()
+            .run_expr()
+            .chain_err(|| ErrorKind::FailedToSetRuleForFwmark)?;
+        // Rules added later take precedence, so the daemon's own traffic, like API requests made
+        // before the tunnel is up, keeps using the main table
+        let own_traffic_rule = self
+            .ip_cmd(&added_table.version)
+            .arg("rule")
+            .arg("add")
+            .arg("fwmark")
+            .arg(OWN_TRAFFIC_FWMARK.to_string())
+            .arg("table")
+            .arg("main")
+            .into_expr()
+            .run_expr()
+            .chain_err(|| ErrorKind::FailedToSetRuleForFwmark);
+        if let Err(e) = own_traffic_rule {
+            let _ = self
+                .ip_cmd(&added_table.version)
+                .arg("rule")
+                .arg("delete")
+                .arg("table")
+                .arg(&added_table.fwmark)
+                .into_expr()
+                .run_expr();
+            return Err(e);
+        }
 
         self.added_tables.insert(added_table);
         Ok(())
@@ -182,17 +225,16 @@ impl RouteManager {
 
     fn clear_routes(&mut self) -> Result<()> {
         let mut end_result = Ok(());
-        for route in self.added_routes.drain() {
+        for route in self.added_routes.drain().collect::<Vec<_>>() {
             let ip_vers: IpVersion = route.prefix.ip().into();
-            let result = duct::cmd!(
-                "ip",
-                ip_vers.as_ref(),
-                "route",
-                "delete",
-                route.prefix.to_string()
-            )
-            .run_expr()
-            .chain_err(|| ErrorKind::FailedToRemoveRoute);
+            let result = self
+                .ip_cmd(&ip_vers)
+                .arg("route")
+                .arg("delete")
+                .arg(route.prefix.to_string())
+                .into_expr()
+                .run_expr()
+                .chain_err(|| ErrorKind::FailedToRemoveRoute);
             if let Err(e) = result {
                 log::error!("Failed to remove route {} - {}", route.prefix, e);
                 end_result = Err(e);
@@ -203,17 +245,28 @@ impl RouteManager {
 
     fn clear_tables(&mut self) -> Result<()> {
         let mut end_result = Ok(());
-        for table in self.added_tables.drain() {
-            let result = duct::cmd!(
-                "ip",
-                table.version.as_ref(),
-                "rule",
-                "delete",
-                "table",
-                &table.fwmark
-            )
-            .run_expr()
-            .chain_err(|| ErrorKind::FailedToRemoveTable);
+        for table in self.added_tables.drain().collect::<Vec<_>>() {
+            let own_traffic_result = self
+                .ip_cmd(&table.version)
+                .arg("rule")
+                .arg("delete")
+                .arg("fwmark")
+                .arg(OWN_TRAFFIC_FWMARK.to_string())
+                .arg("table")
+                .arg("main")
+                .into_expr()
+                .run_expr()
+                .chain_err(|| ErrorKind::FailedToRemoveTable);
+            let result = self
+                .ip_cmd(&table.version)
+                .arg("rule")
+                .arg("delete")
+                .arg("table")
+                .arg(&table.fwmark)
+                .into_expr()
+                .run_expr()
+                .chain_err(|| ErrorKind::FailedToRemoveTable)
+                .and(own_traffic_result);
 
             if let Err(e) = result {
                 log::error!("Failed to remove routing table {} - {}", &table.fwmark, e);
@@ -256,6 +309,7 @@ impl super::RoutingT for RouteManager {
     type Error = Error;
     fn new() -> Result<Self> {
         Ok(RouteManager {
+            namespace: None,
             added_routes: HashSet::new(),
             added_tables: HashSet::new(),
             // the main routing table only has to be adjusted for default routes
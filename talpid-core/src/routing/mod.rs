@@ -60,6 +60,14 @@ impl RouteManager {
         })
     }
 
+    /// Creates a RouteManager for the routing table of a network namespace.
+    #[cfg(target_os = "linux")]
+    pub fn new_in_namespace(namespace: &str) -> Self {
+        RouteManager {
+            inner: imp::RouteManager::new_in_namespace(namespace),
+        }
+    }
+
     /// Set routes in the routing table.
     pub fn add_routes(&mut self, required_routes: RequiredRoutes) -> Result<(), imp::Error> {
         self.inner.add_routes(required_routes)
@@ -0,0 +1,175 @@
+use super::stats;
+use error_chain::ChainedError;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use talpid_types::tunnel::TunnelStats;
+
+/// How often the traffic counters of the tunnel are sampled.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How long the tunnel may go without receiving anything while sending, before it's considered
+/// stalled.
+const STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Watches the traffic counters of a tunnel interface, to detect tunnels that stopped receiving
+/// traffic without the tunnel itself noticing. Stops when dropped.
+pub struct ConnectivityMonitor {
+    stop: Arc<AtomicBool>,
+}
+
+impl ConnectivityMonitor {
+    /// Starts monitoring the given tunnel interface, which is in the network namespace
+    /// `namespace` if set. `on_stall` is called with the time since traffic was first sent
+    /// without anything being received, once that exceeds the timeout.
+    pub fn start<F>(
+        interface: String,
+        is_wireguard: bool,
+        namespace: Option<String>,
+        on_stall: F,
+    ) -> Self
+    where
+        F: FnOnce(Duration) + Send + 'static,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+
+        thread::spawn(move || {
+            let mut checker = StallChecker::new(STALL_TIMEOUT);
+            loop {
+                thread::sleep(CHECK_INTERVAL);
+                if thread_stop.load(Ordering::SeqCst) {
+                    return;
+                }
+
+                let stats = match stats::get_tunnel_stats(
+                    &interface,
+                    is_wireguard,
+                    namespace.as_ref().map(String::as_str),
+                ) {
+                    Ok(stats) => stats,
+                    Err(error) => {
+                        log::warn!(
+                            "{}",
+                            error.display_chain_with_msg("Unable to monitor tunnel connectivity")
+                        );
+                        return;
+                    }
+                };
+                if let Some(stalled_for) = checker.update(&stats, Instant::now()) {
+                    on_stall(stalled_for);
+                    return;
+                }
+            }
+        });
+
+        ConnectivityMonitor { stop }
+    }
+}
+
+impl Drop for ConnectivityMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Keeps track of when the tunnel last received anything, and when it first sent something after
+/// that.
+struct StallChecker {
+    timeout: Duration,
+    last_rx_bytes: Option<u64>,
+    tx_bytes_at_last_rx: u64,
+    first_unanswered_tx: Option<Instant>,
+}
+
+impl StallChecker {
+    fn new(timeout: Duration) -> Self {
+        StallChecker {
+            timeout,
+            last_rx_bytes: None,
+            tx_bytes_at_last_rx: 0,
+            first_unanswered_tx: None,
+        }
+    }
+
+    /// Returns the time since traffic was first sent without anything being received, if the
+    /// tunnel is stalled.
+    fn update(&mut self, stats: &TunnelStats, now: Instant) -> Option<Duration> {
+        if self.last_rx_bytes != Some(stats.rx_bytes) {
+            self.last_rx_bytes = Some(stats.rx_bytes);
+            self.tx_bytes_at_last_rx = stats.tx_bytes;
+            self.first_unanswered_tx = None;
+            return None;
+        }
+
+        if stats.tx_bytes <= self.tx_bytes_at_last_rx {
+            return None;
+        }
+        let first_unanswered_tx = *self.first_unanswered_tx.get_or_insert(now);
+        let unanswered_for = now.duration_since(first_unanswered_tx);
+        if unanswered_for >= self.timeout {
+            Some(unanswered_for)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(rx_bytes: u64, tx_bytes: u64) -> TunnelStats {
+        TunnelStats {
+            rx_bytes,
+            tx_bytes,
+            ..TunnelStats::default()
+        }
+    }
+
+    #[test]
+    fn only_stalled_when_sending_without_receiving() {
+        let timeout = Duration::from_secs(30);
+        let mut checker = StallChecker::new(timeout);
+        let start = Instant::now();
+
+        assert_eq!(checker.update(&stats(100, 100), start), None);
+        // Sending without receiving within the timeout is fine
+        assert_eq!(checker.update(&stats(100, 200), start + timeout / 2), None);
+        assert_eq!(
+            checker.update(&stats(100, 300), start + timeout + timeout / 2),
+            Some(timeout)
+        );
+        // Receiving anything resets the timeout
+        assert_eq!(checker.update(&stats(150, 400), start + timeout * 2), None);
+        assert_eq!(
+            checker.update(&stats(150, 500), start + timeout * 2 + timeout / 2),
+            None
+        );
+    }
+
+    #[test]
+    fn idle_time_does_not_count_towards_stall() {
+        let timeout = Duration::from_secs(30);
+        let mut checker = StallChecker::new(timeout);
+        let start = Instant::now();
+
+        assert_eq!(checker.update(&stats(100, 100), start), None);
+        // Idle tunnels are not stalled
+        assert_eq!(checker.update(&stats(100, 100), start + timeout * 2), None);
+        // Sending after being idle starts the timeout from when the traffic was first seen
+        assert_eq!(checker.update(&stats(100, 200), start + timeout * 3), None);
+        assert_eq!(
+            checker.update(&stats(100, 300), start + timeout * 3 + timeout / 2),
+            None
+        );
+        assert_eq!(
+            checker.update(&stats(100, 400), start + timeout * 4),
+            Some(timeout)
+        );
+    }
+}
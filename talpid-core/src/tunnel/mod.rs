@@ -2,8 +2,10 @@ use std::{
     collections::HashMap,
     ffi::OsString,
     io,
-    net::IpAddr,
+    net::{IpAddr, Ipv6Addr},
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
 #[cfg(unix)]
@@ -13,6 +15,13 @@ use talpid_types::net::{openvpn as openvpn_types, GenericTunnelOptions, TunnelPa
 /// A module for all OpenVPN related tunnel management.
 pub mod openvpn;
 
+/// Detects tunnels that stopped receiving traffic, regardless of the tunnel type.
+pub mod connectivity_monitor;
+use self::connectivity_monitor::ConnectivityMonitor;
+
+/// Traffic statistics for the tunnel interface.
+pub mod stats;
+
 #[cfg(unix)]
 mod wireguard;
 
@@ -35,6 +44,10 @@ error_chain! {
         UnsupportedPlatform {
             description("Tunnel type not supported on this operating system")
         }
+        /// Failed to render a configuration file for the tunnel.
+        ExportConfigError {
+            description("Failed to render a configuration file for the tunnel")
+        }
     }
 
     links {
@@ -54,6 +67,9 @@ pub enum TunnelEvent {
     Up(TunnelMetadata),
     /// Sent when the tunnel goes down.
     Down,
+    /// Sent when the tunnel is up but has been sending traffic for the given duration without
+    /// receiving anything.
+    TrafficStalled(Duration),
 }
 
 /// Information about a VPN tunnel.
@@ -65,6 +81,8 @@ pub struct TunnelMetadata {
     pub ips: Vec<IpAddr>,
     /// The IP to the default gateway on the tunnel interface.
     pub gateway: IpAddr,
+    /// The IPv6 address of the gateway on the tunnel interface, if IPv6 is used in the tunnel.
+    pub ipv6_gateway: Option<Ipv6Addr>,
 }
 
 impl TunnelEvent {
@@ -94,10 +112,15 @@ impl TunnelEvent {
                     .expect("No \"route_vpn_gateway\" in tunnel up event")
                     .parse()
                     .expect("Tunnel gateway IP not in valid format");
+                // Only set when IPv6 is enabled and the server pushed an IPv6 configuration.
+                let ipv6_gateway = env
+                    .get("ifconfig_ipv6_remote")
+                    .and_then(|ipv6_gateway| ipv6_gateway.parse().ok());
                 Some(TunnelEvent::Up(TunnelMetadata {
                     interface,
                     ips,
                     gateway,
+                    ipv6_gateway,
                 }))
             }
             openvpn_plugin::EventType::RoutePredown => Some(TunnelEvent::Down),
@@ -125,6 +148,7 @@ impl TunnelMonitor {
         L: Fn(TunnelEvent) + Send + Sync + 'static,
     {
         Self::ensure_ipv6_can_be_used_if_enabled(&tunnel_parameters.get_generic_options())?;
+        let on_event = Self::monitor_connectivity(tunnel_parameters, on_event);
 
         match tunnel_parameters {
             TunnelParameters::OpenVpn(config) => {
@@ -148,6 +172,11 @@ impl TunnelMonitor {
     where
         L: Fn(TunnelEvent) + Send + Sync + 'static,
     {
+        let mut params = params.clone();
+        if params.options.mtu.is_none() && params.options.automatic_mtu {
+            params.options.mtu =
+                wireguard::mtu_detection::detect_tunnel_mtu(params.connection.peer.endpoint);
+        }
         let config = wireguard::config::Config::from_parameters(&params)
             .chain_err(|| ErrorKind::TunnelMonitoringError)?;
         let monitor = wireguard::WireguardMonitor::start(
@@ -179,6 +208,44 @@ impl TunnelMonitor {
         })
     }
 
+    /// Wraps `on_event` so that the traffic of the tunnel is monitored while it is up. Sends
+    /// `TunnelEvent::TrafficStalled` if the tunnel stops receiving traffic.
+    fn monitor_connectivity<L>(
+        tunnel_parameters: &TunnelParameters,
+        on_event: L,
+    ) -> impl Fn(TunnelEvent) + Send + Sync + 'static
+    where
+        L: Fn(TunnelEvent) + Send + Sync + 'static,
+    {
+        let is_wireguard = match tunnel_parameters {
+            TunnelParameters::Wireguard(_) => true,
+            TunnelParameters::OpenVpn(_) => false,
+        };
+        let namespace = tunnel_parameters.get_namespace().map(str::to_owned);
+        let on_event = Arc::new(on_event);
+        let connectivity_monitor = Mutex::new(None);
+
+        move |event: TunnelEvent| {
+            match event {
+                TunnelEvent::Up(ref metadata) => {
+                    let on_event = on_event.clone();
+                    let monitor = ConnectivityMonitor::start(
+                        metadata.interface.clone(),
+                        is_wireguard,
+                        namespace.clone(),
+                        move |stalled_for| on_event(TunnelEvent::TrafficStalled(stalled_for)),
+                    );
+                    *connectivity_monitor.lock().unwrap() = Some(monitor);
+                }
+                TunnelEvent::Down => {
+                    connectivity_monitor.lock().unwrap().take();
+                }
+                _ => (),
+            }
+            on_event(event);
+        }
+    }
+
     fn ensure_ipv6_can_be_used_if_enabled(tunnel_options: &GenericTunnelOptions) -> Result<()> {
         if tunnel_options.enable_ipv6 && !is_ipv6_enabled_in_os() {
             bail!(ErrorKind::EnableIpv6Error);
@@ -223,6 +290,17 @@ impl CloseHandle {
             }
         }
     }
+
+    /// Tells the tunnel that the host has moved to another network, e.g. because the default
+    /// route changed. WireGuard tunnels update their routes and peer endpoints, while OpenVPN
+    /// follows the new route on its own.
+    pub fn network_changed(&self) {
+        match self {
+            CloseHandle::OpenVpn(_) => (),
+            #[cfg(unix)]
+            CloseHandle::Wireguard(handle) => handle.network_changed(),
+        }
+    }
 }
 
 enum InternalTunnelMonitor {
@@ -253,6 +331,30 @@ impl InternalTunnelMonitor {
     }
 }
 
+/// Renders a configuration file for bringing up the tunnel described by `tunnel_parameters`
+/// without the daemon. WireGuard tunnels are rendered for `wg-quick`, with the given DNS servers
+/// or the relay's if there are none, and OpenVPN tunnels as `.ovpn` files, which use the DNS
+/// servers pushed by the relay.
+#[cfg_attr(windows, allow(unused_variables))]
+pub fn export_config(
+    tunnel_parameters: &TunnelParameters,
+    dns_servers: &[IpAddr],
+    resource_dir: &Path,
+) -> Result<String> {
+    match tunnel_parameters {
+        TunnelParameters::OpenVpn(params) => {
+            openvpn::export_config(params, resource_dir).chain_err(|| ErrorKind::ExportConfigError)
+        }
+        #[cfg(unix)]
+        TunnelParameters::Wireguard(params) => {
+            let config = wireguard::config::Config::from_parameters(params)
+                .chain_err(|| ErrorKind::ExportConfigError)?;
+            Ok(config.to_wg_quick_format(dns_servers))
+        }
+        #[cfg(windows)]
+        TunnelParameters::Wireguard(_) => bail!(ErrorKind::UnsupportedPlatform),
+    }
+}
 
 fn is_ipv6_enabled_in_os() -> bool {
     #[cfg(windows)]
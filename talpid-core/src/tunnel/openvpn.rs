@@ -67,6 +67,14 @@ error_chain! {
         CredentialsWriteError {
             description("Error while writing credentials to temporary file")
         }
+        /// The CA certificate could not be read.
+        ReadCaError {
+            description("Failed to read the CA certificate")
+        }
+        /// The certificate revocation list could not be read.
+        ReadCrlError {
+            description("Failed to read the certificate revocation list")
+        }
     }
 }
 
@@ -359,6 +367,9 @@ impl<C: OpenVpnBuilder> OpenVpnMonitor<C> {
             .enable_ipv6(params.generic_options.enable_ipv6)
             .tunnel_alias(tunnel_alias)
             .ca(resource_dir.join("ca.crt"));
+        if let Some(crl) = get_crl_path(resource_dir) {
+            cmd.crl(crl);
+        }
         if let Some(proxy_auth_file) = proxy_auth_file {
             cmd.proxy_auth(proxy_auth_file);
         }
@@ -386,6 +397,36 @@ impl<C: OpenVpnBuilder> OpenVpnMonitor<C> {
     }
 }
 
+/// Renders an `.ovpn` file for connecting with the given parameters, using the same arguments
+/// as the daemon starts OpenVPN with. The CA certificate and the certificate revocation list are
+/// inlined, and the credentials are asked for by OpenVPN when it starts.
+pub fn export_config(params: &openvpn::TunnelParameters, resource_dir: &Path) -> Result<String> {
+    let ca =
+        fs::read_to_string(resource_dir.join("ca.crt")).chain_err(|| ErrorKind::ReadCaError)?;
+    let mut config = OpenVpnCommand::new("openvpn")
+        .remote(params.config.get_tunnel_endpoint().endpoint)
+        .tunnel_options(&params.options)
+        .enable_ipv6(params.generic_options.enable_ipv6)
+        .to_config_file();
+    config.push_str("auth-user-pass\n");
+    config.push_str(&format!("<ca>\n{}</ca>\n", ca));
+    if let Some(crl_path) = get_crl_path(resource_dir) {
+        let crl = fs::read_to_string(crl_path).chain_err(|| ErrorKind::ReadCrlError)?;
+        config.push_str(&format!("<crl-verify>\n{}</crl-verify>\n", crl));
+    }
+    Ok(config)
+}
+
+/// Returns the path to the certificate revocation list, if one is bundled.
+fn get_crl_path(resource_dir: &Path) -> Option<PathBuf> {
+    let path = resource_dir.join("crl.pem");
+    if path.exists() {
+        Some(path)
+    } else {
+        None
+    }
+}
+
 /// A handle to an `OpenVpnMonitor` for closing it.
 #[derive(Debug, Clone)]
 pub struct OpenVpnCloseHandle<H: ProcessHandle = OpenVpnProcHandle> {
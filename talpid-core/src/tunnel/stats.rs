@@ -0,0 +1,305 @@
+use chrono::{offset::Utc, DateTime, NaiveDateTime};
+use error_chain::ChainedError;
+use talpid_types::tunnel::TunnelStats;
+
+#[cfg(unix)]
+use std::{path::Path, time::Duration};
+
+/// Directory where wireguard-go creates the UAPI sockets for its interfaces.
+#[cfg(unix)]
+const WIREGUARD_SOCKET_DIR: &str = "/var/run/wireguard";
+#[cfg(unix)]
+const UAPI_TIMEOUT: Duration = Duration::from_secs(1);
+
+error_chain! {
+    errors {
+        /// Failed to read the counters of the tunnel interface.
+        ReadInterfaceStatsError(interface: String) {
+            description("Failed to read tunnel interface statistics")
+            display("Failed to read statistics for tunnel interface {}", interface)
+        }
+        /// Failed to query or parse the statistics from the WireGuard UAPI socket.
+        WireguardUapiError {
+            description("Failed to query WireGuard tunnel statistics")
+        }
+        /// Failed to dump the kernel WireGuard device over netlink.
+        WireguardKernelError {
+            description("Failed to query WireGuard kernel device statistics")
+        }
+        /// Failed to enter the network namespace of the tunnel interface.
+        NamespaceError(namespace: String) {
+            description("Failed to read tunnel statistics in network namespace")
+            display("Failed to read tunnel statistics in network namespace {}", namespace)
+        }
+        /// The statistics can't be read on this platform.
+        UnsupportedPlatform {
+            description("Tunnel statistics are not available on this platform")
+        }
+    }
+}
+
+/// Returns the traffic statistics for the given tunnel interface. WireGuard tunnels are queried
+/// through their UAPI socket, or over netlink for kernel devices, which also reports the latest
+/// handshake. If that fails, or for other tunnels, the counters of the interface are used. If the
+/// interface has been moved into a network namespace, `namespace` is the name of it.
+pub fn get_tunnel_stats(
+    interface: &str,
+    is_wireguard: bool,
+    namespace: Option<&str>,
+) -> Result<TunnelStats> {
+    match namespace {
+        Some(namespace) => {
+            run_in_namespace(namespace, || read_tunnel_stats(interface, is_wireguard))
+                .chain_err(|| ErrorKind::NamespaceError(namespace.to_owned()))?
+        }
+        None => read_tunnel_stats(interface, is_wireguard),
+    }
+}
+
+/// Reads the statistics of an interface in the network namespace of the calling thread.
+fn read_tunnel_stats(interface: &str, is_wireguard: bool) -> Result<TunnelStats> {
+    let interface_stats = read_interface_stats(interface);
+    if !is_wireguard {
+        return interface_stats;
+    }
+
+    match query_wireguard(interface) {
+        Ok(mut stats) => {
+            // WireGuard only reports byte counts
+            if let Ok(interface_stats) = interface_stats {
+                stats.rx_packets = interface_stats.rx_packets;
+                stats.tx_packets = interface_stats.tx_packets;
+            }
+            Ok(stats)
+        }
+        Err(error) => {
+            log::debug!(
+                "{}",
+                error.display_chain_with_msg("Using interface counters for tunnel statistics")
+            );
+            interface_stats
+        }
+    }
+}
+
+/// Interface counters of the network namespace of the calling thread. Unlike `/sys/class/net`,
+/// which keeps showing the namespace sysfs was mounted in, this follows the thread into other
+/// namespaces.
+#[cfg(target_os = "linux")]
+const NET_DEV_PATH: &str = "/proc/thread-self/net/dev";
+
+#[cfg(target_os = "linux")]
+fn read_interface_stats(interface: &str) -> Result<TunnelStats> {
+    let net_dev = std::fs::read_to_string(NET_DEV_PATH)
+        .chain_err(|| ErrorKind::ReadInterfaceStatsError(interface.to_owned()))?;
+    parse_net_dev(&net_dev, interface)
+        .ok_or_else(|| ErrorKind::ReadInterfaceStatsError(interface.to_owned()).into())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_interface_stats(_interface: &str) -> Result<TunnelStats> {
+    bail!(ErrorKind::UnsupportedPlatform)
+}
+
+#[cfg(target_os = "linux")]
+fn run_in_namespace<T>(namespace: &str, f: impl FnOnce() -> T) -> crate::netns::Result<T> {
+    crate::netns::NetworkNamespace::create(namespace)?.run(f)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run_in_namespace<T>(_namespace: &str, _f: impl FnOnce() -> T) -> Result<T> {
+    bail!(ErrorKind::UnsupportedPlatform)
+}
+
+/// Finds the counters of `interface` in the contents of `/proc/net/dev`.
+#[cfg(target_os = "linux")]
+fn parse_net_dev(net_dev: &str, interface: &str) -> Option<TunnelStats> {
+    // The first two lines are headers
+    for line in net_dev.lines().skip(2) {
+        let mut parts = line.splitn(2, ':');
+        let (name, counters) = match (parts.next(), parts.next()) {
+            (Some(name), Some(counters)) => (name.trim(), counters),
+            _ => continue,
+        };
+        if name != interface {
+            continue;
+        }
+        let counters = counters
+            .split_whitespace()
+            .map(|counter| counter.parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+        // Eight receive counters are followed by eight transmit counters
+        if counters.len() < 16 {
+            return None;
+        }
+        return Some(TunnelStats {
+            rx_bytes: counters[0],
+            tx_bytes: counters[8],
+            rx_packets: Some(counters[1]),
+            tx_packets: Some(counters[9]),
+            last_handshake: None,
+        });
+    }
+    None
+}
+
+#[cfg(unix)]
+fn query_wireguard(interface: &str) -> Result<TunnelStats> {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+    };
+
+    let socket_path = Path::new(WIREGUARD_SOCKET_DIR).join(format!("{}.sock", interface));
+    // Only wireguard-go creates a UAPI socket, kernel devices are queried over netlink
+    #[cfg(target_os = "linux")]
+    {
+        if !socket_path.exists() {
+            return super::wireguard::kernel::get_device_stats(interface)
+                .chain_err(|| ErrorKind::WireguardKernelError);
+        }
+    }
+    let mut socket =
+        UnixStream::connect(&socket_path).chain_err(|| ErrorKind::WireguardUapiError)?;
+    socket
+        .set_read_timeout(Some(UAPI_TIMEOUT))
+        .chain_err(|| ErrorKind::WireguardUapiError)?;
+    socket
+        .write_all(b"get=1\n\n")
+        .chain_err(|| ErrorKind::WireguardUapiError)?;
+
+    // The response ends with an empty line, after which the socket is kept open
+    let mut response = String::new();
+    for line in BufReader::new(socket).lines() {
+        let line = line.chain_err(|| ErrorKind::WireguardUapiError)?;
+        if line.is_empty() {
+            break;
+        }
+        response.push_str(&line);
+        response.push('\n');
+    }
+    parse_uapi_response(&response)
+}
+
+#[cfg(not(unix))]
+fn query_wireguard(_interface: &str) -> Result<TunnelStats> {
+    bail!(ErrorKind::UnsupportedPlatform)
+}
+
+/// Sums up the byte counts of all peers in a UAPI `get` response and picks the latest handshake.
+fn parse_uapi_response(response: &str) -> Result<TunnelStats> {
+    let mut stats = TunnelStats::default();
+    let mut handshake_sec = 0;
+
+    for line in response.lines() {
+        let mut parts = line.splitn(2, '=');
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => (key, value),
+            _ => continue,
+        };
+        match key {
+            "rx_bytes" => stats.rx_bytes += parse_value(value)?,
+            "tx_bytes" => stats.tx_bytes += parse_value(value)?,
+            "last_handshake_time_sec" => handshake_sec = parse_value(value)?,
+            "last_handshake_time_nsec" => {
+                // A time of zero means that no handshake has completed with the peer
+                if handshake_sec == 0 {
+                    continue;
+                }
+                let handshake_nsec = parse_value(value)?;
+                let handshake =
+                    NaiveDateTime::from_timestamp_opt(handshake_sec as i64, handshake_nsec as u32)
+                        .map(|time| DateTime::<Utc>::from_utc(time, Utc))
+                        .ok_or(ErrorKind::WireguardUapiError)?;
+                if stats
+                    .last_handshake
+                    .map(|latest| handshake > latest)
+                    .unwrap_or(true)
+                {
+                    stats.last_handshake = Some(handshake);
+                }
+            }
+            "errno" if value != "0" => bail!(ErrorKind::WireguardUapiError),
+            _ => (),
+        }
+    }
+    Ok(stats)
+}
+
+fn parse_value(value: &str) -> Result<u64> {
+    value.parse().chain_err(|| ErrorKind::WireguardUapiError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn sums_peer_counters_and_uses_latest_handshake() {
+        let response = "private_key=0000\n\
+                        listen_port=51820\n\
+                        public_key=1111\n\
+                        last_handshake_time_sec=1550000000\n\
+                        last_handshake_time_nsec=500\n\
+                        rx_bytes=100\n\
+                        tx_bytes=200\n\
+                        public_key=2222\n\
+                        last_handshake_time_sec=0\n\
+                        last_handshake_time_nsec=0\n\
+                        rx_bytes=1\n\
+                        tx_bytes=2\n\
+                        errno=0\n";
+
+        let stats = parse_uapi_response(response).unwrap();
+
+        assert_eq!(stats.rx_bytes, 101);
+        assert_eq!(stats.tx_bytes, 202);
+        assert_eq!(
+            stats.last_handshake,
+            Some(Utc.timestamp(1_550_000_000, 500))
+        );
+    }
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parses_interface_counters() {
+        let net_dev = "Inter-|   Receive                       |  Transmit\n\
+                       face |bytes packets errs drop fifo frame compressed multicast|bytes \
+                       packets errs drop fifo colls carrier compressed\n\
+                       lo: 100 2 0 0 0 0 0 0 100 2 0 0 0 0 0 0\n\
+                       wg-mullvad: 5000 10 0 0 0 0 0 0 3000 7 0 0 0 0 0 0\n";
+
+        let stats = parse_net_dev(net_dev, "wg-mullvad").unwrap();
+
+        assert_eq!(stats.rx_bytes, 5000);
+        assert_eq!(stats.tx_bytes, 3000);
+        assert_eq!(stats.rx_packets, Some(10));
+        assert_eq!(stats.tx_packets, Some(7));
+        assert!(parse_net_dev(net_dev, "wg").is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reads_counters_inside_namespace() {
+        // Creating a network namespace requires root
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+        let namespace = "talpid-stats-test";
+        crate::netns::NetworkNamespace::create(namespace).unwrap();
+        // Send something over the loopback interface of the host
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .send_to(b"ping", socket.local_addr().unwrap())
+            .unwrap();
+
+        let in_namespace = get_tunnel_stats("lo", false, Some(namespace));
+        let outside_namespace = get_tunnel_stats("lo", false, None);
+        duct::cmd!("ip", "netns", "delete", namespace)
+            .run()
+            .unwrap();
+
+        assert_eq!(in_namespace.unwrap().tx_bytes, 0);
+        assert!(outside_namespace.unwrap().tx_bytes > 0);
+    }
+}
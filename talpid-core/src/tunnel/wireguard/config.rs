@@ -1,18 +1,31 @@
-use std::{borrow::Cow, ffi::CString, net::IpAddr};
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    ffi::CString,
+    net::{IpAddr, Ipv6Addr},
+};
 use talpid_types::net::{wireguard, GenericTunnelOptions};
 
+#[derive(Clone)]
 pub struct Config {
     pub tunnel: wireguard::TunnelConfig,
     pub peers: Vec<wireguard::PeerConfig>,
     pub gateway: IpAddr,
+    pub ipv6_gateway: Option<Ipv6Addr>,
     pub mtu: u16,
+    pub persistent_keepalive: Option<u16>,
     #[cfg(target_os = "linux")]
     pub fwmark: i32,
+    #[cfg(target_os = "linux")]
+    pub backend: Option<wireguard::Backend>,
+    #[cfg(target_os = "linux")]
+    pub namespace: Option<String>,
 }
 
 /// Smallest MTU that supports IPv6
 const SMALLEST_IPV6_MTU: u16 = 1420;
-const DEFAULT_MTU: u16 = SMALLEST_IPV6_MTU;
+/// MTU used for tunnels unless another one is configured.
+pub const DEFAULT_MTU: u16 = SMALLEST_IPV6_MTU;
 
 error_chain! {
     errors {
@@ -27,17 +40,23 @@ error_chain! {
         NoPeersSuppliedError{
             description("No peers supplied")
         }
+
+        DuplicateAllowedIpError(allowed_ip: String) {
+            description("Allowed IP used by more than one peer")
+            display("Allowed IP {} is used by more than one peer", allowed_ip)
+        }
     }
 }
 
 impl Config {
     pub fn from_parameters(params: &wireguard::TunnelParameters) -> Result<Config> {
         let tunnel = params.connection.tunnel.clone();
-        let peer = vec![params.connection.peer.clone()];
+        let peers = params.connection.peers().cloned().collect();
         Self::new(
             tunnel,
-            peer,
+            peers,
             params.connection.gateway,
+            params.connection.ipv6_gateway,
             &params.options,
             &params.generic_options,
         )
@@ -47,6 +66,7 @@ impl Config {
         mut tunnel: wireguard::TunnelConfig,
         mut peers: Vec<wireguard::PeerConfig>,
         gateway: IpAddr,
+        ipv6_gateway: Option<Ipv6Addr>,
         wg_options: &wireguard::TunnelOptions,
         generic_options: &GenericTunnelOptions,
     ) -> Result<Config> {
@@ -64,6 +84,16 @@ impl Config {
             ensure!(!peer.allowed_ips.is_empty(), ErrorKind::InvalidPeerIpError);
         }
 
+        // WireGuard routes each allowed IP to a single peer, so sharing one would silently take it
+        // away from the other peer
+        let mut seen_allowed_ips = HashSet::new();
+        for allowed_ip in peers.iter().flat_map(|peer| peer.allowed_ips.iter()) {
+            ensure!(
+                seen_allowed_ips.insert(allowed_ip),
+                ErrorKind::DuplicateAllowedIpError(allowed_ip.to_string())
+            );
+        }
+
         tunnel.addresses = tunnel
             .addresses
             .into_iter()
@@ -78,9 +108,15 @@ impl Config {
             tunnel,
             peers,
             gateway,
+            ipv6_gateway: ipv6_gateway.filter(|_| is_ipv6_enabled),
             mtu,
+            persistent_keepalive: wg_options.persistent_keepalive,
             #[cfg(target_os = "linux")]
             fwmark: wg_options.fwmark,
+            #[cfg(target_os = "linux")]
+            backend: wg_options.backend,
+            #[cfg(target_os = "linux")]
+            namespace: wg_options.namespace.clone(),
         })
     }
 
@@ -100,10 +136,19 @@ impl Config {
         wg_conf.add("replace_peers", "true");
 
         for peer in &self.peers {
+            wg_conf.add("public_key", peer.public_key.as_bytes().as_ref());
+            if let Some(preshared_key) = &peer.preshared_key {
+                wg_conf.add("preshared_key", preshared_key.as_bytes().as_ref());
+            }
             wg_conf
-                .add("public_key", peer.public_key.as_bytes().as_ref())
                 .add("endpoint", peer.endpoint.to_string().as_str())
                 .add("replace_allowed_ips", "true");
+            if let Some(interval) = self.persistent_keepalive {
+                wg_conf.add(
+                    "persistent_keepalive_interval",
+                    interval.to_string().as_str(),
+                );
+            }
             for addr in &peer.allowed_ips {
                 wg_conf.add("allowed_ip", addr.to_string().as_str());
             }
@@ -112,6 +157,81 @@ impl Config {
         let bytes = wg_conf.into_config();
         CString::new(bytes).expect("null bytes inside config")
     }
+
+    /// Returns only the endpoints of the peers, for pointing a running device at them again after
+    /// the network has changed. Unlike a full configuration, this leaves the keys, allowed IPs and
+    /// sessions of the peers in place.
+    pub fn to_userspace_endpoints_format(&self) -> CString {
+        let mut wg_conf = WgConfigBuffer::new();
+        for peer in &self.peers {
+            wg_conf
+                .add("public_key", peer.public_key.as_bytes().as_ref())
+                .add("endpoint", peer.endpoint.to_string().as_str());
+        }
+
+        let bytes = wg_conf.into_config();
+        CString::new(bytes).expect("null bytes inside config")
+    }
+
+    /// Returns the configuration in the format read by `wg-quick`, so that the same tunnel can be
+    /// brought up without the daemon. `dns_servers` are the DNS servers used inside the tunnel,
+    /// where an empty list means the gateways of the relay.
+    pub fn to_wg_quick_format(&self, dns_servers: &[IpAddr]) -> String {
+        let addresses = self
+            .tunnel
+            .addresses
+            .iter()
+            .map(|address| match address {
+                IpAddr::V4(address) => format!("{}/32", address),
+                IpAddr::V6(address) => format!("{}/128", address),
+            })
+            .collect::<Vec<_>>();
+        let dns_servers = if dns_servers.is_empty() {
+            let mut gateways = vec![self.gateway];
+            gateways.extend(self.ipv6_gateway.map(IpAddr::V6));
+            gateways
+        } else {
+            dns_servers.to_vec()
+        };
+        let dns_servers = dns_servers
+            .iter()
+            .map(|server| server.to_string())
+            .collect::<Vec<_>>();
+
+        let mut lines = vec![
+            "[Interface]".to_owned(),
+            format!(
+                "PrivateKey = {}",
+                base64::encode(self.tunnel.private_key.as_bytes())
+            ),
+            format!("Address = {}", addresses.join(", ")),
+            format!("DNS = {}", dns_servers.join(", ")),
+            format!("MTU = {}", self.mtu),
+        ];
+        for peer in &self.peers {
+            lines.push(String::new());
+            lines.push("[Peer]".to_owned());
+            lines.push(format!("PublicKey = {}", peer.public_key));
+            if let Some(preshared_key) = &peer.preshared_key {
+                lines.push(format!(
+                    "PresharedKey = {}",
+                    base64::encode(preshared_key.as_bytes())
+                ));
+            }
+            let allowed_ips = peer
+                .allowed_ips
+                .iter()
+                .map(|allowed_ip| allowed_ip.to_string())
+                .collect::<Vec<_>>();
+            lines.push(format!("AllowedIPs = {}", allowed_ips.join(", ")));
+            lines.push(format!("Endpoint = {}", peer.endpoint));
+            if let Some(interval) = self.persistent_keepalive {
+                lines.push(format!("PersistentKeepalive = {}", interval));
+            }
+        }
+        lines.push(String::new());
+        lines.join("\n")
+    }
 }
 
 pub enum ConfValue<'a> {
@@ -163,3 +283,121 @@ impl WgConfigBuffer {
         self.buf
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn peer(allowed_ip: &str, last_octet: u8) -> wireguard::PeerConfig {
+        wireguard::PeerConfig {
+            public_key: [last_octet; 32].into(),
+            allowed_ips: vec![allowed_ip.parse().unwrap()],
+            endpoint: (Ipv4Addr::new(10, 0, 0, last_octet), 51820).into(),
+            preshared_key: None,
+        }
+    }
+
+    #[test]
+    fn rejects_allowed_ip_shared_by_peers() {
+        let tunnel = wireguard::TunnelConfig {
+            private_key: [0; 32].into(),
+            addresses: vec![Ipv4Addr::new(10, 64, 0, 2).into()],
+        };
+        let wg_options = wireguard::TunnelOptions {
+            mtu: None,
+            automatic_mtu: false,
+            persistent_keepalive: None,
+            #[cfg(target_os = "linux")]
+            fwmark: 0,
+            #[cfg(target_os = "linux")]
+            backend: None,
+            #[cfg(target_os = "linux")]
+            namespace: None,
+        };
+        let generic_options = GenericTunnelOptions { enable_ipv6: false };
+        let gateway = Ipv4Addr::new(10, 64, 0, 1).into();
+        let new_config = |peers| {
+            Config::new(
+                tunnel.clone(),
+                peers,
+                gateway,
+                None,
+                &wg_options,
+                &generic_options,
+            )
+        };
+
+        assert!(new_config(vec![peer("0.0.0.0/0", 1), peer("192.168.10.0/24", 2)]).is_ok());
+        assert!(new_config(vec![peer("192.168.10.0/24", 1), peer("192.168.10.0/24", 2)]).is_err());
+    }
+
+    #[test]
+    fn formats_wg_quick_config() {
+        let config = Config {
+            tunnel: wireguard::TunnelConfig {
+                private_key: [0; 32].into(),
+                addresses: vec![Ipv4Addr::new(10, 64, 0, 2).into()],
+            },
+            peers: vec![peer("0.0.0.0/0", 1)],
+            gateway: Ipv4Addr::new(10, 64, 0, 1).into(),
+            ipv6_gateway: None,
+            mtu: DEFAULT_MTU,
+            persistent_keepalive: None,
+            #[cfg(target_os = "linux")]
+            fwmark: 0,
+            #[cfg(target_os = "linux")]
+            backend: None,
+            #[cfg(target_os = "linux")]
+            namespace: None,
+        };
+
+        assert_eq!(
+            config.to_wg_quick_format(&[]),
+            "[Interface]\n\
+             PrivateKey = AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=\n\
+             Address = 10.64.0.2/32\n\
+             DNS = 10.64.0.1\n\
+             MTU = 1420\n\
+             \n\
+             [Peer]\n\
+             PublicKey = AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=\n\
+             AllowedIPs = 0.0.0.0/0\n\
+             Endpoint = 10.0.0.1:51820\n"
+        );
+        let custom_dns = [
+            Ipv4Addr::new(1, 1, 1, 1).into(),
+            Ipv4Addr::new(8, 8, 8, 8).into(),
+        ];
+        assert!(config
+            .to_wg_quick_format(&custom_dns)
+            .contains("\nDNS = 1.1.1.1, 8.8.8.8\n"));
+    }
+    #[test]
+    fn formats_endpoint_update() {
+        let config = Config {
+            tunnel: wireguard::TunnelConfig {
+                private_key: [0; 32].into(),
+                addresses: vec![Ipv4Addr::new(10, 64, 0, 2).into()],
+            },
+            peers: vec![peer("0.0.0.0/0", 1)],
+            gateway: Ipv4Addr::new(10, 64, 0, 1).into(),
+            ipv6_gateway: None,
+            mtu: DEFAULT_MTU,
+            persistent_keepalive: Some(25),
+            #[cfg(target_os = "linux")]
+            fwmark: 0,
+            #[cfg(target_os = "linux")]
+            backend: None,
+            #[cfg(target_os = "linux")]
+            namespace: None,
+        };
+
+        assert_eq!(
+            config.to_userspace_endpoints_format().to_str().unwrap(),
+            "public_key=0101010101010101010101010101010101010101010101010101010101010101\n\
+             endpoint=10.0.0.1:51820\n\
+             \n"
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use super::icmp::Pinger;
+use crate::tunnel::stats;
+use chrono::offset::Utc;
+use error_chain::ChainedError;
+use std::{
+    fmt,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How often the gateway is pinged, and how long to wait for each reply.
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+/// Number of pings in a row that may be lost before the tunnel is considered broken.
+const MAX_CONSECUTIVE_LOSSES: u32 = 3;
+/// WireGuard rejects sessions older than this. Since the pings keep traffic flowing, new
+/// handshakes should complete well before it.
+const STALE_HANDSHAKE_AGE: Duration = Duration::from_secs(180);
+
+/// Change in the health of a tunnel.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum HealthEvent {
+    /// The tunnel works again after pings were lost.
+    Healthy,
+    /// Pings were lost, but not enough in a row to consider the tunnel broken.
+    Degraded { consecutive_losses: u32 },
+    /// The tunnel is considered broken. No more events are sent after this.
+    Failed(FailureReason),
+}
+
+/// Why a tunnel is considered broken.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FailureReason {
+    /// This many pings in a row were lost.
+    PingTimeout(u32),
+    /// The latest handshake is too old for the session to still be valid.
+    StaleHandshake(Duration),
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureReason::PingTimeout(losses) => write!(f, "{} pings in a row were lost", losses),
+            FailureReason::StaleHandshake(age) => write!(
+                f,
+                "latest handshake completed {} seconds ago",
+                age.as_secs()
+            ),
+        }
+    }
+}
+
+/// Stops the health monitor when dropped.
+pub struct HealthMonitorHandle {
+    stop: Arc<AtomicBool>,
+}
+
+impl Drop for HealthMonitorHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Spawns a thread that pings `gateway` through the tunnel interface and checks the age of the
+/// latest handshake, calling `on_event` whenever the health of the tunnel changes. If the
+/// interface is in a network namespace, the pings are sent from within that namespace.
+pub fn spawn_health_monitor<F: Fn(HealthEvent) + Send + 'static>(
+    gateway: IpAddr,
+    interface: String,
+    namespace: Option<String>,
+    on_event: F,
+) -> HealthMonitorHandle {
+    let stop = Arc::new(AtomicBool::new(false));
+    let handle = HealthMonitorHandle { stop: stop.clone() };
+
+    thread::spawn(move || {
+        if let Some(namespace) = namespace {
+            if let Err(error) = enter_namespace(&namespace) {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to monitor the health of the tunnel")
+                );
+                return;
+            }
+        }
+        let mut pinger = match Pinger::new(gateway, &interface) {
+            Ok(pinger) => pinger,
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to monitor the health of the tunnel")
+                );
+                return;
+            }
+        };
+        let mut checker = HealthChecker::new(MAX_CONSECUTIVE_LOSSES);
+
+        while !stop.load(Ordering::SeqCst) {
+            let start = Instant::now();
+            let ping_result = pinger.ping(PING_INTERVAL);
+            if let Err(ref error) = ping_result {
+                log::debug!("{}", error.display_chain_with_msg("Ping failed"));
+            }
+            if stop.load(Ordering::SeqCst) {
+                return;
+            }
+
+            let event = checker.update(ping_result.is_ok(), handshake_age(&interface));
+            if let Some(event) = event {
+                let is_fatal = match event {
+                    HealthEvent::Failed(_) => true,
+                    _ => false,
+                };
+                on_event(event);
+                if is_fatal {
+                    return;
+                }
+            }
+
+            if let Some(remaining) = PING_INTERVAL.checked_sub(start.elapsed()) {
+                thread::sleep(remaining);
+            }
+        }
+    });
+
+    handle
+}
+
+/// Moves the calling thread into the given network namespace.
+#[cfg(target_os = "linux")]
+fn enter_namespace(namespace: &str) -> crate::netns::Result<()> {
+    crate::netns::NetworkNamespace::create(namespace)?.enter()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn enter_namespace(namespace: &str) -> super::Result<()> {
+    bail!(
+        "Unable to enter network namespace {}, namespaces are only supported on Linux",
+        namespace
+    )
+}
+
+/// Returns the time since the latest handshake, or `None` if unknown. The monitor thread has
+/// already entered the namespace of the interface, if any.
+fn handshake_age(interface: &str) -> Option<Duration> {
+    let stats = stats::get_tunnel_stats(interface, true, None).ok()?;
+    Utc::now()
+        .signed_duration_since(stats.last_handshake?)
+        .to_std()
+        .ok()
+}
+
+/// Decides the health of the tunnel from the results of the checks.
+struct HealthChecker {
+    max_consecutive_losses: u32,
+    consecutive_losses: u32,
+}
+
+impl HealthChecker {
+    fn new(max_consecutive_losses: u32) -> Self {
+        HealthChecker {
+            max_consecutive_losses,
+            consecutive_losses: 0,
+        }
+    }
+
+    fn update(
+        &mut self,
+        ping_succeeded: bool,
+        handshake_age: Option<Duration>,
+    ) -> Option<HealthEvent> {
+        if let Some(age) = handshake_age {
+            if age > STALE_HANDSHAKE_AGE {
+                return Some(HealthEvent::Failed(FailureReason::StaleHandshake(age)));
+            }
+        }
+
+        if ping_succeeded {
+            let was_degraded = self.consecutive_losses > 0;
+            self.consecutive_losses = 0;
+            if was_degraded {
+                Some(HealthEvent::Healthy)
+            } else {
+                None
+            }
+        } else {
+            self.consecutive_losses += 1;
+            if self.consecutive_losses >= self.max_consecutive_losses {
+                Some(HealthEvent::Failed(FailureReason::PingTimeout(
+                    self.consecutive_losses,
+                )))
+            } else {
+                Some(HealthEvent::Degraded {
+                    consecutive_losses: self.consecutive_losses,
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tolerates_losses_below_limit() {
+        let mut checker = HealthChecker::new(3);
+        let fresh = Some(Duration::from_secs(10));
+
+        assert_eq!(checker.update(true, fresh), None);
+        assert_eq!(
+            checker.update(false, fresh),
+            Some(HealthEvent::Degraded {
+                consecutive_losses: 1
+            })
+        );
+        assert_eq!(
+            checker.update(false, None),
+            Some(HealthEvent::Degraded {
+                consecutive_losses: 2
+            })
+        );
+        assert_eq!(checker.update(true, fresh), Some(HealthEvent::Healthy));
+        checker.update(false, fresh);
+        checker.update(false, fresh);
+        assert_eq!(
+            checker.update(false, fresh),
+            Some(HealthEvent::Failed(FailureReason::PingTimeout(3)))
+        );
+    }
+
+    #[test]
+    fn fails_on_stale_handshake() {
+        let mut checker = HealthChecker::new(3);
+        let stale = Duration::from_secs(200);
+
+        assert_eq!(
+            checker.update(true, Some(stale)),
+            Some(HealthEvent::Failed(FailureReason::StaleHandshake(stale)))
+        );
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    io, mem,
+    net::IpAddr,
+    os::unix::io::RawFd,
+    time::{Duration, Instant},
+};
+
+error_chain! {
+    errors {
+        /// Failed to open a raw ICMP socket
+        OpenSocketError {
+            description("Failed to open ICMP socket")
+        }
+        /// Failed to bind the socket to the tunnel interface
+        BindInterfaceError(interface: String) {
+            description("Failed to bind ICMP socket to interface")
+            display("Failed to bind ICMP socket to interface {}", interface)
+        }
+        /// Failed to send an echo request
+        SendError {
+            description("Failed to send ICMP echo request")
+        }
+        /// Failed to receive an echo reply
+        ReceiveError {
+            description("Failed to receive ICMP echo reply")
+        }
+        /// No echo reply arrived in time
+        TimeoutError {
+            description("Timed out waiting for ICMP echo reply")
+        }
+    }
+}
+
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+const ICMP_HEADER_LEN: usize = 8;
+
+// Not exposed by the libc crate for macOS
+#[cfg(target_os = "macos")]
+const IP_BOUND_IF: libc::c_int = 25;
+#[cfg(target_os = "macos")]
+const IPV6_BOUND_IF: libc::c_int = 125;
+
+/// Sends ICMP echo requests to a single host through a specific interface, using a raw socket
+/// instead of the `ping` binary.
+pub struct Pinger {
+    fd: RawFd,
+    address: IpAddr,
+    id: u16,
+    seq: u16,
+}
+
+impl Pinger {
+    /// Opens a raw socket for pinging `address`, bound to `interface`.
+    pub fn new(address: IpAddr, interface: &str) -> Result<Self> {
+        let (domain, protocol) = match address {
+            IpAddr::V4(_) => (libc::AF_INET, libc::IPPROTO_ICMP),
+            IpAddr::V6(_) => (libc::AF_INET6, libc::IPPROTO_ICMPV6),
+        };
+        let fd = unsafe { libc::socket(domain, libc::SOCK_RAW, protocol) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error()).chain_err(|| ErrorKind::OpenSocketError);
+        }
+
+        let pinger = Pinger {
+            fd,
+            address,
+            id: std::process::id() as u16,
+            seq: 0,
+        };
+        pinger
+            .bind_to_interface(interface)
+            .chain_err(|| ErrorKind::BindInterfaceError(interface.to_owned()))?;
+        Ok(pinger)
+    }
+
+    /// Sends an echo request and waits for the matching reply for at most `timeout`.
+    pub fn ping(&mut self, timeout: Duration) -> Result<()> {
+        self.seq = self.seq.wrapping_add(1);
+        let request = echo_request(self.address.is_ipv6(), self.id, self.seq);
+        self.send(&request).chain_err(|| ErrorKind::SendError)?;
+
+        let start = Instant::now();
+        let mut buffer = [0u8; 1500];
+        loop {
+            let remaining = match timeout.checked_sub(start.elapsed()) {
+                Some(remaining) if remaining > Duration::from_millis(0) => remaining,
+                _ => bail!(ErrorKind::TimeoutError),
+            };
+            let length = match self.receive(&mut buffer, remaining) {
+                Ok(length) => length,
+                Err(ref error)
+                    if error.kind() == io::ErrorKind::WouldBlock
+                        || error.kind() == io::ErrorKind::TimedOut =>
+                {
+                    bail!(ErrorKind::TimeoutError)
+                }
+                Err(error) => return Err(error).chain_err(|| ErrorKind::ReceiveError),
+            };
+            if is_echo_reply(&buffer[..length], self.address.is_ipv6(), self.id, self.seq) {
+                return Ok(());
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn bind_to_interface(&self, interface: &str) -> io::Result<()> {
+        let result = unsafe {
+            libc::setsockopt(
+                self.fd,
+                libc::SOL_SOCKET,
+                libc::SO_BINDTODEVICE,
+                interface.as_ptr() as *const libc::c_void,
+                interface.len() as libc::socklen_t,
+            )
+        };
+        check_result(result)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn bind_to_interface(&self, interface: &str) -> io::Result<()> {
+        let interface = std::ffi::CString::new(interface)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+        let index = unsafe { libc::if_nametoindex(interface.as_ptr()) };
+        if index == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (level, option) = match self.address {
+            IpAddr::V4(_) => (libc::IPPROTO_IP, IP_BOUND_IF),
+            IpAddr::V6(_) => (libc::IPPROTO_IPV6, IPV6_BOUND_IF),
+        };
+        let result = unsafe {
+            libc::setsockopt(
+                self.fd,
+                level,
+                option,
+                &index as *const _ as *const libc::c_void,
+                mem::size_of_val(&index) as libc::socklen_t,
+            )
+        };
+        check_result(result)
+    }
+
+    fn send(&self, packet: &[u8]) -> io::Result<()> {
+        let result = match self.address {
+            IpAddr::V4(address) => {
+                let mut sockaddr: libc::sockaddr_in = unsafe { mem::zeroed() };
+                sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+                sockaddr.sin_addr.s_addr = u32::from(address).to_be();
+                #[cfg(target_os = "macos")]
+                {
+                    sockaddr.sin_len = mem::size_of::<libc::sockaddr_in>() as u8;
+                }
+                unsafe {
+                    libc::sendto(
+                        self.fd,
+                        packet.as_ptr() as *const libc::c_void,
+                        packet.len(),
+                        0,
+                        &sockaddr as *const _ as *const libc::sockaddr,
+                        mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                    )
+                }
+            }
+            IpAddr::V6(address) => {
+                let mut sockaddr: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+                sockaddr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sockaddr.sin6_addr.s6_addr = address.octets();
+                #[cfg(target_os = "macos")]
+                {
+                    sockaddr.sin6_len = mem::size_of::<libc::sockaddr_in6>() as u8;
+                }
+                unsafe {
+                    libc::sendto(
+                        self.fd,
+                        packet.as_ptr() as *const libc::c_void,
+                        packet.len(),
+                        0,
+                        &sockaddr as *const _ as *const libc::sockaddr,
+                        mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                    )
+                }
+            }
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn receive(&self, buffer: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let timeval = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        check_result(unsafe {
+            libc::setsockopt(
+                self.fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeval as *const _ as *const libc::c_void,
+                mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        })?;
+
+        let length = unsafe {
+            libc::recv(
+                self.fd,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                0,
+            )
+        };
+        if length < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(length as usize)
+    }
+}
+
+impl Drop for Pinger {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+fn check_result(result: libc::c_int) -> io::Result<()> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Builds an echo request. The checksum of ICMPv6 packets is filled in by the kernel.
+fn echo_request(is_ipv6: bool, id: u16, seq: u16) -> [u8; ICMP_HEADER_LEN] {
+    let mut packet = [0u8; ICMP_HEADER_LEN];
+    packet[0] = if is_ipv6 {
+        ICMPV6_ECHO_REQUEST
+    } else {
+        ICMP_ECHO_REQUEST
+    };
+    packet[4..6].copy_from_slice(&id.to_be_bytes());
+    packet[6..8].copy_from_slice(&seq.to_be_bytes());
+    if !is_ipv6 {
+        let checksum = checksum(&packet);
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+    packet
+}
+
+/// Checks if a received packet is the reply to the given request. Raw IPv4 sockets receive the
+/// IP header as well, while raw IPv6 sockets only receive the ICMPv6 message.
+fn is_echo_reply(packet: &[u8], is_ipv6: bool, id: u16, seq: u16) -> bool {
+    let (icmp, reply_type) = if is_ipv6 {
+        (packet, ICMPV6_ECHO_REPLY)
+    } else {
+        let header_len = match packet.first() {
+            Some(first_byte) => usize::from(first_byte & 0x0f) * 4,
+            None => return false,
+        };
+        if packet.len() < header_len {
+            return false;
+        }
+        (&packet[header_len..], ICMP_ECHO_REPLY)
+    };
+    icmp.len() >= ICMP_HEADER_LEN
+        && icmp[0] == reply_type
+        && icmp[4..6] == id.to_be_bytes()
+        && icmp[6..8] == seq.to_be_bytes()
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data.chunks(2).fold(0u32, |sum, chunk| {
+        let word = (u32::from(chunk[0]) << 8) | u32::from(*chunk.get(1).unwrap_or(&0));
+        sum + word
+    });
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
@@ -0,0 +1,663 @@
+use super::{Config, ErrorKind, Result, ResultExt, Tunnel};
+use chrono::{offset::Utc, DateTime, NaiveDateTime};
+use ipnetwork::IpNetwork;
+use std::{
+    ffi::CString,
+    io, mem,
+    net::{IpAddr, SocketAddr},
+    os::unix::io::RawFd,
+};
+use talpid_types::tunnel::TunnelStats;
+
+/// Name of the link created for kernel tunnels.
+const INTERFACE_NAME: &str = "wg-mullvad";
+
+// Netlink constants. Not all of them are exposed by the libc crate.
+const NETLINK_ROUTE: libc::c_int = 0;
+const NETLINK_GENERIC: libc::c_int = 16;
+
+const NLMSG_HEADER_LEN: usize = 16;
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_DUMP: u16 = 0x300;
+const NLM_F_EXCL: u16 = 0x200;
+const NLM_F_CREATE: u16 = 0x400;
+
+const NLA_HEADER_LEN: usize = 4;
+const NLA_F_NESTED: u16 = 0x8000;
+
+const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const RTM_NEWADDR: u16 = 20;
+const IFLA_IFNAME: u16 = 3;
+const IFLA_MTU: u16 = 4;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_INFO_KIND: u16 = 1;
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+const IFF_UP: u32 = 0x1;
+
+const GENL_HEADER_LEN: usize = 4;
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+// From the `wireguard` generic netlink family, see `uapi/linux/wireguard.h`
+const WG_GENL_NAME: &str = "wireguard";
+const WG_GENL_VERSION: u8 = 1;
+const WG_CMD_GET_DEVICE: u8 = 0;
+const WG_CMD_SET_DEVICE: u8 = 1;
+const WGDEVICE_A_IFINDEX: u16 = 1;
+const WGDEVICE_A_IFNAME: u16 = 2;
+const WGDEVICE_A_PRIVATE_KEY: u16 = 3;
+const WGDEVICE_A_FLAGS: u16 = 5;
+const WGDEVICE_A_FWMARK: u16 = 7;
+const WGDEVICE_A_PEERS: u16 = 8;
+const WGDEVICE_F_REPLACE_PEERS: u32 = 1;
+const WGPEER_A_PUBLIC_KEY: u16 = 1;
+const WGPEER_A_PRESHARED_KEY: u16 = 2;
+const WGPEER_A_FLAGS: u16 = 3;
+const WGPEER_A_ENDPOINT: u16 = 4;
+const WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL: u16 = 5;
+const WGPEER_A_LAST_HANDSHAKE_TIME: u16 = 6;
+const WGPEER_A_RX_BYTES: u16 = 7;
+const WGPEER_A_TX_BYTES: u16 = 8;
+const WGPEER_A_ALLOWEDIPS: u16 = 9;
+const WGPEER_F_REPLACE_ALLOWEDIPS: u32 = 2;
+const WGALLOWEDIP_A_FAMILY: u16 = 1;
+const WGALLOWEDIP_A_IPADDR: u16 = 2;
+const WGALLOWEDIP_A_CIDR_MASK: u16 = 3;
+
+const RECEIVE_BUFFER_SIZE: usize = 8192;
+
+/// A tunnel running on the WireGuard kernel module. The link is created and configured over
+/// netlink, so the traffic never passes through the daemon.
+pub struct KernelTunnel {
+    interface_name: String,
+    interface_index: u32,
+}
+
+impl KernelTunnel {
+    /// Creates and configures a `wireguard` link. Fails with `WireguardModuleUnavailable` if the
+    /// kernel doesn't support such links.
+    pub fn start_tunnel(config: &Config) -> Result<Self> {
+        let mut route_socket =
+            NetlinkSocket::new(NETLINK_ROUTE).chain_err(|| ErrorKind::SetupTunnelDeviceError)?;
+
+        // A link left behind by a daemon that wasn't stopped cleanly would block creating a new one
+        if let Ok(stale_index) = interface_index(INTERFACE_NAME) {
+            log::debug!("Removing stale {} link", INTERFACE_NAME);
+            let _ = delete_link(&mut route_socket, stale_index);
+        }
+
+        if let Err(error) = create_link(&mut route_socket, INTERFACE_NAME, config.mtu) {
+            let kind = if error.raw_os_error() == Some(libc::EOPNOTSUPP) {
+                ErrorKind::WireguardModuleUnavailable
+            } else {
+                ErrorKind::SetupTunnelDeviceError
+            };
+            return Err(error).chain_err(|| kind);
+        }
+
+        let tunnel = KernelTunnel {
+            interface_name: INTERFACE_NAME.to_owned(),
+            interface_index: interface_index(INTERFACE_NAME)
+                .chain_err(|| ErrorKind::SetupTunnelDeviceError)?,
+        };
+        if let Err(error) = tunnel.configure(&mut route_socket, config) {
+            if let Err(delete_error) = delete_link(&mut route_socket, tunnel.interface_index) {
+                log::error!(
+                    "Failed to remove {} link - {}",
+                    INTERFACE_NAME,
+                    delete_error
+                );
+            }
+            return Err(error);
+        }
+        Ok(tunnel)
+    }
+
+    fn configure(&self, route_socket: &mut NetlinkSocket, config: &Config) -> Result<()> {
+        let mut generic_socket =
+            NetlinkSocket::new(NETLINK_GENERIC).chain_err(|| ErrorKind::SetupTunnelDeviceError)?;
+        let family = resolve_family(&mut generic_socket, WG_GENL_NAME)
+            .chain_err(|| ErrorKind::WireguardModuleUnavailable)?;
+        generic_socket
+            .request(
+                family,
+                0,
+                &generic_message(
+                    WG_CMD_SET_DEVICE,
+                    WG_GENL_VERSION,
+                    device_attributes(self.interface_index, config),
+                ),
+            )
+            .chain_err(|| ErrorKind::ConfigureKernelDeviceError)?;
+
+        for address in &config.tunnel.addresses {
+            add_address(route_socket, self.interface_index, *address)
+                .chain_err(|| ErrorKind::SetupTunnelDeviceError)?;
+        }
+        set_link_up(route_socket, self.interface_index)
+            .chain_err(|| ErrorKind::SetupTunnelDeviceError)
+    }
+}
+
+impl Tunnel for KernelTunnel {
+    fn get_interface_name(&self) -> &str {
+        &self.interface_name
+    }
+
+    fn update_endpoints(&self, config: &Config) -> Result<()> {
+        let mut generic_socket =
+            NetlinkSocket::new(NETLINK_GENERIC).chain_err(|| ErrorKind::UpdateEndpointsError)?;
+        let family = resolve_family(&mut generic_socket, WG_GENL_NAME)
+            .chain_err(|| ErrorKind::UpdateEndpointsError)?;
+        generic_socket
+            .request(
+                family,
+                0,
+                &generic_message(
+                    WG_CMD_SET_DEVICE,
+                    WG_GENL_VERSION,
+                    endpoint_attributes(&self.interface_name, config),
+                ),
+            )
+            .map(|_| ())
+            .chain_err(|| ErrorKind::UpdateEndpointsError)
+    }
+
+    fn stop(self: Box<Self>) -> Result<()> {
+        let mut route_socket =
+            NetlinkSocket::new(NETLINK_ROUTE).chain_err(|| ErrorKind::DeleteInterfaceError)?;
+        delete_link(&mut route_socket, self.interface_index)
+            .chain_err(|| ErrorKind::DeleteInterfaceError)
+    }
+}
+
+/// Reads the byte counters and the latest handshake of a kernel WireGuard device in the network
+/// namespace of the calling thread. The counters of all peers are summed up.
+pub fn get_device_stats(interface_name: &str) -> io::Result<TunnelStats> {
+    let mut generic_socket = NetlinkSocket::new(NETLINK_GENERIC)?;
+    let family = resolve_family(&mut generic_socket, WG_GENL_NAME)?;
+    let mut attributes = Attributes::new();
+    attributes.add_str(WGDEVICE_A_IFNAME, interface_name);
+    // The device can only be dumped, and large devices are split over several messages
+    let responses = generic_socket.request(
+        family,
+        NLM_F_DUMP,
+        &generic_message(WG_CMD_GET_DEVICE, WG_GENL_VERSION, attributes),
+    )?;
+    Ok(parse_device_stats(&responses))
+}
+
+fn interface_index(name: &str) -> io::Result<u32> {
+    let name =
+        CString::new(name).map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if index == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(index)
+}
+
+fn create_link(socket: &mut NetlinkSocket, name: &str, mtu: u16) -> io::Result<()> {
+    let mut link_info = Attributes::new();
+    link_info.add_str(IFLA_INFO_KIND, "wireguard");
+    let mut attributes = Attributes::new();
+    attributes
+        .add_str(IFLA_IFNAME, name)
+        .add_u32(IFLA_MTU, u32::from(mtu))
+        .add_nested(IFLA_LINKINFO, link_info);
+
+    let mut message = interface_info_message(0, 0, 0);
+    message.extend(attributes.into_bytes());
+    socket
+        .request(RTM_NEWLINK, NLM_F_CREATE | NLM_F_EXCL, &message)
+        .map(|_| ())
+}
+
+fn set_link_up(socket: &mut NetlinkSocket, index: u32) -> io::Result<()> {
+    let message = interface_info_message(index, IFF_UP, IFF_UP);
+    socket.request(RTM_NEWLINK, 0, &message).map(|_| ())
+}
+
+fn delete_link(socket: &mut NetlinkSocket, index: u32) -> io::Result<()> {
+    let message = interface_info_message(index, 0, 0);
+    socket.request(RTM_DELLINK, 0, &message).map(|_| ())
+}
+
+fn add_address(socket: &mut NetlinkSocket, index: u32, address: IpAddr) -> io::Result<()> {
+    let (family, prefix, address_bytes) = match address {
+        IpAddr::V4(address) => (libc::AF_INET, 32, address.octets().to_vec()),
+        IpAddr::V6(address) => (libc::AF_INET6, 128, address.octets().to_vec()),
+    };
+    // struct ifaddrmsg
+    let mut message = vec![family as u8, prefix, 0, 0];
+    message.extend(&index.to_ne_bytes());
+
+    let mut attributes = Attributes::new();
+    attributes
+        .add(IFA_LOCAL, &address_bytes)
+        .add(IFA_ADDRESS, &address_bytes);
+    message.extend(attributes.into_bytes());
+    socket
+        .request(RTM_NEWADDR, NLM_F_CREATE | NLM_F_EXCL, &message)
+        .map(|_| ())
+}
+
+/// Builds a `struct ifinfomsg` for the given interface index.
+fn interface_info_message(index: u32, flags: u32, change: u32) -> Vec<u8> {
+    let mut message = vec![libc::AF_UNSPEC as u8, 0, 0, 0];
+    message.extend(&index.to_ne_bytes());
+    message.extend(&flags.to_ne_bytes());
+    message.extend(&change.to_ne_bytes());
+    message
+}
+
+/// Looks up the ID of a generic netlink family by its name.
+fn resolve_family(socket: &mut NetlinkSocket, name: &str) -> io::Result<u16> {
+    let mut attributes = Attributes::new();
+    attributes.add_str(CTRL_ATTR_FAMILY_NAME, name);
+    let responses = socket.request(
+        GENL_ID_CTRL,
+        0,
+        &generic_message(CTRL_CMD_GETFAMILY, 1, attributes),
+    )?;
+
+    responses
+        .iter()
+        .filter_map(|response| response.get(GENL_HEADER_LEN..))
+        .flat_map(parse_attributes)
+        .find(|(attribute_type, _)| *attribute_type == CTRL_ATTR_FAMILY_ID)
+        .and_then(|(_, value)| value.get(0..2).map(read_u16))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No such netlink family"))
+}
+
+/// Sums up the peer counters in the replies to a `WG_CMD_GET_DEVICE` dump and picks the latest
+/// handshake.
+fn parse_device_stats(responses: &[Vec<u8>]) -> TunnelStats {
+    let mut stats = TunnelStats::default();
+    let peers = responses
+        .iter()
+        .filter_map(|response| response.get(GENL_HEADER_LEN..))
+        .flat_map(parse_attributes)
+        .filter(|(attribute_type, _)| *attribute_type == WGDEVICE_A_PEERS)
+        .flat_map(|(_, peers)| parse_attributes(peers));
+
+    for (_, peer) in peers {
+        for (attribute_type, value) in parse_attributes(peer) {
+            match attribute_type {
+                WGPEER_A_RX_BYTES if value.len() >= 8 => stats.rx_bytes += read_u64(value),
+                WGPEER_A_TX_BYTES if value.len() >= 8 => stats.tx_bytes += read_u64(value),
+                // A `struct __kernel_timespec`, which is all zeroes if no handshake has completed
+                WGPEER_A_LAST_HANDSHAKE_TIME if value.len() >= 16 => {
+                    let handshake_sec = read_u64(&value[0..8]) as i64;
+                    let handshake_nsec = read_u64(&value[8..16]) as u32;
+                    if handshake_sec == 0 {
+                        continue;
+                    }
+                    let handshake =
+                        NaiveDateTime::from_timestamp_opt(handshake_sec, handshake_nsec)
+                            .map(|time| DateTime::<Utc>::from_utc(time, Utc));
+                    if handshake > stats.last_handshake {
+                        stats.last_handshake = handshake;
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    stats
+}
+
+fn generic_message(command: u8, version: u8, attributes: Attributes) -> Vec<u8> {
+    let mut message = vec![command, version, 0, 0];
+    message.extend(attributes.into_bytes());
+    message
+}
+
+fn device_attributes(interface_index: u32, config: &Config) -> Attributes {
+    let mut peers = Attributes::new();
+    for peer in &config.peers {
+        let mut allowed_ips = Attributes::new();
+        for allowed_ip in &peer.allowed_ips {
+            allowed_ips.add_nested(0, allowed_ip_attributes(allowed_ip));
+        }
+
+        let mut peer_attributes = Attributes::new();
+        peer_attributes
+            .add(WGPEER_A_PUBLIC_KEY, peer.public_key.as_bytes())
+            .add_u32(WGPEER_A_FLAGS, WGPEER_F_REPLACE_ALLOWEDIPS)
+            .add(WGPEER_A_ENDPOINT, &sockaddr_bytes(peer.endpoint))
+            .add_nested(WGPEER_A_ALLOWEDIPS, allowed_ips);
+        if let Some(preshared_key) = &peer.preshared_key {
+            peer_attributes.add(WGPEER_A_PRESHARED_KEY, preshared_key.as_bytes());
+        }
+        if let Some(interval) = config.persistent_keepalive {
+            peer_attributes.add(
+                WGPEER_A_PERSISTENT_KEEPALIVE_INTERVAL,
+                &interval.to_ne_bytes(),
+            );
+        }
+        peers.add_nested(0, peer_attributes);
+    }
+
+    let mut attributes = Attributes::new();
+    attributes
+        .add_u32(WGDEVICE_A_IFINDEX, interface_index)
+        .add(WGDEVICE_A_PRIVATE_KEY, config.tunnel.private_key.as_bytes())
+        .add_u32(WGDEVICE_A_FLAGS, WGDEVICE_F_REPLACE_PEERS)
+        .add_u32(WGDEVICE_A_FWMARK, config.fwmark as u32)
+        .add_nested(WGDEVICE_A_PEERS, peers);
+    attributes
+}
+
+/// Attributes that only set the endpoints of the peers. The device is looked up by name, since
+/// its index may change if it has been moved into another network namespace.
+fn endpoint_attributes(interface_name: &str, config: &Config) -> Attributes {
+    let mut peers = Attributes::new();
+    for peer in &config.peers {
+        let mut peer_attributes = Attributes::new();
+        peer_attributes
+            .add(WGPEER_A_PUBLIC_KEY, peer.public_key.as_bytes())
+            .add(WGPEER_A_ENDPOINT, &sockaddr_bytes(peer.endpoint));
+        peers.add_nested(0, peer_attributes);
+    }
+
+    let mut attributes = Attributes::new();
+    attributes
+        .add_str(WGDEVICE_A_IFNAME, interface_name)
+        .add_nested(WGDEVICE_A_PEERS, peers);
+    attributes
+}
+
+fn allowed_ip_attributes(allowed_ip: &IpNetwork) -> Attributes {
+    let (family, address) = match allowed_ip.ip() {
+        IpAddr::V4(address) => (libc::AF_INET, address.octets().to_vec()),
+        IpAddr::V6(address) => (libc::AF_INET6, address.octets().to_vec()),
+    };
+    let mut attributes = Attributes::new();
+    attributes
+        .add(WGALLOWEDIP_A_FAMILY, &(family as u16).to_ne_bytes())
+        .add(WGALLOWEDIP_A_IPADDR, &address)
+        .add(WGALLOWEDIP_A_CIDR_MASK, &[allowed_ip.prefix()]);
+    attributes
+}
+
+/// Serializes an endpoint as a `struct sockaddr_in` or `struct sockaddr_in6`.
+fn sockaddr_bytes(endpoint: SocketAddr) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    match endpoint {
+        SocketAddr::V4(endpoint) => {
+            bytes.extend(&(libc::AF_INET as u16).to_ne_bytes());
+            bytes.extend(&endpoint.port().to_be_bytes());
+            bytes.extend(&endpoint.ip().octets());
+            bytes.extend(&[0u8; 8]);
+        }
+        SocketAddr::V6(endpoint) => {
+            bytes.extend(&(libc::AF_INET6 as u16).to_ne_bytes());
+            bytes.extend(&endpoint.port().to_be_bytes());
+            bytes.extend(&endpoint.flowinfo().to_be_bytes());
+            bytes.extend(&endpoint.ip().octets());
+            bytes.extend(&endpoint.scope_id().to_ne_bytes());
+        }
+    }
+    bytes
+}
+
+/// Builds a list of netlink attributes, each padded to four bytes.
+struct Attributes {
+    buf: Vec<u8>,
+}
+
+impl Attributes {
+    fn new() -> Self {
+        Attributes { buf: Vec::new() }
+    }
+
+    fn add(&mut self, attribute_type: u16, value: &[u8]) -> &mut Self {
+        self.buf
+            .extend(&((NLA_HEADER_LEN + value.len()) as u16).to_ne_bytes());
+        self.buf.extend(&attribute_type.to_ne_bytes());
+        self.buf.extend(value);
+        self.buf.resize(align(self.buf.len()), 0);
+        self
+    }
+
+    fn add_u32(&mut self, attribute_type: u16, value: u32) -> &mut Self {
+        self.add(attribute_type, &value.to_ne_bytes())
+    }
+
+    fn add_str(&mut self, attribute_type: u16, value: &str) -> &mut Self {
+        let mut value = value.as_bytes().to_vec();
+        value.push(0);
+        self.add(attribute_type, &value)
+    }
+
+    fn add_nested(&mut self, attribute_type: u16, nested: Attributes) -> &mut Self {
+        self.add(attribute_type | NLA_F_NESTED, &nested.buf)
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+fn parse_attributes(mut data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attributes = Vec::new();
+    while data.len() >= NLA_HEADER_LEN {
+        let length = usize::from(read_u16(&data[0..2]));
+        if length < NLA_HEADER_LEN || length > data.len() {
+            break;
+        }
+        let attribute_type = read_u16(&data[2..4]) & !NLA_F_NESTED;
+        attributes.push((attribute_type, &data[NLA_HEADER_LEN..length]));
+        data = &data[align(length).min(data.len())..];
+    }
+    attributes
+}
+
+fn align(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_ne_bytes([bytes[0], bytes[1]])
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut value = [0u8; 8];
+    value.copy_from_slice(&bytes[0..8]);
+    u64::from_ne_bytes(value)
+}
+
+/// A netlink socket that sends one request at a time and waits for it to be acknowledged.
+struct NetlinkSocket {
+    fd: RawFd,
+    seq: u32,
+}
+
+impl NetlinkSocket {
+    fn new(protocol: libc::c_int) -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                protocol,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let socket = NetlinkSocket { fd, seq: 0 };
+
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let result = unsafe {
+            libc::bind(
+                fd,
+                &address as *const _ as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(socket)
+    }
+
+    /// Sends a request and waits for the kernel to acknowledge it. Returns the payloads of the
+    /// messages received in reply before the acknowledgement.
+    fn request(
+        &mut self,
+        message_type: u16,
+        flags: u16,
+        payload: &[u8],
+    ) -> io::Result<Vec<Vec<u8>>> {
+        self.seq = self.seq.wrapping_add(1);
+        let mut message: Vec<u8> = Vec::with_capacity(NLMSG_HEADER_LEN + payload.len());
+        message.extend(&((NLMSG_HEADER_LEN + payload.len()) as u32).to_ne_bytes());
+        message.extend(&message_type.to_ne_bytes());
+        message.extend(&(flags | NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+        message.extend(&self.seq.to_ne_bytes());
+        message.extend(&0u32.to_ne_bytes());
+        message.extend(payload);
+
+        let sent = unsafe {
+            libc::send(
+                self.fd,
+                message.as_ptr() as *const libc::c_void,
+                message.len(),
+                0,
+            )
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut responses = Vec::new();
+        let mut buffer = vec![0u8; RECEIVE_BUFFER_SIZE];
+        loop {
+            let length = unsafe {
+                libc::recv(
+                    self.fd,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    0,
+                )
+            };
+            if length < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut remaining = &buffer[..length as usize];
+            while remaining.len() >= NLMSG_HEADER_LEN {
+                let message_len = read_u32(&remaining[0..4]) as usize;
+                if message_len < NLMSG_HEADER_LEN || message_len > remaining.len() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Truncated netlink message",
+                    ));
+                }
+                let message_type = read_u16(&remaining[4..6]);
+                let seq = read_u32(&remaining[8..12]);
+                let payload = &remaining[NLMSG_HEADER_LEN..message_len];
+
+                if seq == self.seq {
+                    match message_type {
+                        NLMSG_ERROR => {
+                            let code = payload.get(0..4).map(read_u32).ok_or_else(|| {
+                                io::Error::new(
+                                    io::ErrorKind::InvalidData,
+                                    "Truncated netlink error",
+                                )
+                            })? as i32;
+                            return if code == 0 {
+                                Ok(responses)
+                            } else {
+                                Err(io::Error::from_raw_os_error(-code))
+                            };
+                        }
+                        NLMSG_DONE => return Ok(responses),
+                        _ => responses.push(payload.to_vec()),
+                    }
+                }
+                remaining = &remaining[align(message_len).min(remaining.len())..];
+            }
+        }
+    }
+}
+
+impl Drop for NetlinkSocket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_attributes_are_padded_and_parseable() {
+        let mut link_info = Attributes::new();
+        link_info.add_str(IFLA_INFO_KIND, "wireguard");
+        let mut attributes = Attributes::new();
+        attributes
+            .add_str(IFLA_IFNAME, "wg0")
+            .add_nested(IFLA_LINKINFO, link_info);
+        let bytes = attributes.into_bytes();
+
+        assert_eq!(bytes.len() % 4, 0);
+        let parsed = parse_attributes(&bytes);
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0], (IFLA_IFNAME, &b"wg0\0"[..]));
+        assert_eq!(parsed[1].0, IFLA_LINKINFO);
+        assert_eq!(
+            parse_attributes(parsed[1].1),
+            vec![(IFLA_INFO_KIND, &b"wireguard\0"[..])]
+        );
+    }
+    #[test]
+    fn sums_peer_counters_of_device_dump() {
+        let peer = |rx_bytes: u64, tx_bytes: u64, handshake_sec: u64| {
+            let mut handshake = handshake_sec.to_ne_bytes().to_vec();
+            handshake.extend(&500u64.to_ne_bytes());
+            let mut peer = Attributes::new();
+            peer.add(WGPEER_A_RX_BYTES, &rx_bytes.to_ne_bytes())
+                .add(WGPEER_A_TX_BYTES, &tx_bytes.to_ne_bytes())
+                .add(WGPEER_A_LAST_HANDSHAKE_TIME, &handshake);
+            peer
+        };
+        // The peers of a device may be split over several messages
+        let message = |peer: Attributes| {
+            let mut peers = Attributes::new();
+            peers.add_nested(0, peer);
+            let mut attributes = Attributes::new();
+            attributes
+                .add_u32(WGDEVICE_A_IFINDEX, 3)
+                .add_nested(WGDEVICE_A_PEERS, peers);
+            generic_message(WG_CMD_GET_DEVICE, WG_GENL_VERSION, attributes)
+        };
+
+        let stats = parse_device_stats(&[
+            message(peer(100, 200, 1_550_000_000)),
+            message(peer(1, 2, 0)),
+        ]);
+
+        assert_eq!(stats.rx_bytes, 101);
+        assert_eq!(stats.tx_bytes, 202);
+        assert_eq!(
+            stats.last_handshake.map(|handshake| handshake.timestamp()),
+            Some(1_550_000_000)
+        );
+    }
+}
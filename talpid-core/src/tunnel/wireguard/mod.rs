@@ -1,17 +1,28 @@
-use self::config::Config;
+use self::{
+    config::Config,
+    health_monitor::{FailureReason, HealthEvent, HealthMonitorHandle},
+};
 use super::{TunnelEvent, TunnelMetadata};
 use crate::routing;
+#[cfg(target_os = "linux")]
+use crate::{netns::NetworkNamespace, network_interface::NetworkInterface};
+use error_chain::ChainedError;
 use std::{path::Path, sync::mpsc};
+#[cfg(target_os = "linux")]
+use talpid_types::net::wireguard::Backend;
 
 pub mod config;
-mod ping_monitor;
+mod health_monitor;
+mod icmp;
+#[cfg(target_os = "linux")]
+pub mod kernel;
+pub mod mtu_detection;
 pub mod wireguard_go;
 
+#[cfg(target_os = "linux")]
+pub use self::kernel::KernelTunnel;
 pub use self::wireguard_go::WgGoTunnel;
 
-// amount of seconds to run `ping` until it returns.
-const PING_TIMEOUT: u16 = 5;
-
 error_chain! {
     errors {
         /// Config error
@@ -22,6 +33,18 @@ error_chain! {
         SetupTunnelDeviceError {
             description("Failed to create tunnel device")
         }
+        /// The kernel has no support for wireguard links
+        WireguardModuleUnavailable {
+            description("The WireGuard kernel module is not available")
+        }
+        /// Failed to configure the keys and peers of a kernel wireguard device
+        ConfigureKernelDeviceError {
+            description("Failed to configure WireGuard kernel device")
+        }
+        /// Failed to remove the tunnel interface
+        DeleteInterfaceError {
+            description("Failed to remove tunnel interface")
+        }
         /// Failed to setup wireguard tunnel
         StartWireguardError(status: i32) {
             display("Failed to start wireguard tunnel - {}", status)
@@ -34,6 +57,14 @@ error_chain! {
         SetupRoutingError {
             display("Failed to setup routing")
         }
+        /// Failed to point the peers at their endpoints again
+        UpdateEndpointsError {
+            display("Failed to update peer endpoints")
+        }
+        /// Failed to move the tunnel interface into its network namespace
+        NamespaceError(namespace: String) {
+            display("Failed to move tunnel interface into network namespace {}", namespace)
+        }
         /// Failed to move or craete a log file
         PrepareLogFileError {
             display("Failed to setup a logging file")
@@ -46,9 +77,9 @@ error_chain! {
         NoKeyError {
             display("Config has no keys")
         }
-        /// Pinging timed out
-        PingTimeoutError {
-            display("Ping timed out")
+        /// The tunnel failed its health check
+        HealthCheckError(reason: FailureReason) {
+            display("Tunnel health check failed - {}", reason)
         }
     }
 }
@@ -57,12 +88,19 @@ error_chain! {
 pub struct WireguardMonitor {
     /// Tunnel implementation
     tunnel: Box<dyn Tunnel>,
+    /// Configuration the tunnel was started with, needed again when the network changes
+    config: Config,
     /// Route manager
     router: routing::RouteManager,
     /// Callback to signal tunnel events
     event_callback: Box<Fn(TunnelEvent) + Send + Sync + 'static>,
     close_msg_sender: mpsc::Sender<CloseMsg>,
     close_msg_receiver: mpsc::Receiver<CloseMsg>,
+    /// Keeps the health monitor running until the tunnel is closed
+    health_monitor: Option<HealthMonitorHandle>,
+    /// Network namespace the tunnel interface was moved into, if any
+    #[cfg(target_os = "linux")]
+    namespace: Option<NetworkNamespace>,
 }
 
 impl WireguardMonitor {
@@ -71,36 +109,116 @@ impl WireguardMonitor {
         log_path: Option<&Path>,
         on_event: F,
     ) -> Result<WireguardMonitor> {
-        let tunnel = Box::new(WgGoTunnel::start_tunnel(&config, log_path)?);
+        let tunnel = Self::open_tunnel(&config, log_path)?;
+        #[cfg(target_os = "linux")]
+        let namespace = match &config.namespace {
+            Some(namespace) => Some(Self::move_into_namespace(
+                tunnel.get_interface_name(),
+                namespace,
+                config,
+            )?),
+            None => None,
+        };
+        #[cfg(target_os = "linux")]
+        let router = match &namespace {
+            Some(namespace) => routing::RouteManager::new_in_namespace(namespace.name()),
+            None => routing::RouteManager::new().chain_err(|| ErrorKind::SetupRoutingError)?,
+        };
+        #[cfg(not(target_os = "linux"))]
         let router = routing::RouteManager::new().chain_err(|| ErrorKind::SetupRoutingError)?;
         let event_callback = Box::new(on_event);
         let (close_msg_sender, close_msg_receiver) = mpsc::channel();
         let mut monitor = WireguardMonitor {
             tunnel,
+            config: config.clone(),
             router,
             event_callback,
             close_msg_sender,
             close_msg_receiver,
+            health_monitor: None,
+            #[cfg(target_os = "linux")]
+            namespace,
         };
-        monitor.setup_routing(&config)?;
-        monitor.start_pinger(&config);
+        monitor.setup_routing()?;
+        monitor.start_health_monitor(&config);
         monitor.tunnel_up(&config);
 
         Ok(monitor)
     }
 
+    #[cfg(target_os = "linux")]
+    fn open_tunnel(config: &Config, log_path: Option<&Path>) -> Result<Box<dyn Tunnel>> {
+        match config.backend {
+            Some(Backend::Kernel) => Ok(Box::new(KernelTunnel::start_tunnel(config)?)),
+            Some(Backend::Userspace) => Ok(Box::new(WgGoTunnel::start_tunnel(config, log_path)?)),
+            None => match KernelTunnel::start_tunnel(config) {
+                Ok(tunnel) => Ok(Box::new(tunnel)),
+                Err(Error(ErrorKind::WireguardModuleUnavailable, _)) => {
+                    log::info!("WireGuard kernel module is not available, using wireguard-go");
+                    Ok(Box::new(WgGoTunnel::start_tunnel(config, log_path)?))
+                }
+                Err(error) => Err(error),
+            },
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn open_tunnel(config: &Config, log_path: Option<&Path>) -> Result<Box<dyn Tunnel>> {
+        Ok(Box::new(WgGoTunnel::start_tunnel(config, log_path)?))
+    }
+
+    /// Moves the tunnel interface into the given network namespace and configures it there. The
+    /// WireGuard socket stays in the daemon's namespace, so the encrypted traffic still leaves
+    /// through the host's regular routes.
+    #[cfg(target_os = "linux")]
+    fn move_into_namespace(
+        interface: &str,
+        namespace: &str,
+        config: &Config,
+    ) -> Result<NetworkNamespace> {
+        let namespace_error = || ErrorKind::NamespaceError(namespace.to_owned());
+        let network_namespace = NetworkNamespace::create(namespace).chain_err(namespace_error)?;
+        let mut link = network_namespace
+            .move_link(interface)
+            .chain_err(namespace_error)?;
+        link.set_mtu(config.mtu).chain_err(namespace_error)?;
+        for ip in &config.tunnel.addresses {
+            link.set_ip(*ip).chain_err(namespace_error)?;
+        }
+        link.set_up(true).chain_err(namespace_error)?;
+        Ok(network_namespace)
+    }
+
     pub fn close_handle(&self) -> CloseHandle {
         CloseHandle {
             chan: self.close_msg_sender.clone(),
         }
     }
 
-    pub fn wait(self) -> Result<()> {
-        let wait_result = match self.close_msg_receiver.recv() {
-            Ok(CloseMsg::PingErr) => Err(ErrorKind::PingTimeoutError.into()),
-            Ok(CloseMsg::Stop) => Ok(()),
-            Err(_) => Ok(()),
+    pub fn wait(mut self) -> Result<()> {
+        let wait_result = loop {
+            match self.close_msg_receiver.recv() {
+                Ok(CloseMsg::NetworkChanged) => {
+                    if let Err(error) = self.handle_network_change() {
+                        break Err(error);
+                    }
+                }
+                Ok(CloseMsg::HealthCheckFailed(reason)) => {
+                    break Err(ErrorKind::HealthCheckError(reason).into());
+                }
+                Ok(CloseMsg::Stop) | Err(_) => break Ok(()),
+            }
         };
+        drop(self.health_monitor);
+        #[cfg(target_os = "linux")]
+        {
+            // The tunnel implementations remove the interface from the daemon's namespace
+            if let Some(namespace) = &self.namespace {
+                if let Err(e) = namespace.return_link(self.tunnel.get_interface_name()) {
+                    log::error!("Failed to move tunnel interface out of namespace - {}", e);
+                }
+            }
+        }
         if let Err(e) = self.tunnel.stop() {
             log::error!("Failed to stop tunnel - {}", e);
         }
@@ -108,7 +226,41 @@ impl WireguardMonitor {
         wait_result
     }
 
-    fn setup_routing(&mut self, config: &Config) -> Result<()> {
+    /// Keeps the tunnel working after the host has moved to another network, without
+    /// reconnecting. The routes are applied again, since routes to the endpoints go through the old
+    /// default gateway on macOS. The peers are pointed at their endpoints again, which makes the
+    /// tunnel pick a new source address for the encrypted traffic instead of the one that was
+    /// cached for the old network. Only failing to restore the routes is fatal.
+    fn handle_network_change(&mut self) -> Result<()> {
+        log::info!("Network changed, updating routes and peer endpoints of the tunnel");
+        if let Err(error) = self.router.delete_routes() {
+            log::warn!(
+                "Failed to remove routes before re-applying them - {}",
+                error
+            );
+        }
+        self.setup_routing()?;
+        if let Err(error) = self.update_endpoints() {
+            log::error!("{}", error.display_chain());
+        }
+        Ok(())
+    }
+
+    fn update_endpoints(&self) -> Result<()> {
+        // The kernel module only finds devices in the namespace of the calling thread
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(namespace) = &self.namespace {
+                return namespace
+                    .run(|| self.tunnel.update_endpoints(&self.config))
+                    .chain_err(|| ErrorKind::UpdateEndpointsError)?;
+            }
+        }
+        self.tunnel.update_endpoints(&self.config)
+    }
+
+    fn setup_routing(&mut self) -> Result<()> {
+        let config = &self.config;
         let iface_name = self.tunnel.get_interface_name();
         let mut routes: Vec<_> = config
             .peers
@@ -121,9 +273,8 @@ impl WireguardMonitor {
             .collect();
 
         if cfg!(target_os = "macos") {
-            // To survive network roaming on osx, we should listen for new routes and reapply them
-            // here - probably would need RouteManager be extended. Or maybe RouteManager can deal
-            // with it on it's own
+            // These routes go through the default gateway at the time they are added, so they are
+            // added again whenever the network changes
             let default_node = self
                 .router
                 .get_default_route_node()
@@ -141,25 +292,42 @@ impl WireguardMonitor {
 
         let required_routes = routing::RequiredRoutes {
             routes,
+            // Nothing but the tunnel is routed in the namespace, so no separate table is needed
             #[cfg(target_os = "linux")]
-            fwmark: Some(config.fwmark.to_string()),
+            fwmark: match config.namespace {
+                Some(_) => None,
+                None => Some(config.fwmark.to_string()),
+            },
         };
         self.router
             .add_routes(required_routes)
             .chain_err(|| ErrorKind::SetupRoutingError)
     }
 
-    fn start_pinger(&self, config: &Config) {
+    fn start_health_monitor(&mut self, config: &Config) {
         let close_sender = self.close_msg_sender.clone();
 
-        ping_monitor::spawn_ping_monitor(
+        #[cfg(target_os = "linux")]
+        let namespace = config.namespace.clone();
+        #[cfg(not(target_os = "linux"))]
+        let namespace = None;
+
+        let handle = health_monitor::spawn_health_monitor(
             config.gateway,
-            PING_TIMEOUT,
             self.tunnel.get_interface_name().to_string(),
-            move || {
-                let _ = close_sender.send(CloseMsg::PingErr);
+            namespace,
+            move |event| match event {
+                HealthEvent::Healthy => log::info!("Tunnel is responding again"),
+                HealthEvent::Degraded { consecutive_losses } => log::warn!(
+                    "Tunnel is not responding, {} pings in a row were lost",
+                    consecutive_losses
+                ),
+                HealthEvent::Failed(reason) => {
+                    let _ = close_sender.send(CloseMsg::HealthCheckFailed(reason));
+                }
             },
-        )
+        );
+        self.health_monitor = Some(handle);
     }
 
     fn tunnel_up(&self, config: &Config) {
@@ -168,6 +336,7 @@ impl WireguardMonitor {
             interface: interface_name.to_string(),
             ips: config.tunnel.addresses.clone(),
             gateway: config.gateway,
+            ipv6_gateway: config.ipv6_gateway,
         };
         (self.event_callback)(TunnelEvent::Up(metadata));
     }
@@ -175,7 +344,8 @@ impl WireguardMonitor {
 
 enum CloseMsg {
     Stop,
-    PingErr,
+    NetworkChanged,
+    HealthCheckFailed(FailureReason),
 }
 
 #[derive(Clone, Debug)]
@@ -190,9 +360,17 @@ impl CloseHandle {
             log::trace!("Failed to send close message to wireguard tunnel - {}", e);
         }
     }
+
+    /// Makes the tunnel adapt to the host having moved to another network.
+    pub fn network_changed(&self) {
+        if let Err(e) = self.chan.send(CloseMsg::NetworkChanged) {
+            log::trace!("Failed to send network change to wireguard tunnel - {}", e);
+        }
+    }
 }
 
 pub trait Tunnel: Send {
     fn get_interface_name(&self) -> &str;
+    fn update_endpoints(&self, config: &Config) -> Result<()>;
     fn stop(self: Box<Self>) -> Result<()>;
 }
@@ -0,0 +1,176 @@
+//! Path MTU detection towards a WireGuard peer.
+//!
+//! Probing happens before the tunnel is up, using UDP datagrams with the don't-fragment bit set.
+//! The kernel rejects datagrams larger than the MTU it knows for the path with `EMSGSIZE`, which
+//! covers the local link as well as any path MTU learned from earlier ICMP feedback. Since the
+//! firewall only lets established traffic in from the relay, fresh "fragmentation needed"
+//! messages from routers along the path may not be seen, so the result is an upper bound. On
+//! common links it's just the MTU of the local link, so the tunnel MTU is never raised above the
+//! default.
+
+use super::config::DEFAULT_MTU;
+use std::{
+    io, mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    os::unix::io::AsRawFd,
+    thread,
+    time::Duration,
+};
+
+/// Smallest MTU an IPv6 link is allowed to have. Used as the lower bound of the search.
+const MIN_MTU: u16 = 1280;
+/// Largest path MTU that is probed for.
+const MAX_MTU: u16 = 1500;
+/// IPv4 header, UDP header and WireGuard data message overhead.
+const IPV4_WIREGUARD_OVERHEAD: u16 = 20 + 8 + 32;
+/// IPv6 header, UDP header and WireGuard data message overhead.
+const IPV6_WIREGUARD_OVERHEAD: u16 = 40 + 8 + 32;
+const IPV4_HEADER_LEN: u16 = 20;
+const IPV6_HEADER_LEN: u16 = 40;
+const UDP_HEADER_LEN: u16 = 8;
+/// Time to wait between the two probes of a size, giving ICMP feedback time to arrive.
+const PROBE_INTERVAL: Duration = Duration::from_millis(100);
+
+#[cfg(target_os = "linux")]
+const IP_MTU_DISCOVER: libc::c_int = 10;
+#[cfg(target_os = "linux")]
+const IP_PMTUDISC_DO: libc::c_int = 2;
+#[cfg(target_os = "linux")]
+const IPV6_MTU_DISCOVER: libc::c_int = 23;
+#[cfg(target_os = "linux")]
+const IPV6_PMTUDISC_DO: libc::c_int = 2;
+
+// Not exposed by the libc crate for macOS
+#[cfg(target_os = "macos")]
+const IP_DONTFRAG: libc::c_int = 28;
+#[cfg(target_os = "macos")]
+const IPV6_DONTFRAG: libc::c_int = 62;
+
+/// Returns the largest tunnel MTU, up to `DEFAULT_MTU`, that lets encapsulated packets reach
+/// `peer` without being fragmented, or `None` if the path could not be probed or is too small
+/// for a tunnel MTU of at least `MIN_MTU`.
+pub fn detect_tunnel_mtu(peer: SocketAddr) -> Option<u16> {
+    match probe_path_mtu(peer) {
+        Ok(path_mtu) => {
+            let overhead = if peer.is_ipv4() {
+                IPV4_WIREGUARD_OVERHEAD
+            } else {
+                IPV6_WIREGUARD_OVERHEAD
+            };
+            let tunnel_mtu = path_mtu.saturating_sub(overhead);
+            if tunnel_mtu < MIN_MTU {
+                log::warn!(
+                    "Detected path MTU {} to {} leaves room for a tunnel MTU of only {}, using \
+                     the default MTU",
+                    path_mtu,
+                    peer,
+                    tunnel_mtu
+                );
+                return None;
+            }
+            let tunnel_mtu = tunnel_mtu.min(DEFAULT_MTU);
+            log::info!(
+                "Detected path MTU {} to {}, using tunnel MTU {}",
+                path_mtu,
+                peer,
+                tunnel_mtu
+            );
+            Some(tunnel_mtu)
+        }
+        Err(error) => {
+            log::error!("Failed to detect path MTU to {}: {}", peer, error);
+            None
+        }
+    }
+}
+
+/// Binary searches for the largest IP packet size that can be sent to `peer` unfragmented.
+fn probe_path_mtu(peer: SocketAddr) -> io::Result<u16> {
+    let local_address: IpAddr = match peer {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind((local_address, 0))?;
+    socket.connect(peer)?;
+    set_dont_fragment(&socket, peer.is_ipv6())?;
+
+    let header_len = UDP_HEADER_LEN
+        + if peer.is_ipv4() {
+            IPV4_HEADER_LEN
+        } else {
+            IPV6_HEADER_LEN
+        };
+
+    let mut low = MIN_MTU;
+    let mut high = MAX_MTU;
+    while low < high {
+        let mtu = low + (high - low + 1) / 2;
+        if probe(&socket, usize::from(mtu - header_len))? {
+            low = mtu;
+        } else {
+            high = mtu - 1;
+        }
+    }
+    Ok(low)
+}
+
+/// Sends a datagram with a payload of `payload_len` bytes, returning whether it fit the path.
+/// The payload is all zeroes, which the relay discards as an invalid WireGuard message.
+fn probe(socket: &UdpSocket, payload_len: usize) -> io::Result<bool> {
+    let payload = vec![0u8; payload_len];
+    for attempt in 0..2 {
+        if attempt > 0 {
+            thread::sleep(PROBE_INTERVAL);
+        }
+        match socket.send(&payload) {
+            Ok(_) => (),
+            Err(ref error) if error.raw_os_error() == Some(libc::EMSGSIZE) => return Ok(false),
+            // An earlier probe was answered with port unreachable, which says nothing about size
+            Err(ref error) if error.kind() == io::ErrorKind::ConnectionRefused => (),
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(target_os = "linux")]
+fn set_dont_fragment(socket: &UdpSocket, is_ipv6: bool) -> io::Result<()> {
+    let (level, option, value) = if is_ipv6 {
+        (libc::IPPROTO_IPV6, IPV6_MTU_DISCOVER, IPV6_PMTUDISC_DO)
+    } else {
+        (libc::IPPROTO_IP, IP_MTU_DISCOVER, IP_PMTUDISC_DO)
+    };
+    set_socket_option(socket, level, option, value)
+}
+
+#[cfg(target_os = "macos")]
+fn set_dont_fragment(socket: &UdpSocket, is_ipv6: bool) -> io::Result<()> {
+    let (level, option) = if is_ipv6 {
+        (libc::IPPROTO_IPV6, IPV6_DONTFRAG)
+    } else {
+        (libc::IPPROTO_IP, IP_DONTFRAG)
+    };
+    set_socket_option(socket, level, option, 1)
+}
+
+fn set_socket_option(
+    socket: &UdpSocket,
+    level: libc::c_int,
+    option: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            option,
+            &value as *const _ as *const libc::c_void,
+            mem::size_of_val(&value) as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
@@ -3,7 +3,16 @@ use crate::{
     logging,
     network_interface::{NetworkInterface, TunnelDevice},
 };
-use std::{ffi::CString, fs, os::unix::io::AsRawFd, path::Path};
+use std::{
+    ffi::CString,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{io::AsRawFd, net::UnixStream},
+    path::Path,
+};
+
+/// Directory where wireguard-go listens for configuration requests, one socket per device.
+const UAPI_SOCKET_DIR: &str = "/var/run/wireguard";
 
 
 pub struct WgGoTunnel {
@@ -26,6 +35,10 @@ impl WgGoTunnel {
                 .chain_err(|| ErrorKind::SetupTunnelDeviceError)?;
         }
 
+        tunnel_device
+            .set_mtu(config.mtu)
+            .chain_err(|| ErrorKind::SetupTunnelDeviceError)?;
+
         tunnel_device
             .set_up(true)
             .chain_err(|| ErrorKind::SetupTunnelDeviceError)?;
@@ -76,6 +89,14 @@ impl Tunnel for WgGoTunnel {
         &self.interface_name
     }
 
+    fn update_endpoints(&self, config: &Config) -> Result<()> {
+        uapi_set(
+            &self.interface_name,
+            config.to_userspace_endpoints_format().as_bytes(),
+        )
+        .chain_err(|| ErrorKind::UpdateEndpointsError)
+    }
+
     fn stop(self: Box<Self>) -> Result<()> {
         let status = unsafe { wgTurnOff(self.handle) };
         if status < 0 {
@@ -85,6 +106,25 @@ impl Tunnel for WgGoTunnel {
     }
 }
 
+/// Applies `config` to a running device through its UAPI socket.
+fn uapi_set(interface_name: &str, config: &[u8]) -> io::Result<()> {
+    let socket_path = Path::new(UAPI_SOCKET_DIR).join(format!("{}.sock", interface_name));
+    let mut socket = UnixStream::connect(socket_path)?;
+    socket.write_all(b"set=1\n")?;
+    socket.write_all(config)?;
+
+    let mut response = String::new();
+    BufReader::new(socket).read_line(&mut response)?;
+    match response.trim().trim_start_matches("errno=").parse() {
+        Ok(0) => Ok(()),
+        Ok(errno) => Err(io::Error::from_raw_os_error(errno)),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected UAPI response: {}", response.trim()),
+        )),
+    }
+}
+
 #[cfg(unix)]
 pub type Fd = std::os::unix::io::RawFd;
 
@@ -17,6 +17,7 @@ impl BlockedState {
     fn set_firewall_policy(shared_values: &mut SharedTunnelStateValues) -> Option<BlockReason> {
         let policy = FirewallPolicy::Blocked {
             allow_lan: shared_values.allow_lan,
+            allowed_endpoint: shared_values.allowed_endpoint,
         };
 
         match shared_values
@@ -63,10 +64,47 @@ impl TunnelState for BlockedState {
                 Self::set_firewall_policy(shared_values);
                 SameState(self)
             }
+            Ok(TunnelCommand::AllowEndpoint(endpoint)) => {
+                shared_values.allowed_endpoint = Some(endpoint);
+                Self::set_firewall_policy(shared_values);
+                SameState(self)
+            }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self)
             }
+            Ok(TunnelCommand::PersistentBlock(persistent_block)) => {
+                shared_values.persistent_block = persistent_block;
+                SameState(self)
+            }
+            Ok(TunnelCommand::PrepareShutdown) => {
+                shared_values.shutting_down = true;
+                SameState(self)
+            }
+            Ok(TunnelCommand::ForwardedPorts(forwarded_ports)) => {
+                shared_values.forwarded_ports = forwarded_ports;
+                SameState(self)
+            }
+            Ok(TunnelCommand::CustomDns(servers)) => {
+                shared_values.custom_dns = servers;
+                SameState(self)
+            }
+            Ok(TunnelCommand::EncryptedDns(upstream)) => {
+                shared_values.encrypted_dns = upstream;
+                SameState(self)
+            }
+            Ok(TunnelCommand::SplitDns(split_dns)) => {
+                shared_values.split_dns = split_dns;
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetDnsStatus(status_tx)) => {
+                let _ = status_tx.send(shared_values.dns_monitor.status());
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetTunnelStats(stats_tx)) => {
+                let _ = stats_tx.send(None);
+                SameState(self)
+            }
             Ok(TunnelCommand::IsOffline(is_offline)) => {
                 shared_values.is_offline = is_offline;
                 if !is_offline && self.block_reason == BlockReason::IsOffline {
@@ -3,9 +3,10 @@ use futures::{
     sync::{mpsc, oneshot},
     Async, Future, Stream,
 };
+use std::net::IpAddr;
 use talpid_types::{
-    net::{Endpoint, TunnelParameters},
-    tunnel::BlockReason,
+    net::{Endpoint, TransportProtocol, TunnelParameters},
+    tunnel::{BlockReason, ReconnectReason, TunnelStats},
 };
 
 use super::{
@@ -13,9 +14,11 @@ use super::{
     ResultExt, SharedTunnelStateValues, TunnelCommand, TunnelState, TunnelStateTransition,
     TunnelStateWrapper,
 };
+#[cfg(target_os = "linux")]
+use crate::netns::NetworkNamespace;
 use crate::{
     firewall::FirewallPolicy,
-    tunnel::{CloseHandle, TunnelEvent, TunnelMetadata},
+    tunnel::{self, CloseHandle, TunnelEvent, TunnelMetadata},
 };
 
 pub struct ConnectedStateBootstrap {
@@ -24,6 +27,7 @@ pub struct ConnectedStateBootstrap {
     pub tunnel_parameters: TunnelParameters,
     pub tunnel_close_event: oneshot::Receiver<Option<BlockReason>>,
     pub close_handle: CloseHandle,
+    pub retry_attempt: u32,
 }
 
 /// The tunnel is up and working.
@@ -33,6 +37,7 @@ pub struct ConnectedState {
     tunnel_parameters: TunnelParameters,
     tunnel_close_event: oneshot::Receiver<Option<BlockReason>>,
     close_handle: CloseHandle,
+    retry_attempt: u32,
 }
 
 impl ConnectedState {
@@ -43,17 +48,52 @@ impl ConnectedState {
             tunnel_parameters: bootstrap.tunnel_parameters,
             tunnel_close_event: bootstrap.tunnel_close_event,
             close_handle: bootstrap.close_handle,
+            retry_attempt: bootstrap.retry_attempt,
         }
     }
 
     fn set_firewall_policy(&self, shared_values: &mut SharedTunnelStateValues) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            // Only processes in the namespace use the tunnel, so the host is left unrestricted
+            if self.tunnel_parameters.get_namespace().is_some() {
+                return shared_values
+                    .firewall
+                    .reset_policy()
+                    .chain_err(|| "Failed to reset firewall policy for connected state");
+            }
+        }
+
         // If a proxy is specified we need to pass it on as the peer endpoint.
-        let peer_endpoint = self.get_endpoint_from_params();
+        let mut peer_endpoints = vec![self.get_endpoint_from_params()];
+        if let TunnelParameters::Wireguard(ref params) = self.tunnel_parameters {
+            peer_endpoints.extend(
+                params
+                    .connection
+                    .additional_peers
+                    .iter()
+                    .map(|peer| Endpoint {
+                        address: peer.endpoint,
+                        protocol: TransportProtocol::Udp,
+                    }),
+            );
+        }
 
         let policy = FirewallPolicy::Connected {
-            peer_endpoint,
+            peer_endpoints,
             tunnel: self.metadata.clone(),
             allow_lan: shared_values.allow_lan,
+            forwarded_ports: shared_values.forwarded_ports.clone(),
+            dns_servers: self.get_dns_servers(shared_values),
+            split_dns_servers: shared_values
+                .split_dns
+                .values()
+                .flat_map(|servers| servers.iter().cloned())
+                .collect(),
+            dns_upstream: shared_values
+                .encrypted_dns
+                .as_ref()
+                .map(|upstream| upstream.endpoint()),
         };
         shared_values
             .firewall
@@ -71,10 +111,55 @@ impl ConnectedState {
         }
     }
 
+    fn get_dns_servers(&self, shared_values: &SharedTunnelStateValues) -> Vec<IpAddr> {
+        if shared_values.custom_dns.is_empty() {
+            let mut dns_servers = vec![self.metadata.gateway];
+            dns_servers.extend(self.metadata.ipv6_gateway.map(IpAddr::V6));
+            dns_servers
+        } else {
+            shared_values.custom_dns.clone()
+        }
+    }
+
+    fn get_tunnel_stats(&self) -> Option<TunnelStats> {
+        let is_wireguard = match self.tunnel_parameters {
+            TunnelParameters::Wireguard(_) => true,
+            _ => false,
+        };
+        let namespace = self.tunnel_parameters.get_namespace();
+        match tunnel::stats::get_tunnel_stats(&self.metadata.interface, is_wireguard, namespace) {
+            Ok(stats) => Some(stats),
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to get tunnel statistics")
+                );
+                None
+            }
+        }
+    }
+
     fn set_dns(&self, shared_values: &mut SharedTunnelStateValues) -> Result<()> {
+        let dns_servers = self.get_dns_servers(shared_values);
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(namespace) = self.tunnel_parameters.get_namespace() {
+                return NetworkNamespace::create(namespace)
+                    .and_then(|namespace| namespace.set_dns(&dns_servers))
+                    .chain_err(|| "Failed to set DNS for the tunnel namespace");
+            }
+        }
+        shared_values
+            .dns_monitor
+            .set_encrypted_upstream(shared_values.encrypted_dns.clone())
+            .chain_err(|| "Failed to set encrypted DNS upstream")?;
         shared_values
             .dns_monitor
-            .set(&self.metadata.interface, &[self.metadata.gateway.into()])
+            .set(
+                &self.metadata.interface,
+                &dns_servers,
+                &shared_values.split_dns,
+            )
             .chain_err(|| "Failed to set system DNS settings")
     }
 
@@ -88,6 +173,25 @@ impl ConnectedState {
         }
     }
 
+    /// Applies changed DNS settings to both the firewall and the system.
+    fn update_dns(self, shared_values: &mut SharedTunnelStateValues) -> EventConsequence<Self> {
+        if let Err(error) = self.set_firewall_policy(shared_values) {
+            log::error!("{}", error.display_chain());
+            self.disconnect(
+                shared_values,
+                AfterDisconnect::Block(BlockReason::SetFirewallPolicyError),
+            )
+        } else if let Err(error) = self.set_dns(shared_values) {
+            log::error!("{}", error.display_chain());
+            self.disconnect(
+                shared_values,
+                AfterDisconnect::Block(BlockReason::SetDnsError),
+            )
+        } else {
+            EventConsequence::SameState(self)
+        }
+    }
+
     fn disconnect(
         self,
         shared_values: &mut SharedTunnelStateValues,
@@ -122,10 +226,56 @@ impl ConnectedState {
                     }
                 }
             }
+            Ok(TunnelCommand::AllowEndpoint(endpoint)) => {
+                shared_values.allowed_endpoint = Some(endpoint);
+                SameState(self)
+            }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self)
             }
+            Ok(TunnelCommand::PersistentBlock(persistent_block)) => {
+                shared_values.persistent_block = persistent_block;
+                SameState(self)
+            }
+            Ok(TunnelCommand::PrepareShutdown) => {
+                shared_values.shutting_down = true;
+                SameState(self)
+            }
+            Ok(TunnelCommand::ForwardedPorts(forwarded_ports)) => {
+                shared_values.forwarded_ports = forwarded_ports;
+
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self),
+                    Err(error) => {
+                        log::error!("{}", error.display_chain());
+                        self.disconnect(
+                            shared_values,
+                            AfterDisconnect::Block(BlockReason::SetFirewallPolicyError),
+                        )
+                    }
+                }
+            }
+            Ok(TunnelCommand::CustomDns(servers)) => {
+                shared_values.custom_dns = servers;
+                self.update_dns(shared_values)
+            }
+            Ok(TunnelCommand::EncryptedDns(upstream)) => {
+                shared_values.encrypted_dns = upstream;
+                self.update_dns(shared_values)
+            }
+            Ok(TunnelCommand::SplitDns(split_dns)) => {
+                shared_values.split_dns = split_dns;
+                self.update_dns(shared_values)
+            }
+            Ok(TunnelCommand::GetDnsStatus(status_tx)) => {
+                let _ = status_tx.send(shared_values.dns_monitor.status());
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetTunnelStats(stats_tx)) => {
+                let _ = stats_tx.send(self.get_tunnel_stats());
+                SameState(self)
+            }
             Ok(TunnelCommand::IsOffline(is_offline)) => {
                 shared_values.is_offline = is_offline;
                 if is_offline {
@@ -134,12 +284,14 @@ impl ConnectedState {
                         AfterDisconnect::Block(BlockReason::IsOffline),
                     )
                 } else {
+                    self.close_handle.network_changed();
                     SameState(self)
                 }
             }
-            Ok(TunnelCommand::Connect) => {
-                self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
-            }
+            Ok(TunnelCommand::Connect) => self.disconnect(
+                shared_values,
+                AfterDisconnect::Reconnect(0, ReconnectReason::Requested),
+            ),
             Ok(TunnelCommand::Disconnect) | Err(_) => {
                 self.disconnect(shared_values, AfterDisconnect::Nothing)
             }
@@ -156,8 +308,20 @@ impl ConnectedState {
         use self::EventConsequence::*;
 
         match try_handle_event!(self, self.tunnel_events.poll()) {
-            Ok(TunnelEvent::Down) | Err(_) => {
-                self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+            Ok(TunnelEvent::Down) | Err(_) => self.disconnect(
+                shared_values,
+                AfterDisconnect::Reconnect(0, ReconnectReason::TunnelDown),
+            ),
+            Ok(TunnelEvent::TrafficStalled(stalled_for)) => {
+                log::warn!(
+                    "Reconnecting because nothing was received through the tunnel in {} seconds",
+                    stalled_for.as_secs()
+                );
+                let retry_attempt = self.retry_attempt + 1;
+                self.disconnect(
+                    shared_values,
+                    AfterDisconnect::Reconnect(retry_attempt, ReconnectReason::TrafficStalled),
+                )
             }
             Ok(_) => SameState(self),
         }
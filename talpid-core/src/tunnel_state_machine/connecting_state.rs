@@ -12,8 +12,8 @@ use futures::{
 };
 use log::{debug, error, info, trace, warn};
 use talpid_types::{
-    net::{openvpn, Endpoint, TunnelParameters},
-    tunnel::BlockReason,
+    net::{openvpn, TunnelParameters},
+    tunnel::{BlockReason, ReconnectReason},
 };
 
 use super::{
@@ -62,18 +62,29 @@ pub struct ConnectingState {
 impl ConnectingState {
     fn set_firewall_policy(
         shared_values: &mut SharedTunnelStateValues,
-        proxy: &Option<openvpn::ProxySettings>,
-        endpoint: Endpoint,
+        tunnel_parameters: &TunnelParameters,
     ) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            // Only processes in the namespace use the tunnel, so the host is left unrestricted
+            if tunnel_parameters.get_namespace().is_some() {
+                return shared_values
+                    .firewall
+                    .reset_policy()
+                    .chain_err(|| "Failed to reset firewall policy for connecting state");
+            }
+        }
+
         // If a proxy is specified we need to pass it on as the peer endpoint.
-        let peer_endpoint = match proxy {
+        let peer_endpoint = match get_openvpn_proxy_settings(tunnel_parameters) {
             Some(proxy_settings) => proxy_settings.get_endpoint(),
-            None => endpoint,
+            None => tunnel_parameters.get_tunnel_endpoint().endpoint,
         };
 
         let policy = FirewallPolicy::Connecting {
             peer_endpoint,
             allow_lan: shared_values.allow_lan,
+            allowed_endpoint: shared_values.allowed_endpoint,
         };
         shared_values
             .firewall
@@ -204,6 +215,7 @@ impl ConnectingState {
             tunnel_parameters: self.tunnel_parameters,
             tunnel_close_event: self.tunnel_close_event,
             close_handle: self.close_handle,
+            retry_attempt: self.retry_attempt,
         }
     }
 
@@ -217,11 +229,25 @@ impl ConnectingState {
         match try_handle_event!(self, commands.poll()) {
             Ok(TunnelCommand::AllowLan(allow_lan)) => {
                 shared_values.allow_lan = allow_lan;
-                match Self::set_firewall_policy(
-                    shared_values,
-                    &get_openvpn_proxy_settings(&self.tunnel_parameters),
-                    self.tunnel_parameters.get_tunnel_endpoint().endpoint,
-                ) {
+                match Self::set_firewall_policy(shared_values, &self.tunnel_parameters) {
+                    Ok(()) => SameState(self),
+                    Err(error) => {
+                        error!("{}", error.display_chain());
+
+                        NewState(DisconnectingState::enter(
+                            shared_values,
+                            (
+                                self.close_handle,
+                                self.tunnel_close_event,
+                                AfterDisconnect::Block(BlockReason::SetFirewallPolicyError),
+                            ),
+                        ))
+                    }
+                }
+            }
+            Ok(TunnelCommand::AllowEndpoint(endpoint)) => {
+                shared_values.allowed_endpoint = Some(endpoint);
+                match Self::set_firewall_policy(shared_values, &self.tunnel_parameters) {
                     Ok(()) => SameState(self),
                     Err(error) => {
                         error!("{}", error.display_chain());
@@ -241,6 +267,38 @@ impl ConnectingState {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self)
             }
+            Ok(TunnelCommand::PersistentBlock(persistent_block)) => {
+                shared_values.persistent_block = persistent_block;
+                SameState(self)
+            }
+            Ok(TunnelCommand::PrepareShutdown) => {
+                shared_values.shutting_down = true;
+                SameState(self)
+            }
+            Ok(TunnelCommand::ForwardedPorts(forwarded_ports)) => {
+                shared_values.forwarded_ports = forwarded_ports;
+                SameState(self)
+            }
+            Ok(TunnelCommand::CustomDns(servers)) => {
+                shared_values.custom_dns = servers;
+                SameState(self)
+            }
+            Ok(TunnelCommand::EncryptedDns(upstream)) => {
+                shared_values.encrypted_dns = upstream;
+                SameState(self)
+            }
+            Ok(TunnelCommand::SplitDns(split_dns)) => {
+                shared_values.split_dns = split_dns;
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetDnsStatus(status_tx)) => {
+                let _ = status_tx.send(shared_values.dns_monitor.status());
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetTunnelStats(stats_tx)) => {
+                let _ = stats_tx.send(None);
+                SameState(self)
+            }
             Ok(TunnelCommand::IsOffline(is_offline)) => {
                 shared_values.is_offline = is_offline;
                 if is_offline {
@@ -253,6 +311,7 @@ impl ConnectingState {
                         ),
                     ))
                 } else {
+                    self.close_handle.network_changed();
                     SameState(self)
                 }
             }
@@ -261,7 +320,7 @@ impl ConnectingState {
                 (
                     self.close_handle,
                     self.tunnel_close_event,
-                    AfterDisconnect::Reconnect(0),
+                    AfterDisconnect::Reconnect(0, ReconnectReason::Requested),
                 ),
             )),
             Ok(TunnelCommand::Disconnect) | Err(_) => NewState(DisconnectingState::enter(
@@ -311,7 +370,10 @@ impl ConnectingState {
                     (
                         self.close_handle,
                         self.tunnel_close_event,
-                        AfterDisconnect::Reconnect(self.retry_attempt + 1),
+                        AfterDisconnect::Reconnect(
+                            self.retry_attempt + 1,
+                            ReconnectReason::TunnelDown,
+                        ),
                     ),
                 ))
             }
@@ -368,12 +430,7 @@ impl TunnelState for ConnectingState {
         {
             None => BlockedState::enter(shared_values, BlockReason::NoMatchingRelay),
             Some(tunnel_parameters) => {
-                let endpoint = tunnel_parameters.get_tunnel_endpoint().endpoint;
-                if let Err(error) = Self::set_firewall_policy(
-                    shared_values,
-                    &get_openvpn_proxy_settings(&tunnel_parameters),
-                    endpoint,
-                ) {
+                if let Err(error) = Self::set_firewall_policy(shared_values, &tunnel_parameters) {
                     error!("{}", error.display_chain());
                     BlockedState::enter(shared_values, BlockReason::StartTunnelError)
                 } else {
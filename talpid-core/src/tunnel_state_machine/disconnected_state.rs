@@ -10,10 +10,22 @@ use futures::{sync::mpsc, Stream};
 pub struct DisconnectedState;
 
 impl DisconnectedState {
+    /// Applies the blocking policy when block when disconnected is enabled, or when persistent
+    /// block is enabled and the state machine is shutting down. The state machine always passes
+    /// through this state before exiting, so with persistent block the blocking rules are in place
+    /// before the daemon stops, and are left behind afterwards.
     fn set_firewall_policy(shared_values: &mut SharedTunnelStateValues) {
-        let result = if shared_values.block_when_disconnected {
+        let persistent_block = shared_values.persistent_block && shared_values.shutting_down;
+        let result = if shared_values.block_when_disconnected || persistent_block {
+            // Nothing is left to use the allowed endpoint once the state machine has stopped
+            let allowed_endpoint = if shared_values.shutting_down {
+                None
+            } else {
+                shared_values.allowed_endpoint
+            };
             let policy = FirewallPolicy::Blocked {
                 allow_lan: shared_values.allow_lan,
+                allowed_endpoint,
             };
             shared_values
                 .firewall
@@ -60,6 +72,13 @@ impl TunnelState for DisconnectedState {
                 }
                 SameState(self)
             }
+            Ok(TunnelCommand::AllowEndpoint(endpoint)) => {
+                if shared_values.allowed_endpoint != Some(endpoint) {
+                    shared_values.allowed_endpoint = Some(endpoint);
+                    Self::set_firewall_policy(shared_values);
+                }
+                SameState(self)
+            }
             Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                 if shared_values.block_when_disconnected != block_when_disconnected {
                     shared_values.block_when_disconnected = block_when_disconnected;
@@ -67,6 +86,41 @@ impl TunnelState for DisconnectedState {
                 }
                 SameState(self)
             }
+            Ok(TunnelCommand::PersistentBlock(persistent_block)) => {
+                shared_values.persistent_block = persistent_block;
+                SameState(self)
+            }
+            Ok(TunnelCommand::PrepareShutdown) => {
+                if !shared_values.shutting_down {
+                    shared_values.shutting_down = true;
+                    Self::set_firewall_policy(shared_values);
+                }
+                SameState(self)
+            }
+            Ok(TunnelCommand::ForwardedPorts(forwarded_ports)) => {
+                shared_values.forwarded_ports = forwarded_ports;
+                SameState(self)
+            }
+            Ok(TunnelCommand::CustomDns(servers)) => {
+                shared_values.custom_dns = servers;
+                SameState(self)
+            }
+            Ok(TunnelCommand::EncryptedDns(upstream)) => {
+                shared_values.encrypted_dns = upstream;
+                SameState(self)
+            }
+            Ok(TunnelCommand::SplitDns(split_dns)) => {
+                shared_values.split_dns = split_dns;
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetDnsStatus(status_tx)) => {
+                let _ = status_tx.send(shared_values.dns_monitor.status());
+                SameState(self)
+            }
+            Ok(TunnelCommand::GetTunnelStats(stats_tx)) => {
+                let _ = stats_tx.send(None);
+                SameState(self)
+            }
             Ok(TunnelCommand::IsOffline(is_offline)) => {
                 shared_values.is_offline = is_offline;
                 SameState(self)
@@ -76,7 +130,15 @@ impl TunnelState for DisconnectedState {
                 NewState(BlockedState::enter(shared_values, reason))
             }
             Ok(_) => SameState(self),
-            Err(_) => Finished,
+            Err(_) => {
+                // The commands channel can close without a shutdown notice, e.g. if the owner of
+                // the state machine crashes.
+                if !shared_values.shutting_down {
+                    shared_values.shutting_down = true;
+                    Self::set_firewall_policy(shared_values);
+                }
+                Finished
+            }
         }
     }
 }
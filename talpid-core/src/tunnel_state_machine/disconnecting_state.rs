@@ -5,7 +5,7 @@ use futures::{
     sync::{mpsc, oneshot},
     Async, Future, Stream,
 };
-use talpid_types::tunnel::{ActionAfterDisconnect, BlockReason};
+use talpid_types::tunnel::{ActionAfterDisconnect, BlockReason, ReconnectReason};
 
 use super::{
     BlockedState, ConnectingState, DisconnectedState, EventConsequence, ResultExt,
@@ -35,15 +35,53 @@ impl DisconnectingState {
                     shared_values.allow_lan = allow_lan;
                     AfterDisconnect::Nothing
                 }
+                Ok(TunnelCommand::AllowEndpoint(endpoint)) => {
+                    shared_values.allowed_endpoint = Some(endpoint);
+                    AfterDisconnect::Nothing
+                }
                 Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Nothing
                 }
+                Ok(TunnelCommand::PersistentBlock(persistent_block)) => {
+                    shared_values.persistent_block = persistent_block;
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::PrepareShutdown) => {
+                    shared_values.shutting_down = true;
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::ForwardedPorts(forwarded_ports)) => {
+                    shared_values.forwarded_ports = forwarded_ports;
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::CustomDns(servers)) => {
+                    shared_values.custom_dns = servers;
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::EncryptedDns(upstream)) => {
+                    shared_values.encrypted_dns = upstream;
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::SplitDns(split_dns)) => {
+                    shared_values.split_dns = split_dns;
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::GetDnsStatus(status_tx)) => {
+                    let _ = status_tx.send(shared_values.dns_monitor.status());
+                    AfterDisconnect::Nothing
+                }
+                Ok(TunnelCommand::GetTunnelStats(stats_tx)) => {
+                    let _ = stats_tx.send(None);
+                    AfterDisconnect::Nothing
+                }
                 Ok(TunnelCommand::IsOffline(is_offline)) => {
                     shared_values.is_offline = is_offline;
                     AfterDisconnect::Nothing
                 }
-                Ok(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
+                Ok(TunnelCommand::Connect) => {
+                    AfterDisconnect::Reconnect(0, ReconnectReason::Requested)
+                }
                 Ok(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
                 _ => AfterDisconnect::Nothing,
             },
@@ -52,41 +90,117 @@ impl DisconnectingState {
                     shared_values.allow_lan = allow_lan;
                     AfterDisconnect::Block(reason)
                 }
+                Ok(TunnelCommand::AllowEndpoint(endpoint)) => {
+                    shared_values.allowed_endpoint = Some(endpoint);
+                    AfterDisconnect::Block(reason)
+                }
                 Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Block(reason)
                 }
+                Ok(TunnelCommand::PersistentBlock(persistent_block)) => {
+                    shared_values.persistent_block = persistent_block;
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::PrepareShutdown) => {
+                    shared_values.shutting_down = true;
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::ForwardedPorts(forwarded_ports)) => {
+                    shared_values.forwarded_ports = forwarded_ports;
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::CustomDns(servers)) => {
+                    shared_values.custom_dns = servers;
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::EncryptedDns(upstream)) => {
+                    shared_values.encrypted_dns = upstream;
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::SplitDns(split_dns)) => {
+                    shared_values.split_dns = split_dns;
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::GetDnsStatus(status_tx)) => {
+                    let _ = status_tx.send(shared_values.dns_monitor.status());
+                    AfterDisconnect::Block(reason)
+                }
+                Ok(TunnelCommand::GetTunnelStats(stats_tx)) => {
+                    let _ = stats_tx.send(None);
+                    AfterDisconnect::Block(reason)
+                }
                 Ok(TunnelCommand::IsOffline(is_offline)) => {
                     shared_values.is_offline = is_offline;
                     if !is_offline && reason == BlockReason::IsOffline {
-                        AfterDisconnect::Reconnect(0)
+                        AfterDisconnect::Reconnect(0, ReconnectReason::BackOnline)
                     } else {
                         AfterDisconnect::Block(reason)
                     }
                 }
-                Ok(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
+                Ok(TunnelCommand::Connect) => {
+                    AfterDisconnect::Reconnect(0, ReconnectReason::Requested)
+                }
                 Ok(TunnelCommand::Disconnect) => AfterDisconnect::Nothing,
                 Ok(TunnelCommand::Block(new_reason)) => AfterDisconnect::Block(new_reason),
                 Err(_) => AfterDisconnect::Block(reason),
             },
-            AfterDisconnect::Reconnect(retry_attempt) => match event {
+            AfterDisconnect::Reconnect(retry_attempt, reconnect_reason) => match event {
                 Ok(TunnelCommand::AllowLan(allow_lan)) => {
                     shared_values.allow_lan = allow_lan;
-                    AfterDisconnect::Reconnect(retry_attempt)
+                    AfterDisconnect::Reconnect(retry_attempt, reconnect_reason)
+                }
+                Ok(TunnelCommand::AllowEndpoint(endpoint)) => {
+                    shared_values.allowed_endpoint = Some(endpoint);
+                    AfterDisconnect::Reconnect(retry_attempt, reconnect_reason)
                 }
                 Ok(TunnelCommand::BlockWhenDisconnected(block_when_disconnected)) => {
                     shared_values.block_when_disconnected = block_when_disconnected;
-                    AfterDisconnect::Reconnect(retry_attempt)
+                    AfterDisconnect::Reconnect(retry_attempt, reconnect_reason)
+                }
+                Ok(TunnelCommand::PersistentBlock(persistent_block)) => {
+                    shared_values.persistent_block = persistent_block;
+                    AfterDisconnect::Reconnect(retry_attempt, reconnect_reason)
+                }
+                Ok(TunnelCommand::PrepareShutdown) => {
+                    shared_values.shutting_down = true;
+                    AfterDisconnect::Reconnect(retry_attempt, reconnect_reason)
+                }
+                Ok(TunnelCommand::ForwardedPorts(forwarded_ports)) => {
+                    shared_values.forwarded_ports = forwarded_ports;
+                    AfterDisconnect::Reconnect(retry_attempt, reconnect_reason)
+                }
+                Ok(TunnelCommand::CustomDns(servers)) => {
+                    shared_values.custom_dns = servers;
+                    AfterDisconnect::Reconnect(retry_attempt, reconnect_reason)
+                }
+                Ok(TunnelCommand::EncryptedDns(upstream)) => {
+                    shared_values.encrypted_dns = upstream;
+                    AfterDisconnect::Reconnect(retry_attempt, reconnect_reason)
+                }
+                Ok(TunnelCommand::SplitDns(split_dns)) => {
+                    shared_values.split_dns = split_dns;
+                    AfterDisconnect::Reconnect(retry_attempt, reconnect_reason)
+                }
+                Ok(TunnelCommand::GetDnsStatus(status_tx)) => {
+                    let _ = status_tx.send(shared_values.dns_monitor.status());
+                    AfterDisconnect::Reconnect(retry_attempt, reconnect_reason)
+                }
+                Ok(TunnelCommand::GetTunnelStats(stats_tx)) => {
+                    let _ = stats_tx.send(None);
+                    AfterDisconnect::Reconnect(retry_attempt, reconnect_reason)
                 }
                 Ok(TunnelCommand::IsOffline(is_offline)) => {
                     shared_values.is_offline = is_offline;
                     if is_offline {
                         AfterDisconnect::Block(BlockReason::IsOffline)
                     } else {
-                        AfterDisconnect::Reconnect(retry_attempt)
+                        AfterDisconnect::Reconnect(retry_attempt, reconnect_reason)
                     }
                 }
-                Ok(TunnelCommand::Connect) => AfterDisconnect::Reconnect(retry_attempt),
+                Ok(TunnelCommand::Connect) => {
+                    AfterDisconnect::Reconnect(retry_attempt, reconnect_reason)
+                }
                 Ok(TunnelCommand::Disconnect) | Err(_) => AfterDisconnect::Nothing,
                 Ok(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
             },
@@ -122,7 +236,7 @@ impl DisconnectingState {
         match self.after_disconnect {
             AfterDisconnect::Nothing => DisconnectedState::enter(shared_values, ()),
             AfterDisconnect::Block(reason) => BlockedState::enter(shared_values, reason),
-            AfterDisconnect::Reconnect(retry_attempt) => {
+            AfterDisconnect::Reconnect(retry_attempt, _) => {
                 ConnectingState::enter(shared_values, retry_attempt)
             }
         }
@@ -175,7 +289,7 @@ impl TunnelState for DisconnectingState {
 pub enum AfterDisconnect {
     Nothing,
     Block(BlockReason),
-    Reconnect(u32),
+    Reconnect(u32, ReconnectReason),
 }
 
 impl AfterDisconnect {
@@ -184,7 +298,7 @@ impl AfterDisconnect {
         match self {
             AfterDisconnect::Nothing => ActionAfterDisconnect::Nothing,
             AfterDisconnect::Block(..) => ActionAfterDisconnect::Block,
-            AfterDisconnect::Reconnect(..) => ActionAfterDisconnect::Reconnect,
+            AfterDisconnect::Reconnect(_, reason) => ActionAfterDisconnect::Reconnect(*reason),
         }
     }
 }
@@ -8,18 +8,25 @@ mod disconnected_state;
 mod disconnecting_state;
 
 use std::{
+    net::IpAddr,
     path::{Path, PathBuf},
     sync::mpsc as sync_mpsc,
     thread,
 };
 
 use error_chain::ChainedError;
-use futures::{sync::mpsc, Async, Future, Poll, Stream};
+use futures::{
+    sync::{mpsc, oneshot},
+    Async, Future, Poll, Stream,
+};
 use tokio_core::reactor::Core;
 
 use talpid_types::{
-    net::TunnelParameters,
-    tunnel::{BlockReason, TunnelStateTransition},
+    net::{
+        dns::{DnsStatus, EncryptedDnsUpstream, SplitDns},
+        Endpoint, TunnelParameters,
+    },
+    tunnel::{BlockReason, TunnelStateTransition, TunnelStats},
 };
 
 use self::{
@@ -50,9 +57,18 @@ error_chain! {
 }
 
 /// Spawn the tunnel state machine thread, returning a channel for sending tunnel commands.
+///
+/// The `allowed_endpoint` is kept reachable for this process in the blocking and connecting
+/// states, e.g. so that an API can still be queried while all other traffic is blocked.
 pub fn spawn<P, T>(
     allow_lan: bool,
     block_when_disconnected: bool,
+    persistent_block: bool,
+    allowed_endpoint: Option<Endpoint>,
+    forwarded_ports: Vec<u16>,
+    custom_dns: Vec<IpAddr>,
+    encrypted_dns: Option<EncryptedDnsUpstream>,
+    split_dns: SplitDns,
     tunnel_parameters_generator: impl TunnelParametersGenerator,
     log_dir: Option<PathBuf>,
     resource_dir: PathBuf,
@@ -73,6 +89,12 @@ where
         match create_event_loop(
             allow_lan,
             block_when_disconnected,
+            persistent_block,
+            allowed_endpoint,
+            forwarded_ports,
+            custom_dns,
+            encrypted_dns,
+            split_dns,
             is_offline,
             tunnel_parameters_generator,
             log_dir,
@@ -110,6 +132,12 @@ where
 fn create_event_loop<T>(
     allow_lan: bool,
     block_when_disconnected: bool,
+    persistent_block: bool,
+    allowed_endpoint: Option<Endpoint>,
+    forwarded_ports: Vec<u16>,
+    custom_dns: Vec<IpAddr>,
+    encrypted_dns: Option<EncryptedDnsUpstream>,
+    split_dns: SplitDns,
     is_offline: bool,
     tunnel_parameters_generator: impl TunnelParametersGenerator,
     log_dir: Option<PathBuf>,
@@ -125,6 +153,12 @@ where
     let state_machine = TunnelStateMachine::new(
         allow_lan,
         block_when_disconnected,
+        persistent_block,
+        allowed_endpoint,
+        forwarded_ports,
+        custom_dns,
+        encrypted_dns,
+        split_dns,
         is_offline,
         tunnel_parameters_generator,
         log_dir,
@@ -146,10 +180,34 @@ where
 pub enum TunnelCommand {
     /// Enable or disable LAN access in the firewall.
     AllowLan(bool),
+    /// Set the endpoint that is kept reachable for this process while the tunnel is not up, after
+    /// its address has changed.
+    AllowEndpoint(Endpoint),
     /// Enable or disable the block_when_disconnected feature.
     BlockWhenDisconnected(bool),
-    /// Notify the state machine of the connectivity of the device.
+    /// Enable or disable leaving the blocking firewall policy in place when the state machine
+    /// exits.
+    PersistentBlock(bool),
+    /// Set the ports that should accept incoming connections over the tunnel.
+    ForwardedPorts(Vec<u16>),
+    /// Set the DNS servers to use in the tunnel. The tunnel gateway is used when empty.
+    CustomDns(Vec<IpAddr>),
+    /// Set an encrypted DNS server that all DNS queries are forwarded to through a local stub
+    /// resolver, or `None` to use plain DNS.
+    EncryptedDns(Option<EncryptedDnsUpstream>),
+    /// Set the DNS servers to use for names within specific domains, instead of the tunnel DNS
+    /// servers.
+    SplitDns(SplitDns),
+    /// Request a description of how the DNS settings are currently managed.
+    GetDnsStatus(oneshot::Sender<DnsStatus>),
+    /// Request the traffic statistics of the tunnel. `None` is sent back when not connected.
+    GetTunnelStats(oneshot::Sender<Option<TunnelStats>>),
+    /// Notify the state machine of the connectivity of the device. Also sent while online when the
+    /// network the device is connected through changes.
     IsOffline(bool),
+    /// Notify the state machine that it is about to be stopped. With persistent block enabled,
+    /// the blocking policy is applied instead of resetting the firewall when disconnected.
+    PrepareShutdown,
     /// Open tunnel connection.
     Connect,
     /// Close tunnel connection.
@@ -174,6 +232,12 @@ impl TunnelStateMachine {
     fn new(
         allow_lan: bool,
         block_when_disconnected: bool,
+        persistent_block: bool,
+        allowed_endpoint: Option<Endpoint>,
+        forwarded_ports: Vec<u16>,
+        custom_dns: Vec<IpAddr>,
+        encrypted_dns: Option<EncryptedDnsUpstream>,
+        split_dns: SplitDns,
         is_offline: bool,
         tunnel_parameters_generator: impl TunnelParametersGenerator,
         log_dir: Option<PathBuf>,
@@ -188,6 +252,13 @@ impl TunnelStateMachine {
             dns_monitor,
             allow_lan,
             block_when_disconnected,
+            persistent_block,
+            shutting_down: false,
+            allowed_endpoint,
+            forwarded_ports,
+            custom_dns,
+            encrypted_dns,
+            split_dns,
             is_offline,
             tunnel_parameters_generator: Box::new(tunnel_parameters_generator),
             log_dir,
@@ -268,6 +339,21 @@ struct SharedTunnelStateValues {
     allow_lan: bool,
     /// Should network access be allowed when in the disconnected state.
     block_when_disconnected: bool,
+    /// Should the blocking firewall policy be left in place when the state machine exits.
+    persistent_block: bool,
+    /// True when the state machine is about to be stopped.
+    shutting_down: bool,
+    /// Endpoint that this process can still reach when network access is blocked or while
+    /// connecting.
+    allowed_endpoint: Option<Endpoint>,
+    /// Ports that accept incoming connections over the tunnel.
+    forwarded_ports: Vec<u16>,
+    /// DNS servers to use instead of the tunnel gateway, if any.
+    custom_dns: Vec<IpAddr>,
+    /// Encrypted DNS server to forward DNS queries to, if any.
+    encrypted_dns: Option<EncryptedDnsUpstream>,
+    /// DNS servers to use for names within specific domains.
+    split_dns: SplitDns,
     /// True when the computer is known to be offline.
     is_offline: bool,
     /// The generator of new `TunnelParameter`s
@@ -0,0 +1,83 @@
+use crate::net::{Endpoint, TransportProtocol};
+use chrono::{offset::Utc, DateTime};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, net::IpAddr};
+
+/// Port used for DNS-over-HTTPS.
+pub const DNS_OVER_HTTPS_PORT: u16 = 443;
+/// Port used for DNS-over-TLS.
+pub const DNS_OVER_TLS_PORT: u16 = 853;
+
+/// Maps domains to the DNS servers that should resolve names within them, instead of the servers
+/// used for all other names.
+pub type SplitDns = BTreeMap<String, Vec<IpAddr>>;
+
+/// Describes how the DNS settings are currently managed on the system.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+pub struct DnsStatus {
+    /// The backend used to manage the DNS settings, or `None` if no DNS settings are enforced.
+    pub backend: Option<String>,
+    /// The DNS servers that are currently enforced.
+    pub servers: Vec<IpAddr>,
+    /// The interface that the DNS servers are bound to.
+    pub interface: Option<String>,
+    /// When the DNS settings were last applied, either when set or when re-applied after being
+    /// changed by another program.
+    pub last_applied: Option<DateTime<Utc>>,
+}
+
+/// An encrypted DNS server that a local stub resolver forwards queries to.
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptedDnsUpstream {
+    /// DNS-over-HTTPS, where queries are posted to `path` on the server.
+    Https {
+        address: IpAddr,
+        /// Name used to verify the certificate of the server.
+        hostname: String,
+        path: String,
+    },
+    /// DNS-over-TLS.
+    Tls {
+        address: IpAddr,
+        /// Name used to verify the certificate of the server.
+        hostname: String,
+    },
+}
+
+impl EncryptedDnsUpstream {
+    /// Returns the endpoint the stub resolver connects to.
+    pub fn endpoint(&self) -> Endpoint {
+        match self {
+            EncryptedDnsUpstream::Https { address, .. } => {
+                Endpoint::new(*address, DNS_OVER_HTTPS_PORT, TransportProtocol::Tcp)
+            }
+            EncryptedDnsUpstream::Tls { address, .. } => {
+                Endpoint::new(*address, DNS_OVER_TLS_PORT, TransportProtocol::Tcp)
+            }
+        }
+    }
+
+    /// Returns the name used to verify the certificate of the server.
+    pub fn hostname(&self) -> &str {
+        match self {
+            EncryptedDnsUpstream::Https { hostname, .. }
+            | EncryptedDnsUpstream::Tls { hostname, .. } => hostname,
+        }
+    }
+}
+
+impl fmt::Display for EncryptedDnsUpstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            EncryptedDnsUpstream::Https {
+                address,
+                hostname,
+                path,
+            } => write!(f, "https://{}{} ({})", hostname, path, address),
+            EncryptedDnsUpstream::Tls { address, hostname } => {
+                write!(f, "tls://{} ({})", hostname, address)
+            }
+        }
+    }
+}
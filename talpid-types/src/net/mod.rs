@@ -6,6 +6,7 @@ use std::{
     str::FromStr,
 };
 
+pub mod dns;
 pub mod openvpn;
 pub mod wireguard;
 
@@ -33,6 +34,18 @@ impl TunnelParameters {
             TunnelParameters::Wireguard(params) => &params.generic_options,
         }
     }
+
+    /// Returns the network namespace the tunnel interface is moved into, if any. Only WireGuard
+    /// tunnels on Linux can be run in a namespace.
+    #[cfg(target_os = "linux")]
+    pub fn get_namespace(&self) -> Option<&str> {
+        match &self {
+            TunnelParameters::OpenVpn(_) => None,
+            TunnelParameters::Wireguard(params) => {
+                params.options.namespace.as_ref().map(String::as_str)
+            }
+        }
+    }
 }
 
 impl From<wireguard::TunnelParameters> for TunnelParameters {
@@ -155,6 +168,47 @@ impl Error for TransportProtocolParseError {
     }
 }
 
+/// Firewall mark set on the sockets that the daemon uses to reach the API and the encrypted DNS
+/// upstream. The firewall only lets traffic with this mark through to those endpoints.
+#[cfg(target_os = "linux")]
+pub const OWN_TRAFFIC_FWMARK: u32 = 0x6d61_7069;
+
+/// Returns true if the given address belongs to one of the private, unique local or link-local
+/// networks that are considered part of the local network.
+pub fn is_local_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => address.is_private() || address.is_link_local(),
+        IpAddr::V6(address) => {
+            let first_segment = address.segments()[0];
+            // fc00::/7 or fe80::/10
+            (first_segment & 0xfe00) == 0xfc00 || (first_segment & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// Returns true if the given address belongs to one of the private networks that addresses inside
+/// the tunnel are assigned from, such as the relay's own DNS server. These addresses are routed
+/// through the tunnel even though they are private.
+pub fn is_tunnel_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let octets = address.octets();
+            // 10.64.0.0/10 for WireGuard or 10.8.0.0/13 for OpenVPN
+            octets[0] == 10 && ((octets[1] & 0xc0) == 64 || (octets[1] & 0xf8) == 8)
+        }
+        IpAddr::V6(address) => {
+            // fc00:bbbb:bbbb::/48
+            address.segments()[..3] == [0xfc00, 0xbbbb, 0xbbbb]
+        }
+    }
+}
+
+/// Returns true if traffic to the given address leaves the device outside the tunnel, on the local
+/// network, and therefore requires LAN access to be allowed.
+pub fn is_reached_outside_tunnel(address: IpAddr) -> bool {
+    is_local_address(address) && !is_tunnel_address(address)
+}
+
 /// Holds optional settings that can apply to different kinds of tunnels
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Hash)]
 pub struct GenericTunnelOptions {
@@ -3,7 +3,7 @@ use ipnetwork::IpNetwork;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{
     fmt,
-    net::{IpAddr, SocketAddr},
+    net::{IpAddr, Ipv6Addr, SocketAddr},
 };
 
 
@@ -20,9 +20,21 @@ pub struct ConnectionConfig {
     pub tunnel: TunnelConfig,
     pub peer: PeerConfig,
     pub gateway: IpAddr,
+    /// IPv6 address of the gateway inside the tunnel. Used as a DNS server when IPv6 is enabled.
+    #[serde(default)]
+    pub ipv6_gateway: Option<Ipv6Addr>,
+    /// Peers to use besides `peer`, e.g. for reaching an office network while the internet is
+    /// reached through `peer`. The allowed IPs of all peers must be distinct.
+    #[serde(default)]
+    pub additional_peers: Vec<PeerConfig>,
 }
 
 impl ConnectionConfig {
+    /// Returns all peers, starting with the main one.
+    pub fn peers(&self) -> impl Iterator<Item = &PeerConfig> {
+        std::iter::once(&self.peer).chain(self.additional_peers.iter())
+    }
+
     pub fn get_tunnel_endpoint(&self) -> TunnelEndpoint {
         let host = self.peer.endpoint;
         TunnelEndpoint {
@@ -40,6 +52,8 @@ pub struct PeerConfig {
     pub public_key: PublicKey,
     pub allowed_ips: Vec<IpNetwork>,
     pub endpoint: SocketAddr,
+    #[serde(default)]
+    pub preshared_key: Option<PresharedKey>,
 }
 
 #[derive(Clone, Eq, PartialEq, Deserialize, Serialize, Debug)]
@@ -53,9 +67,46 @@ pub struct TunnelConfig {
 pub struct TunnelOptions {
     /// MTU for the wireguard tunnel
     pub mtu: Option<u16>,
+    /// Derive the MTU from the path MTU to the relay, probed before the tunnel is brought up.
+    /// Ignored if `mtu` is set.
+    #[serde(default)]
+    pub automatic_mtu: bool,
+    /// Interval in seconds at which keepalive packets are sent to the peers, to keep NAT and
+    /// stateful firewall mappings open while the tunnel is idle.
+    #[serde(default)]
+    pub persistent_keepalive: Option<u16>,
     /// firewall mark
     #[cfg(target_os = "linux")]
     pub fwmark: i32,
+    /// Forces the tunnels to use the given WireGuard implementation. If unset, the kernel module
+    /// is used when it's available.
+    #[cfg(target_os = "linux")]
+    #[serde(default)]
+    pub backend: Option<Backend>,
+    /// Moves the tunnel interface into the network namespace with this name instead of routing
+    /// the host's traffic through it. Only processes running in the namespace use the tunnel.
+    #[cfg(target_os = "linux")]
+    #[serde(default)]
+    pub namespace: Option<String>,
+}
+
+/// WireGuard implementation that a tunnel can run on. Only Linux has more than one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    /// The in-kernel WireGuard module.
+    Kernel,
+    /// wireguard-go running in the daemon on top of a TUN device.
+    Userspace,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Backend::Kernel => "kernel".fmt(fmt),
+            Backend::Userspace => "userspace".fmt(fmt),
+        }
+    }
 }
 
 /// Wireguard x25519 private key
@@ -143,6 +194,48 @@ impl fmt::Display for PublicKey {
     }
 }
 
+/// Wireguard symmetric key shared by both ends of a tunnel, mixed into the handshake in addition to
+/// the x25519 keys. Never printed, not even in `Debug` output.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PresharedKey([u8; 32]);
+
+impl PresharedKey {
+    /// Get the preshared key as bytes
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for PresharedKey {
+    fn from(preshared_key: [u8; 32]) -> PresharedKey {
+        PresharedKey(preshared_key)
+    }
+}
+
+impl Serialize for PresharedKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serialize_key(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for PresharedKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserialize_key(deserializer)
+    }
+}
+
+impl fmt::Debug for PresharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PresharedKey(<redacted>)")
+    }
+}
+
 fn serialize_key<S>(key: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -1,4 +1,5 @@
 use crate::net::TunnelEndpoint;
+use chrono::{offset::Utc, DateTime};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -25,7 +26,35 @@ pub enum TunnelStateTransition {
 pub enum ActionAfterDisconnect {
     Nothing,
     Block,
-    Reconnect,
+    Reconnect(ReconnectReason),
+}
+
+/// Reason for reconnecting the tunnel.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReconnectReason {
+    /// A reconnect was requested, e.g. because the settings changed.
+    Requested,
+    /// The tunnel went down unexpectedly.
+    TunnelDown,
+    /// Traffic was sent through the tunnel without anything being received.
+    TrafficStalled,
+    /// The device came back online.
+    BackOnline,
+}
+
+impl fmt::Display for ReconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::ReconnectReason::*;
+        let description = match *self {
+            Requested => "Reconnect requested",
+            TunnelDown => "The tunnel went down",
+            TrafficStalled => "Nothing was received through the tunnel",
+            BackOnline => "The device came back online",
+        };
+
+        write!(f, "{}", description)
+    }
 }
 
 impl TunnelStateTransition {
@@ -86,3 +115,18 @@ impl fmt::Display for BlockReason {
         write!(f, "{}", description)
     }
 }
+
+/// Traffic statistics for a connected tunnel.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TunnelStats {
+    /// Number of bytes received through the tunnel.
+    pub rx_bytes: u64,
+    /// Number of bytes sent through the tunnel.
+    pub tx_bytes: u64,
+    /// Number of packets received on the tunnel interface, if known.
+    pub rx_packets: Option<u64>,
+    /// Number of packets sent on the tunnel interface, if known.
+    pub tx_packets: Option<u64>,
+    /// When the latest handshake with the peer completed. Only available for WireGuard.
+    pub last_handshake: Option<DateTime<Utc>>,
+}